@@ -8,6 +8,7 @@ use chrono::Utc;
 use rusqlite::Connection;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use vvtv_store::StateStore;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct BackupManifest {
@@ -39,6 +40,18 @@ struct VerifyOptions {
     backup_dir: PathBuf,
 }
 
+#[derive(Debug, Clone)]
+enum SourcesCommand {
+    List,
+    Clear { source_url: Option<String> },
+}
+
+#[derive(Debug, Clone)]
+struct SourcesOptions {
+    command: SourcesCommand,
+    state_db: PathBuf,
+}
+
 fn main() -> Result<()> {
     let mut args = env::args().skip(1);
     let cmd = args.next().unwrap_or_default();
@@ -47,6 +60,7 @@ fn main() -> Result<()> {
         "backup" => run_backup(parse_backup_args(args.collect())?),
         "restore" => run_restore(parse_restore_args(args.collect())?),
         "verify" => run_verify(parse_verify_args(args.collect())?),
+        "sources" => run_sources(parse_sources_args(&args.collect::<Vec<_>>())?),
         _ => {
             print_usage();
             if cmd.is_empty() {
@@ -147,6 +161,44 @@ fn parse_verify_args(args: Vec<String>) -> Result<VerifyOptions> {
     })
 }
 
+fn parse_sources_args(args: &[String]) -> Result<SourcesOptions> {
+    let mut state_db = PathBuf::from("runtime/state/vvtv.db");
+    let mut source_url: Option<String> = None;
+    let mut all = false;
+    let subcommand = args.first().cloned().unwrap_or_default();
+
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--state-db" => {
+                i += 1;
+                state_db = PathBuf::from(require_value(args, i, "--state-db")?);
+            }
+            "--url" if subcommand == "clear" => {
+                i += 1;
+                source_url = Some(require_value(args, i, "--url")?);
+            }
+            "--all" if subcommand == "clear" => {
+                all = true;
+            }
+            flag => bail!("unknown flag for sources {subcommand}: {flag}"),
+        }
+        i += 1;
+    }
+
+    let command = match subcommand.as_str() {
+        "list" => SourcesCommand::List,
+        "clear" => {
+            if source_url.is_some() == all {
+                bail!("sources clear needs exactly one of --url or --all");
+            }
+            SourcesCommand::Clear { source_url }
+        }
+        other => bail!("unknown sources command: {other}"),
+    };
+    Ok(SourcesOptions { command, state_db })
+}
+
 fn require_value(args: &[String], index: usize, flag: &str) -> Result<String> {
     args.get(index)
         .cloned()
@@ -284,6 +336,45 @@ fn run_verify(opts: VerifyOptions) -> Result<()> {
     Ok(())
 }
 
+fn run_sources(opts: SourcesOptions) -> Result<()> {
+    if !opts.state_db.exists() {
+        bail!("state db not found: {}", opts.state_db.display());
+    }
+    let mut store = StateStore::open(&opts.state_db)?;
+
+    match opts.command {
+        SourcesCommand::List => {
+            let now = Utc::now();
+            let ledger = store.load_source_ledger()?;
+            for entry in ledger.entries.values() {
+                println!(
+                    "source_url={} failures={} permanent={} blocked={} first_seen={} last_seen={} retry_after={} expires_at={} last_error={:?}",
+                    entry.source_url,
+                    entry.failure_count,
+                    entry.permanent,
+                    entry.blocks(now),
+                    entry.first_seen.to_rfc3339(),
+                    entry.last_seen.to_rfc3339(),
+                    entry.retry_after.to_rfc3339(),
+                    entry.expires_at.to_rfc3339(),
+                    entry.last_error
+                );
+            }
+            println!("broken_sources={}", ledger.entries.len());
+        }
+        SourcesCommand::Clear {
+            source_url: Some(source_url),
+        } => {
+            let cleared = store.clear_broken_source(&source_url)?;
+            println!("cleared={}", usize::from(cleared));
+        }
+        SourcesCommand::Clear { source_url: None } => {
+            println!("cleared={}", store.clear_broken_sources()?);
+        }
+    }
+    Ok(())
+}
+
 fn snapshot_sqlite(source: &Path, destination: &Path) -> Result<()> {
     if destination.exists() {
         fs::remove_file(destination)
@@ -338,6 +429,6 @@ fn sha256_file(path: &Path) -> Result<String> {
 
 fn print_usage() {
     println!(
-        "Usage:\n  vvtv-admin backup [--state-db PATH] [--owner-card PATH] [--output-dir PATH]\n  vvtv-admin restore --backup-dir PATH [--state-db PATH] [--owner-card PATH] [--force]\n  vvtv-admin verify --backup-dir PATH"
+        "Usage:\n  vvtv-admin backup [--state-db PATH] [--owner-card PATH] [--output-dir PATH]\n  vvtv-admin restore --backup-dir PATH [--state-db PATH] [--owner-card PATH] [--force]\n  vvtv-admin verify --backup-dir PATH\n  vvtv-admin sources list [--state-db PATH]\n  vvtv-admin sources clear (--url URL | --all) [--state-db PATH]"
     );
 }
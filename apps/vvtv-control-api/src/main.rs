@@ -1,14 +1,15 @@
 use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
 use std::sync::Arc;
 
 use anyhow::{Result, anyhow};
 use axum::{
     Json, Router,
-    extract::{Query, Request, State},
+    extract::{Path, Query, Request, State},
     http::StatusCode,
     middleware::{self, Next},
     response::{IntoResponse, Response},
-    routing::{get, post},
+    routing::{delete, get, post},
 };
 use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc, Weekday};
 use hmac::{Hmac, Mac};
@@ -17,7 +18,7 @@ use sha2::Sha256;
 use tokio::sync::RwLock;
 use tracing::info;
 use vvtv_store::{AlertStateRecord, ReportData, StateStore};
-use vvtv_types::{DailyReport, PipelineMetrics, WeeklyReport};
+use vvtv_types::{DailyReport, PinnedSlot, PipelineMetrics, PlanState, StorageUsage, WeeklyReport};
 
 type HmacSha256 = Hmac<Sha256>;
 
@@ -28,6 +29,7 @@ struct ApiState {
     control_token: String,
     control_secret: String,
     state_db_path: String,
+    owner_card_path: String,
     webhook_url: Option<String>,
     alert_cooldown_secs: i64,
     qa_min_threshold: f32,
@@ -70,6 +72,8 @@ async fn main() -> Result<()> {
             .unwrap_or_else(|_| "dev-secret".to_string()),
         state_db_path: std::env::var("VVTV_STATE_DB")
             .unwrap_or_else(|_| "runtime/state/vvtv.db".to_string()),
+        owner_card_path: std::env::var("VVTV_OWNER_CARD_PATH")
+            .unwrap_or_else(|_| "config/owner_card.sample.yaml".to_string()),
         webhook_url: std::env::var("VVTV_ALERT_WEBHOOK_URL").ok(),
         alert_cooldown_secs: std::env::var("VVTV_ALERT_COOLDOWN_SECS")
             .ok()
@@ -109,6 +113,8 @@ async fn main() -> Result<()> {
         .route("/reload-owner-card", post(reload_owner_card))
         .route("/emergency-mode", post(toggle_emergency))
         .route("/curator-mode", post(set_curator_mode))
+        .route("/pins", post(upsert_pin))
+        .route("/pins/{pin_id}", delete(remove_pin))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             require_control_auth,
@@ -119,6 +125,7 @@ async fn main() -> Result<()> {
         .route("/v1/reports/daily", get(daily_report))
         .route("/v1/reports/weekly", get(weekly_report))
         .route("/v1/alerts", get(alerts))
+        .route("/v1/pins", get(list_pins))
         .route("/metrics", get(prometheus_metrics))
         .nest("/v1/control", control_routes)
         .with_state(state);
@@ -161,6 +168,7 @@ async fn daily_report(
 
     match build_report_from_range(
         &state.state_db_path,
+        &state.owner_card_path,
         start,
         end,
         Some(query.date.clone()),
@@ -210,6 +218,7 @@ async fn weekly_report(
 
     match build_report_from_range(
         &state.state_db_path,
+        &state.owner_card_path,
         start,
         end,
         None,
@@ -234,7 +243,10 @@ async fn alerts(State(state): State<ApiState>) -> impl IntoResponse {
 
 async fn prometheus_metrics(State(state): State<ApiState>) -> impl IntoResponse {
     let metrics = load_live_metrics(&state).unwrap_or_else(|_| sample_metrics());
-    let body = format!(
+    let storage = StateStore::open(&state.state_db_path)
+        .and_then(|store| store.load_storage_usage())
+        .unwrap_or_default();
+    let mut body = format!(
         "# HELP vvtv_buffer_minutes Buffer minutes ready for stream\n\
 # TYPE vvtv_buffer_minutes gauge\n\
 vvtv_buffer_minutes {}\n\
@@ -264,9 +276,39 @@ vvtv_stream_disruptions {}\n",
         metrics.curator_actions,
         metrics.stream_disruptions,
     );
+    body.push_str(&render_storage_gauges(&storage));
     (StatusCode::OK, body)
 }
 
+fn render_storage_gauges(storage: &[StorageUsage]) -> String {
+    let mut out = String::new();
+    let mut gauge = |name: &str, help: &str, value: fn(&StorageUsage) -> u64| {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} gauge");
+        for usage in storage {
+            let _ = writeln!(out, "{name}{{area=\"{}\"}} {}", usage.area, value(usage));
+        }
+    };
+    gauge("vvtv_disk_used_bytes", "Bytes used per storage area", |u| {
+        u.used_bytes
+    });
+    gauge(
+        "vvtv_disk_budget_bytes",
+        "Disk budget per storage area",
+        |u| u.budget_bytes,
+    );
+    gauge(
+        "vvtv_disk_pressure_bytes",
+        "Usage that raises DISK_PRESSURE per storage area",
+        |u| u.pressure_bytes,
+    );
+    gauge(
+        "vvtv_disk_evicted_bytes",
+        "Bytes evicted by the last storage GC per storage area",
+        |u| u.evicted_bytes,
+    );
+    out
+}
+
 async fn reload_owner_card() -> impl IntoResponse {
     Json(serde_json::json!({ "ok": true, "action": "reload-owner-card" }))
 }
@@ -281,6 +323,65 @@ async fn set_curator_mode() -> impl IntoResponse {
     Json(serde_json::json!({ "ok": true, "mode": "automatic-with-guardrails" }))
 }
 
+async fn list_pins(State(state): State<ApiState>) -> impl IntoResponse {
+    match StateStore::open(&state.state_db_path).and_then(|store| store.load_pinned_slots()) {
+        Ok(pins) => Json(serde_json::json!({ "count": pins.len(), "pins": pins })).into_response(),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": format!("failed loading pins: {err}") })),
+        )
+            .into_response(),
+    }
+}
+
+async fn upsert_pin(
+    State(state): State<ApiState>,
+    Json(pin): Json<PinnedSlot>,
+) -> impl IntoResponse {
+    if !valid_pin_id(&pin.pin_id) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": format!("pin_id must be 1-{MAX_PIN_ID_LEN} of [A-Za-z0-9_-]")
+            })),
+        )
+            .into_response();
+    }
+    match StateStore::open(&state.state_db_path).and_then(|mut store| store.save_pinned_slot(&pin))
+    {
+        Ok(()) => {
+            info!(pin_id = pin.pin_id, "pinned-slot-saved");
+            Json(serde_json::json!({ "ok": true, "pin_id": pin.pin_id })).into_response()
+        }
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": format!("failed saving pin: {err}") })),
+        )
+            .into_response(),
+    }
+}
+
+async fn remove_pin(
+    State(state): State<ApiState>,
+    Path(pin_id): Path<String>,
+) -> impl IntoResponse {
+    match StateStore::open(&state.state_db_path)
+        .and_then(|mut store| store.delete_pinned_slot(&pin_id))
+    {
+        Ok(true) => Json(serde_json::json!({ "ok": true, "pin_id": pin_id })).into_response(),
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": "pin not found" })),
+        )
+            .into_response(),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": format!("failed deleting pin: {err}") })),
+        )
+            .into_response(),
+    }
+}
+
 async fn require_control_auth(
     State(state): State<ApiState>,
     request: Request,
@@ -335,6 +436,16 @@ async fn require_control_auth(
     next.run(request).await
 }
 
+// Pin ids end up in plan ids, paths and logs, so they stay short and plain.
+const MAX_PIN_ID_LEN: usize = 64;
+
+fn valid_pin_id(pin_id: &str) -> bool {
+    (1..=MAX_PIN_ID_LEN).contains(&pin_id.len())
+        && pin_id
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-')
+}
+
 fn timestamp_fresh(ts: &str) -> bool {
     let parsed = match ts.parse::<i64>() {
         Ok(v) => v,
@@ -417,6 +528,21 @@ fn evaluate_alerts(state: &ApiState) -> anyhow::Result<Vec<AlertItem>> {
         }
     }
 
+    for usage in store
+        .load_storage_usage()?
+        .iter()
+        .filter(|usage| usage.under_pressure())
+    {
+        out.push(AlertItem {
+            code: "DISK_PRESSURE".to_string(),
+            severity: if usage.is_full() { "critical" } else { "high" }.to_string(),
+            message: format!(
+                "storage area {} uses {} of {} bytes (pressure at {})",
+                usage.area, usage.used_bytes, usage.budget_bytes, usage.pressure_bytes
+            ),
+        });
+    }
+
     let discovery_fail_count = audits_24h
         .iter()
         .filter(|a| a.reason_code.contains("DISCOVERY_FAILED_DOMAIN"))
@@ -574,6 +700,7 @@ fn parse_iso_week(raw: &str) -> Option<(i32, u32)> {
 
 fn build_report_from_range(
     db_path: &str,
+    owner_card_path: &str,
     start: chrono::DateTime<Utc>,
     end: chrono::DateTime<Utc>,
     date: Option<String>,
@@ -582,7 +709,11 @@ fn build_report_from_range(
     let store = StateStore::open(db_path)?;
     let data = store.load_report_data_between(start, end)?;
     let metrics = aggregate_metrics(&data);
-    let summary = summarize_report(&data, &metrics);
+    // A missing or invalid card only hides the cap; the report itself still renders.
+    let domain_cap = vvtv_config::load_owner_card(owner_card_path)
+        .ok()
+        .and_then(|card| card.search_policy.max_domain_share);
+    let summary = summarize_report(&data, &metrics, domain_cap);
 
     if let Some(date) = date {
         let report = DailyReport {
@@ -678,15 +809,40 @@ fn aggregate_metrics(data: &ReportData) -> PipelineMetrics {
     }
 }
 
-fn summarize_report(data: &ReportData, metrics: &PipelineMetrics) -> String {
-    let mut domains: std::collections::BTreeMap<&str, usize> = std::collections::BTreeMap::new();
-    for p in &data.plans {
-        *domains.entry(p.source_domain.as_str()).or_default() += 1;
+fn summarize_report(
+    data: &ReportData,
+    metrics: &PipelineMetrics,
+    domain_cap: Option<f32>,
+) -> String {
+    // Share of aired minutes per domain, most frequent domains first.
+    let mut domains: HashMap<&str, (usize, u64)> = HashMap::new();
+    let mut aired_sec = 0u64;
+    for p in data
+        .plans
+        .iter()
+        .filter(|p| matches!(p.state, PlanState::Scheduled | PlanState::Committed))
+    {
+        let entry = domains.entry(p.source_domain.as_str()).or_default();
+        entry.0 += 1;
+        entry.1 += u64::from(p.duration_sec);
+        aired_sec += u64::from(p.duration_sec);
     }
+    let mut domains: Vec<_> = domains.into_iter().collect();
+    domains.sort_by(|a, b| b.1.0.cmp(&a.1.0).then_with(|| a.0.cmp(b.0)));
+    let cap = domain_cap.map_or_else(|| "none".to_string(), |cap| format!("{cap:.2}"));
     let top_domains = domains
         .iter()
-        .map(|(d, c)| format!("{d}:{c}"))
         .take(3)
+        .map(|(d, (count, sec))| {
+            // Second totals stay far below 2^52, where f64 starts rounding.
+            #[allow(clippy::cast_precision_loss)]
+            let share = if aired_sec == 0 {
+                0.0
+            } else {
+                *sec as f64 / aired_sec as f64
+            };
+            format!("{d}:{count} share={share:.2}/cap={cap}")
+        })
         .collect::<Vec<_>>()
         .join(", ");
 
@@ -722,6 +878,8 @@ fn summarize_report(data: &ReportData, metrics: &PipelineMetrics) -> String {
 
 #[cfg(test)]
 mod tests {
+    use vvtv_types::PlanItem;
+
     use super::*;
 
     #[test]
@@ -731,6 +889,16 @@ mod tests {
         assert!(!timestamp_fresh(&(now - 1000).to_string()));
     }
 
+    #[test]
+    fn pin_ids_are_short_and_plain() {
+        assert!(valid_pin_id("late-news_2"));
+        assert!(valid_pin_id(&"a".repeat(MAX_PIN_ID_LEN)));
+        assert!(!valid_pin_id(""));
+        assert!(!valid_pin_id(&"a".repeat(MAX_PIN_ID_LEN + 1)));
+        assert!(!valid_pin_id("../news"));
+        assert!(!valid_pin_id("notícias"));
+    }
+
     #[test]
     fn signature_stable() {
         let a = sign(
@@ -752,4 +920,60 @@ mod tests {
         assert_eq!(a, b);
         assert_eq!(a.len(), 64);
     }
+
+    #[test]
+    fn report_orders_domains_by_count_with_share_and_cap() {
+        let plan = |domain: &str, id: &str, state: PlanState| PlanItem {
+            plan_id: id.to_string(),
+            source_url: format!("https://{domain}/{id}"),
+            source_domain: domain.to_string(),
+            discovered_at: Utc::now(),
+            title: id.to_string(),
+            duration_sec: 600,
+            theme_tags: vec![],
+            visual_features: vec![],
+            quality_signals: vec![],
+            selection_reason: "test".to_string(),
+            policy_match_score: 1.0,
+            state,
+            slot_start_at: None,
+            pin_id: None,
+            score_breakdown: None,
+            drop_reason: None,
+        };
+        let data = ReportData {
+            plans: vec![
+                plan("a.com", "1", PlanState::Scheduled),
+                plan("z.com", "2", PlanState::Scheduled),
+                plan("z.com", "3", PlanState::Committed),
+                plan("z.com", "4", PlanState::Scheduled),
+                plan("a.com", "5", PlanState::Reserved),
+            ],
+            assets: vec![],
+            audits: vec![],
+            metrics: vec![],
+        };
+
+        let summary = summarize_report(&data, &sample_metrics(), Some(0.6));
+        assert!(
+            summary.contains("domains=[z.com:3 share=0.75/cap=0.60, a.com:1 share=0.25/cap=0.60]"),
+            "{summary}"
+        );
+    }
+
+    #[test]
+    fn storage_gauges_are_labelled_by_area() {
+        let usage = |area: &str, used_bytes| StorageUsage {
+            area: area.to_string(),
+            used_bytes,
+            budget_bytes: 100,
+            pressure_bytes: 85,
+            evicted_files: 0,
+            evicted_bytes: 0,
+        };
+        let body = render_storage_gauges(&[usage("hls", 10), usage("ingest", 90)]);
+        assert!(body.contains("# TYPE vvtv_disk_used_bytes gauge\n"));
+        assert!(body.contains("vvtv_disk_used_bytes{area=\"ingest\"} 90\n"));
+        assert!(body.contains("vvtv_disk_budget_bytes{area=\"hls\"} 100\n"));
+    }
 }
@@ -1,4 +1,7 @@
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
 
 use anyhow::{Result, anyhow};
 use chrono::{DateTime, Local, Timelike, Utc};
@@ -8,17 +11,26 @@ use vvtv_config::OwnerCardStore;
 use vvtv_control_agent::{ControlAgent, ResilienceConfig};
 use vvtv_curator::Curator;
 use vvtv_discovery::DiscoveryEngine;
-use vvtv_fetcher::{FetchContext, Fetcher};
+use vvtv_fetcher::{
+    CommitSelection, DownloadOutcome, DownloadPolicy, Downloader, FetchContext, Fetcher,
+};
 use vvtv_nightly::Nightly;
 use vvtv_planner::Planner;
-use vvtv_prep::PrepPipeline;
-use vvtv_queue::QueueManager;
-use vvtv_store::{SchedulerCursors, StateStore};
+use vvtv_prep::{PrepCache, PrepControl, PrepPipeline};
+use vvtv_queue::{PlacementAction, PlacementReport, QueueBuildResult, QueueManager};
+use vvtv_storage::StorageManager;
+use vvtv_store::{PlanningRunRecord, RecoveredState, SchedulerCursors, StateStore};
 use vvtv_stream::HlsStreamer;
 use vvtv_types::{
-    AuditEvent, DailyReport, DiscoveryInput, PipelineMetrics, PlanState, WeeklyReport,
+    AssetItem, AuditEvent, DailyReport, DiscoveryInput, PipelineMetrics, PlanState, PlannedDay,
+    PrepJob, PrepJobState, RunContext, WeeklyReport,
 };
 
+const STATE_DB: &str = "runtime/state/vvtv.db";
+
+// How often running prep jobs look for plans dropped in the meantime.
+const PLAN_WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt()
@@ -30,7 +42,10 @@ async fn main() -> Result<()> {
     let owner_card_store =
         OwnerCardStore::load_from_path(Path::new("config/owner_card.sample.yaml"))?;
     let owner_card = owner_card_store.current();
-    let mut store = StateStore::open("runtime/state/vvtv.db")?;
+    let mut store = StateStore::open(STATE_DB)?;
+    if let Ok(run_id) = std::env::var("VVTV_REPLAY_RUN_ID") {
+        return replay_planning_run(&store, &run_id);
+    }
     let audit = InMemoryAuditSink::new();
     let cloud_agent = build_cloud_agent()?;
     let instance_id = uuid::Uuid::new_v4().to_string();
@@ -52,7 +67,7 @@ async fn main() -> Result<()> {
         }
 
         if !did_boot_recovery {
-            try_recover_on_boot(&mut store, &audit)?;
+            try_recover_on_boot(&owner_card, &mut store, &audit)?;
             did_boot_recovery = true;
         }
 
@@ -138,13 +153,22 @@ fn validate_startup_config() -> Result<()> {
     Ok(())
 }
 
-fn try_recover_on_boot(store: &mut StateStore, audit: &InMemoryAuditSink) -> Result<()> {
+fn try_recover_on_boot(
+    owner_card: &vvtv_types::OwnerCard,
+    store: &mut StateStore,
+    audit: &InMemoryAuditSink,
+) -> Result<()> {
     let recovered = store.load_recovery()?;
     if recovered.queue.is_empty() || recovered.assets.is_empty() {
         return Ok(());
     }
 
-    let hls_output = HlsStreamer::build_hls(&recovered.queue, &recovered.assets, "runtime/hls")?;
+    let hls_output = HlsStreamer::build_hls(
+        &recovered.queue,
+        &recovered.assets,
+        owner_card.quality_policy.hls_segment_sec,
+        "runtime/hls",
+    )?;
     let playlist = std::fs::read_to_string(&hls_output.playlist_path)
         .unwrap_or_else(|_| HlsStreamer::render_playlist(&recovered.queue));
 
@@ -154,7 +178,7 @@ fn try_recover_on_boot(store: &mut StateStore, audit: &InMemoryAuditSink) -> Res
         .filter(|a| a.qa_status == vvtv_types::QaStatus::Passed)
         .count();
     let metrics = PipelineMetrics {
-        buffer_minutes: (recovered.queue.len() as i64) * 10,
+        buffer_minutes: QueueManager::buffer_minutes(&recovered.queue, &recovered.assets),
         plans_created: recovered.plans.len(),
         plans_committed: recovered.assets.len(),
         qa_pass_rate: if recovered.assets.is_empty() {
@@ -203,27 +227,115 @@ fn run_discovery_window(
     store: &mut StateStore,
     audit: &InMemoryAuditSink,
 ) -> Result<()> {
-    let discovered = DiscoveryEngine::discover(owner_card, &seed_discovery_inputs());
-    let day = Planner::build_day(owner_card, discovered);
+    // Pins set through the control API replace owner-card pins with the same id.
+    let stored_pins = store.load_pinned_slots()?;
+    let mut pins: Vec<_> = owner_card
+        .schedule_policy
+        .pinned_slots
+        .iter()
+        .filter(|pin| !stored_pins.iter().any(|stored| stored.pin_id == pin.pin_id))
+        .cloned()
+        .collect();
+    pins.extend(stored_pins);
+    let started_at = Utc::now();
+    let history = store.load_airing_history(
+        started_at,
+        i64::from(
+            owner_card
+                .editorial_profile
+                .freshness_policy
+                .recent_theme_window_hours,
+        ),
+    )?;
+    store.expire_broken_sources(started_at)?;
+    let source_ledger = store.load_source_ledger()?;
+    let record = PlanningRunRecord {
+        run_id: uuid::Uuid::new_v4().to_string(),
+        seed: run_seed(),
+        started_at,
+        owner_card: owner_card.clone(),
+        pins,
+        inputs: seed_discovery_inputs(),
+        history,
+        source_ledger,
+    };
+    store.save_planning_run(&record)?;
+
+    let mut day = plan_from_record(&record);
+    let superseded = Planner::supersede(&store.load_plans()?, &mut day, &record.run_id);
+    if !superseded.is_empty() {
+        store.save_plans(&superseded)?;
+        let mut event = audit_event("vvtv-planner", "supersede-plans", "PLANS_SUPERSEDED", None);
+        event.after = Some(format!(
+            "run_id={} plans={}",
+            record.run_id,
+            superseded.len()
+        ));
+        record_audit(audit, store, event)?;
+    }
+    for pin_id in &day.unresolved_pins {
+        let mut event = audit_event("vvtv-planner", "place-pin", "PIN_UNRESOLVED", None);
+        event.before = Some(pin_id.clone());
+        record_audit(audit, store, event)?;
+    }
     let mut all_plans = day.scheduled;
     all_plans.extend(day.reserves);
     store.save_plans(&all_plans)?;
 
-    record_audit(
-        audit,
-        store,
-        audit_event(
-            "vvtv-discovery",
-            "discover-window",
-            "DISCOVERY_WINDOW_OK",
-            Some(all_plans.len() as f32),
-        ),
-    )?;
+    let mut event = audit_event(
+        "vvtv-discovery",
+        "discover-window",
+        "DISCOVERY_WINDOW_OK",
+        Some(all_plans.len() as f32),
+    );
+    event.after = Some(format!("run_id={} seed={}", record.run_id, record.seed));
+    record_audit(audit, store, event)?;
 
-    info!(plans_created = all_plans.len(), "discovery-window-complete");
+    info!(
+        plans_created = all_plans.len(),
+        run_id = record.run_id,
+        seed = record.seed,
+        "discovery-window-complete"
+    );
     Ok(())
 }
 
+fn plan_from_record(record: &PlanningRunRecord) -> PlannedDay {
+    let run = RunContext::seeded(record.seed, record.started_at);
+    let discovered = DiscoveryEngine::discover_with_ledger(
+        &run,
+        &record.owner_card,
+        &record.inputs,
+        &record.source_ledger,
+    );
+    Planner::build_day_with_pins(
+        &run,
+        &record.owner_card,
+        record.started_at,
+        &record.pins,
+        &record.history,
+        &record.source_ledger,
+        discovered,
+    )
+}
+
+fn replay_planning_run(store: &StateStore, run_id: &str) -> Result<()> {
+    let record = store
+        .load_planning_run(run_id)?
+        .ok_or_else(|| anyhow!("planning run not found: {run_id}"))?;
+    let day = plan_from_record(&record);
+    info!(run_id, seed = record.seed, "planning-run-replayed");
+    println!("{}", serde_json::to_string_pretty(&day)?);
+    Ok(())
+}
+
+fn run_seed() -> u64 {
+    std::env::var("VVTV_SEED")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_else(|| uuid::Uuid::new_v4().as_u64_pair().0)
+}
+
 async fn run_commit_window(
     owner_card: &vvtv_types::OwnerCard,
     store: &mut StateStore,
@@ -249,23 +361,43 @@ async fn run_commit_window(
         .cloned()
         .collect();
 
-    let fetched = Fetcher::commit_t_minus_4h(
+    let run = RunContext::seeded(run_seed(), Utc::now());
+    if !enforce_disk_budget(owner_card, audit, store, &refreshed, run.now())? {
+        return Ok(());
+    }
+    store.expire_broken_sources(run.now())?;
+    let mut ctx = FetchContext::from_ledger(&store.load_source_ledger()?, run.now());
+    let selection = Fetcher::commit_t_minus_4h_with(&run, owner_card, scheduled, reserves, &ctx);
+    let plans = record_commit_selection(audit, store, &refreshed.plans, &selection)?;
+    let attempted = selection.assets.len();
+    let downloads = download_assets(
+        &run,
         owner_card,
-        Utc::now(),
-        scheduled,
-        reserves,
-        &FetchContext::default(),
-    );
-    let prepared = PrepPipeline::process(owner_card, fetched.clone());
+        audit,
+        store,
+        &plans,
+        selection.assets,
+        &mut ctx,
+    )
+    .await?;
+    let fetched = downloads.assets;
+    let prepared = prepare_assets(owner_card, audit, store, fetched.clone())?;
     store.save_assets(&prepared)?;
 
-    let queue_result = QueueManager::build(owner_card, &prepared, &prepared);
-    let curated = Curator::auto_curate(owner_card, queue_result.queue);
-    store.replace_queue(&curated.queue)?;
+    // The queue is rebuilt whole, so it takes every committed asset still to
+    // air, not only the ones this window prepared.
+    let airing = unaired_assets(&store.load_recovery()?, run.now());
+    let queue_result = QueueManager::build_with(&run, owner_card, &airing, &airing);
+    record_missed_pins(audit, store, &queue_result)?;
+    let curated = Curator::auto_curate_with(&run, owner_card, &airing, queue_result.queue);
+    let placed = QueueManager::enforce_placement(owner_card, &airing, curated.queue);
+    record_placement_corrections(audit, store, &placed)?;
+    store.replace_queue(&placed.queue)?;
 
-    let hls_output = HlsStreamer::build_hls(&curated.queue, &prepared, "runtime/hls")?;
+    let segment_sec = owner_card.quality_policy.hls_segment_sec;
+    let hls_output = HlsStreamer::build_hls(&placed.queue, &airing, segment_sec, "runtime/hls")?;
     let playlist = std::fs::read_to_string(&hls_output.playlist_path)
-        .unwrap_or_else(|_| HlsStreamer::render_playlist(&curated.queue));
+        .unwrap_or_else(|_| HlsStreamer::render_playlist(&placed.queue));
     let qa_passed = prepared
         .iter()
         .filter(|a| a.qa_status == vvtv_types::QaStatus::Passed)
@@ -275,32 +407,21 @@ async fn run_commit_window(
         buffer_minutes: queue_result.buffer_minutes,
         plans_created: refreshed.plans.len(),
         plans_committed: fetched.len(),
-        qa_pass_rate: if prepared.is_empty() {
-            0.0
-        } else {
-            qa_passed as f32 / prepared.len() as f32
-        },
-        fallback_rate: if refreshed.plans.is_empty() {
-            0.0
-        } else {
-            (refreshed.plans.len().saturating_sub(fetched.len())) as f32
-                / refreshed.plans.len() as f32
-        },
+        qa_pass_rate: ratio(qa_passed, prepared.len()),
+        fallback_rate: ratio(downloads.fallbacks.len(), attempted),
         curator_actions: curated.actions_applied,
         stream_disruptions: usize::from(queue_result.emergency_triggered),
     };
     store.save_metrics(&metrics)?;
 
-    record_audit(
-        audit,
-        store,
-        audit_event(
-            "vvtv-commit",
-            "commit-window",
-            "COMMIT_WINDOW_OK",
-            Some(metrics.plans_committed as f32),
-        ),
-    )?;
+    let mut event = audit_event(
+        "vvtv-commit",
+        "commit-window",
+        "COMMIT_WINDOW_OK",
+        Some(metrics.plans_committed as f32),
+    );
+    event.after = run.seed().map(|seed| format!("seed={seed}"));
+    record_audit(audit, store, event)?;
 
     info!(
         recovery = false,
@@ -311,7 +432,7 @@ async fn run_commit_window(
         fallback_rate = metrics.fallback_rate,
         curator_actions = metrics.curator_actions,
         stream_disruptions = metrics.stream_disruptions,
-        playlist_segments = curated.queue.len(),
+        playlist_segments = placed.queue.len(),
         playlist_bytes = playlist.len(),
         hls_playlist_path = %hls_output.playlist_path.display(),
         hls_segment_count_estimate = hls_output.segment_count_estimate,
@@ -343,7 +464,7 @@ async fn run_nightly(
         .filter(|a| a.qa_status == vvtv_types::QaStatus::Passed)
         .count();
     let metrics = PipelineMetrics {
-        buffer_minutes: (recovered.queue.len() as i64) * 10,
+        buffer_minutes: QueueManager::buffer_minutes(&recovered.queue, &recovered.assets),
         plans_created: recovered.plans.len(),
         plans_committed: recovered.assets.len(),
         qa_pass_rate: if recovered.assets.is_empty() {
@@ -404,6 +525,391 @@ async fn run_nightly(
     Ok(())
 }
 
+// Stored assets of committed plans whose slot has not started yet, in slot
+// order.
+fn unaired_assets(state: &RecoveredState, now: DateTime<Utc>) -> Vec<AssetItem> {
+    let slots: HashMap<&str, DateTime<Utc>> = state
+        .plans
+        .iter()
+        .filter(|plan| plan.state == PlanState::Committed)
+        .filter_map(|plan| Some((plan.plan_id.as_str(), plan.slot_start_at?)))
+        .filter(|(_, slot)| *slot >= now)
+        .collect();
+    let mut assets: Vec<_> = state
+        .assets
+        .iter()
+        .filter_map(|asset| Some((*slots.get(asset.plan_id.as_str())?, asset.clone())))
+        .collect();
+    assets.sort_by_key(|(slot, _)| *slot);
+    assets.into_iter().map(|(_, asset)| asset).collect()
+}
+
+// Stores committed plans so later windows never commit them again and audits
+// every plan whose slot passed without a commit. Returns the plans as they
+// stand after the commit.
+fn record_commit_selection(
+    audit: &InMemoryAuditSink,
+    store: &mut StateStore,
+    plans: &[vvtv_types::PlanItem],
+    selection: &CommitSelection,
+) -> Result<Vec<vvtv_types::PlanItem>> {
+    store.save_plans(&selection.committed)?;
+    store.save_plans(&selection.missed)?;
+    store.save_plans(&selection.replaced)?;
+    let dropped = selection
+        .missed
+        .iter()
+        .map(|plan| ("COMMIT_MISSED", plan))
+        .chain(
+            selection
+                .replaced
+                .iter()
+                .map(|plan| ("COMMIT_RESERVE", plan)),
+        );
+    for (reason_code, plan) in dropped {
+        let mut event = audit_event("vvtv-fetcher", "commit-window", reason_code, None);
+        event.before = Some(plan.plan_id.clone());
+        event.after.clone_from(&plan.drop_reason);
+        record_audit(audit, store, event)?;
+    }
+
+    let updated: HashMap<&str, &vvtv_types::PlanItem> = selection
+        .committed
+        .iter()
+        .chain(&selection.missed)
+        .chain(&selection.replaced)
+        .map(|plan| (plan.plan_id.as_str(), plan))
+        .collect();
+    Ok(plans
+        .iter()
+        .map(|plan| (*updated.get(plan.plan_id.as_str()).unwrap_or(&plan)).clone())
+        .collect())
+}
+
+// Plans whose source could not be fetched are stored as Dropped and their
+// slot goes to the closest reserve; every fallback is audited. Failures feed the
+// broken-source ledger and a successful fetch clears its entry.
+async fn download_assets(
+    run: &RunContext,
+    owner_card: &vvtv_types::OwnerCard,
+    audit: &InMemoryAuditSink,
+    store: &mut StateStore,
+    plans: &[vvtv_types::PlanItem],
+    assets: Vec<AssetItem>,
+    ctx: &mut FetchContext,
+) -> Result<DownloadOutcome> {
+    let downloader = Downloader::new(
+        DownloadPolicy::from_owner_card(owner_card),
+        "runtime/ingest/partial",
+        "runtime/ingest/objects",
+    )?
+    .with_clock(run.clock());
+    let outcome = Fetcher::download_committed(run, &downloader, plans, assets, ctx).await;
+    store.save_plans(&outcome.dropped)?;
+    store.save_plans(&outcome.promoted)?;
+    for failure in &outcome.failures {
+        store.record_source_failure(
+            &failure.source_url,
+            &failure.error.to_string(),
+            failure.error.is_permanent(),
+            run.now(),
+            &owner_card.fetch_policy,
+        )?;
+    }
+    for source_url in &outcome.fetched_urls {
+        store.clear_broken_source(source_url)?;
+    }
+    for asset in &outcome.assets {
+        if let Some(sha256) = asset.content_sha256() {
+            let bytes = std::fs::metadata(&asset.local_path).map_or(0, |meta| meta.len());
+            store.add_content_ref(sha256, &asset.local_path, bytes, &asset.plan_id)?;
+        }
+    }
+
+    for fallback in &outcome.fallbacks {
+        let reason_code = if fallback.replacement_plan_id.is_some() {
+            "FALLBACK_RESERVE"
+        } else {
+            "FALLBACK_NO_RESERVE"
+        };
+        let mut event = audit_event("vvtv-fetcher", "commit-fallback", reason_code, None);
+        event.before = Some(fallback.plan_id.clone());
+        let failure = outcome
+            .failures
+            .iter()
+            .find(|failure| failure.plan_id == fallback.plan_id);
+        event.after = failure.map(|failure| {
+            format!(
+                "replacement={} permanent={} url={} error={}",
+                fallback.replacement_plan_id.as_deref().unwrap_or("none"),
+                failure.error.is_permanent(),
+                failure.source_url,
+                failure.error
+            )
+        });
+        record_audit(audit, store, event)?;
+    }
+    Ok(outcome)
+}
+
+// Releases the content of aired and dropped plans, then runs the storage GC.
+// Anything queued or still waiting to air (the emergency pool draws from it) is
+// protected. Returns false, after auditing, when there is no room to commit.
+fn enforce_disk_budget(
+    owner_card: &vvtv_types::OwnerCard,
+    audit: &InMemoryAuditSink,
+    store: &mut StateStore,
+    state: &RecoveredState,
+    now: DateTime<Utc>,
+) -> Result<bool> {
+    let plans: HashMap<&str, &vvtv_types::PlanItem> = state
+        .plans
+        .iter()
+        .map(|plan| (plan.plan_id.as_str(), plan))
+        .collect();
+    let queued: HashSet<&str> = state
+        .queue
+        .iter()
+        .map(|entry| entry.asset_id.as_str())
+        .collect();
+
+    let mut protected = HashSet::new();
+    for asset in &state.assets {
+        let done = plans.get(asset.plan_id.as_str()).is_none_or(|plan| {
+            plan.state == PlanState::Dropped
+                || plan.slot_start_at.is_some_and(|start| {
+                    start + chrono::Duration::seconds(i64::from(plan.duration_sec)) <= now
+                })
+        });
+        if done && !queued.contains(asset.asset_id.as_str()) {
+            if let Some(sha256) = asset.content_sha256() {
+                store.release_content_ref(sha256, &asset.plan_id)?;
+            }
+        } else {
+            protected.extend(asset.prepared_files().map(PathBuf::from));
+        }
+    }
+    for blob in store.load_content_blobs()? {
+        if blob.ref_count > 0 {
+            protected.insert(PathBuf::from(blob.path));
+        }
+    }
+
+    let report = StorageManager::new("runtime", &owner_card.storage_policy).collect(&protected)?;
+    let evicted: Vec<String> = report
+        .evicted
+        .iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    store.remove_content_blobs_at(&evicted)?;
+    store.save_storage_usage(&report.usage)?;
+    for usage in report.under_pressure() {
+        info!(
+            area = usage.area,
+            used_bytes = usage.used_bytes,
+            budget_bytes = usage.budget_bytes,
+            "disk-pressure"
+        );
+    }
+
+    let full: Vec<_> = report
+        .usage
+        .iter()
+        .filter(|usage| usage.is_full())
+        .collect();
+    if full.iter().any(|usage| usage.area != "hls") {
+        let mut event = audit_event(
+            "vvtv-storage",
+            "commit-refused",
+            "DISK_BUDGET_EXHAUSTED",
+            None,
+        );
+        event.after = Some(
+            full.iter()
+                .map(|usage| format!("{}={}/{}", usage.area, usage.used_bytes, usage.budget_bytes))
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+        record_audit(audit, store, event)?;
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+// Content already prepared under the current profile is reused from the prep
+// cache instead of being encoded again. Every rejection is audited under the
+// reason code of its first failed QA check, with the full list in `after`.
+// Prep runs as persisted jobs: work a crashed run left queued or running is
+// resumed alongside the new assets, jobs of dropped plans are cancelled and
+// every state change is written as it happens.
+fn prepare_assets(
+    owner_card: &vvtv_types::OwnerCard,
+    audit: &InMemoryAuditSink,
+    store: &mut StateStore,
+    assets: Vec<AssetItem>,
+) -> Result<Vec<AssetItem>> {
+    let now = Utc::now();
+    let fresh: HashSet<String> = assets.iter().map(|a| a.asset_id.clone()).collect();
+    let mut jobs: Vec<PrepJob> = store
+        .load_pending_prep_jobs()?
+        .into_iter()
+        .filter(|job| !fresh.contains(&job.job_id))
+        .map(|mut job| {
+            job.state = PrepJobState::Queued;
+            job
+        })
+        .collect();
+    let resumed = jobs.len();
+    jobs.extend(assets.into_iter().map(|asset| PrepJob::queued(asset, now)));
+
+    let live: HashSet<String> = store
+        .load_recovery()?
+        .plans
+        .into_iter()
+        .filter(|plan| plan.state != PlanState::Dropped)
+        .map(|plan| plan.plan_id)
+        .collect();
+    let control = PrepControl::new();
+    for job in jobs.iter().filter(|job| !live.contains(&job.plan_id)) {
+        control.cancel(&job.plan_id);
+    }
+    store.save_prep_jobs(&jobs)?;
+
+    let mut cache = PrepCache::new(store.load_prep_cache()?);
+    let mut saved = Ok(());
+    let mut cancelled = Vec::new();
+    let mut failed = Vec::new();
+    let plan_ids: HashSet<String> = jobs.iter().map(|job| job.plan_id.clone()).collect();
+    let outcome = std::thread::scope(|scope| {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let (control, plan_ids) = (&control, &plan_ids);
+        scope.spawn(move || watch_dropped_plans(control, plan_ids, &stopped));
+        let outcome = PrepPipeline::run_jobs(owner_card, jobs, &mut cache, control, |job| {
+            if saved.is_ok() {
+                saved = store.save_prep_jobs(std::slice::from_ref(job));
+            }
+            match job.state {
+                PrepJobState::Cancelled => cancelled.push(job.plan_id.clone()),
+                PrepJobState::Failed => failed.push(job.clone()),
+                _ => {}
+            }
+        });
+        drop(stop);
+        outcome
+    });
+    saved?;
+    store.save_prep_cache(&outcome.new_entries)?;
+    info!(
+        resumed,
+        cancelled = cancelled.len(),
+        failed = failed.len(),
+        cache_hits = outcome.cache_hits,
+        prepared = outcome.new_entries.len(),
+        "prep-jobs"
+    );
+    for plan_id in cancelled {
+        let mut event = audit_event("vvtv-prep", "prep-job", "PREP_CANCELLED", None);
+        event.before = Some(plan_id);
+        record_audit(audit, store, event)?;
+    }
+    // The failure kind (`MEDIA_TIMEOUT`, `MEDIA_CORRUPT_INPUT`, ...) with the
+    // tool's own explanation.
+    for job in failed {
+        let mut event = audit_event(
+            "vvtv-prep",
+            "prep-job",
+            job.error_code.as_deref().unwrap_or("MEDIA_FAILED"),
+            None,
+        );
+        event.before = Some(job.plan_id);
+        event.after = job.error;
+        record_audit(audit, store, event)?;
+    }
+    for asset in &outcome.assets {
+        let Some(report) = asset
+            .qa_report
+            .as_ref()
+            .filter(|_| asset.qa_status == vvtv_types::QaStatus::Rejected)
+        else {
+            continue;
+        };
+        let reasons = report.reason_codes();
+        let mut event = audit_event(
+            "vvtv-prep",
+            "qa-reject",
+            reasons.first().copied().unwrap_or("QA_REJECTED"),
+            None,
+        );
+        event.before = Some(asset.plan_id.clone());
+        event.after = Some(reasons.join(" "));
+        record_audit(audit, store, event)?;
+    }
+    Ok(outcome.assets)
+}
+
+// Plans can be dropped from elsewhere (admin, control API) while prep runs.
+// Polls the store on its own connection and cancels the jobs of dropped plans,
+// which kills a running encode, until `stopped` hangs up.
+fn watch_dropped_plans(
+    control: &PrepControl,
+    plan_ids: &HashSet<String>,
+    stopped: &mpsc::Receiver<()>,
+) {
+    let store = match StateStore::open(STATE_DB) {
+        Ok(store) => store,
+        Err(err) => {
+            info!(error = %err, "prep-plan-watch-unavailable");
+            return;
+        }
+    };
+    while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(PLAN_WATCH_INTERVAL) {
+        let Ok(plans) = store.load_plans() else {
+            continue;
+        };
+        for plan in plans {
+            if plan.state == PlanState::Dropped && plan_ids.contains(&plan.plan_id) {
+                control.cancel(&plan.plan_id);
+            }
+        }
+    }
+}
+
+fn record_missed_pins(
+    audit: &InMemoryAuditSink,
+    store: &mut StateStore,
+    queue_result: &QueueBuildResult,
+) -> Result<()> {
+    let action = if queue_result.emergency_triggered {
+        "emergency-fill"
+    } else {
+        "queue-build"
+    };
+    for plan_id in &queue_result.missed_pins {
+        let mut event = audit_event("vvtv-queue", action, "PIN_MISSED", None);
+        event.before = Some(plan_id.clone());
+        record_audit(audit, store, event)?;
+    }
+    Ok(())
+}
+
+fn record_placement_corrections(
+    audit: &InMemoryAuditSink,
+    store: &mut StateStore,
+    report: &PlacementReport,
+) -> Result<()> {
+    for correction in &report.corrections {
+        let action = match correction.action {
+            PlacementAction::Swapped => "placement-swap",
+            PlacementAction::Dropped => "placement-drop",
+        };
+        let mut event = audit_event("vvtv-queue", action, "PLACEMENT_VIOLATION", None);
+        event.before = Some(correction.asset_id.clone());
+        event.after = Some(format!("rule_id={}", correction.rule_id));
+        record_audit(audit, store, event)?;
+    }
+    Ok(())
+}
+
 fn record_audit(
     audit: &InMemoryAuditSink,
     store: &mut StateStore,
@@ -446,6 +952,14 @@ fn seed_discovery_inputs() -> Vec<DiscoveryInput> {
     ]
 }
 
+fn ratio(part: usize, total: usize) -> f32 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f32 / total as f32
+    }
+}
+
 fn audit_event(module: &str, action: &str, reason_code: &str, score: Option<f32>) -> AuditEvent {
     AuditEvent {
         event_id: uuid::Uuid::new_v4().to_string(),
@@ -538,4 +1052,59 @@ mod tests {
         cursors.last_nightly_date = Some(date_key(local_time));
         assert!(!due_nightly(local_time, &cursors));
     }
+
+    #[test]
+    fn queue_keeps_earlier_committed_assets_until_they_air() {
+        let now = Utc.with_ymd_and_hms(2026, 2, 27, 10, 0, 0).unwrap();
+        let plan = |plan_id: &str, state: &str, slot_hours: i64| -> vvtv_types::PlanItem {
+            serde_json::from_value(serde_json::json!({
+                "plan_id": plan_id,
+                "source_url": format!("https://example.com/{plan_id}"),
+                "source_domain": "example.com",
+                "discovered_at": now,
+                "title": plan_id,
+                "duration_sec": 600,
+                "theme_tags": [],
+                "visual_features": [],
+                "quality_signals": [],
+                "selection_reason": "",
+                "policy_match_score": 0.5,
+                "state": state,
+                "slot_start_at": now + chrono::Duration::hours(slot_hours),
+            }))
+            .expect("plan")
+        };
+        let asset = |plan_id: &str| -> AssetItem {
+            serde_json::from_value(serde_json::json!({
+                "asset_id": format!("asset-{plan_id}"),
+                "plan_id": plan_id,
+                "local_path": format!("runtime/prepared/{plan_id}.mp4"),
+                "checksum": "",
+                "resolution": {"width": 1280, "height": 720},
+                "audio_lufs": -16.0,
+                "qa_status": "Passed",
+            }))
+            .expect("asset")
+        };
+        let state = RecoveredState {
+            plans: vec![
+                plan("this-window", "Committed", 3),
+                plan("earlier-window", "Committed", 1),
+                plan("aired", "Committed", -1),
+                plan("dropped", "Dropped", 2),
+            ],
+            assets: ["this-window", "earlier-window", "aired", "dropped"]
+                .into_iter()
+                .map(asset)
+                .collect(),
+            queue: vec![],
+            audits: vec![],
+        };
+
+        let ids: Vec<_> = unaired_assets(&state, now)
+            .into_iter()
+            .map(|asset| asset.plan_id)
+            .collect();
+        assert_eq!(ids, ["earlier-window", "this-window"]);
+    }
 }
@@ -1,12 +1,16 @@
-use chrono::Utc;
+use chrono::{Duration, TimeZone, Utc};
+use vvtv_curator::Curator;
 use vvtv_discovery::DiscoveryEngine;
 use vvtv_fetcher::{FetchContext, Fetcher};
 use vvtv_planner::Planner;
-use vvtv_prep::PrepPipeline;
-use vvtv_queue::QueueManager;
+use vvtv_prep::{PrepCache, PrepControl, PrepPipeline};
+use vvtv_queue::{PlacementAction, QueueManager};
 use vvtv_types::{
-    AutotunePolicy, CuratorPolicy, DiscoveryInput, EditorialProfile, MusicPolicy, OwnerCard,
-    QualityPolicy, SafetyPolicy, SchedulePolicy, SearchPolicy,
+    AiringHistory, AssetItem, AutotunePolicy, CuratorPolicy, DiscoveryInput, EditorialProfile,
+    FetchPolicy, FreshnessPolicy, LoudnessMeasurement, LoudnessReport, MusicPolicy,
+    OptimizerPolicy, OwnerCard, PlacementRule, PrepJob, PrepJobState, PrepPolicy, QaStatus,
+    QualityPolicy, QueueEntry, RunContext, SafetyPolicy, SchedulePolicy, SearchPolicy, SlotType,
+    SourceLedger, StoragePolicy, ThemePolicy,
 };
 
 fn owner_card() -> OwnerCard {
@@ -16,6 +20,9 @@ fn owner_card() -> OwnerCard {
             target_avg_duration_sec: 900,
             max_consecutive_same_theme: 2,
             min_unique_themes_per_block: 2,
+            theme_policy: ThemePolicy::default(),
+            placement_rules: vec![],
+            freshness_policy: FreshnessPolicy::default(),
         },
         search_policy: SearchPolicy {
             allowlist_domains: vec![
@@ -24,6 +31,8 @@ fn owner_card() -> OwnerCard {
             ],
             blacklist_domains: vec!["blocked.com".to_string()],
             blocked_keywords: vec!["forbidden".to_string()],
+            max_domain_share: None,
+            min_distinct_sources_per_block: 0,
         },
         schedule_policy: SchedulePolicy {
             planning_horizon_hours: 24,
@@ -31,11 +40,14 @@ fn owner_card() -> OwnerCard {
             commit_interval_minutes: 30,
             buffer_target_minutes: 60,
             buffer_critical_minutes: 20,
+            pinned_slots: vec![],
+            optimizer: OptimizerPolicy::default(),
         },
         quality_policy: QualityPolicy {
             min_resolution_height: 720,
             target_audio_lufs: -16.0,
             max_audio_deviation_lufs: 2.5,
+            ..QualityPolicy::default()
         },
         music_policy: MusicPolicy {
             preferred_moods: vec!["night".to_string()],
@@ -54,6 +66,9 @@ fn owner_card() -> OwnerCard {
             max_daily_adjustment_pct: 5.0,
             enabled: true,
         },
+        fetch_policy: FetchPolicy::default(),
+        storage_policy: StoragePolicy::default(),
+        prep_policy: PrepPolicy::default(),
     }
 }
 
@@ -83,13 +98,18 @@ fn full_flow_happy_path() {
 
     let discovered = DiscoveryEngine::discover(&card, &inputs);
     let day = Planner::build_day(&card, discovered);
-    let fetched = Fetcher::commit_t_minus_4h(
+    let mut fetched = Fetcher::commit_t_minus_4h(
         &card,
         Utc::now(),
         day.scheduled,
         day.reserves,
         &FetchContext::default(),
-    );
+    )
+    .assets;
+    // Without ffmpeg, prep relies on loudness measured upstream.
+    for asset in &mut fetched {
+        asset.loudness = Some(measured_loudness());
+    }
     let prepared = PrepPipeline::process(&card, fetched.clone());
     let queue = QueueManager::build(&card, &prepared, &prepared);
 
@@ -98,6 +118,117 @@ fn full_flow_happy_path() {
     assert!(queue.buffer_minutes >= 20);
 }
 
+fn measured_loudness() -> LoudnessReport {
+    let measurement = LoudnessMeasurement {
+        integrated_lufs: -16.0,
+        true_peak_dbtp: -1.5,
+        loudness_range_lu: 6.0,
+        threshold_lufs: -26.0,
+    };
+    LoudnessReport {
+        source: measurement,
+        normalized: measurement,
+    }
+}
+
+#[test]
+fn unmeasured_loudness_keeps_assets_off_air() {
+    let card = owner_card();
+    let inputs: Vec<_> = (0..3)
+        .map(|i| DiscoveryInput {
+            source_url: format!("https://example-source-a.com/v/{i}"),
+            title: format!("Item {i}"),
+            duration_sec: 900,
+            theme_tags: vec![format!("t{i}")],
+            visual_features: vec![],
+            quality_signals: vec![],
+            hd_confirmed: true,
+        })
+        .collect();
+
+    let day = Planner::build_day(&card, DiscoveryEngine::discover(&card, &inputs));
+    let fetched = Fetcher::commit_t_minus_4h(
+        &card,
+        Utc::now(),
+        day.scheduled,
+        day.reserves,
+        &FetchContext::default(),
+    )
+    .assets;
+    let prepared = PrepPipeline::process(&card, fetched);
+
+    assert!(!prepared.is_empty());
+    for asset in &prepared {
+        assert_eq!(asset.qa_status, QaStatus::Rejected);
+        let report = asset.qa_report.as_ref().expect("qa report");
+        assert_eq!(report.reason_codes(), ["QA_LOUDNESS_OFF_TARGET"]);
+    }
+    assert!(QueueManager::build(&card, &prepared, &[]).queue.is_empty());
+}
+
+#[test]
+fn prep_jobs_report_every_state_and_skip_cancelled_plans() {
+    let card = owner_card();
+    let inputs: Vec<_> = (0..4)
+        .map(|i| DiscoveryInput {
+            source_url: format!("https://example-source-a.com/v/{i}"),
+            title: format!("Item {i}"),
+            duration_sec: 600,
+            theme_tags: vec![format!("t{i}")],
+            visual_features: vec![],
+            quality_signals: vec![],
+            hd_confirmed: true,
+        })
+        .collect();
+    let discovered = DiscoveryEngine::discover(&card, &inputs);
+    let day = Planner::build_day(&card, discovered);
+    let now = Utc::now();
+    let jobs: Vec<_> = Fetcher::commit_t_minus_4h(
+        &card,
+        now,
+        day.scheduled,
+        day.reserves,
+        &FetchContext::default(),
+    )
+    .assets
+    .into_iter()
+    .map(|asset| PrepJob::queued(asset, now))
+    .collect();
+    assert!(jobs.len() >= 2);
+    let dropped = jobs[0].plan_id.clone();
+
+    let control = PrepControl::new();
+    control.cancel(&dropped);
+    let mut updates: Vec<(String, PrepJobState, u32)> = Vec::new();
+    let outcome = PrepPipeline::run_jobs(
+        &card,
+        jobs.clone(),
+        &mut PrepCache::default(),
+        &control,
+        |job| updates.push((job.job_id.clone(), job.state, job.attempts)),
+    );
+
+    assert_eq!(outcome.assets.len(), jobs.len() - 1);
+    assert!(outcome.assets.iter().all(|asset| asset.plan_id != dropped));
+    for job in &jobs {
+        let states: Vec<_> = updates
+            .iter()
+            .filter(|(id, _, _)| *id == job.job_id)
+            .map(|(_, state, attempts)| (*state, *attempts))
+            .collect();
+        if job.plan_id == dropped {
+            assert_eq!(states, [(PrepJobState::Cancelled, 0)]);
+        } else {
+            assert_eq!(states.len(), 2);
+            assert_eq!(states[0], (PrepJobState::Running, 1));
+            assert!(matches!(
+                states[1].0,
+                PrepJobState::Done | PrepJobState::Failed
+            ));
+        }
+    }
+}
+
 #[test]
 fn broken_link_uses_reserve() {
     let card = owner_card();
@@ -135,7 +266,287 @@ fn broken_link_uses_reserve() {
     let day = Planner::build_day(&card, discovered.clone());
     let mut ctx = FetchContext::default();
     ctx.broken_urls.insert(discovered[0].source_url.clone());
-    let fetched = Fetcher::commit_t_minus_4h(&card, Utc::now(), day.scheduled, day.reserves, &ctx);
+    let selection =
+        Fetcher::commit_t_minus_4h(&card, Utc::now(), day.scheduled, day.reserves, &ctx);
+
+    assert!(!selection.assets.is_empty());
+    assert!(
+        selection
+            .committed
+            .iter()
+            .all(|plan| plan.source_url != discovered[0].source_url)
+    );
+}
+
+#[test]
+fn seeded_runs_are_byte_for_byte_reproducible() {
+    let mut card = owner_card();
+    card.schedule_policy.optimizer.enabled = true;
+    let inputs: Vec<_> = (0..6)
+        .map(|i| DiscoveryInput {
+            source_url: format!("https://example-source-a.com/v/{i}"),
+            title: format!("Item {i}"),
+            duration_sec: 600 + i * 60,
+            theme_tags: vec![format!("t{}", i % 3)],
+            visual_features: vec![],
+            quality_signals: vec![],
+            hd_confirmed: true,
+        })
+        .collect();
+    let now = Utc::now();
+
+    let replay = |seed: u64| {
+        let run = RunContext::seeded(seed, now);
+        let discovered = DiscoveryEngine::discover_with(&run, &card, &inputs);
+        let day = Planner::build_day_with_pins(
+            &run,
+            &card,
+            now,
+            &[],
+            &AiringHistory::default(),
+            &SourceLedger::default(),
+            discovered,
+        );
+        let mut fetched = Fetcher::commit_t_minus_4h_with(
+            &run,
+            &card,
+            day.scheduled.clone(),
+            day.reserves.clone(),
+            &FetchContext::default(),
+        )
+        .assets;
+        for asset in &mut fetched {
+            asset.qa_status = QaStatus::Passed;
+        }
+        let queue = QueueManager::build_with(&run, &card, &fetched, &fetched);
+        let curated = Curator::auto_curate_with(&run, &card, &fetched, queue.queue);
+        serde_json::to_string(&(&day.scheduled, &day.reserves, &fetched, &curated.queue))
+            .expect("serialize replay")
+    };
+
+    assert_eq!(replay(99), replay(99));
+    assert_ne!(replay(99), replay(100));
+}
+
+#[test]
+fn queue_validation_corrects_placement_violations() {
+    let mut card = owner_card();
+    card.editorial_profile.placement_rules = serde_json::from_str::<Vec<PlacementRule>>(
+        r#"[
+            {"rule_id": "late", "tag": "horror", "kind": "window", "from": "23:00", "until": "05:00"},
+            {"rule_id": "apart", "tag": "gore", "kind": "never_adjacent", "other_tag": "kids"}
+        ]"#,
+    )
+    .expect("parse rules");
+    let noon = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+    // Runtimes differ, so start times must follow the new order.
+    let assets: Vec<AssetItem> = [("kids", 1_800.0), ("news", 1_200.0)]
+        .into_iter()
+        .map(|(asset_id, runtime_sec)| {
+            serde_json::from_value(serde_json::json!({
+                "asset_id": asset_id,
+                "plan_id": format!("plan-{asset_id}"),
+                "local_path": format!("runtime/prepared/{asset_id}.mp4"),
+                "checksum": "",
+                "resolution": {"width": 1280, "height": 720},
+                "audio_lufs": -16.0,
+                "qa_status": "Passed",
+                "trim": {"head_sec": 0.0, "tail_sec": 0.0, "effective_duration_sec": runtime_sec},
+            }))
+            .expect("parse asset")
+        })
+        .collect();
+    let queue: Vec<_> = ["kids", "gore", "news", "horror"]
+        .iter()
+        .zip(0..)
+        .map(|(tag, slot)| QueueEntry {
+            entry_id: format!("e-{tag}"),
+            asset_id: (*tag).to_string(),
+            start_at: noon + Duration::minutes(10 * slot),
+            slot_type: SlotType::Main,
+            fallback_level: 0,
+            curation_trace_id: None,
+            pinned: false,
+            tags: vec![(*tag).to_string()],
+        })
+        .collect();
+
+    // A swap alone also moves the later start times.
+    let swapped = QueueManager::enforce_placement(&card, &assets, queue[..3].to_vec());
+    let starts: Vec<_> = swapped.queue.iter().map(|e| e.start_at).collect();
+    assert_eq!(
+        starts,
+        vec![
+            noon,
+            noon + Duration::minutes(30),
+            noon + Duration::minutes(50)
+        ]
+    );
+
+    let report = QueueManager::enforce_placement(&card, &assets, queue);
+    let order: Vec<_> = report
+        .queue
+        .iter()
+        .map(|e| (e.asset_id.as_str(), e.start_at))
+        .collect();
+    assert_eq!(
+        order,
+        vec![
+            ("kids", noon),
+            ("news", noon + Duration::minutes(30)),
+            ("gore", noon + Duration::minutes(50)),
+        ]
+    );
+    let corrections: Vec<_> = report
+        .corrections
+        .iter()
+        .map(|c| (c.rule_id.as_str(), c.asset_id.as_str(), c.action))
+        .collect();
+    assert_eq!(
+        corrections,
+        vec![
+            ("apart", "gore", PlacementAction::Swapped),
+            ("late", "horror", PlacementAction::Dropped),
+        ]
+    );
+}
+
+#[test]
+fn overlapping_discovery_windows_commit_each_slot_once() {
+    let card = owner_card();
+    let inputs: Vec<_> = (0..12)
+        .map(|i| DiscoveryInput {
+            source_url: format!(
+                "https://example-source-{}.com/v/{i}",
+                if i % 2 == 0 { "a" } else { "b" }
+            ),
+            title: format!("Item {i}"),
+            duration_sec: 1_800,
+            theme_tags: vec![format!("t{}", i % 4)],
+            visual_features: vec![],
+            quality_signals: vec![],
+            hd_confirmed: true,
+        })
+        .collect();
+    let t0 = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+    // Plans by id, as the state store keeps them.
+    let mut stored: Vec<vvtv_types::PlanItem> = Vec::new();
+    let save = |stored: &mut Vec<vvtv_types::PlanItem>, plans: Vec<vvtv_types::PlanItem>| {
+        for plan in plans {
+            stored.retain(|kept| kept.plan_id != plan.plan_id);
+            stored.push(plan);
+        }
+    };
+
+    let mut commits = 0;
+    for (seed, now) in [(1, t0), (2, t0 + Duration::hours(1))] {
+        let run = RunContext::seeded(seed, now);
+        let discovered =
+            DiscoveryEngine::discover_with_ledger(&run, &card, &inputs, &SourceLedger::default());
+        let mut day = Planner::build_day_with_pins(
+            &run,
+            &card,
+            now,
+            &[],
+            &AiringHistory::default(),
+            &SourceLedger::default(),
+            discovered,
+        );
+        let superseded = Planner::supersede(&stored, &mut day, &format!("run-{seed}"));
+        save(&mut stored, superseded);
+        save(&mut stored, day.scheduled);
+        save(&mut stored, day.reserves);
 
-    assert!(!fetched.is_empty());
+        let (scheduled, reserves): (Vec<_>, Vec<_>) = stored
+            .iter()
+            .filter(|plan| {
+                matches!(
+                    plan.state,
+                    vvtv_types::PlanState::Scheduled | vvtv_types::PlanState::Reserved
+                )
+            })
+            .cloned()
+            .partition(|plan| plan.state == vvtv_types::PlanState::Scheduled);
+        let selection = Fetcher::commit_t_minus_4h_with(
+            &run,
+            &card,
+            scheduled,
+            reserves,
+            &FetchContext::default(),
+        );
+        assert!(!selection.committed.is_empty());
+        commits += selection.committed.len();
+        save(&mut stored, selection.committed);
+        save(&mut stored, selection.missed);
+        save(&mut stored, selection.replaced);
+    }
+
+    let mut committed: Vec<_> = stored
+        .iter()
+        .filter(|plan| plan.state == vvtv_types::PlanState::Committed)
+        .map(|plan| {
+            let start = plan.slot_start_at.expect("committed plans carry a slot");
+            (
+                start,
+                start + Duration::seconds(i64::from(plan.duration_sec)),
+            )
+        })
+        .collect();
+    assert_eq!(committed.len(), commits);
+    committed.sort();
+    for pair in committed.windows(2) {
+        assert!(pair[0].1 <= pair[1].0, "slots overlap: {pair:?}");
+    }
+}
+
+#[test]
+fn curator_swap_reflows_start_times() {
+    let card = owner_card();
+    let noon = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+    let assets: Vec<AssetItem> = [("a", 600.0), ("b", 1_800.0), ("c", 300.0)]
+        .into_iter()
+        .map(|(asset_id, runtime_sec)| {
+            serde_json::from_value(serde_json::json!({
+                "asset_id": asset_id,
+                "plan_id": format!("plan-{asset_id}"),
+                "local_path": format!("runtime/prepared/{asset_id}.mp4"),
+                "checksum": "",
+                "resolution": {"width": 1280, "height": 720},
+                "audio_lufs": -16.0,
+                "qa_status": "Passed",
+                "trim": {"head_sec": 0.0, "tail_sec": 0.0, "effective_duration_sec": runtime_sec},
+            }))
+            .expect("parse asset")
+        })
+        .collect();
+    let mut queue: Vec<_> = ["a", "b", "c"]
+        .iter()
+        .map(|asset_id| QueueEntry {
+            entry_id: format!("e-{asset_id}"),
+            asset_id: (*asset_id).to_string(),
+            start_at: noon,
+            slot_type: SlotType::Main,
+            fallback_level: 0,
+            curation_trace_id: None,
+            pinned: false,
+            tags: vec![(*asset_id).to_string()],
+        })
+        .collect();
+    QueueManager::reflow(&assets, &mut queue);
+
+    let curated = Curator::auto_curate(&card, &assets, queue);
+    let order: Vec<_> = curated
+        .queue
+        .iter()
+        .map(|e| (e.asset_id.as_str(), e.start_at))
+        .collect();
+    assert_eq!(curated.actions_applied, 1);
+    assert_eq!(
+        order,
+        vec![
+            ("a", noon),
+            ("c", noon + Duration::minutes(10)),
+            ("b", noon + Duration::minutes(15)),
+        ]
+    );
 }
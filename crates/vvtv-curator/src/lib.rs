@@ -1,5 +1,7 @@
-use uuid::Uuid;
-use vvtv_types::{OwnerCard, QueueEntry};
+use vvtv_queue::QueueManager;
+use vvtv_types::{
+    AssetItem, OwnerCard, QueueEntry, RunContext, adjacency_violation, window_violation,
+};
 
 pub struct CuratorResult {
     pub queue: Vec<QueueEntry>,
@@ -10,8 +12,26 @@ pub struct Curator;
 
 impl Curator {
     #[must_use]
-    pub fn auto_curate(owner_card: &OwnerCard, mut queue: Vec<QueueEntry>) -> CuratorResult {
-        if !owner_card.curator_policy.auto_apply || queue.len() < 3 {
+    pub fn auto_curate(
+        owner_card: &OwnerCard,
+        assets: &[AssetItem],
+        queue: Vec<QueueEntry>,
+    ) -> CuratorResult {
+        Self::auto_curate_with(&RunContext::system(), owner_card, assets, queue)
+    }
+
+    #[must_use]
+    pub fn auto_curate_with(
+        run: &RunContext,
+        owner_card: &OwnerCard,
+        assets: &[AssetItem],
+        queue: Vec<QueueEntry>,
+    ) -> CuratorResult {
+        if !owner_card.curator_policy.auto_apply
+            || queue.len() < 3
+            || queue[1].pinned
+            || queue[2].pinned
+        {
             return CuratorResult {
                 queue,
                 actions_applied: 0,
@@ -19,8 +39,21 @@ impl Curator {
         }
 
         // Simple anti-repetition move: swap positions 1 and 2 once per cycle.
-        queue.swap(1, 2);
-        let trace_id = Uuid::new_v4().to_string();
+        // Entries differ in length, so start times are reflowed from the
+        // `assets` runtimes after the swap.
+        let mut swapped = queue.clone();
+        swapped.swap(1, 2);
+        QueueManager::reflow(assets, &mut swapped);
+
+        // Never trade a valid grid for one that breaks a placement rule.
+        if breaks_placement(owner_card, &queue, &swapped) {
+            return CuratorResult {
+                queue,
+                actions_applied: 0,
+            };
+        }
+        let mut queue = swapped;
+        let trace_id = run.next_id();
         if let Some(first) = queue.get_mut(1) {
             first.curation_trace_id = Some(trace_id.clone());
         }
@@ -34,3 +67,21 @@ impl Curator {
         }
     }
 }
+
+// Checks the windows of every entry the swap moved and the adjacencies around
+// the swapped pair.
+fn breaks_placement(owner_card: &OwnerCard, before: &[QueueEntry], queue: &[QueueEntry]) -> bool {
+    let rules = &owner_card.editorial_profile.placement_rules;
+    let moved = queue
+        .iter()
+        .zip(before)
+        .enumerate()
+        .skip(1)
+        .filter(|(idx, (entry, old))| *idx <= 2 || entry.start_at != old.start_at)
+        .any(|(_, (entry, _))| window_violation(rules, &entry.tags, entry.start_at).is_some());
+    moved
+        || queue
+            .windows(2)
+            .take(3)
+            .any(|pair| adjacency_violation(rules, &pair[0].tags, &pair[1].tags).is_some())
+}
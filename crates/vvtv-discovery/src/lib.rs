@@ -1,15 +1,35 @@
-use chrono::Utc;
-use uuid::Uuid;
-use vvtv_types::{DiscoveryInput, OwnerCard, PlanItem, PlanState};
+use vvtv_types::{DiscoveryInput, OwnerCard, PlanItem, PlanState, RunContext, SourceLedger};
 
 pub struct DiscoveryEngine;
 
 impl DiscoveryEngine {
     #[must_use]
     pub fn discover(owner_card: &OwnerCard, candidates: &[DiscoveryInput]) -> Vec<PlanItem> {
+        Self::discover_with(&RunContext::system(), owner_card, candidates)
+    }
+
+    #[must_use]
+    pub fn discover_with(
+        run: &RunContext,
+        owner_card: &OwnerCard,
+        candidates: &[DiscoveryInput],
+    ) -> Vec<PlanItem> {
+        Self::discover_with_ledger(run, owner_card, candidates, &SourceLedger::default())
+    }
+
+    // Candidates whose source is still backing off in the broken-source ledger
+    // are skipped until their retry time passes.
+    #[must_use]
+    pub fn discover_with_ledger(
+        run: &RunContext,
+        owner_card: &OwnerCard,
+        candidates: &[DiscoveryInput],
+        ledger: &SourceLedger,
+    ) -> Vec<PlanItem> {
         let mut accepted: Vec<PlanItem> = candidates
             .iter()
-            .filter_map(|candidate| map_candidate(owner_card, candidate))
+            .filter(|candidate| !ledger.is_blocked(&candidate.source_url, run.now()))
+            .filter_map(|candidate| map_candidate(run, owner_card, candidate))
             .collect();
 
         accepted.sort_by(|a, b| {
@@ -21,7 +41,11 @@ impl DiscoveryEngine {
     }
 }
 
-fn map_candidate(owner_card: &OwnerCard, candidate: &DiscoveryInput) -> Option<PlanItem> {
+fn map_candidate(
+    run: &RunContext,
+    owner_card: &OwnerCard,
+    candidate: &DiscoveryInput,
+) -> Option<PlanItem> {
     let source_domain = extract_domain(&candidate.source_url);
     if !is_allowlisted(owner_card, &source_domain)
         || is_blocked_domain(owner_card, &source_domain)
@@ -41,10 +65,10 @@ fn map_candidate(owner_card: &OwnerCard, candidate: &DiscoveryInput) -> Option<P
     );
 
     Some(PlanItem {
-        plan_id: Uuid::new_v4().to_string(),
+        plan_id: run.next_id(),
         source_url: candidate.source_url.clone(),
         source_domain,
-        discovered_at: Utc::now(),
+        discovered_at: run.now(),
         title: candidate.title.clone(),
         duration_sec: candidate.duration_sec,
         theme_tags: candidate.theme_tags.clone(),
@@ -53,6 +77,10 @@ fn map_candidate(owner_card: &OwnerCard, candidate: &DiscoveryInput) -> Option<P
         selection_reason,
         policy_match_score: score,
         state: PlanState::Candidate,
+        slot_start_at: None,
+        pin_id: None,
+        score_breakdown: None,
+        drop_reason: None,
     })
 }
 
@@ -139,8 +167,9 @@ fn extract_domain(url: &str) -> String {
 #[cfg(test)]
 mod tests {
     use vvtv_types::{
-        AutotunePolicy, CuratorPolicy, EditorialProfile, MusicPolicy, OwnerCard, QualityPolicy,
-        SafetyPolicy, SchedulePolicy, SearchPolicy,
+        AutotunePolicy, BrokenSource, CuratorPolicy, EditorialProfile, FetchPolicy,
+        FreshnessPolicy, MusicPolicy, OptimizerPolicy, OwnerCard, PrepPolicy, QualityPolicy,
+        SafetyPolicy, SchedulePolicy, SearchPolicy, StoragePolicy, ThemePolicy,
     };
 
     use super::*;
@@ -189,6 +218,45 @@ mod tests {
         assert!(accepted[0].policy_match_score >= accepted[1].policy_match_score);
     }
 
+    #[test]
+    fn discover_skips_sources_backing_off_in_the_ledger() {
+        let card = sample_owner_card();
+        let input = DiscoveryInput {
+            source_url: "https://media.example.com/broken".to_string(),
+            title: "broken".to_string(),
+            duration_sec: 900,
+            theme_tags: vec!["travel".to_string()],
+            visual_features: vec![],
+            quality_signals: vec![],
+            hd_confirmed: true,
+        };
+        let run = RunContext::system();
+        let now = run.now();
+        let entry = BrokenSource::record_failure(
+            None,
+            &input.source_url,
+            "HTTP 404",
+            true,
+            now,
+            &FetchPolicy::default(),
+        );
+        let retry_after = entry.retry_after;
+        let mut ledger = SourceLedger::default();
+        ledger.entries.insert(input.source_url.clone(), entry);
+        let inputs = [input];
+
+        let blocked = DiscoveryEngine::discover_with_ledger(&run, &card, &inputs, &ledger);
+        assert!(blocked.is_empty());
+
+        let recovered = DiscoveryEngine::discover_with_ledger(
+            &RunContext::at(retry_after),
+            &card,
+            &inputs,
+            &ledger,
+        );
+        assert_eq!(recovered.len(), 1);
+    }
+
     fn sample_owner_card() -> OwnerCard {
         OwnerCard {
             schema_version: 1,
@@ -196,11 +264,16 @@ mod tests {
                 target_avg_duration_sec: 900,
                 max_consecutive_same_theme: 2,
                 min_unique_themes_per_block: 3,
+                theme_policy: ThemePolicy::default(),
+                placement_rules: vec![],
+                freshness_policy: FreshnessPolicy::default(),
             },
             search_policy: SearchPolicy {
                 allowlist_domains: vec!["example.com".to_string()],
                 blacklist_domains: vec!["evil.example.com".to_string()],
                 blocked_keywords: vec!["violence".to_string()],
+                max_domain_share: None,
+                min_distinct_sources_per_block: 0,
             },
             schedule_policy: SchedulePolicy {
                 planning_horizon_hours: 24,
@@ -208,11 +281,14 @@ mod tests {
                 commit_interval_minutes: 30,
                 buffer_target_minutes: 120,
                 buffer_critical_minutes: 20,
+                pinned_slots: vec![],
+                optimizer: OptimizerPolicy::default(),
             },
             quality_policy: QualityPolicy {
                 min_resolution_height: 720,
                 target_audio_lufs: -16.0,
                 max_audio_deviation_lufs: 2.5,
+                ..QualityPolicy::default()
             },
             music_policy: MusicPolicy {
                 preferred_moods: vec!["night".to_string()],
@@ -231,6 +307,9 @@ mod tests {
                 max_daily_adjustment_pct: 10.0,
                 enabled: true,
             },
+            fetch_policy: FetchPolicy::default(),
+            storage_policy: StoragePolicy::default(),
+            prep_policy: PrepPolicy::default(),
         }
     }
 }
@@ -0,0 +1,233 @@
+use std::future::Future;
+use std::path::Path;
+
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use vvtv_types::Clock;
+
+use crate::download::Bandwidth;
+use crate::{DownloadError, ProgressBoard};
+
+const HASH_BUFFER_BYTES: usize = 64 * 1024;
+
+// One way of reaching sources, picked by URL scheme. A backend only moves
+// bytes: hashing, the size limit, the bandwidth cap and progress all live in
+// the `PartialFile` it writes to, so every scheme resumes and checksums alike.
+pub trait FetchBackend {
+    // Continues `partial` from its current offset when the source can serve a
+    // range, or restarts it when it cannot.
+    fn get(
+        &self,
+        url: &Url,
+        partial: &mut PartialFile<'_>,
+    ) -> impl Future<Output = Result<(), DownloadError>> + Send;
+}
+
+// The `<key>.part` file of one transfer, hashed as it grows.
+pub struct PartialFile<'a> {
+    key: &'a str,
+    path: &'a Path,
+    file: Option<File>,
+    hasher: Sha256,
+    written: u64,
+    expected: Option<u64>,
+    max_bytes: u64,
+    progress: &'a ProgressBoard,
+    clock: &'a dyn Clock,
+    bandwidth: Option<&'a Bandwidth>,
+}
+
+impl<'a> PartialFile<'a> {
+    // Picks up whatever an earlier attempt left at `path`.
+    pub(crate) async fn open(
+        key: &'a str,
+        path: &'a Path,
+        max_bytes: u64,
+        progress: &'a ProgressBoard,
+        clock: &'a dyn Clock,
+        bandwidth: Option<&'a Bandwidth>,
+    ) -> Result<Self, DownloadError> {
+        let (hasher, written) = hash_partial(path).await?;
+        Ok(Self {
+            key,
+            path,
+            file: None,
+            hasher,
+            written,
+            expected: None,
+            max_bytes,
+            progress,
+            clock,
+            bandwidth,
+        })
+    }
+
+    #[must_use]
+    pub fn offset(&self) -> u64 {
+        self.written
+    }
+
+    /// Appends from the current offset; `remaining` is what the source still
+    /// has to send, when it says so.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the partial file cannot be opened.
+    pub async fn resume(&mut self, remaining: Option<u64>) -> Result<(), DownloadError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path)
+            .await?;
+        self.start(file, remaining.map(|len| self.written + len))
+            .await
+    }
+
+    /// Throws away what was kept and starts from the first byte.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the partial file cannot be recreated.
+    pub async fn restart(&mut self, total: Option<u64>) -> Result<(), DownloadError> {
+        self.hasher = Sha256::new();
+        self.written = 0;
+        let file = File::create(self.path).await?;
+        self.start(file, total).await
+    }
+
+    /// The source no longer agrees with what was kept; the next attempt starts
+    /// over.
+    ///
+    /// # Errors
+    ///
+    /// Always: [`DownloadError::Interrupted`] with `reason`, or the I/O error
+    /// of removing the partial file.
+    pub async fn discard(&mut self, reason: String) -> Result<(), DownloadError> {
+        self.file = None;
+        fs::remove_file(self.path).await?;
+        Err(DownloadError::Interrupted(reason))
+    }
+
+    /// Hashes and appends `chunk`, waiting for the bandwidth cap.
+    ///
+    /// # Errors
+    ///
+    /// [`DownloadError::TooLarge`] past the size limit,
+    /// [`DownloadError::TooSlow`] once the deadline is out of reach, and I/O
+    /// errors of the partial file.
+    pub async fn write(&mut self, chunk: &[u8]) -> Result<(), DownloadError> {
+        self.written += chunk.len() as u64;
+        if self.written > self.max_bytes {
+            return self.too_large().await;
+        }
+        if let Some(bandwidth) = self.bandwidth {
+            bandwidth.acquire(chunk.len()).await;
+        }
+        self.hasher.update(chunk);
+        let Some(file) = self.file.as_mut() else {
+            return Err(DownloadError::Interrupted(
+                "partial file is not open".into(),
+            ));
+        };
+        file.write_all(chunk).await?;
+        self.check_pace().await
+    }
+
+    /// Called while the source is silent: gives up once the job can no longer
+    /// be ready before its deadline.
+    ///
+    /// # Errors
+    ///
+    /// [`DownloadError::TooSlow`] when the deadline is out of reach; the bytes
+    /// so far are kept for the next attempt.
+    pub async fn check_pace(&mut self) -> Result<(), DownloadError> {
+        if self
+            .progress
+            .advance(self.key, self.written, self.clock.now())
+        {
+            return Ok(());
+        }
+        self.flush().await?;
+        Err(DownloadError::TooSlow)
+    }
+
+    /// Keeps the bytes received so far for the next attempt.
+    ///
+    /// # Errors
+    ///
+    /// Always: [`DownloadError::Interrupted`] with `reason`, or the I/O error
+    /// of flushing the partial file.
+    pub async fn interrupt(&mut self, reason: String) -> Result<(), DownloadError> {
+        self.flush().await?;
+        Err(DownloadError::Interrupted(reason))
+    }
+
+    // Returns the SHA-256 and size of the complete body.
+    pub(crate) async fn finish(mut self) -> Result<(String, u64), DownloadError> {
+        if let Some(file) = self.file.take() {
+            file.sync_all().await?;
+        }
+        if let Some(total) = self.expected.filter(|total| self.written < *total) {
+            return Err(DownloadError::Interrupted(format!(
+                "received {} of {total} bytes",
+                self.written
+            )));
+        }
+        Ok((hex(&self.hasher.finalize()), self.written))
+    }
+
+    async fn start(&mut self, file: File, expected: Option<u64>) -> Result<(), DownloadError> {
+        self.file = Some(file);
+        self.expected = expected;
+        if expected.is_some_and(|total| total > self.max_bytes) {
+            return self.too_large().await;
+        }
+        self.progress.begin(self.key, self.written, expected);
+        Ok(())
+    }
+
+    async fn too_large(&mut self) -> Result<(), DownloadError> {
+        self.file = None;
+        fs::remove_file(self.path).await?;
+        Err(DownloadError::TooLarge {
+            limit: self.max_bytes,
+        })
+    }
+
+    async fn flush(&mut self) -> Result<(), DownloadError> {
+        if let Some(file) = self.file.as_mut() {
+            file.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+async fn hash_partial(partial: &Path) -> Result<(Sha256, u64), DownloadError> {
+    let mut hasher = Sha256::new();
+    let mut file = match File::open(partial).await {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok((hasher, 0)),
+        Err(err) => return Err(err.into()),
+    };
+    let mut buffer = vec![0; HASH_BUFFER_BYTES];
+    let mut total = 0;
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok((hasher, total));
+        }
+        hasher.update(&buffer[..read]);
+        total += read as u64;
+    }
+}
+
+pub(crate) fn hex(digest: &[u8]) -> String {
+    use std::fmt::Write;
+
+    digest.iter().fold(String::new(), |mut out, byte| {
+        let _ = write!(out, "{byte:02x}");
+        out
+    })
+}
@@ -0,0 +1,348 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use reqwest::Url;
+use thiserror::Error;
+use tokio::fs;
+use tokio::time::Instant;
+use vvtv_types::{Clock, OwnerCard, SystemClock, domain_matches};
+
+use crate::backend::PartialFile;
+use crate::file::FileBackend;
+use crate::http::HttpBackend;
+use crate::s3::{S3Backend, S3Settings};
+use crate::{FetchBackend, ProgressBoard};
+
+// Longest wait between two attempts, however many retries the policy allows.
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone)]
+pub struct DownloadPolicy {
+    pub allowlist_domains: Vec<String>,
+    pub max_bytes: u64,
+    pub timeout: Duration,
+    pub max_redirects: u8,
+    pub max_resume_attempts: u8,
+    pub max_retries: u8,
+    pub retry_backoff: Duration,
+    pub max_concurrent: usize,
+    // Shared by all downloads; 0 means unlimited.
+    pub max_bytes_per_sec: u64,
+    // Directories `file://` sources may be read from.
+    pub file_roots: Vec<PathBuf>,
+    // Endpoint and keys for `s3://` sources; `None` refuses them.
+    pub s3: Option<S3Settings>,
+}
+
+impl DownloadPolicy {
+    #[must_use]
+    pub fn from_owner_card(owner_card: &OwnerCard) -> Self {
+        let fetch = &owner_card.fetch_policy;
+        Self {
+            allowlist_domains: owner_card.search_policy.allowlist_domains.clone(),
+            max_bytes: fetch.max_download_mb.saturating_mul(1024 * 1024),
+            timeout: Duration::from_secs(fetch.download_timeout_secs),
+            max_redirects: fetch.max_redirects,
+            max_resume_attempts: fetch.max_resume_attempts,
+            max_retries: fetch.max_retries,
+            retry_backoff: Duration::from_millis(fetch.retry_backoff_ms),
+            max_concurrent: usize::from(fetch.max_concurrent_downloads.max(1)),
+            max_bytes_per_sec: fetch.max_bandwidth_bytes_per_sec,
+            file_roots: fetch.file_roots.iter().map(PathBuf::from).collect(),
+            s3: fetch.s3.as_ref().map(S3Settings::from_policy),
+        }
+    }
+
+    pub(crate) fn allows(&self, url: &Url) -> bool {
+        url.host_str().is_some_and(|host| {
+            self.allowlist_domains
+                .iter()
+                .any(|domain| domain_matches(host, domain))
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DownloadError {
+    #[error("source {0} is not on the allowlist")]
+    NotAllowed(String),
+    #[error("no fetch backend for scheme {0}")]
+    UnsupportedScheme(String),
+    #[error("source {0} does not exist")]
+    Missing(String),
+    #[error("credentials unavailable: {0}")]
+    Credentials(String),
+    #[error("redirect to {location} was not followed")]
+    Redirect { location: String },
+    #[error("source answered HTTP {0}")]
+    Status(u16),
+    #[error("source is larger than {limit} bytes")]
+    TooLarge { limit: u64 },
+    #[error("download did not finish in time")]
+    Timeout,
+    #[error("download would not be ready before its slot")]
+    TooSlow,
+    #[error("connection dropped: {0}")]
+    Interrupted(String),
+    #[error("transport error: {0}")]
+    Transport(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl DownloadError {
+    // Permanent failures will not go away by asking again: the source is gone,
+    // outside the allowlist, unreachable with the configured credentials or too
+    // large. Everything else is worth a retry.
+    #[must_use]
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            Self::NotAllowed(_)
+            | Self::UnsupportedScheme(_)
+            | Self::Missing(_)
+            | Self::Credentials(_)
+            | Self::Redirect { .. }
+            | Self::TooLarge { .. } => true,
+            Self::Status(code) => (400..500).contains(code) && !matches!(code, 408 | 429),
+            Self::Timeout
+            | Self::TooSlow
+            | Self::Interrupted(_)
+            | Self::Transport(_)
+            | Self::Io(_) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DownloadedFile {
+    pub path: PathBuf,
+    pub sha256: String,
+    pub bytes: u64,
+    pub resumed: bool,
+    // The same content was already in the asset store under this hash.
+    pub deduplicated: bool,
+}
+
+// Fetches sources into `<partial_dir>/<key>.part` through the backend their
+// URL scheme names, hashing as it writes, and only moves the file into the
+// content-addressed `<asset_dir>/<sha256>.mp4` once the body is complete, so
+// identical content under two URLs is stored once. A partial file left by a
+// dropped connection or an earlier run is resumed instead of starting over.
+// Progress is published on a shared board and a transfer whose ETA misses its
+// deadline is abandoned early.
+pub struct Downloader {
+    policy: DownloadPolicy,
+    http: HttpBackend,
+    file: FileBackend,
+    s3: S3Backend,
+    partial_dir: PathBuf,
+    asset_dir: PathBuf,
+    progress: ProgressBoard,
+    // Deadlines are checked against this clock; the system clock by default.
+    clock: Arc<dyn Clock>,
+    bandwidth: Option<Bandwidth>,
+}
+
+impl Downloader {
+    /// # Errors
+    ///
+    /// Fails when an HTTP client for the backends cannot be built.
+    pub fn new(
+        policy: DownloadPolicy,
+        partial_dir: impl Into<PathBuf>,
+        asset_dir: impl Into<PathBuf>,
+    ) -> Result<Self, DownloadError> {
+        let bandwidth = (policy.max_bytes_per_sec > 0).then(|| Bandwidth {
+            bytes_per_sec: policy.max_bytes_per_sec,
+            next_free: Mutex::new(Instant::now()),
+        });
+        Ok(Self {
+            http: HttpBackend::new(&policy)?,
+            file: FileBackend::new(&policy.file_roots),
+            s3: S3Backend::new(policy.s3.clone())?,
+            policy,
+            partial_dir: partial_dir.into(),
+            asset_dir: asset_dir.into(),
+            progress: ProgressBoard::default(),
+            clock: Arc::new(SystemClock),
+            bandwidth,
+        })
+    }
+
+    // Judges whether transfers make their deadline by `clock`, usually the
+    // clock of the run that planned the slots.
+    #[must_use]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    #[must_use]
+    pub fn policy(&self) -> &DownloadPolicy {
+        &self.policy
+    }
+
+    #[must_use]
+    pub fn progress(&self) -> &ProgressBoard {
+        &self.progress
+    }
+
+    /// Fetches `url` into the asset directory, named by its SHA-256, resuming
+    /// whatever an earlier attempt under `key` left behind.
+    ///
+    /// # Errors
+    ///
+    /// Any [`DownloadError`] of the transfer; [`DownloadError::Timeout`] keeps
+    /// the partial file for the next window.
+    pub async fn download(&self, key: &str, url: &str) -> Result<DownloadedFile, DownloadError> {
+        let parsed = Url::parse(url).map_err(|_| DownloadError::NotAllowed(url.to_string()))?;
+        if !matches!(parsed.scheme(), "http" | "https" | "file" | "s3") {
+            return Err(DownloadError::UnsupportedScheme(
+                parsed.scheme().to_string(),
+            ));
+        }
+        fs::create_dir_all(&self.partial_dir).await?;
+        fs::create_dir_all(&self.asset_dir).await?;
+
+        let partial = self.partial_dir.join(format!("{key}.part"));
+        // The partial file is kept on timeout so the next window can resume it.
+        let (sha256, bytes, resumed) =
+            tokio::time::timeout(self.policy.timeout, self.transfer(key, &parsed, &partial))
+                .await
+                .map_err(|_| DownloadError::Timeout)??;
+        self.progress.finish(key);
+
+        let path = self.asset_dir.join(format!("{sha256}.mp4"));
+        let deduplicated = fs::try_exists(&path).await?;
+        if deduplicated {
+            fs::remove_file(&partial).await?;
+        } else {
+            fs::rename(&partial, &path).await?;
+        }
+        Ok(DownloadedFile {
+            path,
+            sha256,
+            bytes,
+            resumed,
+            deduplicated,
+        })
+    }
+
+    /// Retries transient failures with exponential backoff; permanent ones and
+    /// transfers that cannot make their slot are returned straight away.
+    ///
+    /// # Errors
+    ///
+    /// The last error once retries run out, or the first that is not worth a
+    /// retry.
+    pub async fn download_with_retry(
+        &self,
+        key: &str,
+        url: &str,
+    ) -> Result<DownloadedFile, DownloadError> {
+        let mut retries = 0;
+        loop {
+            match self.download(key, url).await {
+                Err(err)
+                    if !err.is_permanent()
+                        && !matches!(err, DownloadError::TooSlow)
+                        && retries < self.policy.max_retries =>
+                {
+                    tokio::time::sleep(retry_delay(self.policy.retry_backoff, retries)).await;
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn transfer(
+        &self,
+        key: &str,
+        url: &Url,
+        partial: &Path,
+    ) -> Result<(String, u64, bool), DownloadError> {
+        let mut resumed = fs::try_exists(partial).await?;
+        let mut attempts = 0;
+        loop {
+            let mut file = PartialFile::open(
+                key,
+                partial,
+                self.policy.max_bytes,
+                &self.progress,
+                self.clock.as_ref(),
+                self.bandwidth.as_ref(),
+            )
+            .await?;
+            let result = match self.get(url, &mut file).await {
+                Ok(()) => file.finish().await,
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok((sha256, bytes)) => return Ok((sha256, bytes, resumed)),
+                Err(DownloadError::Interrupted(_))
+                    if attempts < self.policy.max_resume_attempts =>
+                {
+                    attempts += 1;
+                    resumed = true;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    async fn get(&self, url: &Url, partial: &mut PartialFile<'_>) -> Result<(), DownloadError> {
+        match url.scheme() {
+            "http" | "https" => self.http.get(url, partial).await,
+            "file" => self.file.get(url, partial).await,
+            "s3" => self.s3.get(url, partial).await,
+            scheme => Err(DownloadError::UnsupportedScheme(scheme.to_string())),
+        }
+    }
+}
+
+// Paces all transfers of one downloader to a shared byte rate: each chunk
+// reserves its share of the link and waits for its turn.
+pub(crate) struct Bandwidth {
+    bytes_per_sec: u64,
+    next_free: Mutex<Instant>,
+}
+
+impl Bandwidth {
+    pub(crate) async fn acquire(&self, bytes: usize) {
+        let bytes = u128::try_from(bytes).unwrap_or(u128::MAX);
+        let nanos = bytes.saturating_mul(1_000_000_000) / u128::from(self.bytes_per_sec);
+        let cost = u64::try_from(nanos).map_or(Duration::MAX, Duration::from_nanos);
+        let start = {
+            let mut next_free = self
+                .next_free
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let start = (*next_free).max(Instant::now());
+            *next_free = start + cost;
+            start
+        };
+        tokio::time::sleep_until(start).await;
+    }
+}
+
+// Doubles `base` for every retry already made, up to `MAX_RETRY_BACKOFF`.
+fn retry_delay(base: Duration, retries: u8) -> Duration {
+    base.saturating_mul(2_u32.saturating_pow(u32::from(retries)))
+        .min(MAX_RETRY_BACKOFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delay_doubles_up_to_the_cap() {
+        let base = Duration::from_secs(2);
+        assert_eq!(retry_delay(base, 0), base);
+        assert_eq!(retry_delay(base, 3), Duration::from_secs(16));
+        assert_eq!(retry_delay(base, 40), MAX_RETRY_BACKOFF);
+        assert_eq!(retry_delay(Duration::MAX, u8::MAX), MAX_RETRY_BACKOFF);
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use futures_util::StreamExt;
+use futures_util::stream::FuturesUnordered;
+use vvtv_types::{AssetItem, PlanItem, PlanState, RunContext};
+
+use crate::{DownloadError, DownloadedFile, Downloader, FetchContext, to_asset};
+
+// Reserves tried for a single failed slot before giving up on it.
+const MAX_FALLBACK_CANDIDATES: usize = 3;
+
+#[derive(Debug)]
+pub struct DownloadFailure {
+    pub plan_id: String,
+    pub source_url: String,
+    pub error: DownloadError,
+}
+
+#[derive(Debug)]
+pub struct Fallback {
+    pub plan_id: String,
+    pub replacement_plan_id: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct DownloadOutcome {
+    pub assets: Vec<AssetItem>,
+    pub fetched_urls: Vec<String>,
+    // Every failed source, including reserves tried during a fallback.
+    pub failures: Vec<DownloadFailure>,
+    // Plans whose source failed, moved to `Dropped` with a reason.
+    pub dropped: Vec<PlanItem>,
+    // Reserves that took over a failed slot, moved to `Committed`.
+    pub promoted: Vec<PlanItem>,
+    pub fallbacks: Vec<Fallback>,
+}
+
+pub(crate) struct FallbackChain<'a> {
+    run: &'a RunContext,
+    downloader: &'a Downloader,
+    reserves: Vec<PlanItem>,
+    outcome: DownloadOutcome,
+    // Downloaded assets and fallbacks, tagged with the position of their slot
+    // so the outcome lists them in slot order whatever finished first.
+    downloaded: Vec<(usize, AssetItem)>,
+    fallbacks: Vec<(usize, Fallback)>,
+}
+
+// One download on the pool: the committed plan of a slot, or a reserve
+// standing in for it after `tried` earlier candidates failed.
+enum Task<'p> {
+    Slot {
+        slot: usize,
+        asset: AssetItem,
+        plan: &'p PlanItem,
+    },
+    Reserve {
+        slot: usize,
+        failed: &'p PlanItem,
+        reserve: PlanItem,
+        tried: usize,
+    },
+}
+
+impl<'a> FallbackChain<'a> {
+    pub(crate) fn new(
+        run: &'a RunContext,
+        downloader: &'a Downloader,
+        plans: &[PlanItem],
+        assets: &[AssetItem],
+        ctx: &FetchContext,
+    ) -> Self {
+        let committed: Vec<&str> = assets.iter().map(|a| a.plan_id.as_str()).collect();
+        let reserves = plans
+            .iter()
+            .filter(|plan| {
+                plan.state == PlanState::Reserved
+                    && !committed.contains(&plan.plan_id.as_str())
+                    && !ctx.broken_urls.contains(&plan.source_url)
+            })
+            .cloned()
+            .collect();
+        Self {
+            run,
+            downloader,
+            reserves,
+            outcome: DownloadOutcome::default(),
+            downloaded: Vec::new(),
+            fallbacks: Vec::new(),
+        }
+    }
+
+    // Each result is handled as soon as its download ends, so a failed slot
+    // starts on its reserve while the rest of the pool keeps going. Reserves
+    // jump the queue: their slot is earlier than any plan still waiting.
+    pub(crate) async fn run(
+        mut self,
+        plans: &[PlanItem],
+        assets: Vec<AssetItem>,
+        ctx: &mut FetchContext,
+    ) -> DownloadOutcome {
+        let by_id: HashMap<&str, &PlanItem> = plans
+            .iter()
+            .map(|plan| (plan.plan_id.as_str(), plan))
+            .collect();
+
+        let mut jobs = Vec::new();
+        for asset in assets {
+            match by_id.get(asset.plan_id.as_str()).copied() {
+                Some(plan) => jobs.push((asset, plan)),
+                None => self.outcome.assets.push(asset),
+            }
+        }
+        // Earliest slot first; plans without a slot go last.
+        jobs.sort_by_key(|(_, plan)| (plan.slot_start_at.is_none(), plan.slot_start_at));
+        let keys: HashSet<&str> = jobs.iter().map(|(_, plan)| plan.plan_id.as_str()).collect();
+        self.downloader.progress().retain(|key| keys.contains(key));
+
+        let mut queue: VecDeque<Task<'_>> = jobs
+            .into_iter()
+            .enumerate()
+            .map(|(slot, (asset, plan))| Task::Slot { slot, asset, plan })
+            .collect();
+        let workers = self.downloader.policy().max_concurrent.max(1);
+        let mut pool = FuturesUnordered::new();
+        loop {
+            while pool.len() < workers {
+                let Some(task) = queue.pop_front() else {
+                    break;
+                };
+                pool.push(fetch(self.downloader, task));
+            }
+            let Some((task, result)) = pool.next().await else {
+                break;
+            };
+            self.settle(task, result, ctx, &mut queue);
+        }
+
+        self.downloaded.sort_by_key(|(slot, _)| *slot);
+        self.outcome
+            .assets
+            .extend(self.downloaded.into_iter().map(|(_, asset)| asset));
+        self.fallbacks.sort_by_key(|(slot, _)| *slot);
+        self.outcome.fallbacks = self
+            .fallbacks
+            .into_iter()
+            .map(|(_, fallback)| fallback)
+            .collect();
+        self.outcome
+    }
+
+    fn settle<'p>(
+        &mut self,
+        task: Task<'p>,
+        result: Result<DownloadedFile, DownloadError>,
+        ctx: &mut FetchContext,
+        queue: &mut VecDeque<Task<'p>>,
+    ) {
+        match (task, result) {
+            (Task::Slot { slot, asset, plan }, Ok(file)) => {
+                self.outcome.fetched_urls.push(plan.source_url.clone());
+                self.downloaded.push((slot, with_file(asset, &file)));
+            }
+            (Task::Slot { slot, plan, .. }, Err(error)) => {
+                self.drop_plan(plan, error, ctx);
+                self.replace(slot, plan, 0, ctx, queue);
+            }
+            (
+                Task::Reserve {
+                    slot,
+                    failed,
+                    reserve,
+                    ..
+                },
+                Ok(file),
+            ) => {
+                let mut promoted = reserve;
+                promoted.state = PlanState::Committed;
+                promoted.slot_start_at = failed.slot_start_at;
+                let asset = with_file(to_asset(self.run, &promoted), &file);
+                self.outcome.fetched_urls.push(promoted.source_url.clone());
+                self.downloaded.push((slot, asset));
+                self.fallbacks.push((
+                    slot,
+                    Fallback {
+                        plan_id: failed.plan_id.clone(),
+                        replacement_plan_id: Some(promoted.plan_id.clone()),
+                    },
+                ));
+                self.outcome.promoted.push(promoted);
+            }
+            (
+                Task::Reserve {
+                    slot,
+                    failed,
+                    reserve,
+                    tried,
+                },
+                Err(error),
+            ) => {
+                self.drop_plan(&reserve, error, ctx);
+                self.replace(slot, failed, tried, ctx, queue);
+            }
+        }
+    }
+
+    // Queues the closest reserve for a failed slot: same theme first, then
+    // the nearest duration. After `MAX_FALLBACK_CANDIDATES` failed reserves,
+    // or with none left, the slot is given up.
+    fn replace<'p>(
+        &mut self,
+        slot: usize,
+        failed: &'p PlanItem,
+        tried: usize,
+        ctx: &FetchContext,
+        queue: &mut VecDeque<Task<'p>>,
+    ) {
+        self.reserves
+            .retain(|reserve| !ctx.broken_urls.contains(&reserve.source_url));
+        let next = self
+            .reserves
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, reserve)| fallback_rank(failed, reserve))
+            .map(|(idx, _)| idx)
+            .filter(|_| tried < MAX_FALLBACK_CANDIDATES);
+        match next {
+            Some(idx) => queue.push_front(Task::Reserve {
+                slot,
+                failed,
+                reserve: self.reserves.remove(idx),
+                tried: tried + 1,
+            }),
+            None => self.fallbacks.push((
+                slot,
+                Fallback {
+                    plan_id: failed.plan_id.clone(),
+                    replacement_plan_id: None,
+                },
+            )),
+        }
+    }
+
+    fn drop_plan(&mut self, plan: &PlanItem, error: DownloadError, ctx: &mut FetchContext) {
+        let kind = if error.is_permanent() {
+            ctx.broken_urls.insert(plan.source_url.clone());
+            "permanent"
+        } else {
+            "transient"
+        };
+        let mut dropped = plan.clone();
+        dropped.state = PlanState::Dropped;
+        dropped.drop_reason = Some(format!("{kind}: {error}"));
+        self.outcome.dropped.push(dropped);
+        self.outcome.failures.push(DownloadFailure {
+            plan_id: plan.plan_id.clone(),
+            source_url: plan.source_url.clone(),
+            error,
+        });
+    }
+}
+
+async fn fetch<'p>(
+    downloader: &Downloader,
+    task: Task<'p>,
+) -> (Task<'p>, Result<DownloadedFile, DownloadError>) {
+    let (key, url, deadline) = match &task {
+        Task::Slot { plan, .. } => (&plan.plan_id, &plan.source_url, plan.slot_start_at),
+        Task::Reserve {
+            failed, reserve, ..
+        } => (&reserve.plan_id, &reserve.source_url, failed.slot_start_at),
+    };
+    downloader.progress().set_deadline(key, deadline);
+    let result = downloader.download_with_retry(key, url).await;
+    (task, result)
+}
+
+fn fallback_rank(failed: &PlanItem, reserve: &PlanItem) -> (bool, u32) {
+    let same_theme = reserve
+        .theme_tags
+        .iter()
+        .any(|tag| failed.theme_tags.contains(tag));
+    (
+        !same_theme,
+        failed.duration_sec.abs_diff(reserve.duration_sec),
+    )
+}
+
+fn with_file(mut asset: AssetItem, file: &DownloadedFile) -> AssetItem {
+    asset.local_path = file.path.to_string_lossy().into_owned();
+    asset.checksum = format!("sha256:{}", file.sha256);
+    asset
+}
@@ -0,0 +1,71 @@
+use std::io::{ErrorKind, SeekFrom};
+use std::path::PathBuf;
+
+use reqwest::Url;
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::{DownloadError, FetchBackend, PartialFile};
+
+const READ_BUFFER_BYTES: usize = 64 * 1024;
+
+// `file://` sources below one of the configured roots. Symlinks are resolved
+// before the check, so a link cannot lead outside a root.
+pub struct FileBackend {
+    roots: Vec<PathBuf>,
+}
+
+impl FileBackend {
+    #[must_use]
+    pub fn new(roots: &[PathBuf]) -> Self {
+        Self {
+            roots: roots.to_vec(),
+        }
+    }
+
+    async fn resolve(&self, url: &Url) -> Result<PathBuf, DownloadError> {
+        let denied = || DownloadError::NotAllowed(url.to_string());
+        let requested = url.to_file_path().map_err(|()| denied())?;
+        let path = match fs::canonicalize(&requested).await {
+            Ok(path) => path,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                return Err(DownloadError::Missing(url.to_string()));
+            }
+            Err(err) => return Err(err.into()),
+        };
+        for root in &self.roots {
+            if fs::canonicalize(root)
+                .await
+                .is_ok_and(|root| path.starts_with(root))
+            {
+                return Ok(path);
+            }
+        }
+        Err(denied())
+    }
+}
+
+impl FetchBackend for FileBackend {
+    async fn get(&self, url: &Url, partial: &mut PartialFile<'_>) -> Result<(), DownloadError> {
+        let path = self.resolve(url).await?;
+        let mut source = File::open(&path).await?;
+        let len = source.metadata().await?.len();
+
+        let offset = partial.offset();
+        if offset > 0 && offset <= len {
+            source.seek(SeekFrom::Start(offset)).await?;
+            partial.resume(Some(len - offset)).await?;
+        } else {
+            partial.restart(Some(len)).await?;
+        }
+
+        let mut buffer = vec![0; READ_BUFFER_BYTES];
+        loop {
+            let read = source.read(&mut buffer).await?;
+            if read == 0 {
+                return Ok(());
+            }
+            partial.write(&buffer[..read]).await?;
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+use reqwest::header::{CONTENT_RANGE, LOCATION, RANGE};
+use reqwest::{Client, RequestBuilder, Response, StatusCode, Url, redirect};
+
+use crate::{DownloadError, DownloadPolicy, FetchBackend, PartialFile};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+// How often a stalled transfer re-checks whether it can still make its slot.
+const PROGRESS_CHECK: Duration = Duration::from_millis(500);
+
+// `http://` and `https://` sources on the allowlist. Redirects are followed
+// only while they stay on it.
+pub struct HttpBackend {
+    client: Client,
+    policy: DownloadPolicy,
+}
+
+impl HttpBackend {
+    /// # Errors
+    ///
+    /// [`DownloadError::Transport`] when the HTTP client cannot be built.
+    pub fn new(policy: &DownloadPolicy) -> Result<Self, DownloadError> {
+        let redirect_policy = policy.clone();
+        let client = Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .redirect(redirect::Policy::custom(move |attempt| {
+                if attempt.previous().len() > usize::from(redirect_policy.max_redirects)
+                    || !redirect_policy.allows(attempt.url())
+                {
+                    attempt.stop()
+                } else {
+                    attempt.follow()
+                }
+            }))
+            .build()
+            .map_err(|err| DownloadError::Transport(err.to_string()))?;
+        Ok(Self {
+            client,
+            policy: policy.clone(),
+        })
+    }
+}
+
+impl FetchBackend for HttpBackend {
+    async fn get(&self, url: &Url, partial: &mut PartialFile<'_>) -> Result<(), DownloadError> {
+        if !self.policy.allows(url) {
+            return Err(DownloadError::NotAllowed(url.to_string()));
+        }
+        receive(self.client.get(url.clone()), partial).await
+    }
+}
+
+// Sends `request` with a Range header when part of the body is already on disk
+// and streams the answer into `partial`. Shared with the S3 backend, which
+// speaks the same protocol once its request is signed.
+pub(crate) async fn receive(
+    mut request: RequestBuilder,
+    partial: &mut PartialFile<'_>,
+) -> Result<(), DownloadError> {
+    let offset = partial.offset();
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={offset}-"));
+    }
+    let mut response = request
+        .send()
+        .await
+        .map_err(|err| DownloadError::Transport(err.to_string()))?;
+
+    let status = response.status();
+    if status == StatusCode::PARTIAL_CONTENT && resumes_at(&response, offset) {
+        partial.resume(response.content_length()).await?;
+    } else if status.is_success() && status != StatusCode::PARTIAL_CONTENT {
+        partial.restart(response.content_length()).await?;
+    } else if status == StatusCode::PARTIAL_CONTENT || status == StatusCode::RANGE_NOT_SATISFIABLE {
+        return partial
+            .discard(format!("range from byte {offset} rejected"))
+            .await;
+    } else if status.is_redirection() {
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        return Err(DownloadError::Redirect { location });
+    } else {
+        return Err(DownloadError::Status(status.as_u16()));
+    }
+
+    loop {
+        match tokio::time::timeout(PROGRESS_CHECK, response.chunk()).await {
+            Ok(Ok(Some(chunk))) => partial.write(&chunk).await?,
+            Ok(Ok(None)) => return Ok(()),
+            Ok(Err(err)) => return partial.interrupt(err.to_string()).await,
+            Err(_) => partial.check_pace().await?,
+        }
+    }
+}
+
+fn resumes_at(response: &Response, offset: u64) -> bool {
+    response
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("bytes "))
+        .and_then(|range| range.split('-').next())
+        .and_then(|start| start.trim().parse::<u64>().ok())
+        == Some(offset)
+}
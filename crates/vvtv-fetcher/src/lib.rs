@@ -1,14 +1,55 @@
+mod backend;
+mod download;
+mod fallback;
+mod file;
+mod http;
+mod progress;
+mod s3;
+
 use std::collections::HashSet;
 
 use chrono::{DateTime, Duration, Utc};
-use uuid::Uuid;
-use vvtv_types::{AssetItem, OwnerCard, PlanItem, QaStatus, Resolution};
+use vvtv_types::{
+    AssetItem, OwnerCard, PlanItem, PlanState, QaStatus, Resolution, RunContext, SourceLedger,
+};
+
+pub use backend::{FetchBackend, PartialFile};
+pub use download::{DownloadError, DownloadPolicy, DownloadedFile, Downloader};
+pub use fallback::{DownloadFailure, DownloadOutcome, Fallback};
+pub use file::FileBackend;
+pub use http::HttpBackend;
+pub use progress::{JobProgress, ProgressBoard};
+pub use s3::{S3Backend, S3Credentials, S3Settings};
+
+use fallback::FallbackChain;
 
 #[derive(Default)]
 pub struct FetchContext {
     pub broken_urls: HashSet<String>,
 }
 
+impl FetchContext {
+    #[must_use]
+    pub fn from_ledger(ledger: &SourceLedger, now: DateTime<Utc>) -> Self {
+        Self {
+            broken_urls: ledger.blocked_urls(now).map(str::to_string).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CommitSelection {
+    pub assets: Vec<AssetItem>,
+    // Plans behind `assets`, now `Committed`; a reserve carries the slot it fills.
+    pub committed: Vec<PlanItem>,
+    // Scheduled plans whose slot started before they were committed, or whose
+    // source is broken with no reserve left to take over, now `Dropped`.
+    pub missed: Vec<PlanItem>,
+    // Scheduled plans with a broken source whose slot went to a reserve, now
+    // `Dropped` with the reserve named in the reason.
+    pub replaced: Vec<PlanItem>,
+}
+
 pub struct Fetcher;
 
 impl Fetcher {
@@ -19,60 +60,114 @@ impl Fetcher {
         scheduled: Vec<PlanItem>,
         reserves: Vec<PlanItem>,
         ctx: &FetchContext,
-    ) -> Vec<AssetItem> {
+    ) -> CommitSelection {
+        Self::commit_t_minus_4h_with(&RunContext::at(now), owner_card, scheduled, reserves, ctx)
+    }
+
+    // Commits exactly the scheduled plans whose slot starts within the next
+    // `commit_lead_hours`. A slot whose source is known to be broken goes to the
+    // next usable reserve and the broken plan is dropped, or reported as missed
+    // when no reserve is left. Plans already committed are skipped and plans
+    // whose slot has passed are reported as missed.
+    #[must_use]
+    pub fn commit_t_minus_4h_with(
+        run: &RunContext,
+        owner_card: &OwnerCard,
+        mut scheduled: Vec<PlanItem>,
+        reserves: Vec<PlanItem>,
+        ctx: &FetchContext,
+    ) -> CommitSelection {
+        let now = run.now();
         let cutoff = now + Duration::hours(i64::from(owner_card.schedule_policy.commit_lead_hours));
-        let target_items =
-            usize::from((owner_card.schedule_policy.buffer_target_minutes / 10).max(1));
-        let mut assets = Vec::new();
-        let mut used_plan_ids = HashSet::new();
+        let mut reserves = reserves.into_iter().filter(|reserve| {
+            reserve.state == PlanState::Reserved && !ctx.broken_urls.contains(&reserve.source_url)
+        });
+        let mut selection = CommitSelection::default();
 
-        for item in scheduled {
-            if !eligible_for_commit(&item, cutoff, ctx) {
+        scheduled.sort_by_key(|item| item.slot_start_at);
+        for mut item in scheduled {
+            if item.state != PlanState::Scheduled {
                 continue;
             }
-            used_plan_ids.insert(item.plan_id.clone());
-            assets.push(to_asset(&item));
-            if assets.len() >= target_items {
-                return assets;
-            }
-        }
-
-        for reserve in reserves {
-            if used_plan_ids.contains(&reserve.plan_id)
-                || !eligible_for_commit(&reserve, cutoff, ctx)
-            {
+            let Some(slot) = item.slot_start_at else {
+                continue;
+            };
+            if slot < now {
+                item.state = PlanState::Dropped;
+                item.drop_reason =
+                    Some(format!("missed-commit: slot {} passed", slot.to_rfc3339()));
+                selection.missed.push(item);
                 continue;
             }
-            used_plan_ids.insert(reserve.plan_id.clone());
-            assets.push(to_asset(&reserve));
-            if assets.len() >= target_items {
-                break;
+            if slot > cutoff {
+                continue;
             }
+            let mut plan = if ctx.broken_urls.contains(&item.source_url) {
+                item.state = PlanState::Dropped;
+                let Some(mut reserve) = reserves.next() else {
+                    item.drop_reason = Some("missed-commit: broken source, no reserve".to_string());
+                    selection.missed.push(item);
+                    continue;
+                };
+                item.drop_reason = Some(format!(
+                    "broken-source: slot went to reserve {}",
+                    reserve.plan_id
+                ));
+                selection.replaced.push(item);
+                reserve.slot_start_at = Some(slot);
+                reserve
+            } else {
+                item
+            };
+            plan.state = PlanState::Committed;
+            selection.assets.push(to_asset(run, &plan));
+            selection.committed.push(plan);
         }
 
-        assets
+        selection
     }
-}
 
-fn eligible_for_commit(item: &PlanItem, cutoff: DateTime<Utc>, ctx: &FetchContext) -> bool {
-    item.discovered_at <= cutoff && !ctx.broken_urls.contains(&item.source_url)
+    // Downloads the committed assets on a pool of `max_concurrent` workers,
+    // earliest slot first, retrying transient failures. A plan that still fails,
+    // or would not be ready before its slot, is dropped and its slot handed to
+    // the closest reserve; permanent failures also land in `ctx.broken_urls`.
+    pub async fn download_committed(
+        run: &RunContext,
+        downloader: &Downloader,
+        plans: &[PlanItem],
+        assets: Vec<AssetItem>,
+        ctx: &mut FetchContext,
+    ) -> DownloadOutcome {
+        FallbackChain::new(run, downloader, plans, &assets, ctx)
+            .run(plans, assets, ctx)
+            .await
+    }
 }
 
-fn to_asset(plan: &PlanItem) -> AssetItem {
-    let vertical = plan
-        .visual_features
-        .iter()
-        .any(|feature| feature.to_lowercase().contains("vertical"));
-    let (width, height) = if vertical { (720, 1280) } else { (1280, 720) };
-
+fn to_asset(run: &RunContext, plan: &PlanItem) -> AssetItem {
     AssetItem {
-        asset_id: Uuid::new_v4().to_string(),
+        asset_id: run.next_id(),
         plan_id: plan.plan_id.clone(),
         local_path: format!("/var/vvtv/assets/{}.mp4", plan.plan_id),
-        checksum: format!("chk-{}", &plan.plan_id[..plan.plan_id.len().min(8)]),
-        resolution: Resolution { width, height },
+        checksum: format!("chk-{}", plan.plan_id.chars().take(8).collect::<String>()),
+        // Placeholder until prep probes the file and records its framing.
+        resolution: Resolution {
+            width: 1280,
+            height: 720,
+        },
         audio_lufs: -19.0,
         qa_status: QaStatus::Pending,
+        pinned_start_at: plan.pin_id.as_ref().and(plan.slot_start_at),
+        tags: plan.content_tags(),
+        loudness: None,
+        planned_duration_sec: Some(plan.duration_sec),
+        qa_report: None,
+        encode_profile: None,
+        renditions: Vec::new(),
+        framing: None,
+        trim: None,
+        previews: None,
+        subtitles: Vec::new(),
     }
 }
 
@@ -80,53 +175,149 @@ fn to_asset(plan: &PlanItem) -> AssetItem {
 mod tests {
     use chrono::{Duration, Utc};
     use vvtv_types::{
-        AutotunePolicy, CuratorPolicy, EditorialProfile, MusicPolicy, OwnerCard, PlanItem,
-        PlanState, QualityPolicy, SafetyPolicy, SchedulePolicy, SearchPolicy,
+        AutotunePolicy, BrokenSource, CuratorPolicy, EditorialProfile, FetchPolicy,
+        FreshnessPolicy, MusicPolicy, OptimizerPolicy, OwnerCard, PlanItem, PlanState, PrepPolicy,
+        QualityPolicy, SafetyPolicy, SchedulePolicy, SearchPolicy, StoragePolicy, ThemePolicy,
     };
 
     use super::*;
 
     #[test]
-    fn commit_backfills_with_reserves_to_target_buffer() {
-        let card = sample_card(40);
+    fn commit_takes_exactly_the_slots_inside_the_lead_window() {
+        let card = sample_card(20);
         let now = Utc::now();
-        let scheduled = vec![sample_plan("a", now), sample_plan("b", now)];
-        let reserves = vec![
-            sample_plan("r1", now),
-            sample_plan("r2", now),
-            sample_plan("r3", now),
+        let scheduled = vec![
+            slotted("late", now + Duration::hours(5)),
+            slotted("b", now + Duration::hours(3)),
+            slotted("a", now + Duration::hours(1)),
+            sample_plan("unslotted", now),
         ];
+        let reserves = vec![reserve("r1", now)];
 
-        let assets =
+        let selection =
             Fetcher::commit_t_minus_4h(&card, now, scheduled, reserves, &FetchContext::default());
 
-        assert_eq!(assets.len(), 4);
+        let ids: Vec<_> = selection
+            .assets
+            .iter()
+            .map(|a| a.plan_id.as_str())
+            .collect();
+        assert_eq!(ids, ["a", "b"]);
+        assert!(
+            selection
+                .committed
+                .iter()
+                .all(|p| p.state == PlanState::Committed)
+        );
+        assert!(selection.missed.is_empty());
     }
 
     #[test]
     fn commit_skips_broken_urls() {
         let card = sample_card(20);
         let now = Utc::now();
-        let bad = sample_plan("bad", now);
-        let good = sample_plan("good", now);
+        let bad = slotted("bad", now + Duration::hours(1));
+        let good = slotted("good", now + Duration::hours(2));
         let mut ctx = FetchContext::default();
         ctx.broken_urls.insert(bad.source_url.clone());
 
-        let assets = Fetcher::commit_t_minus_4h(&card, now, vec![bad, good.clone()], vec![], &ctx);
+        let selection = Fetcher::commit_t_minus_4h(
+            &card,
+            now,
+            vec![bad.clone(), good.clone()],
+            vec![reserve("r1", now)],
+            &ctx,
+        );
+
+        let ids: Vec<_> = selection
+            .assets
+            .iter()
+            .map(|a| a.plan_id.as_str())
+            .collect();
+        assert_eq!(ids, ["r1", "good"]);
+        assert_eq!(selection.committed[0].slot_start_at, bad.slot_start_at);
+        assert_eq!(selection.replaced.len(), 1);
+        assert_eq!(selection.replaced[0].plan_id, "bad");
+        assert_eq!(selection.replaced[0].state, PlanState::Dropped);
+        assert!(selection.missed.is_empty());
+
+        // Without a reserve the broken plan is a miss, not left scheduled.
+        let selection = Fetcher::commit_t_minus_4h(&card, now, vec![bad], vec![], &ctx);
+        assert!(selection.assets.is_empty());
+        assert_eq!(selection.missed.len(), 1);
+        assert_eq!(selection.missed[0].state, PlanState::Dropped);
+        assert!(
+            selection.missed[0]
+                .drop_reason
+                .as_deref()
+                .is_some_and(|r| r.contains("no reserve"))
+        );
+    }
+
+    #[test]
+    fn commit_skips_sources_backing_off_in_the_ledger() {
+        let card = sample_card(20);
+        let now = Utc::now();
+        let bad = slotted("bad", now + Duration::hours(1));
+        let good = slotted("good", now + Duration::hours(2));
+        let mut ledger = SourceLedger::default();
+        ledger.entries.insert(
+            bad.source_url.clone(),
+            BrokenSource::record_failure(
+                None,
+                &bad.source_url,
+                "timeout",
+                false,
+                now,
+                &FetchPolicy::default(),
+            ),
+        );
 
-        assert_eq!(assets.len(), 1);
-        assert_eq!(assets[0].plan_id, good.plan_id);
+        let ctx = FetchContext::from_ledger(&ledger, now);
+        let selection =
+            Fetcher::commit_t_minus_4h(&card, now, vec![bad, good.clone()], vec![], &ctx);
+        assert_eq!(selection.assets.len(), 1);
+        assert_eq!(selection.assets[0].plan_id, good.plan_id);
+        assert!(
+            FetchContext::from_ledger(&ledger, now + Duration::hours(1))
+                .broken_urls
+                .is_empty()
+        );
     }
 
     #[test]
-    fn commit_rejects_future_discoveries() {
+    fn commit_follows_air_time_not_discovery_time() {
         let card = sample_card(20);
         let now = Utc::now();
-        let future = sample_plan("future", now + Duration::hours(8));
+        let mut fresh = slotted("fresh", now + Duration::hours(1));
+        fresh.discovered_at = now + Duration::hours(8);
+        let mut done = slotted("done", now + Duration::hours(2));
+        done.state = PlanState::Committed;
+        let passed = slotted("passed", now - Duration::minutes(5));
+
+        let selection = Fetcher::commit_t_minus_4h(
+            &card,
+            now,
+            vec![fresh, done, passed],
+            vec![],
+            &FetchContext::default(),
+        );
 
-        let assets =
-            Fetcher::commit_t_minus_4h(&card, now, vec![future], vec![], &FetchContext::default());
-        assert!(assets.is_empty());
+        let ids: Vec<_> = selection
+            .assets
+            .iter()
+            .map(|a| a.plan_id.as_str())
+            .collect();
+        assert_eq!(ids, ["fresh"]);
+        assert_eq!(selection.missed.len(), 1);
+        assert_eq!(selection.missed[0].plan_id, "passed");
+        assert_eq!(selection.missed[0].state, PlanState::Dropped);
+        assert!(
+            selection.missed[0]
+                .drop_reason
+                .as_deref()
+                .is_some_and(|r| r.starts_with("missed-commit"))
+        );
     }
 
     fn sample_card(buffer_target_minutes: u16) -> OwnerCard {
@@ -136,11 +327,16 @@ mod tests {
                 target_avg_duration_sec: 900,
                 max_consecutive_same_theme: 2,
                 min_unique_themes_per_block: 3,
+                theme_policy: ThemePolicy::default(),
+                placement_rules: vec![],
+                freshness_policy: FreshnessPolicy::default(),
             },
             search_policy: SearchPolicy {
                 allowlist_domains: vec!["example.com".to_string()],
                 blacklist_domains: vec![],
                 blocked_keywords: vec![],
+                max_domain_share: None,
+                min_distinct_sources_per_block: 0,
             },
             schedule_policy: SchedulePolicy {
                 planning_horizon_hours: 24,
@@ -148,11 +344,14 @@ mod tests {
                 commit_interval_minutes: 30,
                 buffer_target_minutes,
                 buffer_critical_minutes: 20,
+                pinned_slots: vec![],
+                optimizer: OptimizerPolicy::default(),
             },
             quality_policy: QualityPolicy {
                 min_resolution_height: 720,
                 target_audio_lufs: -16.0,
                 max_audio_deviation_lufs: 2.5,
+                ..QualityPolicy::default()
             },
             music_policy: MusicPolicy {
                 preferred_moods: vec![],
@@ -171,6 +370,9 @@ mod tests {
                 max_daily_adjustment_pct: 10.0,
                 enabled: true,
             },
+            fetch_policy: FetchPolicy::default(),
+            storage_policy: StoragePolicy::default(),
+            prep_policy: PrepPolicy::default(),
         }
     }
 
@@ -188,6 +390,22 @@ mod tests {
             selection_reason: "test".to_string(),
             policy_match_score: 0.95,
             state: PlanState::Scheduled,
+            slot_start_at: None,
+            pin_id: None,
+            score_breakdown: None,
+            drop_reason: None,
         }
     }
+
+    fn slotted(id: &str, slot: chrono::DateTime<Utc>) -> PlanItem {
+        let mut plan = sample_plan(id, slot - Duration::hours(12));
+        plan.slot_start_at = Some(slot);
+        plan
+    }
+
+    fn reserve(id: &str, discovered_at: chrono::DateTime<Utc>) -> PlanItem {
+        let mut plan = sample_plan(id, discovered_at);
+        plan.state = PlanState::Reserved;
+        plan
+    }
 }
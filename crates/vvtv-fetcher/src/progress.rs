@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+
+// How long a transfer runs before its rate is trusted for an ETA.
+const ETA_GRACE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct JobProgress {
+    pub key: String,
+    pub bytes_done: u64,
+    pub total_bytes: Option<u64>,
+    // When the item has to be ready, usually the slot start of its plan.
+    pub deadline: Option<DateTime<Utc>>,
+    pub finished: bool,
+    started: Instant,
+    // Bytes already on disk when this transfer started (resumed partials).
+    start_offset: u64,
+}
+
+impl JobProgress {
+    #[must_use]
+    pub fn bytes_per_sec(&self) -> Option<u64> {
+        let (transferred, elapsed) = self.settled()?;
+        let rate = u128::from(transferred) * 1_000_000 / elapsed.as_micros().max(1);
+        Some(u64::try_from(rate).unwrap_or(u64::MAX))
+    }
+
+    // Unknown until the rate has settled or when the size is not announced.
+    // The rest is expected to arrive at the rate seen so far.
+    #[must_use]
+    pub fn eta(&self) -> Option<Duration> {
+        if self.finished {
+            return Some(Duration::ZERO);
+        }
+        let remaining = self.total_bytes?.saturating_sub(self.bytes_done);
+        let (transferred, elapsed) = self.settled()?;
+        if transferred == 0 {
+            return Some(Duration::MAX);
+        }
+        let nanos = u128::from(remaining) * elapsed.as_nanos() / u128::from(transferred);
+        Some(u64::try_from(nanos).map_or(Duration::MAX, Duration::from_nanos))
+    }
+
+    // Bytes moved by this transfer and the time it took, once it has run
+    // long enough for the rate to mean something.
+    fn settled(&self) -> Option<(u64, Duration)> {
+        let elapsed = self.started.elapsed();
+        (elapsed >= ETA_GRACE).then(|| (self.bytes_done.saturating_sub(self.start_offset), elapsed))
+    }
+
+    // False only when the ETA is known and lands after the deadline.
+    #[must_use]
+    pub fn ready_in_time(&self, now: DateTime<Utc>) -> bool {
+        let (Some(deadline), Some(eta)) = (self.deadline, self.eta()) else {
+            return true;
+        };
+        chrono::Duration::from_std(eta)
+            .ok()
+            .and_then(|eta| now.checked_add_signed(eta))
+            .is_some_and(|ready_at| ready_at <= deadline)
+    }
+}
+
+// Live progress of every download, shared between the worker pool and whoever
+// needs to know whether an item will be ready in time.
+#[derive(Debug, Clone, Default)]
+pub struct ProgressBoard {
+    jobs: Arc<Mutex<HashMap<String, JobProgress>>>,
+}
+
+impl ProgressBoard {
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<JobProgress> {
+        self.lock().get(key).cloned()
+    }
+
+    #[must_use]
+    pub fn snapshot(&self) -> Vec<JobProgress> {
+        let mut jobs: Vec<_> = self.lock().values().cloned().collect();
+        jobs.sort_by(|a, b| a.key.cmp(&b.key));
+        jobs
+    }
+
+    pub fn set_deadline(&self, key: &str, deadline: Option<DateTime<Utc>>) {
+        self.lock()
+            .entry(key.to_string())
+            .or_insert_with(|| new_job(key))
+            .deadline = deadline;
+    }
+
+    // Forgets every job whose key `keep` rejects, e.g. those of an earlier batch.
+    pub fn retain(&self, keep: impl Fn(&str) -> bool) {
+        self.lock().retain(|key, _| keep(key));
+    }
+
+    pub(crate) fn begin(&self, key: &str, offset: u64, total_bytes: Option<u64>) {
+        let mut jobs = self.lock();
+        let job = jobs.entry(key.to_string()).or_insert_with(|| new_job(key));
+        job.bytes_done = offset;
+        job.start_offset = offset;
+        job.total_bytes = total_bytes;
+        job.started = Instant::now();
+        job.finished = false;
+    }
+
+    // Records the bytes written so far and reports whether the job is still
+    // expected to be ready in time.
+    pub(crate) fn advance(&self, key: &str, bytes_done: u64, now: DateTime<Utc>) -> bool {
+        let mut jobs = self.lock();
+        let Some(job) = jobs.get_mut(key) else {
+            return true;
+        };
+        job.bytes_done = bytes_done;
+        job.ready_in_time(now)
+    }
+
+    pub(crate) fn finish(&self, key: &str) {
+        if let Some(job) = self.lock().get_mut(key) {
+            job.finished = true;
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, JobProgress>> {
+        self.jobs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn new_job(key: &str) -> JobProgress {
+    JobProgress {
+        key: key.to_string(),
+        bytes_done: 0,
+        total_bytes: None,
+        deadline: None,
+        finished: false,
+        started: Instant::now(),
+        start_offset: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eta_needs_a_settled_rate_and_a_known_size() {
+        let board = ProgressBoard::default();
+        let now = Utc::now();
+        board.set_deadline("a", Some(now + chrono::Duration::seconds(10)));
+        board.begin("a", 0, Some(1000));
+        assert!(board.advance("a", 100, now));
+        assert!(board.get("a").unwrap().eta().is_none());
+
+        board.lock().get_mut("a").unwrap().started =
+            Instant::now().checked_sub(Duration::from_secs(2)).unwrap();
+        let job = board.get("a").unwrap();
+        // 100 bytes in 2s leaves 900 bytes for about 18s, past the deadline.
+        assert!(job.eta().unwrap() > Duration::from_secs(17));
+        assert!(!job.ready_in_time(now));
+        assert!(!board.advance("a", 100, now));
+
+        board.finish("a");
+        assert_eq!(board.get("a").unwrap().eta(), Some(Duration::ZERO));
+        assert!(board.get("a").unwrap().ready_in_time(now));
+    }
+}
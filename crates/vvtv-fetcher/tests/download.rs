@@ -0,0 +1,1007 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use vvtv_fetcher::{
+    DownloadError, DownloadPolicy, Downloader, FetchContext, Fetcher, S3Credentials, S3Settings,
+};
+use vvtv_types::{AssetItem, FixedClock, PlanItem, PlanState, QaStatus, Resolution, RunContext};
+
+const BODY_BYTES: usize = 256 * 1024;
+
+#[tokio::test]
+async fn downloads_and_hashes_the_full_body() {
+    let server = TestServer::start().await;
+    let dirs = TestDirs::new("full");
+    let downloader = downloader(&dirs, policy());
+
+    let file = downloader
+        .download("plan-ok", &server.url("/ok"))
+        .await
+        .expect("download");
+
+    assert_eq!(file.bytes, BODY_BYTES as u64);
+    assert_eq!(file.sha256, sha256_hex(&body()));
+    assert!(!file.resumed);
+    assert_eq!(std::fs::read(&file.path).expect("asset"), body());
+    assert!(!dirs.partial.join("plan-ok.part").exists());
+}
+
+#[tokio::test]
+async fn identical_content_is_stored_once() {
+    let server = TestServer::start().await;
+    let dirs = TestDirs::new("dedupe");
+    let downloader = downloader(&dirs, policy());
+
+    let first = downloader
+        .download("plan-one", &server.url("/ok"))
+        .await
+        .expect("first download");
+    let second = downloader
+        .download("plan-two", &server.url("/redirect-in"))
+        .await
+        .expect("second download");
+
+    assert!(!first.deduplicated);
+    assert!(second.deduplicated);
+    assert_eq!(first.path, second.path);
+    assert!(first.path.ends_with(format!("{}.mp4", sha256_hex(&body()))));
+    assert_eq!(std::fs::read_dir(&dirs.assets).expect("assets").count(), 1);
+    assert!(!dirs.partial.join("plan-two.part").exists());
+}
+
+#[tokio::test]
+async fn resumes_after_a_dropped_connection() {
+    let server = TestServer::start().await;
+    let dirs = TestDirs::new("flaky");
+    let downloader = downloader(&dirs, policy());
+
+    let file = downloader
+        .download("plan-flaky", &server.url("/flaky"))
+        .await
+        .expect("download");
+
+    assert!(file.resumed);
+    assert_eq!(file.sha256, sha256_hex(&body()));
+    assert_eq!(std::fs::read(&file.path).expect("asset"), body());
+    assert_eq!(server.hits.range_requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn gives_up_after_the_resume_budget_and_keeps_the_partial() {
+    let server = TestServer::start().await;
+    let dirs = TestDirs::new("broken");
+    let mut limited = policy();
+    limited.max_resume_attempts = 1;
+    let downloader = downloader(&dirs, limited);
+
+    let err = downloader
+        .download("plan-broken", &server.url("/always-drops"))
+        .await
+        .expect_err("never completes");
+
+    assert!(matches!(err, DownloadError::Interrupted(_)), "{err:?}");
+    assert!(dirs.partial.join("plan-broken.part").exists());
+}
+
+#[tokio::test]
+async fn follows_redirects_only_within_the_allowlist() {
+    let server = TestServer::start().await;
+    let dirs = TestDirs::new("redirect");
+    let downloader = downloader(&dirs, policy());
+
+    let inside = downloader
+        .download("plan-in", &server.url("/redirect-in"))
+        .await
+        .expect("allowlisted redirect");
+    assert_eq!(inside.sha256, sha256_hex(&body()));
+
+    let err = downloader
+        .download("plan-out", &server.url("/redirect-out"))
+        .await
+        .expect_err("foreign redirect");
+    assert!(
+        matches!(&err, DownloadError::Redirect { location } if location.contains("elsewhere.invalid")),
+        "{err:?}"
+    );
+}
+
+#[tokio::test]
+async fn rejects_sources_over_the_size_limit() {
+    let server = TestServer::start().await;
+    let dirs = TestDirs::new("big");
+    let mut small = policy();
+    small.max_bytes = 1024;
+    let downloader = downloader(&dirs, small);
+
+    let err = downloader
+        .download("plan-big", &server.url("/ok"))
+        .await
+        .expect_err("too large");
+    assert!(
+        matches!(err, DownloadError::TooLarge { limit: 1024 }),
+        "{err:?}"
+    );
+
+    let err = downloader
+        .download("plan-chunked", &server.url("/no-length"))
+        .await
+        .expect_err("too large without content-length");
+    assert!(matches!(err, DownloadError::TooLarge { .. }), "{err:?}");
+    assert!(!dirs.partial.join("plan-chunked.part").exists());
+}
+
+#[tokio::test]
+async fn times_out_slow_sources() {
+    let server = TestServer::start().await;
+    let dirs = TestDirs::new("slow");
+    let mut quick = policy();
+    quick.timeout = Duration::from_millis(300);
+    let downloader = downloader(&dirs, quick);
+
+    let err = downloader
+        .download("plan-slow", &server.url("/slow"))
+        .await
+        .expect_err("slow");
+    assert!(matches!(err, DownloadError::Timeout), "{err:?}");
+}
+
+#[tokio::test]
+async fn abandons_downloads_that_would_miss_their_slot() {
+    let server = TestServer::start().await;
+    let dirs = TestDirs::new("too-slow");
+    let mut retrying = policy();
+    retrying.max_retries = 2;
+    let downloader = downloader(&dirs, retrying);
+    downloader
+        .progress()
+        .set_deadline("plan-slow", Some(Utc::now() + chrono::Duration::seconds(3)));
+
+    let started = std::time::Instant::now();
+    let err = downloader
+        .download_with_retry("plan-slow", &server.url("/slow"))
+        .await
+        .expect_err("slow");
+
+    assert!(matches!(err, DownloadError::TooSlow), "{err:?}");
+    assert!(started.elapsed() < Duration::from_secs(3));
+    let progress = downloader.progress().get("plan-slow").expect("tracked");
+    assert_eq!(progress.bytes_done, 1024);
+    assert_eq!(progress.total_bytes, Some(BODY_BYTES as u64));
+    assert!(!progress.finished);
+    assert!(dirs.partial.join("plan-slow.part").exists());
+}
+
+#[tokio::test]
+async fn deadlines_are_judged_by_the_downloader_clock() {
+    let server = TestServer::start().await;
+    let dirs = TestDirs::new("run-clock");
+    let planned_at = Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap();
+    let downloader = downloader(&dirs, policy()).with_clock(Arc::new(FixedClock(planned_at)));
+    // Years away by the system clock, but seconds away for the run.
+    downloader
+        .progress()
+        .set_deadline("plan-slow", Some(planned_at + chrono::Duration::seconds(3)));
+
+    let err = downloader
+        .download("plan-slow", &server.url("/slow"))
+        .await
+        .expect_err("slow");
+    assert!(matches!(err, DownloadError::TooSlow), "{err:?}");
+}
+
+#[tokio::test]
+async fn concurrent_downloads_share_the_bandwidth_cap() {
+    let server = TestServer::start().await;
+    let dirs = TestDirs::new("bandwidth");
+    let mut capped = policy();
+    capped.max_bytes_per_sec = 2 * BODY_BYTES as u64;
+    let downloader = downloader(&dirs, capped);
+
+    let (one, two) = (server.url("/ok"), server.url("/ok?copy"));
+    let started = std::time::Instant::now();
+    let (first, second) = tokio::join!(
+        downloader.download("plan-one", &one),
+        downloader.download("plan-two", &two),
+    );
+
+    first.expect("first download");
+    second.expect("second download");
+    // Two bodies at two bodies per second, minus the first chunk let through.
+    assert!(started.elapsed() >= Duration::from_millis(700));
+    assert!(
+        downloader
+            .progress()
+            .snapshot()
+            .iter()
+            .all(|job| job.finished)
+    );
+}
+
+#[tokio::test]
+async fn the_pool_serves_the_earliest_slot_first() {
+    let server = TestServer::start().await;
+    let dirs = TestDirs::new("priority");
+    let mut serial = policy();
+    serial.max_concurrent = 1;
+    let downloader = downloader(&dirs, serial);
+    let run = RunContext::seeded(7, Utc::now());
+    let slot = |hour| Some(Utc.with_ymd_and_hms(2030, 1, 1, hour, 0, 0).unwrap());
+
+    let mut plans = vec![
+        plan(&server, "late", "/ok", "noir", 900, PlanState::Scheduled),
+        plan(
+            &server,
+            "unslotted",
+            "/ok",
+            "noir",
+            900,
+            PlanState::Scheduled,
+        ),
+        plan(&server, "early", "/ok", "noir", 900, PlanState::Scheduled),
+    ];
+    plans[0].slot_start_at = slot(22);
+    plans[2].slot_start_at = slot(20);
+    let assets = plans.iter().map(|p| asset(&p.plan_id)).collect();
+
+    let outcome = Fetcher::download_committed(
+        &run,
+        &downloader,
+        &plans,
+        assets,
+        &mut FetchContext::default(),
+    )
+    .await;
+
+    let order: Vec<_> = outcome.assets.iter().map(|a| a.plan_id.as_str()).collect();
+    assert_eq!(order, ["early", "late", "unslotted"]);
+    assert_eq!(
+        server.hits.order.lock().unwrap().as_slice(),
+        ["early", "late", "unslotted"]
+    );
+    let early = downloader.progress().get("early").expect("tracked");
+    assert_eq!(early.deadline, plans[2].slot_start_at);
+    assert!(early.finished);
+
+    // The next batch starts from a board without the jobs of this one.
+    let next = vec![plan(
+        &server,
+        "next",
+        "/ok",
+        "noir",
+        900,
+        PlanState::Scheduled,
+    )];
+    Fetcher::download_committed(
+        &run,
+        &downloader,
+        &next,
+        vec![asset("next")],
+        &mut FetchContext::default(),
+    )
+    .await;
+    let keys: Vec<_> = downloader
+        .progress()
+        .snapshot()
+        .into_iter()
+        .map(|job| job.key)
+        .collect();
+    assert_eq!(keys, ["next"]);
+}
+
+#[tokio::test]
+async fn refuses_sources_outside_the_allowlist() {
+    let dirs = TestDirs::new("denied");
+    let downloader = downloader(&dirs, policy());
+
+    let err = downloader
+        .download("plan-denied", "http://elsewhere.invalid/video.mp4")
+        .await
+        .expect_err("denied");
+    assert!(matches!(err, DownloadError::NotAllowed(_)), "{err:?}");
+
+    let err = downloader
+        .download("plan-missing", "http://127.0.0.1:9/missing")
+        .await
+        .expect_err("nothing listening");
+    assert!(matches!(err, DownloadError::Transport(_)), "{err:?}");
+}
+
+#[tokio::test]
+async fn allows_subdomains_but_not_lookalike_domains() {
+    let dirs = TestDirs::new("lookalike");
+    let downloader = downloader(
+        &dirs,
+        DownloadPolicy {
+            allowlist_domains: vec!["example.invalid".to_string()],
+            ..policy()
+        },
+    );
+    let err = downloader
+        .download("plan-lookalike", "http://evilexample.invalid/video.mp4")
+        .await
+        .expect_err("lookalike");
+    assert!(matches!(err, DownloadError::NotAllowed(_)), "{err:?}");
+    let err = downloader
+        .download("plan-subdomain", "http://cdn.example.invalid/video.mp4")
+        .await
+        .expect_err("unresolvable");
+    assert!(matches!(err, DownloadError::Transport(_)), "{err:?}");
+}
+
+#[tokio::test]
+async fn reports_http_errors() {
+    let server = TestServer::start().await;
+    let dirs = TestDirs::new("status");
+    let downloader = downloader(&dirs, policy());
+
+    let err = downloader
+        .download("plan-gone", &server.url("/gone"))
+        .await
+        .expect_err("gone");
+    assert!(matches!(err, DownloadError::Status(410)), "{err:?}");
+}
+
+#[tokio::test]
+async fn retries_transient_failures_with_backoff() {
+    let server = TestServer::start().await;
+    let dirs = TestDirs::new("retry");
+    let mut retrying = policy();
+    retrying.max_retries = 2;
+    let downloader = downloader(&dirs, retrying);
+
+    let file = downloader
+        .download_with_retry("plan-retry", &server.url("/unavailable-once"))
+        .await
+        .expect("second attempt succeeds");
+
+    assert_eq!(file.sha256, sha256_hex(&body()));
+    assert_eq!(server.hits.unavailable.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn does_not_retry_permanent_failures() {
+    let server = TestServer::start().await;
+    let dirs = TestDirs::new("permanent");
+    let mut retrying = policy();
+    retrying.max_retries = 3;
+    let downloader = downloader(&dirs, retrying);
+
+    let err = downloader
+        .download_with_retry("plan-gone", &server.url("/gone"))
+        .await
+        .expect_err("gone");
+
+    assert!(err.is_permanent());
+    assert_eq!(server.hits.gone.load(Ordering::SeqCst), 1);
+    assert!(!DownloadError::Status(503).is_permanent());
+    assert!(!DownloadError::Timeout.is_permanent());
+    assert!(DownloadError::TooLarge { limit: 1 }.is_permanent());
+}
+
+#[tokio::test]
+async fn failed_plans_fall_back_to_the_closest_reserve() {
+    let server = TestServer::start().await;
+    let dirs = TestDirs::new("fallback");
+    let downloader = downloader(&dirs, policy());
+    let run = RunContext::seeded(7, Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap());
+    let slot = Utc.with_ymd_and_hms(2026, 1, 1, 20, 0, 0).unwrap();
+
+    let mut failing = plan(&server, "a", "/gone", "noir", 900, PlanState::Scheduled);
+    failing.slot_start_at = Some(slot);
+    let plans = vec![
+        failing,
+        plan(
+            &server,
+            "r-other-theme",
+            "/ok",
+            "studio",
+            900,
+            PlanState::Reserved,
+        ),
+        plan(
+            &server,
+            "r-missing",
+            "/missing",
+            "noir",
+            850,
+            PlanState::Reserved,
+        ),
+        plan(&server, "r-noir", "/ok", "noir", 600, PlanState::Reserved),
+    ];
+    let mut ctx = FetchContext::default();
+
+    let outcome =
+        Fetcher::download_committed(&run, &downloader, &plans, vec![asset("a")], &mut ctx).await;
+
+    assert_eq!(outcome.fallbacks.len(), 1);
+    assert_eq!(
+        outcome.fallbacks[0].replacement_plan_id.as_deref(),
+        Some("r-noir")
+    );
+    let dropped: Vec<_> = outcome.dropped.iter().map(|p| p.plan_id.as_str()).collect();
+    assert_eq!(dropped, ["a", "r-missing"]);
+    assert!(outcome.dropped.iter().all(|p| {
+        p.state == PlanState::Dropped
+            && p.drop_reason
+                .as_deref()
+                .is_some_and(|r| r.starts_with("permanent"))
+    }));
+    assert_eq!(ctx.broken_urls.len(), 2);
+    assert_eq!(outcome.failures.len(), 2);
+    assert_eq!(outcome.fetched_urls, [plans[3].source_url.clone()]);
+
+    assert_eq!(outcome.promoted.len(), 1);
+    assert_eq!(outcome.promoted[0].state, PlanState::Committed);
+    assert_eq!(outcome.promoted[0].slot_start_at, Some(slot));
+    assert_eq!(outcome.assets.len(), 1);
+    assert_eq!(outcome.assets[0].plan_id, "r-noir");
+    assert_eq!(
+        outcome.assets[0].checksum,
+        format!("sha256:{}", sha256_hex(&body()))
+    );
+}
+
+#[tokio::test]
+async fn fallback_without_reserves_is_reported() {
+    let server = TestServer::start().await;
+    let dirs = TestDirs::new("no-reserve");
+    let downloader = downloader(&dirs, policy());
+    let run = RunContext::seeded(7, Utc::now());
+    let plans = vec![plan(
+        &server,
+        "a",
+        "/gone",
+        "noir",
+        900,
+        PlanState::Scheduled,
+    )];
+
+    let outcome = Fetcher::download_committed(
+        &run,
+        &downloader,
+        &plans,
+        vec![asset("a")],
+        &mut FetchContext::default(),
+    )
+    .await;
+
+    assert!(outcome.assets.is_empty());
+    assert_eq!(outcome.fallbacks.len(), 1);
+    assert!(outcome.fallbacks[0].replacement_plan_id.is_none());
+}
+
+#[tokio::test]
+async fn reads_file_sources_inside_the_roots_and_resumes_partials() {
+    let dirs = TestDirs::new("file");
+    let source = dirs.root().join("library/clip.mp4");
+    std::fs::create_dir_all(source.parent().expect("parent")).expect("library");
+    std::fs::write(&source, body()).expect("source");
+    std::fs::create_dir_all(&dirs.partial).expect("partial dir");
+    std::fs::write(dirs.partial.join("plan-file.part"), &body()[..1000]).expect("partial");
+    let mut local = policy();
+    local.file_roots = vec![dirs.root().join("library")];
+    let downloader = downloader(&dirs, local);
+
+    let url = format!("file://{}", source.display());
+    let file = downloader
+        .download("plan-file", &url)
+        .await
+        .expect("download");
+
+    assert!(file.resumed);
+    assert_eq!(file.sha256, sha256_hex(&body()));
+    assert_eq!(std::fs::read(&file.path).expect("asset"), body());
+}
+
+#[tokio::test]
+async fn refuses_file_sources_outside_the_roots() {
+    let dirs = TestDirs::new("file-denied");
+    let secret = dirs.root().join("secret.mp4");
+    std::fs::create_dir_all(dirs.root().join("library")).expect("library");
+    std::fs::write(&secret, body()).expect("secret");
+    let mut local = policy();
+    local.file_roots = vec![dirs.root().join("library")];
+    let downloader = downloader(&dirs, local);
+
+    let escape = format!("file://{}/library/../secret.mp4", dirs.root().display());
+    let err = downloader
+        .download("plan-escape", &escape)
+        .await
+        .expect_err("outside the root");
+    assert!(matches!(err, DownloadError::NotAllowed(_)), "{err:?}");
+
+    let missing = format!("file://{}/library/missing.mp4", dirs.root().display());
+    let err = downloader
+        .download("plan-missing", &missing)
+        .await
+        .expect_err("missing");
+    assert!(matches!(err, DownloadError::Missing(_)), "{err:?}");
+    assert!(err.is_permanent());
+
+    let err = downloader
+        .download("plan-ftp", "ftp://127.0.0.1/clip.mp4")
+        .await
+        .expect_err("unsupported");
+    assert!(
+        matches!(&err, DownloadError::UnsupportedScheme(scheme) if scheme == "ftp"),
+        "{err:?}"
+    );
+}
+
+#[tokio::test]
+async fn fetches_s3_objects_with_signed_requests() {
+    let server = TestServer::start().await;
+    let dirs = TestDirs::new("s3");
+    let mut bucketed = policy();
+    bucketed.s3 = Some(s3_settings(&server, Some(credentials())));
+    let downloader = downloader(&dirs, bucketed);
+
+    let file = downloader
+        .download("plan-s3", "s3://media/shows/clip.mp4")
+        .await
+        .expect("download");
+
+    assert_eq!(file.sha256, sha256_hex(&body()));
+    assert_eq!(server.hits.signed.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn s3_needs_credentials_and_an_allowed_bucket() {
+    let server = TestServer::start().await;
+    let dirs = TestDirs::new("s3-denied");
+    let mut keyless = policy();
+    keyless.s3 = Some(s3_settings(&server, None));
+    let keyless = downloader(&dirs, keyless);
+
+    let err = keyless
+        .download("plan-keyless", "s3://media/shows/clip.mp4")
+        .await
+        .expect_err("no keys");
+    assert!(matches!(err, DownloadError::Credentials(_)), "{err:?}");
+
+    let err = keyless
+        .download("plan-other", "s3://other/shows/clip.mp4")
+        .await
+        .expect_err("bucket not allowed");
+    assert!(matches!(err, DownloadError::NotAllowed(_)), "{err:?}");
+
+    let mut forged = policy();
+    forged.s3 = Some(s3_settings(
+        &server,
+        Some(S3Credentials {
+            access_key_id: "test-key".to_string(),
+            secret_access_key: "guess".to_string(),
+        }),
+    ));
+    let err = downloader(&dirs, forged)
+        .download("plan-forged", "s3://media/shows/clip.mp4")
+        .await
+        .expect_err("rejected signature");
+    assert!(matches!(err, DownloadError::Status(403)), "{err:?}");
+    assert_eq!(server.hits.signed.load(Ordering::SeqCst), 0);
+}
+
+fn plan(
+    server: &TestServer,
+    id: &str,
+    path: &str,
+    theme: &str,
+    duration_sec: u32,
+    state: PlanState,
+) -> PlanItem {
+    PlanItem {
+        plan_id: id.to_string(),
+        source_url: format!("{}?plan={id}", server.url(path)),
+        source_domain: "127.0.0.1".to_string(),
+        discovered_at: Utc::now(),
+        title: id.to_string(),
+        duration_sec,
+        theme_tags: vec![theme.to_string()],
+        visual_features: vec![],
+        quality_signals: vec![],
+        selection_reason: "test".to_string(),
+        policy_match_score: 0.9,
+        state,
+        slot_start_at: None,
+        pin_id: None,
+        score_breakdown: None,
+        drop_reason: None,
+    }
+}
+
+fn asset(plan_id: &str) -> AssetItem {
+    AssetItem {
+        asset_id: format!("asset-{plan_id}"),
+        plan_id: plan_id.to_string(),
+        local_path: String::new(),
+        checksum: String::new(),
+        resolution: Resolution {
+            width: 1280,
+            height: 720,
+        },
+        audio_lufs: -19.0,
+        qa_status: QaStatus::Pending,
+        pinned_start_at: None,
+        tags: vec![],
+        loudness: None,
+        planned_duration_sec: None,
+        qa_report: None,
+        encode_profile: None,
+        renditions: Vec::new(),
+        framing: None,
+        trim: None,
+        previews: None,
+        subtitles: Vec::new(),
+    }
+}
+
+fn policy() -> DownloadPolicy {
+    DownloadPolicy {
+        allowlist_domains: vec!["127.0.0.1".to_string()],
+        max_bytes: 16 * 1024 * 1024,
+        timeout: Duration::from_secs(10),
+        max_redirects: 3,
+        max_resume_attempts: 2,
+        max_retries: 0,
+        retry_backoff: Duration::from_millis(10),
+        max_concurrent: 2,
+        max_bytes_per_sec: 0,
+        file_roots: vec![],
+        s3: None,
+    }
+}
+
+fn downloader(dirs: &TestDirs, policy: DownloadPolicy) -> Downloader {
+    Downloader::new(policy, &dirs.partial, &dirs.assets).expect("downloader")
+}
+
+fn credentials() -> S3Credentials {
+    S3Credentials {
+        access_key_id: "test-key".to_string(),
+        secret_access_key: "test-secret".to_string(),
+    }
+}
+
+fn s3_settings(server: &TestServer, credentials: Option<S3Credentials>) -> S3Settings {
+    S3Settings {
+        endpoint: server.url(""),
+        region: "us-east-1".to_string(),
+        allowed_buckets: vec!["media".to_string()],
+        credentials,
+    }
+}
+
+fn body() -> Vec<u8> {
+    (0..BODY_BYTES)
+        .map(|i| u8::try_from(i % 251).expect("fits in a byte"))
+        .collect()
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<_>>()
+        .concat()
+}
+
+struct TestDirs {
+    partial: PathBuf,
+    assets: PathBuf,
+}
+
+impl TestDirs {
+    fn root(&self) -> PathBuf {
+        self.partial.parent().expect("root").to_path_buf()
+    }
+
+    fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!(
+            "vvtv-fetcher-{name}-{}-{}",
+            std::process::id(),
+            unique_suffix()
+        ));
+        Self {
+            partial: root.join("partial"),
+            assets: root.join("assets"),
+        }
+    }
+}
+
+impl Drop for TestDirs {
+    fn drop(&mut self) {
+        if let Some(root) = self.partial.parent() {
+            let _ = std::fs::remove_dir_all(root);
+        }
+    }
+}
+
+fn unique_suffix() -> u128 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default()
+}
+
+// Minimal HTTP/1.1 server: one request per connection, enough to script
+// truncated bodies, range responses and redirects.
+struct TestServer {
+    addr: SocketAddr,
+    hits: Arc<Hits>,
+}
+
+#[derive(Default)]
+struct Hits {
+    range_requests: AtomicUsize,
+    unavailable: AtomicUsize,
+    gone: AtomicUsize,
+    // S3 requests whose SigV4 signature checked out.
+    signed: AtomicUsize,
+    // `plan` query values in the order requests arrived.
+    order: Mutex<Vec<String>>,
+}
+
+impl TestServer {
+    async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let hits = Arc::new(Hits::default());
+        let shared = Arc::clone(&hits);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let hits = Arc::clone(&shared);
+                tokio::spawn(async move {
+                    let _ = serve(stream, addr, &hits).await;
+                });
+            }
+        });
+        Self { addr, hits }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("http://{}{path}", self.addr)
+    }
+}
+
+async fn serve(mut stream: TcpStream, addr: SocketAddr, hits: &Hits) -> std::io::Result<()> {
+    let request = read_request(&mut stream).await?;
+    let (path, range_start) = (request.path.as_str(), request.range_start);
+    if let Some(plan) = request.plan.clone() {
+        hits.order.lock().unwrap().push(plan);
+    }
+    let body = body();
+    let half = body.len() / 2;
+
+    match (path, range_start) {
+        ("/ok", _) => write_full(&mut stream, &body).await,
+        ("/media/shows/clip.mp4", _) => {
+            if verify_sigv4(&request, "test-secret") {
+                hits.signed.fetch_add(1, Ordering::SeqCst);
+                write_full(&mut stream, &body).await
+            } else {
+                let headers = [("Content-Length", "0".to_string())];
+                write_head(&mut stream, "403 Forbidden", &headers).await
+            }
+        }
+        ("/flaky", None) | ("/always-drops", _) => {
+            write_head(
+                &mut stream,
+                "200 OK",
+                &[("Content-Length", body.len().to_string())],
+            )
+            .await?;
+            stream.write_all(&body[..half]).await?;
+            stream.shutdown().await
+        }
+        ("/flaky", Some(start)) => {
+            hits.range_requests.fetch_add(1, Ordering::SeqCst);
+            let rest = &body[start..];
+            write_head(
+                &mut stream,
+                "206 Partial Content",
+                &[
+                    ("Content-Length", rest.len().to_string()),
+                    (
+                        "Content-Range",
+                        format!("bytes {start}-{}/{}", body.len() - 1, body.len()),
+                    ),
+                ],
+            )
+            .await?;
+            stream.write_all(rest).await
+        }
+        ("/redirect-in", _) => redirect(&mut stream, format!("http://{addr}/ok")).await,
+        ("/redirect-out", _) => {
+            redirect(&mut stream, "http://elsewhere.invalid/ok".to_string()).await
+        }
+        ("/no-length", _) => {
+            write_head(
+                &mut stream,
+                "200 OK",
+                &[("Content-Type", "video/mp4".to_string())],
+            )
+            .await?;
+            stream.write_all(&body).await?;
+            stream.shutdown().await
+        }
+        ("/slow", _) => {
+            write_head(
+                &mut stream,
+                "200 OK",
+                &[("Content-Length", body.len().to_string())],
+            )
+            .await?;
+            stream.write_all(&body[..1024]).await?;
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        }
+        ("/unavailable-once", _) => {
+            if hits.unavailable.fetch_add(1, Ordering::SeqCst) == 0 {
+                let headers = [("Content-Length", "0".to_string())];
+                write_head(&mut stream, "503 Service Unavailable", &headers).await
+            } else {
+                write_full(&mut stream, &body).await
+            }
+        }
+        ("/gone", _) => {
+            hits.gone.fetch_add(1, Ordering::SeqCst);
+            write_head(&mut stream, "410 Gone", &[("Content-Length", "0".into())]).await
+        }
+        _ => {
+            write_head(
+                &mut stream,
+                "404 Not Found",
+                &[("Content-Length", "0".into())],
+            )
+            .await
+        }
+    }
+}
+
+struct Request {
+    path: String,
+    plan: Option<String>,
+    range_start: Option<usize>,
+    // Lowercased names.
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Request> {
+    let mut raw = Vec::new();
+    let mut buffer = [0; 1024];
+    while !raw.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        raw.extend_from_slice(&buffer[..read]);
+    }
+    let text = String::from_utf8_lossy(&raw);
+    let target = text
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .unwrap_or("/");
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let headers: Vec<_> = text
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+    let range_start = headers
+        .iter()
+        .find(|(name, _)| name == "range")
+        .and_then(|(_, value)| value.strip_prefix("bytes="))
+        .and_then(|range| range.trim_end_matches('-').parse().ok());
+    Ok(Request {
+        path: path.to_string(),
+        plan: query.strip_prefix("plan=").map(str::to_string),
+        range_start,
+        headers,
+    })
+}
+
+// Recomputes the SigV4 signature the way an S3 endpoint would, from the
+// headers the client says it signed.
+fn verify_sigv4(request: &Request, secret: &str) -> bool {
+    let Some(authorization) = request.header("authorization") else {
+        return false;
+    };
+    let field = |name: &str| {
+        authorization
+            .split([' ', ','])
+            .find_map(|part| part.strip_prefix(name))
+            .unwrap_or_default()
+            .to_string()
+    };
+    let (credential, signed_headers, signature) = (
+        field("Credential="),
+        field("SignedHeaders="),
+        field("Signature="),
+    );
+    let Some((_, scope)) = credential.split_once('/') else {
+        return false;
+    };
+    let canonical_headers = signed_headers.split(';').fold(String::new(), |out, name| {
+        out + name + ":" + request.header(name).unwrap_or_default() + "\n"
+    });
+    let canonical_request = format!(
+        "GET\n{}\n\n{canonical_headers}\n{signed_headers}\n{}",
+        request.path,
+        request.header("x-amz-content-sha256").unwrap_or_default()
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{scope}\n{}",
+        request.header("x-amz-date").unwrap_or_default(),
+        sha256_hex(canonical_request.as_bytes())
+    );
+    let key = scope
+        .split('/')
+        .fold(format!("AWS4{secret}").into_bytes(), |key, part| {
+            hmac_sha256(&key, part.as_bytes())
+        });
+    hex(&hmac_sha256(&key, string_to_sign.as_bytes())) == signature
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("any key length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+async fn write_full(stream: &mut TcpStream, body: &[u8]) -> std::io::Result<()> {
+    write_head(
+        stream,
+        "200 OK",
+        &[("Content-Length", body.len().to_string())],
+    )
+    .await?;
+    stream.write_all(body).await
+}
+
+async fn redirect(stream: &mut TcpStream, location: String) -> std::io::Result<()> {
+    write_head(
+        stream,
+        "302 Found",
+        &[("Location", location), ("Content-Length", "0".to_string())],
+    )
+    .await
+}
+
+async fn write_head(
+    stream: &mut TcpStream,
+    status: &str,
+    headers: &[(&str, String)],
+) -> std::io::Result<()> {
+    let mut head = vec![format!("HTTP/1.1 {status}")];
+    head.extend(
+        headers
+            .iter()
+            .map(|(name, value)| format!("{name}: {value}")),
+    );
+    head.push("Connection: close\r\n\r\n".to_string());
+    let head = head.join("\r\n");
+    stream.write_all(head.as_bytes()).await
+}
@@ -0,0 +1,170 @@
+use std::process::ExitStatus;
+use std::time::Duration;
+
+use thiserror::Error;
+
+// Most stderr lines kept in an error; ffmpeg explains a failure at the end.
+const DETAIL_LINES: usize = 3;
+// `ENOSPC` on Linux and macOS.
+const ENOSPC: i32 = 28;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaErrorKind {
+    MissingBinary,
+    UnsupportedCodec,
+    CorruptInput,
+    DiskFull,
+    Timeout,
+    Cancelled,
+    Failed,
+}
+
+impl MediaErrorKind {
+    #[must_use]
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::MissingBinary => "MEDIA_MISSING_BINARY",
+            Self::UnsupportedCodec => "MEDIA_UNSUPPORTED_CODEC",
+            Self::CorruptInput => "MEDIA_CORRUPT_INPUT",
+            Self::DiskFull => "MEDIA_DISK_FULL",
+            Self::Timeout => "MEDIA_TIMEOUT",
+            Self::Cancelled => "MEDIA_CANCELLED",
+            Self::Failed => "MEDIA_FAILED",
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum MediaError {
+    #[error("{0} is not installed")]
+    MissingBinary(&'static str),
+    #[error("{tool}: unsupported codec: {detail}")]
+    UnsupportedCodec { tool: &'static str, detail: String },
+    #[error("{tool}: corrupt input: {detail}")]
+    CorruptInput { tool: &'static str, detail: String },
+    #[error("{tool}: disk full: {detail}")]
+    DiskFull { tool: &'static str, detail: String },
+    #[error("{tool} timed out after {}s", limit.as_secs())]
+    Timeout { tool: &'static str, limit: Duration },
+    #[error("{0} was cancelled")]
+    Cancelled(&'static str),
+    #[error("{tool} failed ({status}): {detail}")]
+    Failed {
+        tool: &'static str,
+        status: String,
+        detail: String,
+    },
+    #[error("unreadable {tool} output: {detail}")]
+    Output { tool: &'static str, detail: String },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl MediaError {
+    #[must_use]
+    pub fn kind(&self) -> MediaErrorKind {
+        match self {
+            Self::MissingBinary(_) => MediaErrorKind::MissingBinary,
+            Self::UnsupportedCodec { .. } => MediaErrorKind::UnsupportedCodec,
+            Self::CorruptInput { .. } => MediaErrorKind::CorruptInput,
+            Self::DiskFull { .. } => MediaErrorKind::DiskFull,
+            Self::Timeout { .. } => MediaErrorKind::Timeout,
+            Self::Cancelled(_) => MediaErrorKind::Cancelled,
+            Self::Io(err) if err.raw_os_error() == Some(ENOSPC) => MediaErrorKind::DiskFull,
+            Self::Failed { .. } | Self::Output { .. } | Self::Io(_) => MediaErrorKind::Failed,
+        }
+    }
+
+    // Sorts a non-zero exit by what the tool said last on stderr.
+    pub(crate) fn from_exit(tool: &'static str, status: ExitStatus, stderr: &str) -> Self {
+        let detail = tail(stderr);
+        let lower = stderr.to_ascii_lowercase();
+        let said = |needles: &[&str]| needles.iter().any(|needle| lower.contains(needle));
+        if said(&["no space left on device", "disk quota exceeded"]) {
+            Self::DiskFull { tool, detail }
+        } else if said(&[
+            "unknown encoder",
+            "unknown decoder",
+            "encoder not found",
+            ") not found for input stream",
+            "unsupported codec",
+            "codec not currently supported in container",
+            "could not find tag for codec",
+        ]) {
+            Self::UnsupportedCodec { tool, detail }
+        } else if said(&[
+            "invalid data found when processing input",
+            "moov atom not found",
+            "could not find codec parameters",
+            "error while decoding",
+            "invalid nal unit",
+            "corrupt",
+            "truncat",
+        ]) {
+            Self::CorruptInput { tool, detail }
+        } else {
+            Self::Failed {
+                tool,
+                status: status.to_string(),
+                detail,
+            }
+        }
+    }
+}
+
+fn tail(stderr: &str) -> String {
+    let lines: Vec<&str> = stderr
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    lines[lines.len().saturating_sub(DETAIL_LINES)..].join(" | ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+
+    use super::*;
+
+    fn failed(stderr: &str) -> MediaError {
+        MediaError::from_exit("ffmpeg", ExitStatus::from_raw(1 << 8), stderr)
+    }
+
+    #[test]
+    fn classifies_failures_by_stderr() {
+        let kind = |stderr: &str| failed(stderr).kind();
+        assert_eq!(
+            kind(
+                "[mp4 @ 0x1] moov atom not found\nin.mp4: Invalid data found when processing input"
+            ),
+            MediaErrorKind::CorruptInput
+        );
+        assert_eq!(
+            kind("Unknown encoder 'libx265'"),
+            MediaErrorKind::UnsupportedCodec
+        );
+        assert_eq!(
+            kind("Decoder (codec av1) not found for input stream #0:0"),
+            MediaErrorKind::UnsupportedCodec
+        );
+        assert_eq!(
+            kind("av_interleaved_write_frame(): No space left on device"),
+            MediaErrorKind::DiskFull
+        );
+        assert_eq!(kind("Conversion failed!"), MediaErrorKind::Failed);
+        assert_eq!(
+            MediaError::Io(std::io::Error::from_raw_os_error(ENOSPC)).kind(),
+            MediaErrorKind::DiskFull
+        );
+    }
+
+    #[test]
+    fn keeps_the_last_stderr_lines() {
+        let err = failed("banner\n\nline one\nline two\nline three\n");
+        assert_eq!(
+            err.to_string(),
+            "ffmpeg failed (exit status: 1): line one | line two | line three"
+        );
+    }
+}
@@ -0,0 +1,537 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::MediaError;
+use crate::progress::Progress;
+use crate::run::{RunLimits, execute};
+
+#[derive(Debug, Clone)]
+enum Input {
+    File(PathBuf),
+    Lavfi(String),
+    // An ffconcat list of files played back to back.
+    Concat(PathBuf),
+    // A file's video with the CEA-608 captions carried in it exposed as a
+    // second, subtitle stream.
+    Captions(PathBuf),
+}
+
+#[derive(Debug, Clone)]
+enum Output {
+    // Decodes everything and writes nothing; for measurement passes.
+    Null,
+    File(PathBuf),
+    Hls {
+        playlist: PathBuf,
+        segment_pattern: PathBuf,
+        segment_sec: u32,
+    },
+}
+
+// One ffmpeg invocation, built from the operations the pipeline uses. Paths
+// are passed as they are, never through a lossy string conversion, except
+// where a filter graph has to name them.
+#[derive(Debug, Clone)]
+pub struct Ffmpeg {
+    log_level: &'static str,
+    inputs: Vec<Input>,
+    maps: Vec<String>,
+    start_sec: Option<f32>,
+    duration_sec: Option<f32>,
+    video_filter: Option<String>,
+    audio_filter: Option<String>,
+    no_video: bool,
+    video_frames: Option<u32>,
+    copy: bool,
+    video_codec: Option<String>,
+    preset: Option<String>,
+    crf: Option<u8>,
+    video_bitrate_kbps: Option<u32>,
+    gop_frames: Option<u32>,
+    audio_codec: Option<String>,
+    audio_bitrate_kbps: Option<u32>,
+    subtitle_codec: Option<String>,
+    output: Output,
+}
+
+impl Default for Ffmpeg {
+    fn default() -> Self {
+        Self {
+            log_level: "error",
+            inputs: Vec::new(),
+            maps: Vec::new(),
+            start_sec: None,
+            duration_sec: None,
+            video_filter: None,
+            audio_filter: None,
+            no_video: false,
+            video_frames: None,
+            copy: false,
+            video_codec: None,
+            preset: None,
+            crf: None,
+            video_bitrate_kbps: None,
+            gop_frames: None,
+            audio_codec: None,
+            audio_bitrate_kbps: None,
+            subtitle_codec: None,
+            output: Output::Null,
+        }
+    }
+}
+
+impl Ffmpeg {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Filters that report through the log (`loudnorm`, the detectors) need
+    // `info`; the default `error` keeps only what explains a failure.
+    #[must_use]
+    pub fn log_level(mut self, level: &'static str) -> Self {
+        self.log_level = level;
+        self
+    }
+
+    #[must_use]
+    pub fn input(mut self, path: impl AsRef<Path>) -> Self {
+        self.inputs.push(Input::File(path.as_ref().to_path_buf()));
+        self
+    }
+
+    #[must_use]
+    pub fn lavfi(mut self, graph: impl Into<String>) -> Self {
+        self.inputs.push(Input::Lavfi(graph.into()));
+        self
+    }
+
+    #[must_use]
+    pub fn concat(mut self, list: impl AsRef<Path>) -> Self {
+        self.inputs.push(Input::Concat(list.as_ref().to_path_buf()));
+        self
+    }
+
+    // The closed captions of `path`, to be picked with `map("0:1")`.
+    #[must_use]
+    pub fn captions(mut self, path: impl AsRef<Path>) -> Self {
+        self.inputs
+            .push(Input::Captions(path.as_ref().to_path_buf()));
+        self
+    }
+
+    // Picks the streams to write, as `-map` specifiers; ffmpeg's own choice
+    // when none are given.
+    #[must_use]
+    pub fn map(mut self, spec: impl Into<String>) -> Self {
+        self.maps.push(spec.into());
+        self
+    }
+
+    // Drops everything before `secs` of the input. Applied on the output
+    // side, so the cut is frame-accurate when encoding.
+    #[must_use]
+    pub fn start(mut self, secs: f32) -> Self {
+        self.start_sec = Some(secs);
+        self
+    }
+
+    #[must_use]
+    pub fn duration(mut self, secs: f32) -> Self {
+        self.duration_sec = Some(secs);
+        self
+    }
+
+    #[must_use]
+    pub fn video_filter(mut self, filter: impl Into<String>) -> Self {
+        self.video_filter = Some(filter.into());
+        self
+    }
+
+    #[must_use]
+    pub fn audio_filter(mut self, filter: impl Into<String>) -> Self {
+        self.audio_filter = Some(filter.into());
+        self
+    }
+
+    #[must_use]
+    pub fn no_video(mut self) -> Self {
+        self.no_video = true;
+        self
+    }
+
+    // Stops after `count` output video frames; one makes a still image.
+    #[must_use]
+    pub fn frames(mut self, count: u32) -> Self {
+        self.video_frames = Some(count);
+        self
+    }
+
+    // Remuxes every stream without encoding.
+    #[must_use]
+    pub fn copy(mut self) -> Self {
+        self.copy = true;
+        self
+    }
+
+    #[must_use]
+    pub fn video_codec(mut self, codec: impl Into<String>) -> Self {
+        self.video_codec = Some(codec.into());
+        self
+    }
+
+    #[must_use]
+    pub fn preset(mut self, preset: impl Into<String>) -> Self {
+        self.preset = Some(preset.into());
+        self
+    }
+
+    #[must_use]
+    pub fn crf(mut self, crf: u8) -> Self {
+        self.crf = Some(crf);
+        self
+    }
+
+    // Average bitrate, also used as the peak with a two-second buffer so
+    // segments stay close to the advertised bandwidth.
+    #[must_use]
+    pub fn video_bitrate_kbps(mut self, kbps: u32) -> Self {
+        self.video_bitrate_kbps = Some(kbps);
+        self
+    }
+
+    // A keyframe exactly every `frames` frames and nowhere else, so renditions
+    // and segments encoded with the same value line up.
+    #[must_use]
+    pub fn gop(mut self, frames: u32) -> Self {
+        self.gop_frames = Some(frames);
+        self
+    }
+
+    #[must_use]
+    pub fn audio_codec(mut self, codec: impl Into<String>) -> Self {
+        self.audio_codec = Some(codec.into());
+        self
+    }
+
+    #[must_use]
+    pub fn audio_bitrate_kbps(mut self, kbps: u32) -> Self {
+        self.audio_bitrate_kbps = Some(kbps);
+        self
+    }
+
+    #[must_use]
+    pub fn subtitle_codec(mut self, codec: impl Into<String>) -> Self {
+        self.subtitle_codec = Some(codec.into());
+        self
+    }
+
+    #[must_use]
+    pub fn output(mut self, path: impl AsRef<Path>) -> Self {
+        self.output = Output::File(path.as_ref().to_path_buf());
+        self
+    }
+
+    #[must_use]
+    pub fn hls(
+        mut self,
+        playlist: impl AsRef<Path>,
+        segment_pattern: impl AsRef<Path>,
+        segment_sec: u32,
+    ) -> Self {
+        self.output = Output::Hls {
+            playlist: playlist.as_ref().to_path_buf(),
+            segment_pattern: segment_pattern.as_ref().to_path_buf(),
+            segment_sec,
+        };
+        self
+    }
+
+    #[must_use]
+    pub fn args(&self) -> Vec<OsString> {
+        self.build(false)
+    }
+
+    fn build(&self, progress: bool) -> Vec<OsString> {
+        let mut args = Args::default();
+        args.push_all(["-hide_banner", "-nostdin", "-nostats", "-y"]);
+        if progress {
+            args.push_all(["-progress", "pipe:1"]);
+        }
+        args.push_all(["-loglevel", self.log_level]);
+        for input in &self.inputs {
+            match input {
+                Input::File(path) => args.push("-i").push(path),
+                Input::Lavfi(graph) => args.push_all(["-f", "lavfi", "-i", graph]),
+                Input::Concat(list) => args
+                    .push_all(["-f", "concat", "-safe", "0", "-i"])
+                    .push(list),
+                Input::Captions(path) => args
+                    .push_all(["-f", "lavfi", "-i"])
+                    .push(format!("movie={}[out0+subcc]", filter_path(path))),
+            };
+        }
+        for spec in &self.maps {
+            args.push_all(["-map", spec]);
+        }
+        if let Some(secs) = self.start_sec {
+            args.push("-ss").push(secs.to_string());
+        }
+        if let Some(secs) = self.duration_sec {
+            args.push("-t").push(secs.to_string());
+        }
+        if self.no_video {
+            args.push("-vn");
+        }
+        if let Some(filter) = &self.video_filter {
+            args.push_all(["-vf", filter]);
+        }
+        if let Some(filter) = &self.audio_filter {
+            args.push_all(["-af", filter]);
+        }
+        if let Some(count) = self.video_frames {
+            args.push("-frames:v").push(count.to_string());
+        }
+        if self.copy {
+            args.push_all(["-c", "copy"]);
+        }
+        if let Some(codec) = &self.video_codec {
+            args.push_all(["-c:v", codec]);
+        }
+        if let Some(preset) = &self.preset {
+            args.push_all(["-preset", preset]);
+        }
+        if let Some(crf) = self.crf {
+            args.push("-crf").push(crf.to_string());
+        }
+        if let Some(kbps) = self.video_bitrate_kbps {
+            args.push("-b:v")
+                .push(format!("{kbps}k"))
+                .push("-maxrate")
+                .push(format!("{kbps}k"))
+                .push("-bufsize")
+                .push(format!("{}k", kbps.saturating_mul(2)));
+        }
+        if let Some(frames) = self.gop_frames {
+            let frames = frames.to_string();
+            args.push_all(["-g", &frames, "-keyint_min", &frames, "-sc_threshold", "0"]);
+        }
+        if let Some(codec) = &self.audio_codec {
+            args.push_all(["-c:a", codec]);
+        }
+        if let Some(kbps) = self.audio_bitrate_kbps {
+            args.push("-b:a").push(format!("{kbps}k"));
+        }
+        if let Some(codec) = &self.subtitle_codec {
+            args.push_all(["-c:s", codec]);
+        }
+        match &self.output {
+            Output::Null => args.push_all(["-f", "null", "-"]),
+            Output::File(path) => args.push(path),
+            Output::Hls {
+                playlist,
+                segment_pattern,
+                segment_sec,
+            } => args
+                .push_all(["-f", "hls", "-hls_time"])
+                .push(segment_sec.to_string())
+                .push_all(["-hls_list_size", "0", "-hls_flags", "independent_segments"])
+                .push("-hls_segment_filename")
+                .push(segment_pattern)
+                .push(playlist),
+        };
+        args.0
+    }
+
+    /// Runs to completion and returns what ffmpeg logged on stderr.
+    ///
+    /// # Errors
+    ///
+    /// A [`MediaError`] classifying why ffmpeg is missing, failed, ran out of
+    /// time or was cancelled.
+    pub fn run(&self, limits: &RunLimits<'_>) -> Result<String, MediaError> {
+        Ok(execute("ffmpeg", &self.args(), limits, None)?.stderr)
+    }
+
+    /// Like `run`, handing each `-progress` block to `on_progress` as ffmpeg
+    /// writes it.
+    ///
+    /// # Errors
+    ///
+    /// The same as [`Ffmpeg::run`].
+    pub fn run_with_progress(
+        &self,
+        limits: &RunLimits<'_>,
+        mut on_progress: impl FnMut(&Progress),
+    ) -> Result<String, MediaError> {
+        Ok(execute("ffmpeg", &self.build(true), limits, Some(&mut on_progress))?.stderr)
+    }
+}
+
+// `path` as a quoted filter option value: escaped once for the option
+// parser, then quoted for the graph parser.
+fn filter_path(path: &Path) -> String {
+    let option = path
+        .to_string_lossy()
+        .replace('\\', "\\\\")
+        .replace('\'', "\\'")
+        .replace(':', "\\:");
+    format!("'{}'", option.replace('\'', "'\\''"))
+}
+
+#[derive(Default)]
+struct Args(Vec<OsString>);
+
+impl Args {
+    fn push(&mut self, arg: impl Into<OsString>) -> &mut Self {
+        self.0.push(arg.into());
+        self
+    }
+
+    fn push_all<'a>(&mut self, args: impl IntoIterator<Item = &'a str>) -> &mut Self {
+        self.0.extend(args.into_iter().map(OsString::from));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn joined(ffmpeg: &Ffmpeg) -> String {
+        ffmpeg
+            .args()
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn builds_an_encode() {
+        let encode = Ffmpeg::new()
+            .input("in dir/source.mp4")
+            .video_filter("scale=1280:720")
+            .audio_filter("loudnorm=I=-16")
+            .video_codec("libx264")
+            .preset("veryfast")
+            .audio_codec("aac")
+            .audio_bitrate_kbps(128)
+            .output("out.mp4");
+
+        assert_eq!(
+            joined(&encode),
+            "-hide_banner -nostdin -nostats -y -loglevel error -i in dir/source.mp4 \
+             -vf scale=1280:720 -af loudnorm=I=-16 -c:v libx264 -preset veryfast -c:a aac \
+             -b:a 128k out.mp4"
+        );
+        assert_eq!(encode.args()[7], OsString::from("in dir/source.mp4"));
+    }
+
+    #[test]
+    fn trims_on_the_output_side() {
+        let trimmed = Ffmpeg::new()
+            .input("in.mp4")
+            .start(4.5)
+            .duration(590.25)
+            .output("out.mp4");
+        assert_eq!(
+            joined(&trimmed),
+            "-hide_banner -nostdin -nostats -y -loglevel error -i in.mp4 -ss 4.5 -t 590.25 out.mp4"
+        );
+
+        let still = Ffmpeg::new()
+            .input("in.mp4")
+            .start(60.0)
+            .video_filter("scale=640:360")
+            .frames(1)
+            .output("poster.jpg");
+        assert_eq!(
+            joined(&still),
+            "-hide_banner -nostdin -nostats -y -loglevel error -i in.mp4 -ss 60 -vf scale=640:360 \
+             -frames:v 1 poster.jpg"
+        );
+    }
+
+    #[test]
+    fn builds_rate_control_and_keyframe_interval() {
+        let crf = Ffmpeg::new()
+            .input("in.mp4")
+            .video_codec("libx264")
+            .crf(21)
+            .gop(180)
+            .output("out.mp4");
+        assert_eq!(
+            joined(&crf),
+            "-hide_banner -nostdin -nostats -y -loglevel error -i in.mp4 -c:v libx264 -crf 21 \
+             -g 180 -keyint_min 180 -sc_threshold 0 out.mp4"
+        );
+
+        let capped = Ffmpeg::new()
+            .input("in.mp4")
+            .video_bitrate_kbps(3000)
+            .output("out.mp4");
+        assert_eq!(
+            joined(&capped),
+            "-hide_banner -nostdin -nostats -y -loglevel error -i in.mp4 -b:v 3000k \
+             -maxrate 3000k -bufsize 6000k out.mp4"
+        );
+    }
+
+    #[test]
+    fn extracts_subtitle_streams_and_captions() {
+        let stream = Ffmpeg::new()
+            .input("in.mkv")
+            .map("0:3")
+            .subtitle_codec("webvtt")
+            .output("sub-3.vtt");
+        assert_eq!(
+            joined(&stream),
+            "-hide_banner -nostdin -nostats -y -loglevel error -i in.mkv -map 0:3 -c:s webvtt \
+             sub-3.vtt"
+        );
+
+        let captions = Ffmpeg::new()
+            .captions("ingest/it's:1.ts")
+            .map("0:1")
+            .subtitle_codec("webvtt")
+            .output("cc.vtt");
+        assert_eq!(
+            joined(&captions),
+            "-hide_banner -nostdin -nostats -y -loglevel error -f lavfi \
+             -i movie='ingest/it\\'\\''s\\:1.ts'[out0+subcc] -map 0:1 -c:s webvtt cc.vtt"
+        );
+    }
+
+    #[test]
+    fn builds_measurement_and_hls_passes() {
+        let measure = Ffmpeg::new()
+            .log_level("info")
+            .input("a.mp4")
+            .no_video()
+            .audio_filter("loudnorm=print_format=json");
+        assert_eq!(
+            joined(&measure),
+            "-hide_banner -nostdin -nostats -y -loglevel info -i a.mp4 -vn \
+             -af loudnorm=print_format=json -f null -"
+        );
+
+        let hls = Ffmpeg::new().concat("concat.txt").copy().hls(
+            "hls/index.m3u8",
+            "hls/segment_%05d.ts",
+            6,
+        );
+        assert_eq!(
+            joined(&hls),
+            "-hide_banner -nostdin -nostats -y -loglevel error -f concat -safe 0 -i concat.txt \
+             -c copy -f hls -hls_time 6 -hls_list_size 0 -hls_flags independent_segments \
+             -hls_segment_filename hls/segment_%05d.ts hls/index.m3u8"
+        );
+
+        let progress = Ffmpeg::new().lavfi("testsrc").duration(1.0).build(true);
+        assert_eq!(
+            progress[4..6],
+            [OsString::from("-progress"), "pipe:1".into()]
+        );
+    }
+}
@@ -0,0 +1,28 @@
+mod error;
+mod ffmpeg;
+mod probe;
+mod progress;
+mod run;
+mod webvtt;
+
+use std::process::{Command, Stdio};
+
+pub use error::{MediaError, MediaErrorKind};
+pub use ffmpeg::Ffmpeg;
+pub use probe::{ProbeInfo, StreamInfo, StreamKind, probe};
+pub use progress::{Progress, ProgressParser};
+pub use run::RunLimits;
+pub use webvtt::{Cue, parse_cues, render_cues, vtt_timestamp};
+
+// Whether both ffmpeg and ffprobe can be started.
+#[must_use]
+pub fn available() -> bool {
+    ["ffmpeg", "ffprobe"].iter().all(|tool| {
+        Command::new(tool)
+            .arg("-version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok()
+    })
+}
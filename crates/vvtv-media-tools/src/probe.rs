@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::MediaError;
+use crate::run::{RunLimits, execute};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamKind {
+    Video,
+    Audio,
+    Subtitle,
+    Data,
+    Attachment,
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamInfo {
+    pub index: u32,
+    pub kind: StreamKind,
+    pub codec_name: Option<String>,
+    // Codec profile as ffprobe names it, e.g. `High` or `LC`.
+    pub profile: Option<String>,
+    // H.264 level times ten, e.g. 40 for 4.0.
+    pub level: Option<u32>,
+    // Coded size, before the sample aspect ratio and rotation are applied.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    // Pixel shape as `num:den`; `None` for square pixels or when unknown.
+    pub sample_aspect_ratio: Option<(u32, u32)>,
+    // Clockwise turn players apply on display, from the display matrix or
+    // the legacy `rotate` tag, in 0..360.
+    pub rotation_deg: u16,
+    // CEA-608/708 captions carried inside the video stream.
+    pub closed_captions: bool,
+    // Average frame rate; `None` when ffprobe reports `0/0`.
+    pub frame_rate: Option<f32>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+    pub start_sec: Option<f32>,
+    pub duration_sec: Option<f32>,
+    pub language: Option<String>,
+}
+
+// What ffprobe reports about a file's container and streams.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProbeInfo {
+    pub format_name: Option<String>,
+    pub duration_sec: Option<f32>,
+    pub bit_rate: Option<u64>,
+    pub streams: Vec<StreamInfo>,
+}
+
+impl ProbeInfo {
+    /// Reads the output of `ffprobe -of json`.
+    ///
+    /// # Errors
+    ///
+    /// [`MediaError::Output`] when the JSON does not parse.
+    pub fn from_json(json: &str) -> Result<Self, MediaError> {
+        let raw: RawProbe = serde_json::from_str(json).map_err(|err| MediaError::Output {
+            tool: "ffprobe",
+            detail: err.to_string(),
+        })?;
+        let format = raw.format.unwrap_or_default();
+        Ok(Self {
+            format_name: format.format_name,
+            duration_sec: number(format.duration.as_deref()),
+            bit_rate: number(format.bit_rate.as_deref()),
+            streams: raw.streams.into_iter().map(RawStream::into_info).collect(),
+        })
+    }
+
+    // The first stream of `kind`, which is what ffmpeg picks by default.
+    #[must_use]
+    pub fn first(&self, kind: StreamKind) -> Option<&StreamInfo> {
+        self.streams.iter().find(|stream| stream.kind == kind)
+    }
+
+    #[must_use]
+    pub fn has(&self, kind: StreamKind) -> bool {
+        self.first(kind).is_some()
+    }
+}
+
+impl StreamInfo {
+    // The picture as players show it: sample aspect ratio applied to the
+    // width, and the sides swapped for a quarter turn.
+    #[must_use]
+    pub fn display_size(&self) -> Option<(u32, u32)> {
+        let (mut width, height) = (self.width?, self.height?);
+        if let Some((num, den)) = self.sample_aspect_ratio {
+            width = u32::try_from(u64::from(width) * u64::from(num) / u64::from(den)).ok()?;
+        }
+        Some(if self.rotation_deg % 180 == 90 {
+            (height, width)
+        } else {
+            (width, height)
+        })
+    }
+}
+
+/// Streams and container facts of `path`.
+///
+/// # Errors
+///
+/// A [`MediaError`] when ffprobe cannot run or fails on `path`, or
+/// [`MediaError::Output`] when its output does not parse.
+pub fn probe(path: &Path, limits: &RunLimits<'_>) -> Result<ProbeInfo, MediaError> {
+    let mut args: Vec<OsString> = [
+        "-v",
+        "error",
+        "-of",
+        "json",
+        "-show_format",
+        "-show_streams",
+    ]
+    .into_iter()
+    .map(OsString::from)
+    .collect();
+    args.push(path.as_os_str().to_owned());
+    let captured = execute("ffprobe", &args, limits, None)?;
+    ProbeInfo::from_json(&String::from_utf8_lossy(&captured.stdout))
+}
+
+// ffprobe prints most numbers as strings.
+#[derive(Debug, Deserialize)]
+struct RawProbe {
+    #[serde(default)]
+    streams: Vec<RawStream>,
+    format: Option<RawFormat>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RawFormat {
+    format_name: Option<String>,
+    duration: Option<String>,
+    bit_rate: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawStream {
+    #[serde(default)]
+    index: u32,
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    profile: Option<String>,
+    // Negative when the codec has no levels.
+    level: Option<i64>,
+    width: Option<u32>,
+    height: Option<u32>,
+    sample_aspect_ratio: Option<String>,
+    avg_frame_rate: Option<String>,
+    #[serde(default)]
+    closed_captions: u8,
+    sample_rate: Option<String>,
+    channels: Option<u32>,
+    start_time: Option<String>,
+    duration: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+    #[serde(default)]
+    side_data_list: Vec<RawSideData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawSideData {
+    // Counter-clockwise degrees of the display matrix.
+    rotation: Option<serde_json::Value>,
+}
+
+impl RawStream {
+    fn into_info(self) -> StreamInfo {
+        let kind = match self.codec_type.as_deref() {
+            Some("video") => StreamKind::Video,
+            Some("audio") => StreamKind::Audio,
+            Some("subtitle") => StreamKind::Subtitle,
+            Some("data") => StreamKind::Data,
+            Some("attachment") => StreamKind::Attachment,
+            _ => StreamKind::Other,
+        };
+        let rotation_deg = self.rotation_deg();
+        StreamInfo {
+            index: self.index,
+            kind,
+            codec_name: self.codec_name,
+            profile: self.profile,
+            level: self.level.and_then(|level| u32::try_from(level).ok()),
+            width: self.width,
+            height: self.height,
+            sample_aspect_ratio: self.sample_aspect_ratio.as_deref().and_then(sample_aspect),
+            rotation_deg,
+            closed_captions: self.closed_captions != 0,
+            frame_rate: self.avg_frame_rate.as_deref().and_then(ratio),
+            sample_rate: number(self.sample_rate.as_deref()),
+            channels: self.channels,
+            start_sec: number(self.start_time.as_deref()),
+            duration_sec: number(self.duration.as_deref()),
+            language: self.tags.get("language").cloned(),
+        }
+    }
+
+    fn rotation_deg(&self) -> u16 {
+        let matrix = self
+            .side_data_list
+            .iter()
+            .filter_map(|side| side.rotation.as_ref())
+            .find_map(|rotation| {
+                rotation
+                    .as_i64()
+                    .or_else(|| rotation.as_str().and_then(|raw| raw.trim().parse().ok()))
+            })
+            .map(|counter_clockwise: i64| -counter_clockwise);
+        let clockwise = matrix.or_else(|| number(self.tags.get("rotate").map(String::as_str)));
+        clockwise
+            .and_then(|degrees| u16::try_from(degrees.rem_euclid(360)).ok())
+            .unwrap_or(0)
+    }
+}
+
+// `1:1` and the `0:1` ffprobe prints for unknown both mean square pixels.
+fn sample_aspect(raw: &str) -> Option<(u32, u32)> {
+    let (num, den) = raw.split_once(':')?;
+    let (num, den): (u32, u32) = (num.parse().ok()?, den.parse().ok()?);
+    (num > 0 && den > 0 && num != den).then_some((num, den))
+}
+
+fn number<T: std::str::FromStr>(raw: Option<&str>) -> Option<T> {
+    raw.and_then(|raw| raw.trim().parse().ok())
+}
+
+fn ratio(raw: &str) -> Option<f32> {
+    let (num, den) = raw.split_once('/')?;
+    let (num, den): (f32, f32) = (num.parse().ok()?, den.parse().ok()?);
+    (den > 0.0 && num > 0.0).then(|| num / den)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROBE: &str = r#"{
+        "streams": [
+            {
+                "index": 0, "codec_name": "h264", "codec_type": "video",
+                "profile": "High", "level": 40,
+                "width": 1920, "height": 1080, "avg_frame_rate": "30000/1001",
+                "closed_captions": 1,
+                "start_time": "0.000000", "duration": "600.033333",
+                "tags": {"language": "und", "handler_name": "VideoHandler"}
+            },
+            {
+                "index": 1, "codec_name": "aac", "codec_type": "audio",
+                "profile": "LC", "level": -99,
+                "sample_rate": "48000", "channels": 2, "avg_frame_rate": "0/0",
+                "start_time": "0.021333", "tags": {"language": "por"}
+            },
+            {"index": 2, "codec_name": "mov_text", "codec_type": "subtitle"}
+        ],
+        "format": {
+            "format_name": "mov,mp4,m4a,3gp,3g2,mj2",
+            "duration": "600.064000",
+            "bit_rate": "4012345"
+        }
+    }"#;
+
+    #[test]
+    fn types_the_ffprobe_report() {
+        let info = ProbeInfo::from_json(PROBE).unwrap();
+
+        assert_eq!(info.bit_rate, Some(4_012_345));
+        assert!((info.duration_sec.unwrap() - 600.064).abs() < 1e-3);
+        let video = info.first(StreamKind::Video).unwrap();
+        assert_eq!((video.width, video.height), (Some(1920), Some(1080)));
+        assert!((video.frame_rate.unwrap() - 29.97).abs() < 1e-2);
+        assert_eq!(
+            (video.profile.as_deref(), video.level),
+            (Some("High"), Some(40))
+        );
+        assert!(video.closed_captions);
+        let audio = info.first(StreamKind::Audio).unwrap();
+        assert_eq!(audio.sample_rate, Some(48_000));
+        assert_eq!(audio.frame_rate, None);
+        assert_eq!(audio.level, None);
+        assert_eq!(audio.language.as_deref(), Some("por"));
+        assert!(!audio.closed_captions);
+        assert!(info.has(StreamKind::Subtitle));
+        assert!(!info.has(StreamKind::Data));
+
+        assert!(ProbeInfo::from_json("not json").is_err());
+    }
+
+    #[test]
+    fn display_size_applies_rotation_and_pixel_shape() {
+        let info = ProbeInfo::from_json(
+            r#"{"streams": [
+                {"codec_type": "video", "width": 1920, "height": 1080,
+                 "side_data_list": [{"side_data_type": "Display Matrix", "rotation": -90}]},
+                {"codec_type": "video", "width": 1280, "height": 720, "tags": {"rotate": "270"}},
+                {"codec_type": "video", "width": 720, "height": 576, "sample_aspect_ratio": "64:45"},
+                {"codec_type": "video", "width": 640, "height": 480, "sample_aspect_ratio": "0:1"}
+            ]}"#,
+        )
+        .unwrap();
+        let sizes: Vec<_> = info
+            .streams
+            .iter()
+            .map(|stream| (stream.rotation_deg, stream.display_size()))
+            .collect();
+
+        assert_eq!(
+            sizes,
+            [
+                (90, Some((1080, 1920))),
+                (270, Some((720, 1280))),
+                (0, Some((1024, 576))),
+                (0, Some((640, 480))),
+            ]
+        );
+    }
+}
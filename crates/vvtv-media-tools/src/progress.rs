@@ -0,0 +1,89 @@
+// One block of ffmpeg `-progress` output. Fields ffmpeg reports as `N/A` are
+// left out.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Progress {
+    pub frame: Option<u64>,
+    pub fps: Option<f32>,
+    pub out_time_sec: Option<f32>,
+    pub total_size_bytes: Option<u64>,
+    // Encoding speed relative to realtime.
+    pub speed: Option<f32>,
+    // Set on the last block ffmpeg writes.
+    pub done: bool,
+}
+
+// Collects `key=value` lines until the `progress=` line that closes a block.
+#[derive(Debug, Default)]
+pub struct ProgressParser {
+    current: Progress,
+}
+
+impl ProgressParser {
+    pub fn feed(&mut self, line: &str) -> Option<Progress> {
+        let (key, value) = line.trim().split_once('=')?;
+        let value = value.trim();
+        match key {
+            "frame" => self.current.frame = value.parse().ok(),
+            "fps" => self.current.fps = value.parse().ok(),
+            // Despite the name, `out_time_ms` is in microseconds as well.
+            "out_time_us" | "out_time_ms" => {
+                self.current.out_time_sec = value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|micros| *micros >= 0.0)
+                    .map(|micros| micros / 1_000_000.0);
+            }
+            "total_size" => self.current.total_size_bytes = value.parse().ok(),
+            "speed" => self.current.speed = value.trim_end_matches('x').trim().parse().ok(),
+            "progress" => {
+                let mut block = std::mem::take(&mut self.current);
+                block.done = value == "end";
+                return Some(block);
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_progress_blocks() {
+        let output = "\
+frame=240
+fps=59.94
+stream_0_0_q=28.0
+bitrate=1200.5kbits/s
+total_size=1500000
+out_time_us=8000000
+out_time_ms=8000000
+out_time=00:00:08.000000
+dup_frames=0
+drop_frames=0
+speed=2.01x
+progress=continue
+frame=300
+fps=N/A
+total_size=N/A
+out_time_us=10000000
+speed=N/A
+progress=end
+";
+        let mut parser = ProgressParser::default();
+        let blocks: Vec<_> = output.lines().filter_map(|l| parser.feed(l)).collect();
+
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].frame, Some(240));
+        assert_eq!(blocks[0].total_size_bytes, Some(1_500_000));
+        assert!((blocks[0].out_time_sec.unwrap() - 8.0).abs() < 1e-4);
+        assert!((blocks[0].speed.unwrap() - 2.01).abs() < 1e-4);
+        assert!(!blocks[0].done);
+        assert_eq!(blocks[1].fps, None);
+        assert_eq!(blocks[1].speed, None);
+        assert!((blocks[1].out_time_sec.unwrap() - 10.0).abs() < 1e-4);
+        assert!(blocks[1].done);
+    }
+}
@@ -0,0 +1,245 @@
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader, ErrorKind, Read};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::MediaError;
+use crate::progress::{Progress, ProgressParser};
+
+// How often a running process is checked against its deadline and
+// cancellation.
+const POLL: Duration = Duration::from_millis(50);
+
+// Wall-clock deadline and cancellation shared by every process started under
+// it, so several runs of one job draw from one budget.
+#[derive(Clone, Copy, Default)]
+pub struct RunLimits<'a> {
+    deadline: Option<(Instant, Duration)>,
+    cancelled: Option<&'a (dyn Fn() -> bool + Sync)>,
+}
+
+impl<'a> RunLimits<'a> {
+    #[must_use]
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    // The deadline starts counting now.
+    #[must_use]
+    pub fn timeout(limit: Duration) -> Self {
+        Self {
+            deadline: Some((Instant::now() + limit, limit)),
+            cancelled: None,
+        }
+    }
+
+    // `cancelled` is polled while a process runs; once it returns true the
+    // process is killed.
+    #[must_use]
+    pub fn cancelled_by(mut self, cancelled: &'a (dyn Fn() -> bool + Sync)) -> Self {
+        self.cancelled = Some(cancelled);
+        self
+    }
+
+    fn check(&self, tool: &'static str) -> Result<(), MediaError> {
+        if self.cancelled.is_some_and(|cancelled| cancelled()) {
+            return Err(MediaError::Cancelled(tool));
+        }
+        match self.deadline {
+            Some((deadline, limit)) if Instant::now() >= deadline => {
+                Err(MediaError::Timeout { tool, limit })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+pub(crate) struct Captured {
+    pub stdout: Vec<u8>,
+    pub stderr: String,
+}
+
+// Runs `tool` to completion with both pipes captured. With `on_progress`,
+// stdout is read as ffmpeg `-progress` blocks and handed over as they come
+// instead of being kept.
+pub(crate) fn execute(
+    tool: &'static str,
+    args: &[OsString],
+    limits: &RunLimits<'_>,
+    mut on_progress: Option<&mut dyn FnMut(&Progress)>,
+) -> Result<Captured, MediaError> {
+    limits.check(tool)?;
+    let mut child = match Command::new(tool)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            return Err(MediaError::MissingBinary(tool));
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let (progress_tx, progress_rx) = mpsc::channel();
+    let stdout = child.stdout.take().map(|pipe| {
+        if on_progress.is_some() {
+            thread::spawn(move || {
+                let mut parser = ProgressParser::default();
+                for line in BufReader::new(pipe).lines().map_while(Result::ok) {
+                    if let Some(progress) = parser.feed(&line) {
+                        let _ = progress_tx.send(progress);
+                    }
+                }
+                Vec::new()
+            })
+        } else {
+            drain(pipe)
+        }
+    });
+    let stderr = child.stderr.take().map(drain);
+
+    let status = loop {
+        if let Some(callback) = on_progress.as_mut() {
+            for progress in progress_rx.try_iter() {
+                callback(&progress);
+            }
+        }
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if let Err(err) = limits.check(tool) {
+            kill(&mut child);
+            return Err(err);
+        }
+        thread::sleep(POLL);
+    };
+    let captured = Captured {
+        stdout: stdout.and_then(|h| h.join().ok()).unwrap_or_default(),
+        stderr: stderr
+            .and_then(|h| h.join().ok())
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+            .unwrap_or_default(),
+    };
+    if let Some(callback) = on_progress.as_mut() {
+        for progress in progress_rx.try_iter() {
+            callback(&progress);
+        }
+    }
+    if !status.success() {
+        return Err(MediaError::from_exit(tool, status, &captured.stderr));
+    }
+    Ok(captured)
+}
+
+// Reads a pipe to the end on its own thread so a chatty process never blocks
+// on a full pipe while it is being polled.
+fn drain(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut out = Vec::new();
+        let _ = pipe.read_to_end(&mut out);
+        out
+    })
+}
+
+fn kill(child: &mut Child) {
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+    use crate::MediaErrorKind;
+
+    fn sh(script: &str) -> Vec<OsString> {
+        vec!["-c".into(), script.into()]
+    }
+
+    #[test]
+    fn kills_a_process_past_its_deadline() {
+        let started = Instant::now();
+        let err = execute(
+            "sh",
+            &sh("sleep 5"),
+            &RunLimits::timeout(Duration::from_millis(200)),
+            None,
+        )
+        .err()
+        .expect("sleep outlives the limit");
+
+        assert_eq!(err.kind(), MediaErrorKind::Timeout);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn cancellation_stops_the_process() {
+        let flag = AtomicBool::new(false);
+        let cancelled = || flag.load(Ordering::Relaxed);
+        let limits = RunLimits::timeout(Duration::from_secs(30)).cancelled_by(&cancelled);
+
+        let err = thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(100));
+                flag.store(true, Ordering::Relaxed);
+            });
+            execute("sh", &sh("sleep 5"), &limits, None).err()
+        })
+        .expect("cancelled");
+
+        assert_eq!(err.kind(), MediaErrorKind::Cancelled);
+    }
+
+    #[test]
+    fn captures_both_pipes_and_classifies_exits() {
+        let out = execute(
+            "sh",
+            &sh("echo out; echo err >&2"),
+            &RunLimits::none(),
+            None,
+        )
+        .ok()
+        .unwrap();
+        assert_eq!(out.stdout, b"out\n");
+        assert_eq!(out.stderr, "err\n");
+
+        let err = execute(
+            "sh",
+            &sh("echo 'in.mp4: Invalid data found when processing input' >&2; exit 1"),
+            &RunLimits::none(),
+            None,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), MediaErrorKind::CorruptInput);
+
+        let missing = execute("vvtv-no-such-tool", &[], &RunLimits::none(), None)
+            .err()
+            .unwrap();
+        assert_eq!(missing.kind(), MediaErrorKind::MissingBinary);
+    }
+
+    #[test]
+    fn streams_progress_blocks() {
+        let mut seen = Vec::new();
+        execute(
+            "sh",
+            &sh(
+                "printf 'frame=10\\nout_time_us=500000\\nprogress=continue\\n\
+                 frame=20\\nout_time_us=1000000\\nprogress=end\\n'",
+            ),
+            &RunLimits::none(),
+            Some(&mut |progress: &Progress| seen.push(progress.clone())),
+        )
+        .ok()
+        .unwrap();
+        assert_eq!(seen.len(), 2);
+        assert_eq!(seen[1].frame, Some(20));
+        assert!(seen[1].done);
+    }
+}
@@ -0,0 +1,117 @@
+use std::fmt::Write as _;
+use std::time::Duration;
+
+// One cue of a WebVTT file, with times in seconds on the file's timeline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    pub start_sec: f32,
+    pub end_sec: f32,
+    // Whatever follows the end time on the timing line, e.g. `align:start`.
+    pub settings: String,
+    pub text: String,
+}
+
+// The cues of a WebVTT file, as ffmpeg writes them. Cue identifiers and
+// `NOTE`, `STYLE` and `REGION` blocks are dropped.
+#[must_use]
+pub fn parse_cues(vtt: &str) -> Vec<Cue> {
+    let vtt = vtt.replace("\r\n", "\n");
+    vtt.split("\n\n")
+        .filter_map(|block| {
+            let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+            let (start, rest) = lines.next()?.split_once("-->")?;
+            let rest = rest.trim();
+            let (end, settings) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            Some(Cue {
+                start_sec: parse_timestamp(start.trim())?,
+                end_sec: parse_timestamp(end)?,
+                settings: settings.trim().to_string(),
+                text: lines.collect::<Vec<_>>().join("\n"),
+            })
+        })
+        .collect()
+}
+
+// A WebVTT file of `cues`; `header` lines go right after the signature.
+#[must_use]
+pub fn render_cues(header: Option<&str>, cues: &[Cue]) -> String {
+    let mut out = String::from("WEBVTT\n");
+    if let Some(header) = header {
+        out.push_str(header);
+        out.push('\n');
+    }
+    for cue in cues {
+        let _ = write!(
+            out,
+            "\n{} --> {}",
+            vtt_timestamp(cue.start_sec),
+            vtt_timestamp(cue.end_sec)
+        );
+        if !cue.settings.is_empty() {
+            let _ = write!(out, " {}", cue.settings);
+        }
+        let _ = writeln!(out, "\n{}", cue.text);
+    }
+    out
+}
+
+// `hh:mm:ss.mmm`; negative times clamp to zero.
+#[must_use]
+pub fn vtt_timestamp(secs: f32) -> String {
+    let ms = Duration::try_from_secs_f32(secs).map_or(0, |duration| duration.as_millis());
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+// `hh:mm:ss.mmm` or `mm:ss.mmm`.
+fn parse_timestamp(raw: &str) -> Option<f32> {
+    let (clock, millis) = raw.split_once('.')?;
+    let mut secs = 0u32;
+    for part in clock.split(':') {
+        secs = secs.checked_mul(60)?.checked_add(part.parse().ok()?)?;
+    }
+    let millis: u16 = millis.parse().ok()?;
+    Some((Duration::from_secs(secs.into()) + Duration::from_millis(millis.into())).as_secs_f32())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_the_cues_ffmpeg_writes() {
+        let vtt = "WEBVTT\r\n\r\nNOTE converted\r\n\r\n1\r\n00:00:01.500 --> 00:00:04.000 align:start\r\n\
+                   Ola\r\nmundo\r\n\r\n01:02.250 --> 01:05.000\r\n[music]\r\n";
+        let cues = parse_cues(vtt);
+        assert_eq!(
+            cues,
+            [
+                Cue {
+                    start_sec: 1.5,
+                    end_sec: 4.0,
+                    settings: "align:start".to_string(),
+                    text: "Ola\nmundo".to_string(),
+                },
+                Cue {
+                    start_sec: 62.25,
+                    end_sec: 65.0,
+                    settings: String::new(),
+                    text: "[music]".to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            render_cues(Some("X-TIMESTAMP-MAP=MPEGTS:0,LOCAL:00:00:00.000"), &cues),
+            "WEBVTT\nX-TIMESTAMP-MAP=MPEGTS:0,LOCAL:00:00:00.000\n\
+             \n00:00:01.500 --> 00:00:04.000 align:start\nOla\nmundo\n\
+             \n00:01:02.250 --> 00:01:05.000\n[music]\n"
+        );
+        assert_eq!(vtt_timestamp(3_725.25), "01:02:05.250");
+        assert_eq!(vtt_timestamp(-1.0), "00:00:00.000");
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::BTreeSet;
+
+use chrono::{DateTime, Duration, Utc};
+use vvtv_types::{AiringHistory, FreshnessPolicy, PlanItem};
+
+use crate::similarity::{ThemeModel, ThemeProfile};
+
+pub(crate) struct Freshness {
+    policy: FreshnessPolicy,
+    aired_sources: BTreeSet<String>,
+    recent: Vec<(DateTime<Utc>, ThemeProfile)>,
+}
+
+impl Freshness {
+    pub(crate) fn new(
+        policy: &FreshnessPolicy,
+        history: &AiringHistory,
+        model: &ThemeModel,
+    ) -> Self {
+        Self {
+            policy: policy.clone(),
+            aired_sources: history.aired_sources.clone(),
+            recent: history
+                .recent
+                .iter()
+                .map(|item| {
+                    (
+                        item.aired_at,
+                        model.profile_of(&item.theme_tags, &item.visual_features),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    // Exponential decay on the discovery age at the moment the item airs.
+    pub(crate) fn freshness(&self, plan: &PlanItem, at: DateTime<Utc>) -> f32 {
+        let age_hours = (at - plan.discovered_at)
+            .to_std()
+            .map_or(0.0, |age| age.as_secs_f32() / 3600.0);
+        self.policy.freshness_weight * 0.5_f32.powf(age_hours / self.policy.half_life_hours)
+    }
+
+    pub(crate) fn novelty(&self, plan: &PlanItem) -> f32 {
+        if self.aired_sources.contains(&plan.source_url) {
+            0.0
+        } else {
+            self.policy.novelty_bonus
+        }
+    }
+
+    // Scaled by the closest match among themes that aired inside the window.
+    pub(crate) fn recent_theme_penalty(&self, profile: &ThemeProfile, at: DateTime<Utc>) -> f32 {
+        let since = at - Duration::hours(i64::from(self.policy.recent_theme_window_hours));
+        let closest = self
+            .recent
+            .iter()
+            .filter(|(aired_at, _)| *aired_at >= since && *aired_at <= at)
+            .map(|(_, recent)| ThemeModel::similarity(recent, profile))
+            .fold(0.0_f32, f32::max);
+        self.policy.recent_theme_penalty * closest
+    }
+}
@@ -1,192 +1,571 @@
 use std::collections::{HashMap, HashSet, VecDeque};
 
-use vvtv_types::{OwnerCard, PlanItem, PlanState, PlannedDay};
+use chrono::{DateTime, Duration, Utc};
+use vvtv_types::{
+    AiringHistory, OwnerCard, PinTarget, PinnedSlot, PlacementRule, PlanItem, PlanState,
+    PlannedDay, RunContext, ScoreBreakdown, SourceLedger, adjacency_violation, window_violation,
+};
+
+mod freshness;
+mod optimizer;
+mod similarity;
+mod sources;
+
+use freshness::Freshness;
+use similarity::{ThemeModel, ThemeProfile};
+use sources::SourceLimits;
 
 pub struct Planner;
 
+struct PinOccurrence {
+    pin_id: String,
+    start_at: DateTime<Utc>,
+    plan: PlanItem,
+}
+
 impl Planner {
     #[must_use]
     pub fn build_day(owner_card: &OwnerCard, plans: Vec<PlanItem>) -> PlannedDay {
+        let run = RunContext::system();
+        Self::build_day_with_pins(
+            &run,
+            owner_card,
+            run.now(),
+            &owner_card.schedule_policy.pinned_slots,
+            &AiringHistory::default(),
+            &SourceLedger::default(),
+            plans,
+        )
+    }
+
+    #[must_use]
+    pub fn build_day_with_pins(
+        run: &RunContext,
+        owner_card: &OwnerCard,
+        day_start: DateTime<Utc>,
+        pins: &[PinnedSlot],
+        history: &AiringHistory,
+        ledger: &SourceLedger,
+        mut plans: Vec<PlanItem>,
+    ) -> PlannedDay {
+        // Sources still backing off in the ledger are left out; pins are not.
+        // Plans a pin targets stay until the pins are placed, and whatever
+        // the pins leave is filtered after.
+        let blocked = |plan: &PlanItem| ledger.is_blocked(&plan.source_url, run.now());
+        plans.retain(|plan| {
+            !blocked(plan)
+                || pins.iter().any(|pin| {
+                    matches!(&pin.target, PinTarget::Plan { plan_id } if *plan_id == plan.plan_id)
+                })
+        });
         let block_unique_target =
             usize::from(owner_card.editorial_profile.min_unique_themes_per_block).max(1);
         let max_consecutive_same_theme =
             usize::from(owner_card.editorial_profile.max_consecutive_same_theme).max(1);
-        let target_duration = owner_card.editorial_profile.target_avg_duration_sec;
-        let mut scored = plans;
-
-        // Higher score first, then fresher discoveries.
-        scored.sort_by(|a, b| {
-            b.policy_match_score
-                .total_cmp(&a.policy_match_score)
-                .then_with(|| b.discovered_at.cmp(&a.discovered_at))
-        });
+        let mut deduped = rank_and_dedupe(plans);
 
-        let mut deduped = Vec::new();
-        let mut seen_urls = HashSet::new();
-        let mut seen_titles = HashSet::new();
-        for plan in scored {
-            let title_key = normalize_key(&plan.title);
-            if seen_urls.insert(plan.source_url.clone()) && seen_titles.insert(title_key) {
-                deduped.push(plan);
-            }
+        let horizon_end = day_start
+            + Duration::hours(i64::from(owner_card.schedule_policy.planning_horizon_hours));
+        let (mut pending_pins, unresolved_pins) =
+            resolve_pins(run, owner_card, pins, day_start, horizon_end, &mut deduped);
+        deduped.retain(|plan| !blocked(plan));
+
+        let scorer = Scorer::new(owner_card, history);
+        let model = &scorer.model;
+        let rules = &owner_card.editorial_profile.placement_rules;
+        let limits = SourceLimits::new(owner_card, block_unique_target * 2);
+        let mut candidates: Vec<(PlanItem, ThemeProfile)> = deduped
+            .into_iter()
+            .map(|plan| {
+                let profile = model.profile(&plan);
+                (plan, profile)
+            })
+            .collect();
+
+        let mut grid = Grid::new(day_start, block_unique_target);
+        loop {
+            // Only consider fillers that end before the next pinned start.
+            let gap_sec = pending_pins
+                .front()
+                .map(|pin| (pin.start_at - grid.cursor).num_seconds().max(0));
+            let eligible = |(plan, profile): &(PlanItem, ThemeProfile), diverse: bool| {
+                let streak_blocked = grid.streak_count >= max_consecutive_same_theme
+                    && grid
+                        .recent_themes
+                        .back()
+                        .is_some_and(|last| model.too_similar(last, profile));
+                !(streak_blocked
+                    || gap_sec.is_some_and(|gap| i64::from(plan.duration_sec) > gap)
+                    || grid.misplaced(rules, plan)
+                    || !limits.allows(&grid, plan, diverse))
+            };
+
+            // Untagged items only air when no tagged candidate can take the slot;
+            // block source diversity is relaxed last.
+            let pick = [true, false].into_iter().find_map(|diverse| {
+                best_candidate(&candidates, &scorer, &grid, |c| {
+                    !c.1.is_untagged() && eligible(c, diverse)
+                })
+                .or_else(|| {
+                    best_candidate(&candidates, &scorer, &grid, |c| {
+                        c.1.is_untagged() && eligible(c, diverse)
+                    })
+                })
+            });
+
+            let Some(idx) = pick else {
+                // Nothing fits before the next pin: air the pin and keep filling after it.
+                let Some(pin) = pending_pins.pop_front() else {
+                    break;
+                };
+                let profile = model.profile(&pin.plan);
+                let mut plan = pin.plan;
+                plan.pin_id = Some(pin.pin_id);
+                grid.push(plan, profile, model, pin.start_at);
+                continue;
+            };
+
+            let (plan, profile) = candidates.remove(idx);
+            let start_at = grid.cursor;
+            grid.push(plan, profile, model, start_at);
         }
 
-        let mut buckets: HashMap<String, VecDeque<PlanItem>> = HashMap::new();
-        let mut generic_bucket = VecDeque::new();
-        for plan in deduped {
-            let theme = primary_theme(&plan);
-            if theme == "generic" {
-                generic_bucket.push_back(plan);
-            } else {
-                buckets.entry(theme).or_default().push_back(plan);
-            }
+        let mut leftovers: Vec<PlanItem> = candidates
+            .into_iter()
+            .map(|(plan, _)| to_reserved(plan))
+            .collect();
+
+        let constraints = optimizer::Constraints::new(owner_card, day_start, horizon_end);
+        let mut scheduled = grid.scheduled;
+        if limits.trim_to_share(&mut scheduled, &mut leftovers) {
+            scheduled = optimizer::retime(&constraints, scheduled);
+            leftovers.sort_by(|a, b| b.policy_match_score.total_cmp(&a.policy_match_score));
         }
 
-        let mut theme_order: Vec<String> = buckets.keys().cloned().collect();
-        theme_order.sort();
+        let mut optimizer_report = None;
+        if owner_card.schedule_policy.optimizer.enabled {
+            let outcome = optimizer::optimize(
+                owner_card,
+                &constraints,
+                &scorer.model,
+                &scorer.freshness,
+                scheduled,
+                leftovers,
+            );
+            scheduled = outcome.scheduled;
+            leftovers = outcome.pool;
+            optimizer_report = Some(outcome.report);
+        }
 
-        let mut scheduled = Vec::new();
-        let mut reserves = Vec::new();
-        let mut recent_themes: VecDeque<String> = VecDeque::new();
-        let mut streak_theme = String::new();
-        let mut streak_count = 0usize;
-        let mut total_duration = 0u64;
+        annotate_scores(&mut scheduled, &scorer, day_start, block_unique_target);
 
-        loop {
-            let mut pick_theme = None;
-            let mut best_score = f32::MIN;
+        PlannedDay {
+            scheduled,
+            reserves: leftovers,
+            unresolved_pins,
+            optimizer_report,
+        }
+    }
 
-            for theme in &theme_order {
-                let Some(candidate) = buckets.get(theme).and_then(|q| q.front()) else {
-                    continue;
-                };
-                if streak_theme == *theme && streak_count >= max_consecutive_same_theme {
-                    continue;
-                }
+    // Hands the horizon over to the freshly planned `day` of run `run_id`, so
+    // overlapping grids never book the same airtime twice. Scheduled and
+    // reserved plans of earlier runs are returned dropped as superseded. Plans
+    // of `day` that would start before the committed airtime ends become
+    // reserves, pinned ones are left out, and plans already stored in a later
+    // state are not planned again.
+    #[must_use]
+    pub fn supersede(previous: &[PlanItem], day: &mut PlannedDay, run_id: &str) -> Vec<PlanItem> {
+        let settled: HashSet<&str> = previous
+            .iter()
+            .filter(|plan| !matches!(plan.state, PlanState::Scheduled | PlanState::Reserved))
+            .map(|plan| plan.plan_id.as_str())
+            .collect();
+        let committed_until = previous
+            .iter()
+            .filter(|plan| plan.state == PlanState::Committed)
+            .filter_map(|plan| {
+                Some(plan.slot_start_at? + Duration::seconds(i64::from(plan.duration_sec)))
+            })
+            .max();
 
-                let candidate_score = fairness_score(
-                    candidate,
-                    theme,
-                    &recent_themes,
-                    block_unique_target,
-                    target_duration,
-                    total_duration,
-                    scheduled.len(),
-                );
-                if candidate_score > best_score {
-                    best_score = candidate_score;
-                    pick_theme = Some(theme.clone());
-                }
+        let mut scheduled = Vec::new();
+        for plan in std::mem::take(&mut day.scheduled) {
+            if settled.contains(plan.plan_id.as_str()) {
+                continue;
             }
+            let overlaps = committed_until
+                .zip(plan.slot_start_at)
+                .is_some_and(|(until, start)| start < until);
+            if !overlaps {
+                scheduled.push(plan);
+            } else if plan.pin_id.is_none() {
+                day.reserves.push(to_reserved(plan));
+            }
+        }
+        day.scheduled = scheduled;
+        day.reserves
+            .retain(|plan| !settled.contains(plan.plan_id.as_str()));
+
+        let planned: HashSet<&str> = day
+            .scheduled
+            .iter()
+            .chain(&day.reserves)
+            .map(|plan| plan.plan_id.as_str())
+            .collect();
+        previous
+            .iter()
+            .filter(|plan| matches!(plan.state, PlanState::Scheduled | PlanState::Reserved))
+            .filter(|plan| !planned.contains(plan.plan_id.as_str()))
+            .map(|plan| {
+                let mut plan = plan.clone();
+                plan.state = PlanState::Dropped;
+                plan.drop_reason = Some(format!("superseded: planning run {run_id}"));
+                plan
+            })
+            .collect()
+    }
+}
+
+fn rank_and_dedupe(plans: Vec<PlanItem>) -> Vec<PlanItem> {
+    let mut scored = plans;
+
+    // Higher score first, then fresher discoveries.
+    scored.sort_by(|a, b| {
+        b.policy_match_score
+            .total_cmp(&a.policy_match_score)
+            .then_with(|| b.discovered_at.cmp(&a.discovered_at))
+    });
+
+    let mut deduped = Vec::new();
+    let mut seen_urls = HashSet::new();
+    let mut seen_titles = HashSet::new();
+    for plan in scored {
+        let title_key = normalize_key(&plan.title);
+        if seen_urls.insert(plan.source_url.clone()) && seen_titles.insert(title_key) {
+            deduped.push(plan);
+        }
+    }
+    deduped
+}
+
+fn resolve_pins(
+    run: &RunContext,
+    owner_card: &OwnerCard,
+    pins: &[PinnedSlot],
+    day_start: DateTime<Utc>,
+    horizon_end: DateTime<Utc>,
+    candidates: &mut Vec<PlanItem>,
+) -> (VecDeque<PinOccurrence>, Vec<String>) {
+    let mut occurrences = Vec::new();
+    let mut unresolved = Vec::new();
 
-            let Some(theme) = pick_theme else {
-                break;
+    for pin in pins {
+        // A recurring pin of a plan airs the plan itself first, then copies
+        // of it under per-occurrence ids.
+        let mut pinned_plan = None;
+        for start_at in pin.occurrences_between(day_start, horizon_end) {
+            let plan = match &pin.target {
+                PinTarget::Plan { plan_id } => {
+                    if let Some(idx) = candidates.iter().position(|p| p.plan_id == *plan_id) {
+                        let plan = candidates.remove(idx);
+                        pinned_plan = Some(plan.clone());
+                        Some(plan)
+                    } else {
+                        pinned_plan.clone().map(|plan| PlanItem {
+                            plan_id: occurrence_id(pin, start_at),
+                            ..plan
+                        })
+                    }
+                }
+                PinTarget::Source {
+                    source_url,
+                    title,
+                    duration_sec,
+                } => candidates
+                    .iter()
+                    .position(|p| p.source_url == *source_url)
+                    .map(|idx| candidates.remove(idx))
+                    .or_else(|| {
+                        pinned_source_plan(
+                            run,
+                            owner_card,
+                            pin,
+                            source_url,
+                            title,
+                            *duration_sec,
+                            start_at,
+                        )
+                    }),
             };
 
-            if let Some(plan) = buckets.get_mut(&theme).and_then(VecDeque::pop_front) {
-                total_duration += u64::from(plan.duration_sec);
-                push_scheduled(
+            match plan {
+                Some(plan) => occurrences.push(PinOccurrence {
+                    pin_id: pin.pin_id.clone(),
+                    start_at,
                     plan,
-                    &theme,
-                    &mut scheduled,
-                    &mut recent_themes,
-                    block_unique_target,
-                    &mut streak_theme,
-                    &mut streak_count,
-                );
+                }),
+                None => unresolved.push(pin.pin_id.clone()),
             }
         }
+    }
 
-        // Consume generic bucket and leftovers as reserves.
-        reserves.extend(generic_bucket.into_iter().map(to_reserved));
-        for theme in theme_order {
-            if let Some(mut bucket) = buckets.remove(&theme) {
-                reserves.extend(bucket.drain(..).map(to_reserved));
-            }
+    occurrences.sort_by_key(|pin| pin.start_at);
+
+    // Overlapping pins cannot both air; the earlier one wins.
+    let mut accepted: VecDeque<PinOccurrence> = VecDeque::new();
+    for pin in occurrences {
+        let overlaps = accepted.back().is_some_and(|prev| {
+            prev.start_at + Duration::seconds(i64::from(prev.plan.duration_sec)) > pin.start_at
+        });
+        if overlaps {
+            unresolved.push(pin.pin_id);
+        } else {
+            accepted.push_back(pin);
         }
+    }
 
-        PlannedDay {
-            scheduled,
-            reserves,
+    (accepted, unresolved)
+}
+
+fn occurrence_id(pin: &PinnedSlot, start_at: DateTime<Utc>) -> String {
+    format!("pin-{}-{}", pin.pin_id, start_at.timestamp())
+}
+
+// A plan for a pinned source nobody discovered. Pins are held to the same
+// domain allowlist and blacklist as discovered sources; `None` when the
+// source is not allowed.
+fn pinned_source_plan(
+    run: &RunContext,
+    owner_card: &OwnerCard,
+    pin: &PinnedSlot,
+    source_url: &str,
+    title: &str,
+    duration_sec: u32,
+    start_at: DateTime<Utc>,
+) -> Option<PlanItem> {
+    let source_domain = source_url
+        .split("//")
+        .nth(1)
+        .and_then(|rest| rest.split('/').next())
+        .unwrap_or("unknown-domain")
+        .to_string();
+    if !owner_card.search_policy.allows_domain(&source_domain) {
+        return None;
+    }
+    Some(PlanItem {
+        plan_id: occurrence_id(pin, start_at),
+        source_url: source_url.to_string(),
+        source_domain,
+        discovered_at: run.now(),
+        title: title.to_string(),
+        duration_sec,
+        theme_tags: vec![],
+        visual_features: vec![],
+        quality_signals: vec![],
+        selection_reason: format!("pinned pin_id={}", pin.pin_id),
+        policy_match_score: 1.0,
+        state: PlanState::Candidate,
+        slot_start_at: None,
+        pin_id: None,
+        score_breakdown: None,
+        drop_reason: None,
+    })
+}
+
+struct Scorer {
+    model: ThemeModel,
+    freshness: Freshness,
+    target_duration: u32,
+}
+
+impl Scorer {
+    fn new(owner_card: &OwnerCard, history: &AiringHistory) -> Self {
+        let profile = &owner_card.editorial_profile;
+        let model = ThemeModel::new(&profile.theme_policy);
+        let freshness = Freshness::new(&profile.freshness_policy, history, &model);
+        Self {
+            model,
+            freshness,
+            target_duration: profile.target_avg_duration_sec,
+        }
+    }
+}
+
+fn best_candidate(
+    candidates: &[(PlanItem, ThemeProfile)],
+    scorer: &Scorer,
+    grid: &Grid,
+    accept: impl Fn(&(PlanItem, ThemeProfile)) -> bool,
+) -> Option<usize> {
+    let mut pick = None;
+    let mut best_score = f32::MIN;
+    for (idx, candidate) in candidates.iter().enumerate() {
+        if !accept(candidate) {
+            continue;
+        }
+        let candidate_score = fairness_score(&candidate.0, &candidate.1, grid, scorer).total();
+        if candidate_score > best_score {
+            best_score = candidate_score;
+            pick = Some(idx);
         }
     }
+    pick
+}
+
+// Replays the final grid so every filler carries the breakdown of the slot it
+// actually airs in, including after trimming or optimization.
+fn annotate_scores(
+    scheduled: &mut [PlanItem],
+    scorer: &Scorer,
+    day_start: DateTime<Utc>,
+    block_unique_target: usize,
+) {
+    let mut grid = Grid::new(day_start, block_unique_target);
+    for plan in scheduled.iter_mut() {
+        let profile = scorer.model.profile(plan);
+        let start_at = plan.slot_start_at.unwrap_or(grid.cursor);
+        grid.cursor = start_at;
+        if plan.pin_id.is_none() {
+            plan.score_breakdown = Some(fairness_score(plan, &profile, &grid, scorer));
+        }
+        grid.push(plan.clone(), profile, &scorer.model, start_at);
+    }
 }
 
 fn fairness_score(
     candidate: &PlanItem,
-    theme: &str,
-    recent_themes: &VecDeque<String>,
-    block_unique_target: usize,
-    target_duration: u32,
-    total_duration: u64,
-    scheduled_count: usize,
-) -> f32 {
-    let mut score = candidate.policy_match_score * 100.0;
+    profile: &ThemeProfile,
+    grid: &Grid,
+    scorer: &Scorer,
+) -> ScoreBreakdown {
+    let model = &scorer.model;
+    let target_duration = scorer.target_duration;
+    let total_duration = grid.total_duration;
+    let scheduled_count = grid.scheduled.len();
+    let mut breakdown = ScoreBreakdown {
+        policy_match: candidate.policy_match_score * 100.0,
+        ..ScoreBreakdown::default()
+    };
 
     // Encourage theme diversity inside a sliding window.
-    let unique_recent = recent_themes.iter().collect::<HashSet<_>>().len();
-    let seen_in_recent = recent_themes.iter().any(|t| t == theme);
-    if unique_recent < block_unique_target && !seen_in_recent {
-        score += 30.0;
+    let unique_recent = model.distinct(&grid.recent_themes);
+    let seen_in_recent = profile.is_untagged()
+        || grid
+            .recent_themes
+            .iter()
+            .any(|recent| model.too_similar(recent, profile));
+    if unique_recent < grid.block_unique_target && !seen_in_recent {
+        breakdown.theme_diversity = 30.0;
     }
 
     // Keep running duration close to target average.
+    let target = f64::from(target_duration);
     let current_avg = if scheduled_count == 0 {
-        target_duration as f32
+        target
     } else {
-        total_duration as f32 / scheduled_count as f32
+        exact_f64(total_duration) / exact_f64(scheduled_count)
     };
-    let next_avg = (total_duration + u64::from(candidate.duration_sec)) as f32
-        / (scheduled_count as f32 + 1.0);
-    let current_diff = (current_avg - target_duration as f32).abs();
-    let next_diff = (next_avg - target_duration as f32).abs();
-    if next_diff < current_diff {
-        score += 10.0;
+    let next_avg = exact_f64(total_duration + u64::from(candidate.duration_sec))
+        / (exact_f64(scheduled_count) + 1.0);
+    let current_diff = (current_avg - target).abs();
+    let next_diff = (next_avg - target).abs();
+    // The penalty is capped at 25 points, so narrowing it to f32 only rounds.
+    #[allow(clippy::cast_possible_truncation)]
+    let duration_fit = if next_diff < current_diff {
+        10.0
     } else {
-        score -= (next_diff - current_diff).min(25.0);
-    }
+        -(next_diff - current_diff).min(25.0) as f32
+    };
+    breakdown.duration_fit = duration_fit;
+
+    // Prefer fresh discoveries and unseen sources; back off themes that just aired.
+    breakdown.freshness = scorer.freshness.freshness(candidate, grid.cursor);
+    breakdown.novelty = scorer.freshness.novelty(candidate);
+    breakdown.recent_theme_penalty = scorer.freshness.recent_theme_penalty(profile, grid.cursor);
 
-    score
+    breakdown
 }
 
-fn push_scheduled(
-    mut plan: PlanItem,
-    theme: &str,
-    scheduled: &mut Vec<PlanItem>,
-    recent_themes: &mut VecDeque<String>,
+struct Grid {
+    scheduled: Vec<PlanItem>,
+    recent_themes: VecDeque<ThemeProfile>,
+    streak_count: usize,
+    total_duration: u64,
+    domain_seconds: HashMap<String, u64>,
+    cursor: DateTime<Utc>,
     block_unique_target: usize,
-    streak_theme: &mut String,
-    streak_count: &mut usize,
-) {
-    plan.state = PlanState::Scheduled;
-    scheduled.push(plan);
+}
 
-    if *streak_theme == theme {
-        *streak_count += 1;
-    } else {
-        *streak_theme = theme.to_string();
-        *streak_count = 1;
+impl Grid {
+    fn new(day_start: DateTime<Utc>, block_unique_target: usize) -> Self {
+        Self {
+            scheduled: Vec::new(),
+            recent_themes: VecDeque::new(),
+            streak_count: 0,
+            total_duration: 0,
+            domain_seconds: HashMap::new(),
+            cursor: day_start,
+            block_unique_target,
+        }
     }
 
-    recent_themes.push_back(theme.to_string());
-    if recent_themes.len() > block_unique_target * 2 {
-        recent_themes.pop_front();
+    fn misplaced(&self, rules: &[PlacementRule], plan: &PlanItem) -> bool {
+        if rules.is_empty() {
+            return false;
+        }
+        let tags = plan.content_tags();
+        window_violation(rules, &tags, self.cursor).is_some()
+            || self.scheduled.last().is_some_and(|prev| {
+                adjacency_violation(rules, &prev.content_tags(), &tags).is_some()
+            })
+    }
+
+    fn push(
+        &mut self,
+        mut plan: PlanItem,
+        profile: ThemeProfile,
+        model: &ThemeModel,
+        start_at: DateTime<Utc>,
+    ) {
+        plan.state = PlanState::Scheduled;
+        plan.slot_start_at = Some(start_at);
+        self.cursor = start_at + Duration::seconds(i64::from(plan.duration_sec));
+        self.total_duration += u64::from(plan.duration_sec);
+        *self
+            .domain_seconds
+            .entry(plan.source_domain.clone())
+            .or_default() += u64::from(plan.duration_sec);
+        self.scheduled.push(plan);
+
+        let continues_streak = self
+            .recent_themes
+            .back()
+            .is_some_and(|last| model.too_similar(last, &profile));
+        self.streak_count = if continues_streak {
+            self.streak_count + 1
+        } else {
+            1
+        };
+
+        self.recent_themes.push_back(profile);
+        if self.recent_themes.len() > self.block_unique_target * 2 {
+            self.recent_themes.pop_front();
+        }
     }
 }
 
+// Counts and second totals as f64, for every such conversion in the planner.
+// Values past `u32::MAX` saturate there instead of failing; grids and horizons
+// stay far below it, so in practice the conversion is exact.
+fn exact_f64(value: impl TryInto<u32>) -> f64 {
+    value.try_into().map_or(f64::from(u32::MAX), f64::from)
+}
+
 fn to_reserved(mut plan: PlanItem) -> PlanItem {
     plan.state = PlanState::Reserved;
     plan
 }
 
-fn primary_theme(plan: &PlanItem) -> String {
-    plan.theme_tags
-        .iter()
-        .find(|theme| !theme.trim().is_empty())
-        .cloned()
-        .unwrap_or_else(|| "generic".to_string())
-}
-
 fn normalize_key(value: &str) -> String {
     value
         .trim()
@@ -198,10 +577,12 @@ fn normalize_key(value: &str) -> String {
 
 #[cfg(test)]
 mod tests {
-    use chrono::Utc;
+    use chrono::{Duration, TimeZone, Utc};
     use vvtv_types::{
-        AutotunePolicy, CuratorPolicy, EditorialProfile, MusicPolicy, OwnerCard, PlanItem,
-        QualityPolicy, SafetyPolicy, SchedulePolicy, SearchPolicy,
+        AiredItem, AutotunePolicy, BrokenSource, CuratorPolicy, EditorialProfile, FetchPolicy,
+        FreshnessPolicy, MusicPolicy, OptimizerPolicy, OwnerCard, PinRecurrence, PinTarget,
+        PinnedSlot, PlacementConstraint, PlacementRule, PlanItem, PrepPolicy, QualityPolicy,
+        SafetyPolicy, SchedulePolicy, SearchPolicy, StoragePolicy, ThemePolicy,
     };
 
     use super::*;
@@ -253,6 +634,437 @@ mod tests {
         assert_eq!(day.scheduled.len() + day.reserves.len(), 2);
     }
 
+    #[test]
+    fn planner_places_pins_and_fills_around_them() {
+        let card = sample_card();
+        let day_start = Utc::now();
+        let pin_at = day_start + Duration::seconds(2000);
+        let pins = vec![PinnedSlot {
+            pin_id: "prime-time".to_string(),
+            target: PinTarget::Plan {
+                plan_id: "pinned".to_string(),
+            },
+            start_at: pin_at,
+            recurrence: PinRecurrence::Once,
+        }];
+        let plans = vec![
+            sample_plan("a", "theme-a", 0.95, 900),
+            sample_plan("b", "theme-b", 0.93, 900),
+            sample_plan("c", "theme-c", 0.91, 900),
+            sample_plan("pinned", "theme-d", 0.10, 600),
+        ];
+
+        let day = Planner::build_day_with_pins(
+            &RunContext::system(),
+            &card,
+            day_start,
+            &pins,
+            &AiringHistory::default(),
+            &SourceLedger::default(),
+            plans,
+        );
+        let pinned = day
+            .scheduled
+            .iter()
+            .find(|p| p.plan_id == "pinned")
+            .expect("pinned plan scheduled");
+        assert_eq!(pinned.slot_start_at, Some(pin_at));
+        assert_eq!(pinned.pin_id.as_deref(), Some("prime-time"));
+        assert!(day.unresolved_pins.is_empty());
+
+        // Fillers never overlap the pinned slot.
+        for plan in day.scheduled.iter().filter(|p| p.pin_id.is_none()) {
+            let start = plan.slot_start_at.expect("filler has a slot");
+            let end = start + Duration::seconds(i64::from(plan.duration_sec));
+            assert!(end <= pin_at || start >= pin_at + Duration::seconds(600));
+        }
+    }
+
+    #[test]
+    fn planner_reports_unresolved_pins() {
+        let card = sample_card();
+        let pins = vec![PinnedSlot {
+            pin_id: "missing".to_string(),
+            target: PinTarget::Plan {
+                plan_id: "nope".to_string(),
+            },
+            start_at: Utc::now() + Duration::hours(1),
+            recurrence: PinRecurrence::Once,
+        }];
+
+        let day = Planner::build_day_with_pins(
+            &RunContext::system(),
+            &card,
+            Utc::now(),
+            &pins,
+            &AiringHistory::default(),
+            &SourceLedger::default(),
+            vec![sample_plan("a", "theme-a", 0.9, 900)],
+        );
+        assert_eq!(day.unresolved_pins, vec!["missing".to_string()]);
+    }
+
+    #[test]
+    fn planner_repeats_recurring_plan_pins_and_vets_pinned_sources() {
+        let mut card = sample_card();
+        card.schedule_policy.planning_horizon_hours = 48;
+        let day_start = Utc.with_ymd_and_hms(2026, 3, 1, 0, 0, 0).unwrap();
+        let pins = vec![
+            PinnedSlot {
+                pin_id: "news".to_string(),
+                target: PinTarget::Plan {
+                    plan_id: "pinned".to_string(),
+                },
+                start_at: day_start + Duration::hours(20),
+                recurrence: PinRecurrence::Daily,
+            },
+            PinnedSlot {
+                pin_id: "lookalike".to_string(),
+                target: PinTarget::Source {
+                    source_url: "https://evilexample.com/v/1".to_string(),
+                    title: "Lookalike".to_string(),
+                    duration_sec: 600,
+                },
+                start_at: day_start + Duration::hours(2),
+                recurrence: PinRecurrence::Once,
+            },
+        ];
+        let run = RunContext::seeded(7, day_start);
+
+        let day = Planner::build_day_with_pins(
+            &run,
+            &card,
+            day_start,
+            &pins,
+            &AiringHistory::default(),
+            &SourceLedger::default(),
+            vec![sample_plan("pinned", "theme-a", 0.5, 600)],
+        );
+        let news: Vec<_> = day
+            .scheduled
+            .iter()
+            .filter(|p| p.pin_id.as_deref() == Some("news"))
+            .map(|p| (p.plan_id.as_str(), p.slot_start_at))
+            .collect();
+        let second = day_start + Duration::hours(44);
+        let second_id = format!("pin-news-{}", second.timestamp());
+        assert_eq!(
+            news,
+            [
+                ("pinned", Some(day_start + Duration::hours(20))),
+                (second_id.as_str(), Some(second)),
+            ]
+        );
+        assert_eq!(day.unresolved_pins, vec!["lookalike".to_string()]);
+    }
+
+    #[test]
+    fn planner_treats_synonyms_as_the_same_theme() {
+        let mut card = sample_card();
+        card.editorial_profile.theme_policy.synonyms.insert(
+            "noir".to_string(),
+            vec!["film-noir".to_string(), "Film Noir".to_string()],
+        );
+        let plans = vec![
+            sample_plan("a", "noir", 0.95, 900),
+            sample_plan("b", "film-noir", 0.94, 900),
+            sample_plan("c", "Film Noir", 0.93, 900),
+            sample_plan("d", "comedy", 0.50, 900),
+        ];
+
+        let day = Planner::build_day(&card, plans);
+        assert_eq!(day.scheduled.len(), 4);
+        for window in day.scheduled.windows(3) {
+            assert!(
+                window.iter().any(|p| p.plan_id == "d"),
+                "three synonymous themes aired back to back"
+            );
+        }
+    }
+
+    #[test]
+    fn planner_airs_untagged_items_when_needed() {
+        let card = sample_card();
+        let plans = vec![
+            sample_plan("a", "theme-a", 0.95, 900),
+            sample_plan("b", "theme-a", 0.94, 900),
+            sample_plan("c", "theme-a", 0.93, 900),
+            PlanItem {
+                theme_tags: vec![],
+                ..sample_plan("u", "", 0.40, 900)
+            },
+        ];
+
+        let day = Planner::build_day(&card, plans);
+        let order: Vec<_> = day.scheduled.iter().map(|p| p.plan_id.as_str()).collect();
+        assert_eq!(order, vec!["a", "b", "u", "c"]);
+    }
+
+    #[test]
+    fn planner_keeps_window_restricted_tags_out_of_daytime() {
+        let mut card = sample_card();
+        card.editorial_profile.placement_rules = vec![PlacementRule {
+            rule_id: "late".to_string(),
+            tag: "horror".to_string(),
+            constraint: PlacementConstraint::Window {
+                from: "23:00".to_string(),
+                until: "05:00".to_string(),
+            },
+        }];
+        let noon = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        let plans = vec![
+            sample_plan("h", "horror", 0.99, 900),
+            sample_plan("a", "theme-a", 0.50, 900),
+        ];
+
+        let day = Planner::build_day_with_pins(
+            &RunContext::system(),
+            &card,
+            noon,
+            &[],
+            &AiringHistory::default(),
+            &SourceLedger::default(),
+            plans,
+        );
+        let scheduled: Vec<_> = day.scheduled.iter().map(|p| p.plan_id.as_str()).collect();
+        assert_eq!(scheduled, vec!["a"]);
+        assert_eq!(day.reserves[0].plan_id, "h");
+    }
+
+    #[test]
+    fn planner_caps_domain_share_and_mixes_sources() {
+        let mut card = sample_card();
+        card.search_policy.max_domain_share = Some(0.5);
+        card.search_policy.min_distinct_sources_per_block = 2;
+        let from = |domain: &str, id: &str, theme: &str, score: f32| PlanItem {
+            source_url: format!("https://{domain}/{id}"),
+            source_domain: domain.to_string(),
+            ..sample_plan(id, theme, score, 900)
+        };
+        let plans = vec![
+            from("big.com", "a1", "theme-a", 0.99),
+            from("big.com", "a2", "theme-b", 0.98),
+            from("big.com", "a3", "theme-c", 0.97),
+            from("big.com", "a4", "theme-d", 0.96),
+            from("big.com", "a5", "theme-e", 0.95),
+            from("small.com", "b1", "theme-f", 0.40),
+            from("small.com", "b2", "theme-g", 0.39),
+        ];
+
+        let day = Planner::build_day(&card, plans);
+        let big = day
+            .scheduled
+            .iter()
+            .filter(|p| p.source_domain == "big.com")
+            .count();
+        let small = day.scheduled.len() - big;
+        assert_eq!(small, 2);
+        assert!(big <= small, "big.com exceeded its share: {big} vs {small}");
+        assert_eq!(day.scheduled.len() + day.reserves.len(), 7);
+
+        let block = &day.scheduled[..day.scheduled.len().min(6)];
+        assert!(block.iter().any(|p| p.source_domain == "small.com"));
+    }
+
+    #[test]
+    fn planner_scores_freshness_novelty_and_recent_themes() {
+        let card = sample_card();
+        let noon = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        let mut history = AiringHistory::default();
+        history
+            .aired_sources
+            .insert("https://example.com/aired".to_string());
+        history.recent.push(AiredItem {
+            aired_at: noon - Duration::hours(1),
+            theme_tags: vec!["theme-a".to_string()],
+            visual_features: vec![],
+        });
+        let plans = vec![
+            PlanItem {
+                discovered_at: noon,
+                ..sample_plan("recent-theme", "theme-a", 0.8, 900)
+            },
+            PlanItem {
+                discovered_at: noon - Duration::days(10),
+                ..sample_plan("stale", "theme-b", 0.8, 900)
+            },
+            PlanItem {
+                discovered_at: noon,
+                source_url: "https://example.com/aired".to_string(),
+                ..sample_plan("aired", "theme-c", 0.8, 900)
+            },
+        ];
+
+        let day = Planner::build_day_with_pins(
+            &RunContext::system(),
+            &card,
+            noon,
+            &[],
+            &history,
+            &SourceLedger::default(),
+            plans,
+        );
+        let breakdown = |id: &str| {
+            day.scheduled
+                .iter()
+                .find(|p| p.plan_id == id)
+                .and_then(|p| p.score_breakdown)
+                .expect("scheduled plan has a breakdown")
+        };
+        assert_eq!(day.scheduled[0].plan_id, "aired");
+        assert!(breakdown("recent-theme").recent_theme_penalty > 0.0);
+        assert!(breakdown("stale").freshness < breakdown("aired").freshness);
+        assert!(breakdown("aired").novelty.abs() < f32::EPSILON);
+        assert!(breakdown("stale").novelty > 0.0);
+    }
+
+    #[test]
+    fn optimizer_reports_objective_against_greedy_baseline() {
+        let mut card = sample_card();
+        card.schedule_policy.optimizer = OptimizerPolicy {
+            enabled: true,
+            max_iterations: 3_000,
+            evaluation_budget: 1_000_000,
+            seed: 7,
+        };
+        // Greedy drains the diverse themes first and ends on a forced theme-a streak.
+        let plans = vec![
+            sample_plan("a1", "theme-a", 0.95, 900),
+            sample_plan("a2", "theme-a", 0.94, 900),
+            sample_plan("a3", "theme-a", 0.93, 900),
+            sample_plan("a4", "theme-a", 0.92, 900),
+            sample_plan("b1", "theme-b", 0.60, 900),
+            sample_plan("c1", "theme-c", 0.59, 900),
+        ];
+
+        let day = Planner::build_day(&card, plans);
+        let report = day.optimizer_report.expect("optimizer report");
+        assert!(report.optimized_objective >= report.greedy_objective);
+        assert!(report.optimized_violations <= report.greedy_violations);
+        assert!(report.iterations > 0);
+
+        let mut streak = 1;
+        for window in day.scheduled.windows(2) {
+            if window[0].theme_tags == window[1].theme_tags {
+                streak += 1;
+                assert!(streak <= 2, "optimized grid exceeded theme streak limit");
+            } else {
+                streak = 1;
+            }
+        }
+        assert_eq!(
+            day.scheduled.len() + day.reserves.len(),
+            6,
+            "optimizer must not lose or duplicate plans"
+        );
+    }
+
+    #[test]
+    fn sources_backing_off_in_the_ledger_are_not_planned() {
+        let card = sample_card();
+        let noon = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        let blocked = sample_plan("blocked", "theme-a", 0.99, 900);
+        let mut ledger = SourceLedger::default();
+        ledger.entries.insert(
+            blocked.source_url.clone(),
+            BrokenSource::record_failure(
+                None,
+                &blocked.source_url,
+                "timeout",
+                false,
+                noon - Duration::minutes(10),
+                &FetchPolicy::default(),
+            ),
+        );
+        let plans = vec![blocked, sample_plan("ok", "theme-b", 0.5, 900)];
+
+        let day = Planner::build_day_with_pins(
+            &RunContext::seeded(1, noon),
+            &card,
+            noon,
+            &[],
+            &AiringHistory::default(),
+            &ledger,
+            plans.clone(),
+        );
+        let planned: Vec<_> = day
+            .scheduled
+            .iter()
+            .chain(&day.reserves)
+            .map(|p| p.plan_id.as_str())
+            .collect();
+        assert_eq!(planned, ["ok"]);
+
+        let later = Planner::build_day_with_pins(
+            &RunContext::seeded(1, noon + Duration::hours(1)),
+            &card,
+            noon,
+            &[],
+            &AiringHistory::default(),
+            &ledger,
+            plans,
+        );
+        assert_eq!(later.scheduled.len() + later.reserves.len(), 2);
+    }
+
+    #[test]
+    fn plan_pins_survive_a_ledgered_source() {
+        let card = sample_card();
+        let noon = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        let pinned = sample_plan("pinned", "theme-a", 0.5, 600);
+        let unused = sample_plan("unused", "theme-b", 0.9, 600);
+        let mut ledger = SourceLedger::default();
+        for plan in [&pinned, &unused] {
+            ledger.entries.insert(
+                plan.source_url.clone(),
+                BrokenSource::record_failure(
+                    None,
+                    &plan.source_url,
+                    "timeout",
+                    false,
+                    noon - Duration::minutes(10),
+                    &FetchPolicy::default(),
+                ),
+            );
+        }
+        // The second pin starts past the horizon, so its plan is never placed.
+        let pin = |pin_id: &str, plan_id: &str, start_at| PinnedSlot {
+            pin_id: pin_id.to_string(),
+            target: PinTarget::Plan {
+                plan_id: plan_id.to_string(),
+            },
+            start_at,
+            recurrence: PinRecurrence::Once,
+        };
+        let pins = vec![
+            pin("prime-time", "pinned", noon + Duration::hours(1)),
+            pin("next-week", "unused", noon + Duration::days(7)),
+        ];
+
+        let day = Planner::build_day_with_pins(
+            &RunContext::seeded(1, noon),
+            &card,
+            noon,
+            &pins,
+            &AiringHistory::default(),
+            &ledger,
+            vec![pinned, unused, sample_plan("ok", "theme-c", 0.5, 900)],
+        );
+        let placed = day
+            .scheduled
+            .iter()
+            .find(|p| p.plan_id == "pinned")
+            .expect("pinned plan scheduled");
+        assert_eq!(placed.pin_id.as_deref(), Some("prime-time"));
+        assert!(day.unresolved_pins.is_empty());
+        assert!(
+            day.scheduled
+                .iter()
+                .chain(&day.reserves)
+                .all(|p| p.plan_id != "unused")
+        );
+    }
+
     fn sample_card() -> OwnerCard {
         OwnerCard {
             schema_version: 1,
@@ -260,11 +1072,16 @@ mod tests {
                 target_avg_duration_sec: 900,
                 max_consecutive_same_theme: 2,
                 min_unique_themes_per_block: 3,
+                theme_policy: ThemePolicy::default(),
+                placement_rules: vec![],
+                freshness_policy: FreshnessPolicy::default(),
             },
             search_policy: SearchPolicy {
                 allowlist_domains: vec!["example.com".to_string()],
                 blacklist_domains: vec![],
                 blocked_keywords: vec![],
+                max_domain_share: None,
+                min_distinct_sources_per_block: 0,
             },
             schedule_policy: SchedulePolicy {
                 planning_horizon_hours: 24,
@@ -272,11 +1089,14 @@ mod tests {
                 commit_interval_minutes: 30,
                 buffer_target_minutes: 120,
                 buffer_critical_minutes: 20,
+                pinned_slots: vec![],
+                optimizer: OptimizerPolicy::default(),
             },
             quality_policy: QualityPolicy {
                 min_resolution_height: 720,
                 target_audio_lufs: -16.0,
                 max_audio_deviation_lufs: 2.5,
+                ..QualityPolicy::default()
             },
             music_policy: MusicPolicy {
                 preferred_moods: vec![],
@@ -295,6 +1115,9 @@ mod tests {
                 max_daily_adjustment_pct: 10.0,
                 enabled: true,
             },
+            fetch_policy: FetchPolicy::default(),
+            storage_policy: StoragePolicy::default(),
+            prep_policy: PrepPolicy::default(),
         }
     }
 
@@ -312,6 +1135,10 @@ mod tests {
             selection_reason: "test".to_string(),
             policy_match_score: score,
             state: PlanState::Candidate,
+            slot_start_at: None,
+            pin_id: None,
+            score_breakdown: None,
+            drop_reason: None,
         }
     }
 }
@@ -0,0 +1,354 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Duration, Utc};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use vvtv_types::{
+    OptimizerReport, OwnerCard, PlacementRule, PlanItem, PlanState, adjacency_violation,
+    window_violation,
+};
+
+use crate::exact_f64;
+use crate::freshness::Freshness;
+use crate::similarity::{ThemeModel, ThemeProfile};
+use crate::sources::domain_seconds;
+
+const VIOLATION_PENALTY: f64 = 1_000.0;
+
+pub(crate) struct Constraints {
+    day_start: DateTime<Utc>,
+    horizon_end: DateTime<Utc>,
+    max_streak: usize,
+    block_size: usize,
+    block_unique_target: usize,
+    music_ratio: f32,
+    target_duration: u32,
+    placement_rules: Vec<PlacementRule>,
+    max_domain_share: Option<f32>,
+    min_block_sources: usize,
+}
+
+impl Constraints {
+    pub(crate) fn new(
+        owner_card: &OwnerCard,
+        day_start: DateTime<Utc>,
+        horizon_end: DateTime<Utc>,
+    ) -> Self {
+        let block_unique_target =
+            usize::from(owner_card.editorial_profile.min_unique_themes_per_block).max(1);
+        Self {
+            day_start,
+            horizon_end,
+            max_streak: usize::from(owner_card.editorial_profile.max_consecutive_same_theme).max(1),
+            block_size: block_unique_target * 2,
+            block_unique_target,
+            music_ratio: owner_card.music_policy.block_music_ratio,
+            target_duration: owner_card.editorial_profile.target_avg_duration_sec,
+            placement_rules: owner_card.editorial_profile.placement_rules.clone(),
+            max_domain_share: owner_card.search_policy.max_domain_share,
+            min_block_sources: usize::from(owner_card.search_policy.min_distinct_sources_per_block),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Evaluation {
+    pub(crate) objective: f64,
+    pub(crate) violations: u32,
+}
+
+pub(crate) struct Outcome {
+    pub(crate) scheduled: Vec<PlanItem>,
+    pub(crate) pool: Vec<PlanItem>,
+    pub(crate) report: OptimizerReport,
+}
+
+// Simulated annealing over the greedy grid. Pinned items stay anchored at their
+// position; fillers can be swapped, replaced by, or exchanged with pool items.
+pub(crate) fn optimize(
+    owner_card: &OwnerCard,
+    constraints: &Constraints,
+    model: &ThemeModel,
+    freshness: &Freshness,
+    greedy: Vec<PlanItem>,
+    pool: Vec<PlanItem>,
+) -> Outcome {
+    let policy = &owner_card.schedule_policy.optimizer;
+    let mut rng = StdRng::seed_from_u64(policy.seed);
+    let profiles: HashMap<String, ThemeProfile> = greedy
+        .iter()
+        .chain(pool.iter())
+        .map(|plan| (plan.plan_id.clone(), model.profile(plan)))
+        .collect();
+    let themes = Catalog {
+        model,
+        freshness,
+        available_themes: model.distinct(profiles.values()),
+        available_sources: greedy
+            .iter()
+            .chain(pool.iter())
+            .map(|plan| plan.source_domain.as_str())
+            .collect::<HashSet<_>>()
+            .len(),
+        profiles,
+    };
+
+    let baseline = evaluate(constraints, &themes, &greedy);
+    let mut current = (greedy.clone(), pool.clone());
+    let mut current_eval = baseline;
+    let mut best = current.clone();
+    let mut best_eval = baseline;
+    let mut iterations = 0u32;
+    let mut evaluated_items = 0u64;
+
+    // Both limits count work rather than time, so a seed replays the same search.
+    while iterations < policy.max_iterations && evaluated_items < policy.evaluation_budget {
+        iterations += 1;
+        let Some(candidate) = mutate(&mut rng, &current.0, &current.1) else {
+            continue;
+        };
+        evaluated_items += candidate.0.len() as u64;
+        let candidate_eval = evaluate(constraints, &themes, &candidate.0);
+        let delta = candidate_eval.objective - current_eval.objective;
+        let progress = f64::from(iterations) / f64::from(policy.max_iterations.max(1));
+        let temperature = 50.0 * (1.0 - progress).max(0.002);
+        let accept = delta >= 0.0 || rng.random::<f64>() < (delta / temperature).exp();
+        if accept {
+            current = candidate;
+            current_eval = candidate_eval;
+            if current_eval.objective > best_eval.objective {
+                best = current.clone();
+                best_eval = current_eval;
+            }
+        }
+    }
+
+    let applied = best_eval.objective > baseline.objective;
+    let report = OptimizerReport {
+        greedy_objective: baseline.objective,
+        greedy_violations: baseline.violations,
+        optimized_objective: best_eval.objective,
+        optimized_violations: best_eval.violations,
+        iterations,
+        evaluated_items,
+        applied,
+    };
+
+    let (scheduled, mut pool) = if applied { best } else { (greedy, pool) };
+    for plan in &mut pool {
+        plan.state = PlanState::Reserved;
+    }
+    pool.sort_by(|a, b| b.policy_match_score.total_cmp(&a.policy_match_score));
+    Outcome {
+        scheduled: retime(constraints, scheduled),
+        pool,
+        report,
+    }
+}
+
+fn mutate(
+    rng: &mut StdRng,
+    scheduled: &[PlanItem],
+    pool: &[PlanItem],
+) -> Option<(Vec<PlanItem>, Vec<PlanItem>)> {
+    let movable: Vec<usize> = scheduled
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| p.pin_id.is_none())
+        .map(|(idx, _)| idx)
+        .collect();
+    let mut scheduled = scheduled.to_vec();
+    let mut pool = pool.to_vec();
+
+    match rng.random_range(0..4) {
+        0 if movable.len() >= 2 => {
+            let a = movable[rng.random_range(0..movable.len())];
+            let b = movable[rng.random_range(0..movable.len())];
+            if a == b {
+                return None;
+            }
+            scheduled.swap(a, b);
+        }
+        1 if !movable.is_empty() && !pool.is_empty() => {
+            let a = movable[rng.random_range(0..movable.len())];
+            let b = rng.random_range(0..pool.len());
+            std::mem::swap(&mut scheduled[a], &mut pool[b]);
+        }
+        2 if !pool.is_empty() => {
+            let at = rng.random_range(0..=scheduled.len());
+            let b = rng.random_range(0..pool.len());
+            scheduled.insert(at, pool.swap_remove(b));
+        }
+        3 if !movable.is_empty() => {
+            let a = movable[rng.random_range(0..movable.len())];
+            pool.push(scheduled.remove(a));
+        }
+        _ => return None,
+    }
+    Some((scheduled, pool))
+}
+
+// Theme profiles are computed once per run; the annealing loop only looks them up.
+pub(crate) struct Catalog<'a> {
+    model: &'a ThemeModel,
+    freshness: &'a Freshness,
+    profiles: HashMap<String, ThemeProfile>,
+    available_themes: usize,
+    available_sources: usize,
+}
+
+impl Catalog<'_> {
+    fn of(&self, plan: &PlanItem) -> ThemeProfile {
+        self.profiles
+            .get(&plan.plan_id)
+            .cloned()
+            .unwrap_or_else(|| self.model.profile(plan))
+    }
+}
+
+pub(crate) fn evaluate(
+    constraints: &Constraints,
+    themes: &Catalog<'_>,
+    scheduled: &[PlanItem],
+) -> Evaluation {
+    let mut score = 0.0_f64;
+    let mut violations = 0u32;
+    let profiles: Vec<ThemeProfile> = scheduled.iter().map(|plan| themes.of(plan)).collect();
+
+    // Timing: fillers run back to back, pins must not be overrun.
+    let mut cursor = constraints.day_start;
+    let mut total_duration = 0u64;
+    let mut prev_tags: Option<Vec<String>> = None;
+    for (idx, plan) in scheduled.iter().enumerate() {
+        let start = match (plan.pin_id.as_ref(), plan.slot_start_at) {
+            (Some(_), Some(pinned_at)) => {
+                if cursor > pinned_at {
+                    violations += 1;
+                }
+                pinned_at
+            }
+            _ => cursor,
+        };
+        if !constraints.placement_rules.is_empty() {
+            violations += placement_violations(constraints, prev_tags.as_deref(), plan, start);
+            prev_tags = Some(plan.content_tags());
+        }
+        cursor = start + Duration::seconds(i64::from(plan.duration_sec));
+        total_duration += u64::from(plan.duration_sec);
+        score += f64::from(
+            plan.policy_match_score * 100.0
+                + themes.freshness.freshness(plan, start)
+                + themes.freshness.novelty(plan)
+                - themes.freshness.recent_theme_penalty(&profiles[idx], start),
+        );
+    }
+    if cursor > constraints.horizon_end {
+        violations += 1;
+    }
+
+    // Streaks of items too similar to their predecessor.
+    let mut streak = 0usize;
+    for (idx, profile) in profiles.iter().enumerate() {
+        if idx > 0 && themes.model.too_similar(&profiles[idx - 1], profile) {
+            streak += 1;
+        } else {
+            streak = 1;
+        }
+        if streak > constraints.max_streak {
+            violations += 1;
+        }
+    }
+
+    // Block diversity and music ratio per block.
+    let unique_floor = constraints
+        .block_unique_target
+        .min(themes.available_themes)
+        .max(1);
+    let sources_floor = constraints.min_block_sources.min(themes.available_sources);
+    for (block_themes, block_plans) in profiles
+        .chunks(constraints.block_size)
+        .zip(scheduled.chunks(constraints.block_size))
+    {
+        let unique = themes.model.distinct(block_themes);
+        score += 30.0 * exact_f64(unique.min(constraints.block_unique_target));
+        if block_themes.len() == constraints.block_size && unique < unique_floor {
+            violations += 1;
+        }
+
+        let sources = block_plans
+            .iter()
+            .map(|p| p.source_domain.as_str())
+            .collect::<HashSet<_>>()
+            .len();
+        if block_plans.len() == constraints.block_size && sources < sources_floor {
+            violations += 1;
+        }
+
+        let music = block_plans.iter().filter(|p| is_music(p)).count();
+        let allowed = (f64::from(constraints.music_ratio) * exact_f64(block_plans.len())).ceil();
+        if exact_f64(music) > allowed {
+            violations += 1;
+        }
+    }
+
+    // No single domain may exceed its share of the scheduled minutes.
+    if let Some(cap) = constraints.max_domain_share {
+        let dominant = domain_seconds(scheduled).into_values().max().unwrap_or(0);
+        if dominant < total_duration
+            && exact_f64(dominant) / exact_f64(total_duration) > f64::from(cap)
+        {
+            violations += 1;
+        }
+    }
+
+    // Keep the average duration close to the editorial target.
+    if !scheduled.is_empty() {
+        let target = f64::from(constraints.target_duration.max(1));
+        let avg = exact_f64(total_duration) / exact_f64(scheduled.len());
+        score -= (avg - target).abs() / target * 100.0;
+    }
+
+    Evaluation {
+        objective: score - f64::from(violations) * VIOLATION_PENALTY,
+        violations,
+    }
+}
+
+fn placement_violations(
+    constraints: &Constraints,
+    prev_tags: Option<&[String]>,
+    plan: &PlanItem,
+    start: DateTime<Utc>,
+) -> u32 {
+    let rules = &constraints.placement_rules;
+    let tags = plan.content_tags();
+    // Pinned items are the owner's explicit choice and exempt from windows.
+    let window = plan.pin_id.is_none() && window_violation(rules, &tags, start).is_some();
+    let adjacent = prev_tags.is_some_and(|prev| adjacency_violation(rules, prev, &tags).is_some());
+    u32::from(window) + u32::from(adjacent)
+}
+
+pub(crate) fn retime(constraints: &Constraints, scheduled: Vec<PlanItem>) -> Vec<PlanItem> {
+    let mut cursor = constraints.day_start;
+    scheduled
+        .into_iter()
+        .map(|mut plan| {
+            let start = if plan.pin_id.is_some() {
+                plan.slot_start_at.unwrap_or(cursor)
+            } else {
+                cursor
+            };
+            plan.state = PlanState::Scheduled;
+            plan.slot_start_at = Some(start);
+            cursor = start + Duration::seconds(i64::from(plan.duration_sec));
+            plan
+        })
+        .collect()
+}
+
+fn is_music(plan: &PlanItem) -> bool {
+    plan.theme_tags
+        .iter()
+        .chain(plan.visual_features.iter())
+        .any(|tag| tag.to_lowercase().starts_with("music"))
+}
@@ -0,0 +1,142 @@
+use std::collections::{BTreeMap, HashMap};
+
+use vvtv_types::{PlanItem, ThemePolicy};
+
+const TAG_WEIGHT: f32 = 1.0;
+const PARENT_WEIGHT: f32 = 0.5;
+const VISUAL_WEIGHT: f32 = 0.5;
+
+// Weighted feature set built from every theme tag and visual feature of a plan.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ThemeProfile {
+    features: BTreeMap<String, f32>,
+}
+
+impl ThemeProfile {
+    pub(crate) fn is_untagged(&self) -> bool {
+        self.features.is_empty()
+    }
+
+    fn insert(&mut self, key: String, weight: f32) {
+        let entry = self.features.entry(key).or_insert(0.0);
+        *entry = entry.max(weight);
+    }
+}
+
+pub(crate) struct ThemeModel {
+    threshold: f32,
+    canonical: HashMap<String, String>,
+    parents: HashMap<String, Vec<String>>,
+}
+
+impl ThemeModel {
+    pub(crate) fn new(policy: &ThemePolicy) -> Self {
+        let mut canonical = HashMap::new();
+        for (name, aliases) in &policy.synonyms {
+            let name = normalize_tag(name);
+            for alias in aliases {
+                canonical.insert(normalize_tag(alias), name.clone());
+            }
+        }
+
+        let mut parents: HashMap<String, Vec<String>> = HashMap::new();
+        for (parent, children) in &policy.taxonomy {
+            let parent = resolve(&canonical, parent);
+            for child in children {
+                parents
+                    .entry(resolve(&canonical, child))
+                    .or_default()
+                    .push(parent.clone());
+            }
+        }
+
+        Self {
+            threshold: policy.similarity_threshold,
+            canonical,
+            parents,
+        }
+    }
+
+    pub(crate) fn profile(&self, plan: &PlanItem) -> ThemeProfile {
+        self.profile_of(&plan.theme_tags, &plan.visual_features)
+    }
+
+    pub(crate) fn profile_of(
+        &self,
+        theme_tags: &[String],
+        visual_features: &[String],
+    ) -> ThemeProfile {
+        let mut profile = ThemeProfile::default();
+        for tag in theme_tags.iter().filter(|t| !t.trim().is_empty()) {
+            let tag = resolve(&self.canonical, tag);
+            for parent in self.parents.get(&tag).into_iter().flatten() {
+                profile.insert(format!("tag:{parent}"), PARENT_WEIGHT);
+            }
+            profile.insert(format!("tag:{tag}"), TAG_WEIGHT);
+        }
+        for feature in visual_features.iter().filter(|f| !f.trim().is_empty()) {
+            let feature = resolve(&self.canonical, feature);
+            profile.insert(format!("visual:{feature}"), VISUAL_WEIGHT);
+        }
+        profile
+    }
+
+    // Weighted Jaccard: shared weight over combined weight. Untagged items are
+    // never similar to anything.
+    pub(crate) fn similarity(a: &ThemeProfile, b: &ThemeProfile) -> f32 {
+        if a.is_untagged() || b.is_untagged() {
+            return 0.0;
+        }
+        let mut shared = 0.0_f32;
+        let mut combined = 0.0_f32;
+        for (key, weight) in &a.features {
+            let other = b.features.get(key).copied().unwrap_or(0.0);
+            shared += weight.min(other);
+            combined += weight.max(other);
+        }
+        for (key, weight) in &b.features {
+            if !a.features.contains_key(key) {
+                combined += weight;
+            }
+        }
+        if combined <= 0.0 {
+            0.0
+        } else {
+            shared / combined
+        }
+    }
+
+    pub(crate) fn too_similar(&self, a: &ThemeProfile, b: &ThemeProfile) -> bool {
+        Self::similarity(a, b) >= self.threshold
+    }
+
+    // Number of clusters when each profile joins the first earlier one it is too
+    // similar to. Untagged profiles add no diversity.
+    pub(crate) fn distinct<'a>(
+        &self,
+        profiles: impl IntoIterator<Item = &'a ThemeProfile>,
+    ) -> usize {
+        let mut representatives: Vec<&ThemeProfile> = Vec::new();
+        for profile in profiles {
+            if profile.is_untagged() {
+                continue;
+            }
+            if !representatives
+                .iter()
+                .any(|rep| self.too_similar(rep, profile))
+            {
+                representatives.push(profile);
+            }
+        }
+        representatives.len()
+    }
+}
+
+fn resolve(canonical: &HashMap<String, String>, tag: &str) -> String {
+    let tag = normalize_tag(tag);
+    canonical.get(&tag).cloned().unwrap_or(tag)
+}
+
+fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase().replace(['_', ' '], "-")
+}
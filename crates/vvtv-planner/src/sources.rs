@@ -0,0 +1,106 @@
+use std::collections::{BTreeMap, HashSet};
+
+use vvtv_types::{OwnerCard, PlanItem};
+
+use crate::{Grid, exact_f64, to_reserved};
+
+pub(crate) struct SourceLimits {
+    max_share: Option<f32>,
+    domain_budget_sec: Option<f64>,
+    min_block_sources: usize,
+    block_size: usize,
+}
+
+impl SourceLimits {
+    pub(crate) fn new(owner_card: &OwnerCard, block_size: usize) -> Self {
+        let policy = &owner_card.search_policy;
+        let horizon_sec = f64::from(owner_card.schedule_policy.planning_horizon_hours) * 3600.0;
+        Self {
+            max_share: policy.max_domain_share,
+            domain_budget_sec: policy
+                .max_domain_share
+                .map(|share| f64::from(share) * horizon_sec),
+            min_block_sources: usize::from(policy.min_distinct_sources_per_block).min(block_size),
+            block_size: block_size.max(1),
+        }
+    }
+
+    // The domain cap is hard; block source diversity is only requested when
+    // `diverse` is set so the grid can still be filled from too few sources.
+    pub(crate) fn allows(&self, grid: &Grid, plan: &PlanItem, diverse: bool) -> bool {
+        let aired = grid
+            .domain_seconds
+            .get(&plan.source_domain)
+            .copied()
+            .unwrap_or(0)
+            + u64::from(plan.duration_sec);
+        if self
+            .domain_budget_sec
+            .is_some_and(|budget| exact_f64(aired) > budget)
+        {
+            return false;
+        }
+        if !diverse || self.min_block_sources == 0 {
+            return true;
+        }
+
+        let block = block_tail(&grid.scheduled, self.block_size);
+        let domains: HashSet<&str> = block.iter().map(|p| p.source_domain.as_str()).collect();
+        let slots_after = self.block_size - block.len() - 1;
+        let needs_new_source = domains.len() + slots_after < self.min_block_sources;
+        !needs_new_source || !domains.contains(plan.source_domain.as_str())
+    }
+
+    // The budget above is relative to the horizon; a short day can still end up
+    // over the cap, so drop the weakest fillers of the dominant domain until the
+    // actual share fits or no other domain is left to make room for.
+    pub(crate) fn trim_to_share(
+        &self,
+        scheduled: &mut Vec<PlanItem>,
+        reserves: &mut Vec<PlanItem>,
+    ) -> bool {
+        let Some(cap) = self.max_share else {
+            return false;
+        };
+        let mut trimmed = false;
+        loop {
+            let total: u64 = scheduled.iter().map(|p| u64::from(p.duration_sec)).sum();
+            let Some((domain, seconds)) = domain_seconds(scheduled)
+                .into_iter()
+                .max_by_key(|(_, seconds)| *seconds)
+            else {
+                break;
+            };
+            if total == 0
+                || seconds == total
+                || exact_f64(seconds) / exact_f64(total) <= f64::from(cap)
+            {
+                break;
+            }
+            let weakest = scheduled
+                .iter()
+                .enumerate()
+                .filter(|(_, p)| p.pin_id.is_none() && p.source_domain == domain)
+                .min_by(|(_, a), (_, b)| a.policy_match_score.total_cmp(&b.policy_match_score))
+                .map(|(idx, _)| idx);
+            let Some(idx) = weakest else {
+                break;
+            };
+            reserves.push(to_reserved(scheduled.remove(idx)));
+            trimmed = true;
+        }
+        trimmed
+    }
+}
+
+pub(crate) fn domain_seconds(plans: &[PlanItem]) -> BTreeMap<String, u64> {
+    let mut seconds: BTreeMap<String, u64> = BTreeMap::new();
+    for plan in plans {
+        *seconds.entry(plan.source_domain.clone()).or_default() += u64::from(plan.duration_sec);
+    }
+    seconds
+}
+
+fn block_tail(scheduled: &[PlanItem], block_size: usize) -> &[PlanItem] {
+    &scheduled[scheduled.len() - scheduled.len() % block_size..]
+}
@@ -0,0 +1,76 @@
+use std::path::Path;
+
+use vvtv_media_tools::Ffmpeg;
+use vvtv_types::{AspectHandling, EncodeProfile, RateControl};
+
+use crate::framing;
+
+// The video and audio encode of `input` into `profile`: fitted to the
+// profile's frame as `handling` says, resampled to its frame rate, with a
+// keyframe on every `segment_sec` boundary. The caller adds the audio filter
+// and the output.
+pub(crate) fn command(
+    input: &Path,
+    profile: &EncodeProfile,
+    handling: Option<AspectHandling>,
+    segment_sec: u32,
+) -> Ffmpeg {
+    let encode = Ffmpeg::new()
+        .input(input)
+        .video_filter(format!(
+            "{},setsar=1,fps={}",
+            framing::scale_filter(handling, profile.width, profile.height),
+            profile.frame_rate
+        ))
+        .video_codec("libx264")
+        .preset(profile.preset.clone())
+        .gop(profile.gop_frames(segment_sec))
+        .audio_codec("aac")
+        .audio_bitrate_kbps(profile.audio_bitrate_kbps);
+    match profile.rate_control {
+        RateControl::Crf(crf) => encode.crf(crf),
+        RateControl::BitrateKbps(kbps) => encode.video_bitrate_kbps(kbps),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vvtv_types::QualityPolicy;
+
+    use super::*;
+
+    fn joined(ffmpeg: &Ffmpeg) -> String {
+        ffmpeg
+            .args()
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn encodes_with_the_profile_and_segment_aligned_keyframes() {
+        let quality = QualityPolicy::default();
+        let profile = quality.encode_profile_for(1080).unwrap();
+        let args = joined(&command(Path::new("in.mp4"), profile, None, 6).output("out.mp4"));
+
+        assert!(args.contains("pad=1920:1080:(ow-iw)/2:(oh-ih)/2,setsar=1,fps=30"));
+        assert!(args.contains("-preset veryfast -crf 21 -g 180 -keyint_min 180"));
+        assert!(args.ends_with("-c:a aac -b:a 160k out.mp4"));
+
+        let capped = EncodeProfile {
+            rate_control: RateControl::BitrateKbps(2500),
+            frame_rate: 25,
+            ..profile.clone()
+        };
+        let args = joined(&command(
+            Path::new("in.mp4"),
+            &capped,
+            Some(AspectHandling::Crop),
+            4,
+        ));
+        assert!(args.contains("-b:v 2500k -maxrate 2500k -bufsize 5000k -g 100"));
+        assert!(!args.contains("-crf"));
+        assert!(args.contains("crop=1920:1080,setsar=1,fps=25"));
+    }
+}
@@ -0,0 +1,101 @@
+use vvtv_types::{AspectHandling, EncodeProfile, Framing, QualityPolicy, Resolution};
+
+// How the displayed source goes into `profile`'s frame. Sources within
+// `aspect_tolerance_pct` of the frame aspect, or of unknown size, are scaled
+// as they are.
+pub(crate) fn plan(
+    quality: &QualityPolicy,
+    source: &Resolution,
+    rotation_deg: u16,
+    profile: &EncodeProfile,
+) -> Framing {
+    let off_aspect = source.width > 0 && source.height > 0 && {
+        let source_aspect = f32::from(source.width) / f32::from(source.height);
+        let frame_aspect = f32::from(profile.width) / f32::from(profile.height);
+        (source_aspect / frame_aspect - 1.0).abs() * 100.0 > quality.aspect_tolerance_pct
+    };
+    Framing {
+        source: source.clone(),
+        rotation_deg,
+        handling: off_aspect.then_some(quality.aspect_handling),
+    }
+}
+
+// Filters that turn the source into exactly `width`x`height`. ffmpeg has
+// already applied the rotation by the time they run. Non-square pixels are
+// resampled to square first, so the fit works on the displayed shape that
+// `plan` judged rather than the stored one.
+pub(crate) fn scale_filter(handling: Option<AspectHandling>, width: u16, height: u16) -> String {
+    let square = "scale=iw*sar:ih,setsar=1";
+    let fit = format!(
+        "scale=w={width}:h={height}:force_original_aspect_ratio=decrease:force_divisible_by=2"
+    );
+    let fill = format!(
+        "scale=w={width}:h={height}:force_original_aspect_ratio=increase,crop={width}:{height}"
+    );
+    match handling {
+        Some(AspectHandling::Crop) => format!("{square},{fill}"),
+        Some(AspectHandling::BlurredPillarbox) => format!(
+            "{square},split[bg][fg];[bg]{fill},boxblur=luma_radius=20:luma_power=2[blur];\
+             [fg]{fit}[fit];[blur][fit]overlay=(W-w)/2:(H-h)/2"
+        ),
+        None | Some(AspectHandling::Pillarbox | AspectHandling::Reject) => {
+            format!("{square},{fit},pad={width}:{height}:(ow-iw)/2:(oh-ih)/2")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_off_aspect_sources_get_the_policy_handling() {
+        let mut quality = QualityPolicy {
+            aspect_handling: AspectHandling::BlurredPillarbox,
+            ..QualityPolicy::default()
+        };
+        let profile = &quality.encode_profiles[0];
+        let size = |width, height| Resolution { width, height };
+
+        assert_eq!(plan(&quality, &size(1920, 1080), 0, profile).handling, None);
+        // 1.85:1 is within a few percent of 16:9 only with a looser tolerance.
+        assert_eq!(
+            plan(&quality, &size(1998, 1080), 0, profile).handling,
+            Some(AspectHandling::BlurredPillarbox)
+        );
+        quality.aspect_tolerance_pct = 5.0;
+        let profile = &quality.encode_profiles[0];
+        assert_eq!(plan(&quality, &size(1998, 1080), 0, profile).handling, None);
+
+        let vertical = plan(&quality, &size(1080, 1920), 90, profile);
+        assert_eq!(vertical.handling, Some(AspectHandling::BlurredPillarbox));
+        assert_eq!(vertical.rotation_deg, 90);
+        assert_eq!(plan(&quality, &size(0, 0), 0, profile).handling, None);
+
+        // PAL 720x576 with 64:45 pixels displays as 1024x576, which is 16:9.
+        assert_eq!(plan(&quality, &size(1024, 576), 0, profile).handling, None);
+    }
+
+    #[test]
+    fn each_handling_ends_on_the_full_frame() {
+        assert_eq!(
+            scale_filter(Some(AspectHandling::Crop), 1280, 720),
+            "scale=iw*sar:ih,setsar=1,\
+             scale=w=1280:h=720:force_original_aspect_ratio=increase,crop=1280:720"
+        );
+        // An anamorphic source is widened to square pixels before the fit, so
+        // it fills the frame instead of being pillarboxed as 5:4.
+        assert_eq!(
+            scale_filter(None, 1280, 720),
+            "scale=iw*sar:ih,setsar=1,\
+             scale=w=1280:h=720:force_original_aspect_ratio=decrease:force_divisible_by=2,\
+             pad=1280:720:(ow-iw)/2:(oh-ih)/2"
+        );
+        let blurred = scale_filter(Some(AspectHandling::BlurredPillarbox), 1280, 720);
+        assert!(
+            blurred.starts_with("scale=iw*sar:ih,setsar=1,split[bg][fg];[bg]scale=w=1280:h=720")
+        );
+        assert!(blurred.ends_with("[blur][fit]overlay=(W-w)/2:(H-h)/2"));
+    }
+}
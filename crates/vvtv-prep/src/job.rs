@@ -0,0 +1,33 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, PoisonError};
+
+// Plans whose prep work should stop. Clones share the same list, so any
+// holder can cancel while `PrepPipeline::run_jobs` is busy.
+#[derive(Debug, Clone, Default)]
+pub struct PrepControl {
+    cancelled: Arc<Mutex<HashSet<String>>>,
+}
+
+impl PrepControl {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Queued jobs of the plan are skipped and a running one has its process
+    // killed.
+    pub fn cancel(&self, plan_id: &str) {
+        self.cancelled
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(plan_id.to_string());
+    }
+
+    #[must_use]
+    pub fn is_cancelled(&self, plan_id: &str) -> bool {
+        self.cancelled
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains(plan_id)
+    }
+}
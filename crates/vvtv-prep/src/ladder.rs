@@ -0,0 +1,184 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use vvtv_media_tools::{Ffmpeg, ProbeInfo, RunLimits, StreamKind};
+use vvtv_types::{
+    AUDIO_ONLY_RENDITION, AspectHandling, EncodeProfile, QualityPolicy, RateControl, Rendition,
+    Resolution, Trim,
+};
+
+use crate::{encode, trim};
+
+// What the ladder is encoded from. The main output is reused by a rung with
+// the same settings instead of being encoded twice.
+pub(crate) struct LadderSource<'a> {
+    pub path: &'a Path,
+    pub height: u16,
+    pub has_audio: bool,
+    pub audio_filter: Option<&'a str>,
+    pub handling: Option<AspectHandling>,
+    pub trim: Option<Trim>,
+    pub main_profile: &'a EncodeProfile,
+    pub main_output: &'a Path,
+}
+
+// Encodes the video rungs of `quality.abr_ladder` the source reaches, then the
+// audio-only rung, into `dir`. Every video rung uses the keyframe cadence of
+// `hls_segment_sec`, so segments of different rungs line up.
+pub(crate) fn encode_ladder(
+    quality: &QualityPolicy,
+    source: &LadderSource<'_>,
+    dir: &Path,
+    limits: &RunLimits<'_>,
+) -> Result<Vec<Rendition>> {
+    let abr = &quality.abr_ladder;
+    if !abr.enabled {
+        return Ok(Vec::new());
+    }
+    fs::create_dir_all(dir).with_context(|| format!("failed creating {}", dir.display()))?;
+
+    let mut renditions = Vec::new();
+    for rung in abr.rungs_for(source.height) {
+        let path = if same_encode(rung, source.main_profile) {
+            source.main_output.to_path_buf()
+        } else {
+            let path = dir.join(format!("{}.mp4", rung.name));
+            let mut encode = trim::apply(
+                encode::command(source.path, rung, source.handling, quality.hls_segment_sec),
+                source.trim.as_ref(),
+            );
+            if let Some(filter) = source.audio_filter {
+                encode = encode.audio_filter(filter);
+            }
+            encode
+                .output(&path)
+                .run(limits)
+                .with_context(|| format!("encoding rendition {}", rung.name))?;
+            path
+        };
+        let peak = match rung.rate_control {
+            RateControl::BitrateKbps(kbps) => Some(kbps_to_bps(kbps + rung.audio_bitrate_kbps)),
+            RateControl::Crf(_) => None,
+        };
+        renditions.push(describe(&rung.name, &path, peak, limits)?);
+    }
+
+    if let Some(kbps) = abr.audio_only_kbps.filter(|_| source.has_audio) {
+        let path = dir.join(format!("{AUDIO_ONLY_RENDITION}.mp4"));
+        let mut encode = trim::apply(
+            Ffmpeg::new().input(source.path).no_video(),
+            source.trim.as_ref(),
+        );
+        if let Some(filter) = source.audio_filter {
+            encode = encode.audio_filter(filter);
+        }
+        encode
+            .audio_codec("aac")
+            .audio_bitrate_kbps(kbps)
+            .output(&path)
+            .run(limits)
+            .context("encoding the audio-only rendition")?;
+        renditions.push(describe(
+            AUDIO_ONLY_RENDITION,
+            &path,
+            Some(kbps_to_bps(kbps)),
+            limits,
+        )?);
+    }
+    Ok(renditions)
+}
+
+fn same_encode(rung: &EncodeProfile, main: &EncodeProfile) -> bool {
+    EncodeProfile {
+        name: main.name.clone(),
+        ..rung.clone()
+    } == *main
+}
+
+fn kbps_to_bps(kbps: u32) -> u64 {
+    u64::from(kbps) * 1000
+}
+
+// Bitrate-capped rungs advertise their cap; constant-quality ones fall back
+// to the measured average.
+fn describe(
+    name: &str,
+    path: &Path,
+    peak_bps: Option<u64>,
+    limits: &RunLimits<'_>,
+) -> Result<Rendition> {
+    let probe = vvtv_media_tools::probe(path, limits)?;
+    let resolution = probe.first(StreamKind::Video).and_then(|video| {
+        Some(Resolution {
+            width: u16::try_from(video.width?).ok()?,
+            height: u16::try_from(video.height?).ok()?,
+        })
+    });
+    Ok(Rendition {
+        name: name.to_string(),
+        local_path: path.to_string_lossy().into_owned(),
+        resolution,
+        bandwidth_bps: peak_bps.or(probe.bit_rate).unwrap_or_default(),
+        codecs: codecs(&probe),
+    })
+}
+
+// RFC 6381 strings for the HLS `CODECS` attribute of an H.264/AAC file.
+fn codecs(probe: &ProbeInfo) -> String {
+    let mut out = Vec::new();
+    if let Some(video) = probe.first(StreamKind::Video) {
+        let (profile_idc, constraints) = match video.profile.as_deref() {
+            Some("Constrained Baseline") => (0x42, 0xe0),
+            Some("Baseline") => (0x42, 0x00),
+            Some("Main") => (0x4d, 0x40),
+            _ => (0x64, 0x00),
+        };
+        out.push(format!(
+            "avc1.{profile_idc:02x}{constraints:02x}{:02x}",
+            video.level.unwrap_or(40)
+        ));
+    }
+    if let Some(audio) = probe.first(StreamKind::Audio) {
+        let object_type = match audio.profile.as_deref() {
+            Some("HE-AAC") => 5,
+            Some("HE-AACv2") => 29,
+            _ => 2,
+        };
+        out.push(format!("mp4a.40.{object_type}"));
+    }
+    out.join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codecs_follow_the_probed_profiles() {
+        let probe = ProbeInfo::from_json(
+            r#"{"streams": [
+                {"codec_type": "video", "codec_name": "h264", "profile": "Main", "level": 31},
+                {"codec_type": "audio", "codec_name": "aac", "profile": "LC"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(codecs(&probe), "avc1.4d401f,mp4a.40.2");
+
+        let audio_only =
+            ProbeInfo::from_json(r#"{"streams": [{"codec_type": "audio", "profile": "HE-AAC"}]}"#)
+                .unwrap();
+        assert_eq!(codecs(&audio_only), "mp4a.40.5");
+    }
+
+    #[test]
+    fn only_identical_settings_reuse_the_main_output() {
+        let quality = QualityPolicy::default();
+        let main = &quality.encode_profiles[1];
+        let mut rung = main.clone();
+        rung.name = "hd".to_string();
+        assert!(same_encode(&rung, main));
+        rung.rate_control = RateControl::BitrateKbps(2_800);
+        assert!(!same_encode(&rung, main));
+    }
+}
@@ -1,103 +1,531 @@
+mod encode;
+mod framing;
+mod job;
+mod ladder;
+mod loudness;
+mod preview;
+mod qa;
+mod subtitles;
+mod trim;
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::sync::{Mutex, PoisonError, mpsc};
+use std::thread;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
-use vvtv_types::{AssetItem, OwnerCard, QaStatus, Resolution};
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+use vvtv_media_tools::{Ffmpeg, MediaError, MediaErrorKind, RunLimits, StreamKind};
+use vvtv_types::{
+    AspectHandling, AssetItem, Framing, LoudnessReport, OwnerCard, PrepCacheEntry, PrepJob,
+    PrepJobState, Previews, QaCheck, QaReport, QaStatus, QualityPolicy, Resolution,
+};
+
+pub use job::PrepControl;
 
 pub struct PrepPipeline;
 
+// Prepared outputs keyed by (input hash, prep profile hash).
+#[derive(Debug, Default)]
+pub struct PrepCache {
+    entries: HashMap<(String, String), PrepCacheEntry>,
+}
+
+impl PrepCache {
+    pub fn new(entries: impl IntoIterator<Item = PrepCacheEntry>) -> Self {
+        Self {
+            entries: entries
+                .into_iter()
+                .map(|entry| {
+                    (
+                        (entry.input_sha256.clone(), entry.profile_hash.clone()),
+                        entry,
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    // Entries any of whose prepared files has since disappeared from disk
+    // are misses.
+    fn get(&self, input_sha256: &str, profile_hash: &str) -> Option<&PrepCacheEntry> {
+        self.entries
+            .get(&(input_sha256.to_string(), profile_hash.to_string()))
+            .filter(|entry| {
+                std::iter::once(entry.output_path.as_str())
+                    .chain(entry.renditions.iter().map(|rung| rung.local_path.as_str()))
+                    .chain(entry.previews.iter().flat_map(Previews::files))
+                    .chain(
+                        entry
+                            .subtitles
+                            .iter()
+                            .map(|track| track.local_path.as_str()),
+                    )
+                    .all(|path| Path::new(path).exists())
+            })
+    }
+
+    fn insert(&mut self, entry: PrepCacheEntry) {
+        self.entries.insert(
+            (entry.input_sha256.clone(), entry.profile_hash.clone()),
+            entry,
+        );
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PrepOutcome {
+    // Prepared or rejected assets of the jobs that finished, in job order.
+    pub assets: Vec<AssetItem>,
+    // Results prepared in this call, to be persisted by the caller.
+    pub new_entries: Vec<PrepCacheEntry>,
+    pub cache_hits: usize,
+}
+
+// A job handed to a worker thread.
+struct Work {
+    index: usize,
+    asset: AssetItem,
+    output_key: String,
+}
+
 impl PrepPipeline {
     #[must_use]
     pub fn process(owner_card: &OwnerCard, assets: Vec<AssetItem>) -> Vec<AssetItem> {
-        if command_exists("ffmpeg") && command_exists("ffprobe") {
-            assets
-                .into_iter()
-                .map(
-                    |asset| match process_single_with_ffmpeg(owner_card, asset.clone()) {
-                        Ok(processed) => processed,
-                        Err(_) => fallback_reject(asset),
-                    },
-                )
-                .collect()
+        let ffmpeg = vvtv_media_tools::available();
+        assets
+            .into_iter()
+            .map(|asset| {
+                let output_key = asset.asset_id.clone();
+                prepare(owner_card, asset, &output_key, ffmpeg)
+            })
+            .collect()
+    }
+
+    // Like `process`, but content that was already prepared under the same
+    // profile reuses the earlier output instead of being encoded again. Only
+    // assets with a content hash take part in the cache.
+    #[must_use]
+    pub fn process_cached(
+        owner_card: &OwnerCard,
+        assets: Vec<AssetItem>,
+        cache: &mut PrepCache,
+    ) -> PrepOutcome {
+        let now = Utc::now();
+        let jobs = assets
+            .into_iter()
+            .map(|asset| PrepJob::queued(asset, now))
+            .collect();
+        Self::run_jobs(owner_card, jobs, cache, &PrepControl::new(), |_| {})
+    }
+
+    // Runs `jobs` through the cached prep on at most `max_concurrent_jobs`
+    // worker threads; a job whose processes outlive `job_timeout_secs` is
+    // killed and fails. Jobs of plans cancelled through `control` end as
+    // `Cancelled` without an asset. Every state change is handed to
+    // `on_update` as it happens so the caller can persist it.
+    pub fn run_jobs(
+        owner_card: &OwnerCard,
+        mut jobs: Vec<PrepJob>,
+        cache: &mut PrepCache,
+        control: &PrepControl,
+        mut on_update: impl FnMut(&PrepJob),
+    ) -> PrepOutcome {
+        let ffmpeg = vvtv_media_tools::available();
+        let profile_hash = Self::profile_hash(owner_card);
+        let workers = usize::from(owner_card.prep_policy.max_concurrent_jobs.max(1));
+        let timeout = Duration::from_secs(owner_card.prep_policy.job_timeout_secs);
+        let mut outcome = PrepOutcome::default();
+
+        let (work_tx, work_rx) = mpsc::channel::<Work>();
+        let work_rx = Mutex::new(work_rx);
+        let (done_tx, done_rx) = mpsc::channel::<(usize, Result<AssetItem>)>();
+        thread::scope(|scope| {
+            for _ in 0..workers.min(jobs.len()) {
+                let done_tx = done_tx.clone();
+                let work_rx = &work_rx;
+                scope.spawn(move || work(owner_card, ffmpeg, timeout, control, work_rx, &done_tx));
+            }
+            drop(done_tx);
+
+            let mut pending: VecDeque<usize> = (0..jobs.len()).collect();
+            // Output keys being encoded; a second job for the same content
+            // waits so it can reuse the first one's output.
+            let mut in_flight: HashMap<usize, String> = HashMap::new();
+            loop {
+                let mut waiting = VecDeque::new();
+                while let Some(index) = pending.pop_front() {
+                    let job = &mut jobs[index];
+                    if control.is_cancelled(&job.plan_id) {
+                        job.state = PrepJobState::Cancelled;
+                        job.updated_at = Utc::now();
+                        on_update(job);
+                        continue;
+                    }
+                    let input_sha256 = job.asset.content_sha256().map(str::to_string);
+                    if let Some(entry) = input_sha256
+                        .as_deref()
+                        .and_then(|sha| cache.get(sha, &profile_hash))
+                    {
+                        job.asset = apply_entry(job.asset.clone(), entry);
+                        job.state = PrepJobState::Done;
+                        job.updated_at = Utc::now();
+                        outcome.cache_hits += 1;
+                        on_update(job);
+                        continue;
+                    }
+                    let output_key = input_sha256.map_or_else(
+                        || job.asset.asset_id.clone(),
+                        |sha| format!("{sha}-{}", &profile_hash[..16]),
+                    );
+                    if in_flight.len() >= workers
+                        || in_flight.values().any(|key| *key == output_key)
+                    {
+                        waiting.push_back(index);
+                        continue;
+                    }
+                    job.state = PrepJobState::Running;
+                    job.attempts += 1;
+                    job.updated_at = Utc::now();
+                    on_update(job);
+                    in_flight.insert(index, output_key.clone());
+                    let _ = work_tx.send(Work {
+                        index,
+                        asset: job.asset.clone(),
+                        output_key,
+                    });
+                }
+                pending = waiting;
+                if in_flight.is_empty() {
+                    break;
+                }
+                let Ok((index, result)) = done_rx.recv() else {
+                    break;
+                };
+                in_flight.remove(&index);
+                let job = &mut jobs[index];
+                finish_job(job, result, &profile_hash, cache, &mut outcome);
+                on_update(job);
+            }
+            drop(work_tx);
+        });
+
+        outcome.assets = jobs
+            .into_iter()
+            .filter(|job| matches!(job.state, PrepJobState::Done | PrepJobState::Failed))
+            .map(|job| job.asset)
+            .collect();
+        outcome
+    }
+
+    // Everything that changes the prepared output or its QA verdict.
+    #[must_use]
+    pub fn profile_hash(owner_card: &OwnerCard) -> String {
+        let quality = &owner_card.quality_policy;
+        let profile = format!(
+            "v9|profiles={:?}|segment={}|abr={:?}|aspect={:?}@{}|trim={:?}|previews={:?}|subtitles={}|loudnorm-2pass=I={}:TP={}:LRA={}|min_height={}|max_dev={}|max_tp={}|qa={:?}",
+            quality.encode_profiles,
+            quality.hls_segment_sec,
+            quality.abr_ladder,
+            quality.aspect_handling,
+            quality.aspect_tolerance_pct,
+            quality.trim,
+            quality.previews,
+            quality.extract_subtitles,
+            quality.target_audio_lufs,
+            quality.target_true_peak_dbtp,
+            quality.target_loudness_range_lu,
+            quality.min_resolution_height,
+            quality.max_audio_deviation_lufs,
+            quality.max_true_peak_dbtp,
+            quality.qa_checks
+        );
+        Sha256::digest(profile.as_bytes())
+            .iter()
+            .fold(String::new(), |mut out, byte| {
+                let _ = write!(out, "{byte:02x}");
+                out
+            })
+    }
+}
+
+fn prepare(owner_card: &OwnerCard, asset: AssetItem, output_key: &str, ffmpeg: bool) -> AssetItem {
+    if !ffmpeg {
+        return qa_without_ffmpeg(owner_card, asset);
+    }
+    match process_single_with_ffmpeg(owner_card, asset.clone(), output_key, &RunLimits::none()) {
+        Ok(processed) => processed,
+        Err(err) => fallback_reject(asset, &err),
+    }
+}
+
+// Worker thread body: prepares jobs until the dispatcher hangs up.
+fn work(
+    owner_card: &OwnerCard,
+    ffmpeg: bool,
+    timeout: Duration,
+    control: &PrepControl,
+    work_rx: &Mutex<mpsc::Receiver<Work>>,
+    done_tx: &mpsc::Sender<(usize, Result<AssetItem>)>,
+) {
+    while let Ok(work) = work_rx
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .recv()
+    {
+        let result = if ffmpeg {
+            let plan_id = work.asset.plan_id.clone();
+            let cancelled = || control.is_cancelled(&plan_id);
+            let limits = RunLimits::timeout(timeout).cancelled_by(&cancelled);
+            process_single_with_ffmpeg(owner_card, work.asset, &work.output_key, &limits)
         } else {
-            assets
-                .into_iter()
-                .map(|mut asset| {
-                    asset.audio_lufs = owner_card.quality_policy.target_audio_lufs;
-                    let resolution_ok =
-                        asset.resolution.height >= owner_card.quality_policy.min_resolution_height;
-                    let audio_ok = (asset.audio_lufs - owner_card.quality_policy.target_audio_lufs)
-                        .abs()
-                        <= owner_card.quality_policy.max_audio_deviation_lufs;
-                    asset.qa_status = if resolution_ok && audio_ok {
-                        QaStatus::Passed
-                    } else {
-                        QaStatus::Rejected
-                    };
-                    asset
-                })
-                .collect()
+            Ok(qa_without_ffmpeg(owner_card, work.asset))
+        };
+        if done_tx.send((work.index, result)).is_err() {
+            break;
+        }
+    }
+}
+
+// Records what a worker returned for `job`. Failures keep the asset as a QA
+// rejection so the queue never airs it; a cancelled job leaves it untouched.
+fn finish_job(
+    job: &mut PrepJob,
+    result: Result<AssetItem>,
+    profile_hash: &str,
+    cache: &mut PrepCache,
+    outcome: &mut PrepOutcome,
+) {
+    job.updated_at = Utc::now();
+    match result {
+        Ok(prepared) => {
+            // Rejections are not cached so a fixed toolchain gets another try.
+            if let Some(input_sha256) = prepared
+                .content_sha256()
+                .filter(|_| prepared.qa_status != QaStatus::Rejected)
+            {
+                let entry = PrepCacheEntry {
+                    input_sha256: input_sha256.to_string(),
+                    profile_hash: profile_hash.to_string(),
+                    output_path: prepared.local_path.clone(),
+                    resolution: prepared.resolution.clone(),
+                    audio_lufs: prepared.audio_lufs,
+                    qa_status: prepared.qa_status,
+                    loudness: prepared.loudness,
+                    qa_report: prepared.qa_report.clone(),
+                    encode_profile: prepared.encode_profile.clone(),
+                    renditions: prepared.renditions.clone(),
+                    framing: prepared.framing.clone(),
+                    trim: prepared.trim,
+                    previews: prepared.previews.clone(),
+                    subtitles: prepared.subtitles.clone(),
+                };
+                cache.insert(entry.clone());
+                outcome.new_entries.push(entry);
+            }
+            job.asset = prepared;
+            job.state = PrepJobState::Done;
+            job.error = None;
+            job.error_code = None;
+        }
+        Err(err) => {
+            let kind = err
+                .downcast_ref::<MediaError>()
+                .map_or(MediaErrorKind::Failed, MediaError::kind);
+            job.state = if kind == MediaErrorKind::Cancelled {
+                PrepJobState::Cancelled
+            } else {
+                job.asset = fallback_reject(job.asset.clone(), &err);
+                PrepJobState::Failed
+            };
+            job.error = Some(format!("{err:#}"));
+            job.error_code = Some(kind.code().to_string());
         }
     }
 }
 
-fn process_single_with_ffmpeg(owner_card: &OwnerCard, mut asset: AssetItem) -> Result<AssetItem> {
-    let input_path = ensure_source_video(&asset)?;
-    let output_path = prepared_path(&asset.asset_id);
+// Without ffmpeg nothing can be measured: the asset keeps the loudness it
+// arrived with and only the checks that need no decoding are run. An asset
+// with no loudness on record fails, since nothing vouches for its levels.
+fn qa_without_ffmpeg(owner_card: &OwnerCard, mut asset: AssetItem) -> AssetItem {
+    let loudness = asset.loudness.as_ref().map(|report| &report.normalized);
+    let mut report = qa::evaluate(
+        &owner_card.quality_policy,
+        asset.planned_duration_sec,
+        &asset.resolution,
+        loudness,
+        None,
+        None,
+    );
+    if loudness.is_none() {
+        report
+            .checks
+            .push(qa::unmeasured(QaCheck::Loudness, "loudness"));
+    }
+    set_verdict(&mut asset, report);
+    asset
+}
+
+fn set_verdict(asset: &mut AssetItem, report: QaReport) {
+    asset.qa_status = if report.passed() {
+        QaStatus::Passed
+    } else {
+        QaStatus::Rejected
+    };
+    asset.qa_report = Some(report);
+}
+
+fn apply_entry(mut asset: AssetItem, entry: &PrepCacheEntry) -> AssetItem {
+    asset.local_path.clone_from(&entry.output_path);
+    asset.resolution = entry.resolution.clone();
+    asset.audio_lufs = entry.audio_lufs;
+    asset.qa_status = entry.qa_status;
+    asset.loudness = entry.loudness;
+    asset.qa_report.clone_from(&entry.qa_report);
+    asset.encode_profile.clone_from(&entry.encode_profile);
+    asset.renditions.clone_from(&entry.renditions);
+    asset.framing.clone_from(&entry.framing);
+    asset.trim = entry.trim;
+    asset.previews.clone_from(&entry.previews);
+    asset.subtitles.clone_from(&entry.subtitles);
+    asset
+}
+
+fn process_single_with_ffmpeg(
+    owner_card: &OwnerCard,
+    mut asset: AssetItem,
+    output_key: &str,
+    limits: &RunLimits<'_>,
+) -> Result<AssetItem> {
+    let input_path = ensure_source_video(&asset, limits)?;
+    let output_path = prepared_path(output_key);
     if let Some(parent) = output_path.parent() {
         fs::create_dir_all(parent)
             .with_context(|| format!("failed creating {}", parent.display()))?;
     }
 
-    let target_lufs = owner_card.quality_policy.target_audio_lufs;
-    let ffmpeg_status = Command::new("ffmpeg")
-        .args([
-            "-loglevel",
-            "error",
-            "-nostats",
-            "-y",
-            "-i",
-            input_path.to_str().unwrap_or_default(),
-            "-vf",
-            "scale=w=1280:h=720:force_original_aspect_ratio=decrease,pad=1280:720:(ow-iw)/2:(oh-ih)/2",
-            "-af",
-            &format!("loudnorm=I={target_lufs}:TP=-1.5:LRA=11"),
-            "-c:v",
-            "libx264",
-            "-preset",
-            "veryfast",
-            "-c:a",
-            "aac",
-            "-b:a",
-            "128k",
-            output_path.to_str().unwrap_or_default(),
-        ])
-        .status()
-        .context("ffmpeg failed to start")?;
-
-    if !ffmpeg_status.success() {
-        anyhow::bail!("ffmpeg preprocessing failed for asset {}", asset.asset_id);
-    }
-
-    let resolution = probe_resolution(&output_path).unwrap_or(Resolution {
-        width: 1280,
-        height: 720,
+    let quality = &owner_card.quality_policy;
+    let scan = qa::scan(&input_path, &quality.qa_checks, limits)?;
+    let display = scan
+        .display
+        .clone()
+        .unwrap_or_else(|| asset.resolution.clone());
+    let profile = quality
+        .encode_profile_for(display.height)
+        .context("no encode profile configured")?;
+    let framing = framing::plan(quality, &display, scan.rotation_deg, profile);
+    if framing.handling == Some(AspectHandling::Reject) {
+        return Ok(reject_framing(quality, asset, &scan, framing));
+    }
+    let trim = trim::detect(&quality.trim, &scan);
+    let source_loudness = scan
+        .has_audio
+        .then(|| loudness::measure(&input_path, trim.as_ref(), quality, limits))
+        .transpose()?;
+    let audio_filter = source_loudness
+        .as_ref()
+        .map(|source| loudness::normalize_filter(quality, source));
+    let command = encode::command(
+        &input_path,
+        profile,
+        framing.handling,
+        quality.hls_segment_sec,
+    );
+    let mut encode = trim::apply(command, trim.as_ref());
+    if let Some(filter) = &audio_filter {
+        encode = encode.audio_filter(filter);
+    }
+    encode
+        .output(&output_path)
+        .run(limits)
+        .with_context(|| format!("encoding asset {} as {}", asset.asset_id, profile.name))?;
+
+    let resolution = probe_resolution(&output_path, limits).unwrap_or(Resolution {
+        width: profile.width,
+        height: profile.height,
     });
-    let resolution_ok = resolution.height >= owner_card.quality_policy.min_resolution_height;
+    if let Some(source) = source_loudness {
+        let normalized = loudness::measure(&output_path, None, quality, limits)?;
+        asset.audio_lufs = normalized.integrated_lufs;
+        asset.loudness = Some(LoudnessReport { source, normalized });
+    }
+    let mut report = qa::evaluate(
+        quality,
+        asset.planned_duration_sec,
+        &resolution,
+        asset.loudness.as_ref().map(|report| &report.normalized),
+        Some(&scan),
+        trim.as_ref(),
+    );
+    report.checks.push(qa::aspect(&framing));
 
     asset.local_path = output_path.to_string_lossy().to_string();
-    asset.audio_lufs = target_lufs;
     asset.resolution = resolution;
-    asset.qa_status = if resolution_ok {
-        QaStatus::Passed
-    } else {
-        QaStatus::Rejected
-    };
+    asset.encode_profile = Some(profile.name.clone());
+    let handling = framing.handling;
+    asset.framing = Some(framing);
+    asset.trim = trim;
+    set_verdict(&mut asset, report);
+    // Rejected assets never air, so their ladder, previews and subtitles are
+    // not worth the CPU.
+    if asset.qa_status == QaStatus::Passed {
+        let source = ladder::LadderSource {
+            path: &input_path,
+            height: display.height,
+            has_audio: scan.has_audio,
+            audio_filter: audio_filter.as_deref(),
+            handling,
+            trim,
+            main_profile: profile,
+            main_output: &output_path,
+        };
+        let dir = companion_dir(output_key);
+        asset.renditions = ladder::encode_ladder(quality, &source, &dir, limits)?;
+        // Previews only dress the asset up, so a failure leaves it without
+        // them rather than failing a job whose output is good.
+        asset.previews = preview::generate(&quality.previews, &output_path, &dir, limits)
+            .unwrap_or_else(|err| {
+                warn!(
+                    asset_id = asset.asset_id,
+                    error = %format_args!("{err:#}"),
+                    "preview-generation-failed"
+                );
+                None
+            });
+        asset.subtitles =
+            subtitles::extract(quality, &input_path, &scan, trim.as_ref(), &dir, limits)?;
+    }
     Ok(asset)
 }
 
-fn ensure_source_video(asset: &AssetItem) -> Result<PathBuf> {
+// Sources the policy will not reframe are rejected before anything is
+// encoded; the report still carries what the scan found.
+fn reject_framing(
+    quality: &QualityPolicy,
+    mut asset: AssetItem,
+    scan: &qa::MediaScan,
+    framing: Framing,
+) -> AssetItem {
+    let mut report = qa::evaluate(
+        quality,
+        asset.planned_duration_sec,
+        &framing.source,
+        None,
+        Some(scan),
+        None,
+    );
+    report.checks.push(qa::aspect(&framing));
+    asset.framing = Some(framing);
+    set_verdict(&mut asset, report);
+    asset
+}
+
+fn ensure_source_video(asset: &AssetItem, limits: &RunLimits<'_>) -> Result<PathBuf> {
     let input = PathBuf::from(&asset.local_path);
     if input.exists() {
         return Ok(input);
@@ -109,66 +537,28 @@ fn ensure_source_video(asset: &AssetItem) -> Result<PathBuf> {
             .with_context(|| format!("failed creating {}", parent.display()))?;
     }
 
-    let ffmpeg_status = Command::new("ffmpeg")
-        .args([
-            "-loglevel",
-            "error",
-            "-nostats",
-            "-y",
-            "-f",
-            "lavfi",
-            "-i",
-            "testsrc=size=1280x720:rate=30",
-            "-f",
-            "lavfi",
-            "-i",
-            "sine=frequency=1000:sample_rate=48000",
-            "-t",
-            "10",
-            "-c:v",
-            "libx264",
-            "-c:a",
-            "aac",
-            generated.to_str().unwrap_or_default(),
-        ])
-        .status()
-        .context("ffmpeg failed to generate synthetic source")?;
-
-    if !ffmpeg_status.success() {
-        anyhow::bail!(
-            "failed generating synthetic source for asset {}",
-            asset.asset_id
-        );
-    }
-
+    Ffmpeg::new()
+        .lavfi("testsrc=size=1280x720:rate=30")
+        .lavfi("sine=frequency=1000:sample_rate=48000")
+        .duration(10.0)
+        .video_codec("libx264")
+        .audio_codec("aac")
+        .output(&generated)
+        .run(limits)
+        .with_context(|| format!("generating synthetic source for asset {}", asset.asset_id))?;
     Ok(generated)
 }
 
-fn probe_resolution(path: &Path) -> Result<Resolution> {
-    let output = Command::new("ffprobe")
-        .args([
-            "-v",
-            "error",
-            "-select_streams",
-            "v:0",
-            "-show_entries",
-            "stream=width,height",
-            "-of",
-            "csv=s=x:p=0",
-            path.to_str().unwrap_or_default(),
-        ])
-        .output()
-        .context("ffprobe failed to start")?;
-
-    if !output.status.success() {
-        anyhow::bail!("ffprobe failed");
-    }
-
-    let text = String::from_utf8(output.stdout).context("ffprobe output not utf8")?;
-    let mut parts = text.trim().split('x');
-    let width: u16 = parts.next().unwrap_or("0").parse().unwrap_or(0);
-    let height: u16 = parts.next().unwrap_or("0").parse().unwrap_or(0);
-    Ok(Resolution { width, height })
+fn probe_resolution(path: &Path, limits: &RunLimits<'_>) -> Result<Resolution> {
+    let probe = vvtv_media_tools::probe(path, limits)?;
+    let video = probe
+        .first(StreamKind::Video)
+        .with_context(|| format!("no video stream in {}", path.display()))?;
+    let dimension = |value: Option<u32>| value.and_then(|v| u16::try_from(v).ok()).unwrap_or(0);
+    Ok(Resolution {
+        width: dimension(video.width),
+        height: dimension(video.height),
+    })
 }
 
 fn generated_input_path(asset_id: &str) -> PathBuf {
@@ -177,17 +567,91 @@ fn generated_input_path(asset_id: &str) -> PathBuf {
         .join(format!("{asset_id}.mp4"))
 }
 
-fn prepared_path(asset_id: &str) -> PathBuf {
+fn prepared_path(output_key: &str) -> PathBuf {
     Path::new("runtime")
         .join("prepared")
-        .join(format!("{asset_id}.mp4"))
+        .join(format!("{output_key}.mp4"))
 }
 
-fn command_exists(cmd: &str) -> bool {
-    Command::new(cmd).arg("-version").output().is_ok()
+// The ladder, previews and subtitles of an output sit in a directory named
+// after it.
+fn companion_dir(output_key: &str) -> PathBuf {
+    Path::new("runtime").join("prepared").join(output_key)
 }
 
-fn fallback_reject(mut asset: AssetItem) -> AssetItem {
-    asset.qa_status = QaStatus::Rejected;
+fn fallback_reject(mut asset: AssetItem, error: &anyhow::Error) -> AssetItem {
+    set_verdict(&mut asset, qa::prep_failed(error));
     asset
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(output_path: &Path) -> PrepCacheEntry {
+        PrepCacheEntry {
+            input_sha256: "abc".to_string(),
+            profile_hash: "p1".to_string(),
+            output_path: output_path.to_string_lossy().into_owned(),
+            resolution: Resolution {
+                width: 1280,
+                height: 720,
+            },
+            audio_lufs: -16.0,
+            qa_status: QaStatus::Passed,
+            loudness: None,
+            qa_report: None,
+            encode_profile: Some("720p".to_string()),
+            renditions: Vec::new(),
+            framing: None,
+            trim: None,
+            previews: None,
+            subtitles: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn cache_hits_need_the_prepared_output_on_disk() {
+        let dir = std::env::temp_dir().join(format!("vvtv-prep-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let present = dir.join("present.mp4");
+        fs::write(&present, b"prepared").unwrap();
+
+        let cache = PrepCache::new([entry(&present)]);
+        let hit = cache.get("abc", "p1").expect("cached output exists");
+        assert!(cache.get("abc", "p2").is_none());
+
+        let asset = AssetItem {
+            asset_id: "asset-1".to_string(),
+            plan_id: "plan-1".to_string(),
+            local_path: "runtime/ingest/objects/abc.mp4".to_string(),
+            checksum: "sha256:abc".to_string(),
+            resolution: Resolution {
+                width: 640,
+                height: 360,
+            },
+            audio_lufs: -30.0,
+            qa_status: QaStatus::Pending,
+            pinned_start_at: None,
+            tags: vec![],
+            loudness: None,
+            planned_duration_sec: None,
+            qa_report: None,
+            encode_profile: None,
+            renditions: Vec::new(),
+            framing: None,
+            trim: None,
+            previews: None,
+            subtitles: Vec::new(),
+        };
+        let applied = apply_entry(asset, hit);
+        assert_eq!(applied.local_path, present.to_string_lossy());
+        assert_eq!(applied.resolution.height, 720);
+        assert_eq!(applied.qa_status, QaStatus::Passed);
+        assert_eq!(applied.encode_profile.as_deref(), Some("720p"));
+
+        let missing = PrepCache::new([entry(&dir.join("missing.mp4"))]);
+        assert!(missing.get("abc", "p1").is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,132 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use vvtv_media_tools::{Ffmpeg, RunLimits};
+use vvtv_types::{LoudnessMeasurement, QualityPolicy, Trim};
+
+use crate::trim;
+
+// Lower bound `loudnorm` accepts for measured values; silence reports `-inf`.
+const FLOOR: f32 = -99.0;
+
+// The JSON block `loudnorm` prints on stderr with `print_format=json`.
+#[derive(Debug, Deserialize)]
+struct LoudnormStats {
+    #[serde(rename = "input_i")]
+    integrated: String,
+    #[serde(rename = "input_tp")]
+    true_peak: String,
+    #[serde(rename = "input_lra")]
+    range: String,
+    #[serde(rename = "input_thresh")]
+    threshold: String,
+}
+
+// First pass: measures `path`, or the part `trim` keeps, without writing
+// anything.
+pub(crate) fn measure(
+    path: &Path,
+    trim: Option<&Trim>,
+    quality: &QualityPolicy,
+    limits: &RunLimits<'_>,
+) -> Result<LoudnessMeasurement> {
+    let measure = Ffmpeg::new().log_level("info").input(path).no_video();
+    let stderr = trim::apply(measure, trim)
+        .audio_filter(format!("{}:print_format=json", target(quality)))
+        .run(limits)
+        .with_context(|| format!("measuring loudness of {}", path.display()))?;
+    parse_stats(&stderr)
+}
+
+// Second pass: normalizes with the values measured on the source so the gain
+// is applied linearly instead of being guessed as the stream plays.
+pub(crate) fn normalize_filter(quality: &QualityPolicy, source: &LoudnessMeasurement) -> String {
+    format!(
+        "{}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:linear=true",
+        target(quality),
+        source.integrated_lufs,
+        source.true_peak_dbtp,
+        source.loudness_range_lu,
+        source.threshold_lufs
+    )
+}
+
+fn target(quality: &QualityPolicy) -> String {
+    format!(
+        "loudnorm=I={}:TP={}:LRA={}",
+        quality.target_audio_lufs, quality.target_true_peak_dbtp, quality.target_loudness_range_lu
+    )
+}
+
+fn parse_stats(stderr: &str) -> Result<LoudnessMeasurement> {
+    let start = stderr
+        .rfind('{')
+        .context("loudnorm printed no measurement")?;
+    let end = start
+        + stderr[start..]
+            .find('}')
+            .context("loudnorm measurement is truncated")?;
+    let stats: LoudnormStats =
+        serde_json::from_str(&stderr[start..=end]).context("unreadable loudnorm measurement")?;
+    Ok(LoudnessMeasurement {
+        integrated_lufs: value(&stats.integrated),
+        true_peak_dbtp: value(&stats.true_peak),
+        loudness_range_lu: value(&stats.range).max(0.0),
+        threshold_lufs: value(&stats.threshold),
+    })
+}
+
+fn value(raw: &str) -> f32 {
+    raw.trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|value| value.is_finite())
+        .map_or(FLOOR, |value| value.max(FLOOR))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STDERR: &str = r#"Input #0, mov,mp4,m4a,3gp,3g2,mj2, from 'in.mp4':
+  Duration: 00:00:10.00, start: 0.000000, bitrate: 1200 kb/s
+[Parsed_loudnorm_0 @ 0x5581]
+{
+	"input_i" : "-27.41",
+	"input_tp" : "-9.02",
+	"input_lra" : "3.10",
+	"input_thresh" : "-37.60",
+	"output_i" : "-16.12",
+	"output_tp" : "-1.51",
+	"output_lra" : "2.90",
+	"output_thresh" : "-26.31",
+	"normalization_type" : "dynamic",
+	"target_offset" : "0.12"
+}
+"#;
+
+    #[test]
+    fn parses_the_loudnorm_report() {
+        let measured = parse_stats(STDERR).unwrap();
+        assert!((measured.integrated_lufs + 27.41).abs() < 1e-4);
+        assert!((measured.true_peak_dbtp + 9.02).abs() < 1e-4);
+        assert!((measured.loudness_range_lu - 3.1).abs() < 1e-4);
+        assert!((measured.threshold_lufs + 37.6).abs() < 1e-4);
+
+        let silent = STDERR.replace("\"-27.41\"", "\"-inf\"");
+        assert!((parse_stats(&silent).unwrap().integrated_lufs - FLOOR).abs() < f32::EPSILON);
+        assert!(parse_stats("no json here").is_err());
+    }
+
+    #[test]
+    fn second_pass_feeds_back_the_measurement() {
+        let quality = QualityPolicy::default();
+        let filter = normalize_filter(&quality, &parse_stats(STDERR).unwrap());
+        assert_eq!(
+            filter,
+            "loudnorm=I=-16:TP=-1.5:LRA=11:measured_I=-27.41:measured_TP=-9.02:\
+             measured_LRA=3.1:measured_thresh=-37.6:linear=true"
+        );
+    }
+}
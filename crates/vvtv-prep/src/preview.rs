@@ -0,0 +1,204 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use vvtv_media_tools::{Ffmpeg, RunLimits, StreamKind, vtt_timestamp};
+use vvtv_types::{PreviewPolicy, Previews};
+
+const SPRITE_FILE: &str = "thumbnails.jpg";
+
+// Poster, thumbnail sprite with its WebVTT track, and preview clip of the
+// prepared `output`, written into `dir`. Taken from the output rather than
+// the source, so they show the trimmed, reframed picture that airs. `None`
+// when previews are off or the output has no measurable runtime.
+pub(crate) fn generate(
+    policy: &PreviewPolicy,
+    output: &Path,
+    dir: &Path,
+    limits: &RunLimits<'_>,
+) -> Result<Option<Previews>> {
+    if !policy.enabled {
+        return Ok(None);
+    }
+    let probe = vvtv_media_tools::probe(output, limits)?;
+    let (Some(duration), Some((width, height))) = (
+        probe.duration_sec.filter(|duration| *duration > 0.0),
+        probe
+            .first(StreamKind::Video)
+            .and_then(|video| Some((video.width?, video.height?)))
+            .filter(|(width, height)| *width > 0 && *height > 0),
+    ) else {
+        return Ok(None);
+    };
+    fs::create_dir_all(dir).with_context(|| format!("failed creating {}", dir.display()))?;
+
+    let poster_at = duration * policy.poster_at_pct / 100.0;
+    let poster_width = u32::from(policy.poster_width);
+    let poster = dir.join("poster.jpg");
+    Ffmpeg::new()
+        .input(output)
+        .start(poster_at)
+        .video_filter(format!(
+            "scale={poster_width}:{}",
+            scaled_height(poster_width, width, height)
+        ))
+        .frames(1)
+        .output(&poster)
+        .run(limits)
+        .context("grabbing the poster frame")?;
+
+    let sheet = Sprite::new(policy, duration, width, height);
+    let sprite = dir.join(SPRITE_FILE);
+    Ffmpeg::new()
+        .input(output)
+        .video_filter(format!(
+            "fps=1/{},scale={}:{},tile={}x{}",
+            policy.thumbnail_interval_sec,
+            sheet.tile_width,
+            sheet.tile_height,
+            sheet.columns,
+            sheet.rows
+        ))
+        .frames(1)
+        .output(&sprite)
+        .run(limits)
+        .context("tiling the thumbnail sprite")?;
+    let track = dir.join("thumbnails.vtt");
+    fs::write(&track, sheet.track(policy.thumbnail_interval_sec, duration))
+        .with_context(|| format!("failed writing {}", track.display()))?;
+
+    // The clip starts at the poster frame, moved back if the output ends
+    // before the clip would.
+    let clip_sec = within(f64::from(policy.clip_sec), duration);
+    let clip = dir.join("preview.mp4");
+    Ffmpeg::new()
+        .input(output)
+        .start(poster_at.min(duration - clip_sec))
+        .duration(clip_sec)
+        .video_filter(format!("scale=-2:{}", policy.clip_height))
+        .video_codec("libx264")
+        .preset("veryfast")
+        .video_bitrate_kbps(policy.clip_video_kbps)
+        .audio_codec("aac")
+        .audio_bitrate_kbps(policy.clip_audio_kbps)
+        .output(&clip)
+        .run(limits)
+        .context("encoding the preview clip")?;
+
+    let path = |path: &Path| path.to_string_lossy().into_owned();
+    Ok(Some(Previews {
+        poster_path: path(&poster),
+        sprite_path: path(&sprite),
+        thumbnails_vtt_path: path(&track),
+        clip_path: path(&clip),
+    }))
+}
+
+// Layout of the thumbnail sprite: `count` tiles, filled row by row.
+#[derive(Debug, PartialEq, Eq)]
+struct Sprite {
+    count: u32,
+    columns: u32,
+    rows: u32,
+    tile_width: u32,
+    tile_height: u32,
+}
+
+impl Sprite {
+    fn new(policy: &PreviewPolicy, duration_sec: f32, width: u32, height: u32) -> Self {
+        let interval_ms = u128::from(policy.thumbnail_interval_sec) * 1000;
+        let count = u32::try_from(millis(duration_sec).div_ceil(interval_ms))
+            .unwrap_or(u32::MAX)
+            .max(1);
+        let columns = u32::from(policy.sprite_columns).min(count);
+        let tile_width = u32::from(policy.thumbnail_width);
+        Self {
+            count,
+            columns,
+            rows: count.div_ceil(columns),
+            tile_width,
+            tile_height: scaled_height(tile_width, width, height),
+        }
+    }
+
+    // The WebVTT thumbnail track: one cue per interval, the last one ending
+    // with the output.
+    fn track(&self, interval_sec: u32, duration_sec: f32) -> String {
+        let mut out = String::from("WEBVTT\n");
+        for index in 0..self.count {
+            let start = f64::from(index) * f64::from(interval_sec);
+            let _ = write!(
+                out,
+                "\n{} --> {}\n{SPRITE_FILE}#xywh={},{},{},{}\n",
+                vtt_timestamp(within(start, duration_sec)),
+                vtt_timestamp(within(start + f64::from(interval_sec), duration_sec)),
+                index % self.columns * self.tile_width,
+                index / self.columns * self.tile_height,
+                self.tile_width,
+                self.tile_height
+            );
+        }
+        out
+    }
+}
+
+// Height of a `width`-wide picture with the frame's aspect, kept even.
+fn scaled_height(width: u32, frame_width: u32, frame_height: u32) -> u32 {
+    let height = u64::from(width) * u64::from(frame_height) / u64::from(frame_width);
+    u32::try_from(height).unwrap_or(u32::MAX).max(2) & !1
+}
+
+// `secs`, cut at the output runtime. The result never exceeds an f32 value,
+// so narrowing it back only rounds within f32 precision.
+#[allow(clippy::cast_possible_truncation)]
+fn within(secs: f64, duration_sec: f32) -> f32 {
+    secs.min(f64::from(duration_sec)) as f32
+}
+
+fn millis(secs: f32) -> u128 {
+    Duration::try_from_secs_f32(secs).map_or(0, |duration| duration.as_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sprite_tiles_follow_the_output_aspect() {
+        let policy = PreviewPolicy::default();
+        // 95 seconds at one thumbnail per 10 seconds.
+        let sheet = Sprite::new(&policy, 95.0, 1920, 1080);
+        assert_eq!(
+            sheet,
+            Sprite {
+                count: 10,
+                columns: 10,
+                rows: 1,
+                tile_width: 160,
+                tile_height: 90,
+            }
+        );
+        let short = Sprite::new(&policy, 25.0, 1280, 720);
+        assert_eq!((short.count, short.columns, short.rows), (3, 3, 1));
+        let long = Sprite::new(&policy, 3_600.0, 720, 1280);
+        assert_eq!((long.columns, long.rows, long.tile_height), (10, 36, 284));
+    }
+
+    #[test]
+    fn thumbnail_track_points_each_interval_at_its_tile() {
+        let policy = PreviewPolicy {
+            sprite_columns: 2,
+            ..PreviewPolicy::default()
+        };
+        let sheet = Sprite::new(&policy, 25.5, 1920, 1080);
+        assert_eq!(
+            sheet.track(10, 25.5),
+            "WEBVTT\n\
+             \n00:00:00.000 --> 00:00:10.000\nthumbnails.jpg#xywh=0,0,160,90\n\
+             \n00:00:10.000 --> 00:00:20.000\nthumbnails.jpg#xywh=160,0,160,90\n\
+             \n00:00:20.000 --> 00:00:25.500\nthumbnails.jpg#xywh=0,90,160,90\n"
+        );
+    }
+}
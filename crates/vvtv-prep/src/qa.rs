@@ -0,0 +1,558 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use vvtv_media_tools::{Ffmpeg, ProbeInfo, RunLimits, StreamInfo, StreamKind};
+use vvtv_types::{
+    AspectHandling, Framing, LoudnessMeasurement, QaCheck, QaCheckResult, QaReport, QaThresholds,
+    QualityPolicy, Resolution, Trim,
+};
+
+// Shortest frozen or silent stretch the detectors report.
+const MIN_DETECTION_SEC: f32 = 1.0;
+
+// What the detectors and ffprobe found in one file.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct MediaScan {
+    pub duration_sec: Option<f32>,
+    pub has_video: bool,
+    pub has_audio: bool,
+    // The source picture as displayed, which picks the encode profile and
+    // the framing.
+    pub display: Option<Resolution>,
+    pub rotation_deg: u16,
+    pub subtitle_streams: Vec<StreamInfo>,
+    // CEA-608 captions inside the video stream.
+    pub closed_captions: bool,
+    // Audio start minus video start.
+    pub av_offset_sec: Option<f32>,
+    pub black_sec: f32,
+    // Detected stretches as (start, end) seconds, in the order ffmpeg logged
+    // them.
+    pub black_spans: Vec<(f32, f32)>,
+    pub silence_spans: Vec<(f32, f32)>,
+    pub freeze_spans: Vec<(f32, f32)>,
+    pub longest_freeze_sec: f32,
+    pub longest_silence_sec: f32,
+    pub decode_errors: u32,
+}
+
+impl MediaScan {
+    // The detections inside the part of the source that airs, timed from the
+    // new start. Dead air cut by the trim no longer counts against QA.
+    pub fn aired(&self, trim: &Trim) -> MediaScan {
+        let (from, until) = (trim.head_sec, trim.head_sec + trim.effective_duration_sec);
+        let clip = |spans: &[(f32, f32)]| -> Vec<(f32, f32)> {
+            spans
+                .iter()
+                .map(|&(start, end)| (start.max(from) - from, end.min(until) - from))
+                .filter(|(start, end)| end > start)
+                .collect()
+        };
+        let longest = |spans: &[(f32, f32)]| {
+            spans
+                .iter()
+                .fold(0.0_f32, |longest, (start, end)| longest.max(end - start))
+        };
+        let black_spans = clip(&self.black_spans);
+        let silence_spans = clip(&self.silence_spans);
+        let freeze_spans = clip(&self.freeze_spans);
+        MediaScan {
+            duration_sec: Some(trim.effective_duration_sec),
+            black_sec: black_spans.iter().map(|(start, end)| end - start).sum(),
+            longest_silence_sec: longest(&silence_spans),
+            longest_freeze_sec: longest(&freeze_spans),
+            black_spans,
+            silence_spans,
+            freeze_spans,
+            ..self.clone()
+        }
+    }
+}
+
+// Probes the streams, then decodes the whole file once with the black, freeze
+// and silence detectors attached.
+pub(crate) fn scan(
+    path: &Path,
+    thresholds: &QaThresholds,
+    limits: &RunLimits<'_>,
+) -> Result<MediaScan> {
+    let mut scan = from_probe(&vvtv_media_tools::probe(path, limits)?);
+
+    let mut decode = Ffmpeg::new().log_level("info").input(path);
+    if scan.has_video {
+        decode = decode.video_filter(video_detectors(thresholds));
+    }
+    if scan.has_audio {
+        decode = decode.audio_filter(format!(
+            "silencedetect=n={}dB:d={MIN_DETECTION_SEC}",
+            thresholds.silence_noise_db
+        ));
+    }
+    let stderr = decode
+        .run(limits)
+        .with_context(|| format!("decode pass over {}", path.display()))?;
+    apply_detections(&mut scan, &stderr);
+    Ok(scan)
+}
+
+fn video_detectors(thresholds: &QaThresholds) -> String {
+    format!(
+        "blackdetect=d={}:pix_th={},freezedetect=n={}dB:d={MIN_DETECTION_SEC}",
+        thresholds.black_min_duration_sec,
+        thresholds.black_pixel_threshold,
+        thresholds.freeze_noise_db
+    )
+}
+
+fn from_probe(probe: &ProbeInfo) -> MediaScan {
+    let start_of = |kind| {
+        probe
+            .first(kind)
+            .map(|stream| stream.start_sec.unwrap_or(0.0))
+    };
+    let (video_start, audio_start) = (start_of(StreamKind::Video), start_of(StreamKind::Audio));
+    let video = probe.first(StreamKind::Video);
+    MediaScan {
+        duration_sec: probe.duration_sec,
+        has_video: video_start.is_some(),
+        has_audio: audio_start.is_some(),
+        display: video
+            .and_then(StreamInfo::display_size)
+            .and_then(|(width, height)| {
+                Some(Resolution {
+                    width: u16::try_from(width).ok()?,
+                    height: u16::try_from(height).ok()?,
+                })
+            }),
+        rotation_deg: video.map_or(0, |stream| stream.rotation_deg),
+        subtitle_streams: probe
+            .streams
+            .iter()
+            .filter(|stream| stream.kind == StreamKind::Subtitle)
+            .cloned()
+            .collect(),
+        closed_captions: video.is_some_and(|stream| stream.closed_captions),
+        av_offset_sec: video_start
+            .zip(audio_start)
+            .map(|(video, audio)| audio - video),
+        ..MediaScan::default()
+    }
+}
+
+// Reads the detector lines ffmpeg logs, plus decoder errors, from stderr.
+fn apply_detections(scan: &mut MediaScan, stderr: &str) {
+    // freezedetect logs the duration and the end on separate lines.
+    let mut freeze_duration = None;
+    for line in stderr.lines() {
+        if line.contains("[blackdetect") {
+            scan.black_sec += field(line, "black_duration:").unwrap_or(0.0);
+            if let (Some(start), Some(end)) =
+                (field(line, "black_start:"), field(line, "black_end:"))
+            {
+                scan.black_spans.push((start, end));
+            }
+        } else if line.contains("[freezedetect") {
+            if let Some(duration) = field(line, "freeze_duration:") {
+                scan.longest_freeze_sec = scan.longest_freeze_sec.max(duration);
+                freeze_duration = Some(duration);
+            } else if let Some(end) = field(line, "freeze_end:") {
+                if let Some(duration) = freeze_duration.take() {
+                    scan.freeze_spans.push((end - duration, end));
+                }
+            }
+        } else if line.contains("[silencedetect") {
+            if let Some(duration) = field(line, "silence_duration:") {
+                scan.longest_silence_sec = scan.longest_silence_sec.max(duration);
+                // The end line carries the duration; the start is implied.
+                if let Some(end) = field(line, "silence_end:") {
+                    scan.silence_spans.push((end - duration, end));
+                }
+            }
+        } else if is_decode_error(line) {
+            scan.decode_errors += 1;
+        }
+    }
+}
+
+fn is_decode_error(line: &str) -> bool {
+    let lower = line.to_ascii_lowercase();
+    line.starts_with('[')
+        && [
+            "error while decoding",
+            "corrupt",
+            "invalid nal",
+            "concealing",
+        ]
+        .iter()
+        .any(|marker| lower.contains(marker))
+}
+
+fn field(line: &str, name: &str) -> Option<f32> {
+    let rest = &line[line.find(name)? + name.len()..];
+    rest.split_whitespace().next()?.parse().ok()
+}
+
+// Holds the scan, the output resolution and the normalized loudness against
+// the policy. Checks without data (no ffmpeg, no planned duration) are left out.
+// With a `trim`, the detectors only count the part of the source that airs.
+pub(crate) fn evaluate(
+    quality: &QualityPolicy,
+    planned_duration_sec: Option<u32>,
+    resolution: &Resolution,
+    loudness: Option<&LoudnessMeasurement>,
+    scan: Option<&MediaScan>,
+    trim: Option<&Trim>,
+) -> QaReport {
+    let limits = &quality.qa_checks;
+    let mut checks = vec![at_least(
+        QaCheck::Resolution,
+        resolution.height,
+        quality.min_resolution_height,
+        "output height",
+    )];
+
+    if let Some(loudness) = loudness {
+        checks.push(at_most(
+            QaCheck::Loudness,
+            (loudness.integrated_lufs - quality.target_audio_lufs).abs(),
+            quality.max_audio_deviation_lufs,
+            "LU from the loudness target",
+        ));
+        checks.push(at_most(
+            QaCheck::TruePeak,
+            loudness.true_peak_dbtp,
+            quality.max_true_peak_dbtp,
+            "dBTP true peak",
+        ));
+    }
+
+    let Some(source) = scan else {
+        return QaReport { checks };
+    };
+    let aired = trim.map(|trim| source.aired(trim));
+    let scan = aired.as_ref().unwrap_or(source);
+    checks.push(present(QaCheck::VideoStream, scan.has_video, "video"));
+    if limits.require_audio {
+        checks.push(present(QaCheck::AudioStream, scan.has_audio, "audio"));
+    }
+    if let Some(offset) = scan.av_offset_sec {
+        checks.push(at_most(
+            QaCheck::AvSync,
+            offset.abs() * 1000.0,
+            limits.max_av_offset_ms,
+            "ms between audio and video start",
+        ));
+    }
+    if let Some(duration) = scan.duration_sec.filter(|duration| *duration > 0.0) {
+        checks.push(at_most(
+            QaCheck::BlackFrames,
+            scan.black_sec / duration,
+            limits.max_black_ratio,
+            "share of the runtime that is black",
+        ));
+        if let (Some(planned), Some(probed)) = (
+            planned_duration_sec.filter(|planned| *planned > 0),
+            source.duration_sec,
+        ) {
+            let planned = f64::from(planned);
+            checks.push(at_most(
+                QaCheck::DurationMismatch,
+                (f64::from(probed) - planned).abs() / planned * 100.0,
+                limits.max_duration_mismatch_pct,
+                "% off the planned duration",
+            ));
+        }
+    }
+    if scan.has_video {
+        checks.push(at_most(
+            QaCheck::FrozenVideo,
+            scan.longest_freeze_sec,
+            limits.max_freeze_sec,
+            "s longest frozen stretch",
+        ));
+    }
+    if scan.has_audio {
+        checks.push(at_most(
+            QaCheck::Silence,
+            scan.longest_silence_sec,
+            limits.max_silence_sec,
+            "s longest silence",
+        ));
+    }
+    checks.push(at_most(
+        QaCheck::DecodeErrors,
+        scan.decode_errors,
+        limits.max_decode_errors,
+        "decode errors",
+    ));
+    QaReport { checks }
+}
+
+// Fails only when the policy rejects sources off the output aspect.
+pub(crate) fn aspect(framing: &Framing) -> QaCheckResult {
+    let source = &framing.source;
+    let handled = match framing.handling {
+        None => "fits the frame",
+        Some(AspectHandling::Reject) => "off the frame aspect",
+        Some(AspectHandling::Pillarbox) => "pillarboxed",
+        Some(AspectHandling::BlurredPillarbox) => "pillarboxed over a blur",
+        Some(AspectHandling::Crop) => "cropped",
+    };
+    QaCheckResult {
+        check: QaCheck::AspectRatio,
+        passed: framing.handling != Some(AspectHandling::Reject),
+        measured: Some(f64::from(source.width) / f64::from(source.height.max(1))),
+        limit: None,
+        detail: format!("{}x{} source {handled}", source.width, source.height),
+    }
+}
+
+// The report of an asset prep could not process at all.
+pub(crate) fn prep_failed(error: &anyhow::Error) -> QaReport {
+    QaReport {
+        checks: vec![QaCheckResult {
+            check: QaCheck::Prep,
+            passed: false,
+            measured: None,
+            limit: None,
+            detail: format!("{error:#}"),
+        }],
+    }
+}
+
+fn at_most(
+    check: QaCheck,
+    measured: impl Into<f64>,
+    limit: impl Into<f64>,
+    unit: &str,
+) -> QaCheckResult {
+    let (measured, limit) = (measured.into(), limit.into());
+    QaCheckResult {
+        check,
+        passed: measured <= limit,
+        measured: Some(measured),
+        limit: Some(limit),
+        detail: format!("{measured:.2} {unit} (max {limit:.2})"),
+    }
+}
+
+fn at_least(
+    check: QaCheck,
+    measured: impl Into<f64>,
+    limit: impl Into<f64>,
+    unit: &str,
+) -> QaCheckResult {
+    let (measured, limit) = (measured.into(), limit.into());
+    QaCheckResult {
+        check,
+        passed: measured >= limit,
+        measured: Some(measured),
+        limit: Some(limit),
+        detail: format!("{measured:.0} {unit} (min {limit:.0})"),
+    }
+}
+
+// Fails a check whose measurement could not be taken.
+pub(crate) fn unmeasured(check: QaCheck, what: &str) -> QaCheckResult {
+    QaCheckResult {
+        check,
+        passed: false,
+        measured: None,
+        limit: None,
+        detail: format!("{what} not measured"),
+    }
+}
+
+fn present(check: QaCheck, found: bool, kind: &str) -> QaCheckResult {
+    QaCheckResult {
+        check,
+        passed: found,
+        measured: None,
+        limit: None,
+        detail: if found {
+            format!("{kind} stream present")
+        } else {
+            format!("no {kind} stream")
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROBE: &str = r#"{
+        "streams": [
+            {"codec_type": "video", "width": 1920, "height": 1080, "start_time": "0.000000",
+             "tags": {"rotate": "90"}},
+            {"codec_type": "audio", "start_time": "0.480000"}
+        ],
+        "format": {"duration": "600.000000"}
+    }"#;
+
+    const STDERR: &str = "\
+[blackdetect @ 0x55d1] black_start:0 black_end:2.5 black_duration:2.5
+[freezedetect @ 0x55d2] lavfi.freezedetect.freeze_start: 100
+[freezedetect @ 0x55d2] lavfi.freezedetect.freeze_duration: 12.4
+[freezedetect @ 0x55d2] lavfi.freezedetect.freeze_end: 112.4
+[silencedetect @ 0x55d3] silence_start: 300
+[silencedetect @ 0x55d3] silence_end: 303.5 | silence_duration: 3.5
+[h264 @ 0x55d4] error while decoding MB 12 7, bytestream -5
+[blackdetect @ 0x55d1] black_start:598 black_end:600 black_duration:2
+";
+
+    fn scanned() -> MediaScan {
+        let mut scan = from_probe(&ProbeInfo::from_json(PROBE).unwrap());
+        apply_detections(&mut scan, STDERR);
+        scan
+    }
+
+    #[test]
+    fn reads_probe_and_detector_output() {
+        let scan = scanned();
+        assert_eq!(scan.duration_sec, Some(600.0));
+        assert!(scan.has_video && scan.has_audio);
+        assert_eq!(
+            scan.display,
+            Some(Resolution {
+                width: 1080,
+                height: 1920
+            })
+        );
+        assert_eq!(scan.rotation_deg, 90);
+        assert!((scan.av_offset_sec.unwrap() - 0.48).abs() < 1e-4);
+        assert!((scan.black_sec - 4.5).abs() < 1e-4);
+        assert!((scan.longest_freeze_sec - 12.4).abs() < 1e-4);
+        assert!((scan.longest_silence_sec - 3.5).abs() < 1e-4);
+        assert_eq!(scan.decode_errors, 1);
+        assert_eq!(scan.black_spans, [(0.0, 2.5), (598.0, 600.0)]);
+        assert_eq!(scan.silence_spans, [(300.0, 303.5)]);
+        assert_eq!(scan.freeze_spans, [(100.0, 112.4)]);
+    }
+
+    #[test]
+    fn trimmed_dead_air_does_not_count() {
+        let quality = QualityPolicy::default();
+        let resolution = Resolution {
+            width: 1280,
+            height: 720,
+        };
+        // A 20 s silent, black and frozen intro.
+        let mut scan = from_probe(&ProbeInfo::from_json(PROBE).unwrap());
+        apply_detections(
+            &mut scan,
+            "\
+[blackdetect @ 0x55d1] black_start:0 black_end:20 black_duration:20
+[freezedetect @ 0x55d2] lavfi.freezedetect.freeze_start: 0
+[freezedetect @ 0x55d2] lavfi.freezedetect.freeze_duration: 20
+[freezedetect @ 0x55d2] lavfi.freezedetect.freeze_end: 20
+[silencedetect @ 0x55d3] silence_end: 20 | silence_duration: 20
+",
+        );
+        scan.av_offset_sec = None;
+        let untrimmed = evaluate(&quality, Some(600), &resolution, None, Some(&scan), None);
+        assert_eq!(
+            untrimmed.reason_codes(),
+            ["QA_FROZEN_VIDEO", "QA_LONG_SILENCE"]
+        );
+
+        let trim = crate::trim::detect(&quality.trim, &scan).unwrap();
+        assert!((trim.head_sec - 20.0).abs() < 1e-4);
+        let aired = scan.aired(&trim);
+        assert!(aired.black_sec.abs() < 1e-4);
+        assert!(aired.silence_spans.is_empty() && aired.longest_freeze_sec.abs() < 1e-4);
+        let report = evaluate(
+            &quality,
+            Some(600),
+            &resolution,
+            None,
+            Some(&scan),
+            Some(&trim),
+        );
+        assert!(report.passed());
+    }
+
+    #[test]
+    fn every_failed_check_carries_its_own_reason() {
+        let quality = QualityPolicy::default();
+        let resolution = Resolution {
+            width: 1280,
+            height: 720,
+        };
+        let loudness = LoudnessMeasurement {
+            integrated_lufs: -16.2,
+            true_peak_dbtp: -0.5,
+            loudness_range_lu: 6.0,
+            threshold_lufs: -26.0,
+        };
+
+        let report = evaluate(
+            &quality,
+            Some(900),
+            &resolution,
+            Some(&loudness),
+            Some(&scanned()),
+            None,
+        );
+
+        assert!(!report.passed());
+        assert_eq!(
+            report.reason_codes(),
+            [
+                "QA_TRUE_PEAK",
+                "QA_AV_DESYNC",
+                "QA_DURATION_MISMATCH",
+                "QA_FROZEN_VIDEO",
+                "QA_DECODE_ERRORS"
+            ]
+        );
+        let black = report
+            .checks
+            .iter()
+            .find(|check| check.check == QaCheck::BlackFrames)
+            .unwrap();
+        assert!(black.passed);
+        assert!((black.measured.unwrap() - 0.0075).abs() < 1e-4);
+    }
+
+    #[test]
+    fn missing_streams_fail_and_unknown_data_is_skipped() {
+        let quality = QualityPolicy::default();
+        let resolution = Resolution {
+            width: 1280,
+            height: 720,
+        };
+        let scan = from_probe(
+            &ProbeInfo::from_json(r#"{"streams": [{"codec_type": "video"}], "format": {}}"#)
+                .unwrap(),
+        );
+
+        let report = evaluate(&quality, Some(900), &resolution, None, Some(&scan), None);
+        assert_eq!(report.reason_codes(), ["QA_NO_AUDIO"]);
+
+        let bare = evaluate(&quality, None, &resolution, None, None, None);
+        assert!(bare.passed());
+        assert_eq!(bare.checks.len(), 1);
+    }
+
+    #[test]
+    fn only_a_rejecting_policy_fails_the_aspect_check() {
+        let mut framing = Framing {
+            source: Resolution {
+                width: 1080,
+                height: 1920,
+            },
+            rotation_deg: 90,
+            handling: Some(AspectHandling::BlurredPillarbox),
+        };
+        let check = aspect(&framing);
+        assert!(check.passed);
+        assert_eq!(check.detail, "1080x1920 source pillarboxed over a blur");
+
+        framing.handling = Some(AspectHandling::Reject);
+        let report = QaReport {
+            checks: vec![aspect(&framing)],
+        };
+        assert_eq!(report.reason_codes(), ["QA_ASPECT_RATIO"]);
+    }
+}
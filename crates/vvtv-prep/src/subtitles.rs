@@ -0,0 +1,167 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use tracing::warn;
+use vvtv_media_tools::{Cue, Ffmpeg, RunLimits, parse_cues, render_cues};
+use vvtv_types::{QualityPolicy, SubtitleTrack, Trim};
+
+use crate::qa::MediaScan;
+
+// Subtitle codecs ffmpeg can turn into WebVTT. Bitmap subtitles (DVD, PGS,
+// DVB) would need OCR and are left out.
+const TEXT_CODECS: &[&str] = &["mov_text", "subrip", "ass", "ssa", "webvtt", "text"];
+
+// The text subtitle streams of `source`, plus the CEA-608 captions in its
+// video, as WebVTT files in `dir` on the timeline of the trimmed output. The
+// first stream of each language that converts is kept; a track ffmpeg fails
+// on is logged and skipped, and one left without cues after trimming is
+// dropped.
+pub(crate) fn extract(
+    quality: &QualityPolicy,
+    source: &Path,
+    scan: &MediaScan,
+    trim: Option<&Trim>,
+    dir: &Path,
+    limits: &RunLimits<'_>,
+) -> Result<Vec<SubtitleTrack>> {
+    let mut tracks: Vec<SubtitleTrack> = Vec::new();
+    if !quality.extract_subtitles {
+        return Ok(tracks);
+    }
+    fs::create_dir_all(dir).with_context(|| format!("failed creating {}", dir.display()))?;
+
+    let text_streams = scan.subtitle_streams.iter().filter(|stream| {
+        stream
+            .codec_name
+            .as_deref()
+            .is_some_and(|codec| TEXT_CODECS.contains(&codec))
+    });
+    for stream in text_streams {
+        let track = SubtitleTrack {
+            language: stream.language.clone().filter(|language| language != "und"),
+            closed_captions: false,
+            local_path: dir
+                .join(format!("sub-{}.vtt", stream.index))
+                .to_string_lossy()
+                .into_owned(),
+        };
+        if tracks.iter().any(|other| other.key() == track.key()) {
+            continue;
+        }
+        let command = Ffmpeg::new()
+            .input(source)
+            .map(format!("0:{}", stream.index));
+        if converted(command, &track, trim, limits) {
+            tracks.push(track);
+        }
+    }
+
+    if scan.closed_captions {
+        let track = SubtitleTrack {
+            language: None,
+            closed_captions: true,
+            local_path: dir.join("cc.vtt").to_string_lossy().into_owned(),
+        };
+        let command = Ffmpeg::new().captions(source).map("0:1");
+        if converted(command, &track, trim, limits) {
+            tracks.push(track);
+        }
+    }
+    Ok(tracks)
+}
+
+// Runs `convert`, logging a failure and clearing what it left behind, so one
+// broken stream only costs its own track.
+fn converted(
+    command: Ffmpeg,
+    track: &SubtitleTrack,
+    trim: Option<&Trim>,
+    limits: &RunLimits<'_>,
+) -> bool {
+    convert(command, track, trim, limits).unwrap_or_else(|err| {
+        warn!(
+            path = track.local_path,
+            error = %format_args!("{err:#}"),
+            "subtitle-extraction-failed"
+        );
+        let _ = fs::remove_file(&track.local_path);
+        false
+    })
+}
+
+// Writes the track with `command`, then rewrites it on the output timeline.
+// False, and nothing left on disk, when no cue survives.
+fn convert(
+    command: Ffmpeg,
+    track: &SubtitleTrack,
+    trim: Option<&Trim>,
+    limits: &RunLimits<'_>,
+) -> Result<bool> {
+    let path = Path::new(&track.local_path);
+    command.subtitle_codec("webvtt").output(path).run(limits)?;
+    let converted =
+        fs::read_to_string(path).with_context(|| format!("failed reading {}", path.display()))?;
+    let cues = retime(parse_cues(&converted), trim);
+    if cues.is_empty() {
+        let _ = fs::remove_file(path);
+        return Ok(false);
+    }
+    fs::write(path, render_cues(None, &cues))
+        .with_context(|| format!("failed writing {}", path.display()))?;
+    Ok(true)
+}
+
+// Shifts cues back by the cut head and cuts them to the output's runtime.
+// Cues wholly inside a cut part disappear.
+fn retime(cues: Vec<Cue>, trim: Option<&Trim>) -> Vec<Cue> {
+    let Some(trim) = trim else {
+        return cues;
+    };
+    cues.into_iter()
+        .filter_map(|mut cue| {
+            cue.start_sec = (cue.start_sec - trim.head_sec).max(0.0);
+            cue.end_sec = (cue.end_sec - trim.head_sec).min(trim.effective_duration_sec);
+            (cue.end_sec > cue.start_sec).then_some(cue)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cue(start_sec: f32, end_sec: f32, text: &str) -> Cue {
+        Cue {
+            start_sec,
+            end_sec,
+            settings: String::new(),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn cues_follow_the_trimmed_output() {
+        let trim = Trim {
+            head_sec: 6.0,
+            tail_sec: 10.0,
+            effective_duration_sec: 584.0,
+        };
+        let cues = vec![
+            cue(1.0, 3.0, "countdown"),
+            cue(5.0, 8.0, "straddles the head"),
+            cue(100.0, 102.5, "middle"),
+            cue(588.0, 592.0, "straddles the tail"),
+            cue(595.0, 598.0, "credits"),
+        ];
+        assert_eq!(
+            retime(cues.clone(), Some(&trim)),
+            [
+                cue(0.0, 2.0, "straddles the head"),
+                cue(94.0, 96.5, "middle"),
+                cue(582.0, 584.0, "straddles the tail"),
+            ]
+        );
+        assert_eq!(retime(cues.clone(), None), cues);
+    }
+}
@@ -0,0 +1,129 @@
+use vvtv_media_tools::Ffmpeg;
+use vvtv_types::{Trim, TrimPolicy};
+
+use crate::qa::MediaScan;
+
+// Detector spans this close count as touching.
+const GAP_SEC: f32 = 0.1;
+
+// The dead air to cut from each end of the scanned source: black or silent
+// stretches that start at the very beginning or run to the very end. `None`
+// when the runtime is unknown. A source that is dead air throughout is left
+// whole for QA to judge.
+pub(crate) fn detect(policy: &TrimPolicy, scan: &MediaScan) -> Option<Trim> {
+    let duration = scan.duration_sec.filter(|duration| *duration > 0.0)?;
+    let whole = Trim {
+        head_sec: 0.0,
+        tail_sec: 0.0,
+        effective_duration_sec: duration,
+    };
+    if !policy.enabled {
+        return Some(whole);
+    }
+
+    let mut spans: Vec<(f32, f32)> = scan
+        .black_spans
+        .iter()
+        .chain(&scan.silence_spans)
+        .copied()
+        .collect();
+    spans.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let head = spans.iter().fold(0.0_f32, |head, &(start, end)| {
+        if start <= head + GAP_SEC {
+            head.max(end)
+        } else {
+            head
+        }
+    });
+    spans.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let tail_start = spans.iter().fold(duration, |tail_start, &(start, end)| {
+        if end >= tail_start - GAP_SEC {
+            tail_start.min(start)
+        } else {
+            tail_start
+        }
+    });
+    if head >= tail_start {
+        return Some(whole);
+    }
+
+    let cut = |dead: f32, max: f32| {
+        if dead >= policy.min_trim_sec {
+            dead.min(max)
+        } else {
+            0.0
+        }
+    };
+    let head_sec = cut(head, policy.max_head_sec);
+    let tail_sec = cut(duration - tail_start, policy.max_tail_sec);
+    Some(Trim {
+        head_sec,
+        tail_sec,
+        effective_duration_sec: duration - head_sec - tail_sec,
+    })
+}
+
+// Limits `ffmpeg` to the part of the source that airs.
+pub(crate) fn apply(ffmpeg: Ffmpeg, trim: Option<&Trim>) -> Ffmpeg {
+    match trim {
+        Some(trim) if trim.head_sec > 0.0 || trim.tail_sec > 0.0 => ffmpeg
+            .start(trim.head_sec)
+            .duration(trim.effective_duration_sec),
+        _ => ffmpeg,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(black: &[(f32, f32)], silence: &[(f32, f32)]) -> MediaScan {
+        MediaScan {
+            duration_sec: Some(600.0),
+            black_spans: black.to_vec(),
+            silence_spans: silence.to_vec(),
+            ..MediaScan::default()
+        }
+    }
+
+    #[test]
+    fn cuts_touching_dead_air_at_both_ends() {
+        let policy = TrimPolicy::default();
+        // A black slate, then a silent countdown running into it; the pause
+        // mid-programme stays.
+        let trim = detect(
+            &policy,
+            &scan(
+                &[(0.0, 2.5), (597.0, 600.0)],
+                &[(2.45, 6.0), (300.0, 303.5), (590.0, 597.05)],
+            ),
+        )
+        .unwrap();
+        assert!((trim.head_sec - 6.0).abs() < 1e-4);
+        assert!((trim.tail_sec - 10.0).abs() < 1e-4);
+        assert!((trim.effective_duration_sec - 584.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn respects_the_policy_limits() {
+        let policy = TrimPolicy {
+            max_head_sec: 4.0,
+            ..TrimPolicy::default()
+        };
+        let trim = detect(&policy, &scan(&[(0.0, 12.0), (599.5, 600.0)], &[])).unwrap();
+        assert!((trim.head_sec - 4.0).abs() < 1e-4);
+        // Half a second is under `min_trim_sec`.
+        assert!(trim.tail_sec.abs() < 1e-4);
+
+        let whole = detect(&policy, &scan(&[(0.0, 600.0)], &[])).unwrap();
+        assert!((whole.effective_duration_sec - 600.0).abs() < 1e-4);
+
+        let off = TrimPolicy {
+            enabled: false,
+            ..TrimPolicy::default()
+        };
+        let untouched = detect(&off, &scan(&[(0.0, 12.0)], &[])).unwrap();
+        assert!(untouched.head_sec.abs() < 1e-4);
+        assert!(detect(&policy, &MediaScan::default()).is_none());
+    }
+}
@@ -1,6 +1,10 @@
-use chrono::{Duration, Utc};
-use uuid::Uuid;
-use vvtv_types::{AssetItem, OwnerCard, QaStatus, QueueEntry, SlotType};
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use vvtv_types::{
+    AssetItem, OwnerCard, PlacementRule, QaStatus, QueueEntry, RunContext, SlotType,
+    adjacency_violation, window_violation,
+};
 
 pub struct QueueManager;
 
@@ -8,6 +12,25 @@ pub struct QueueBuildResult {
     pub queue: Vec<QueueEntry>,
     pub emergency_triggered: bool,
     pub buffer_minutes: i64,
+    pub missed_pins: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlacementAction {
+    Swapped,
+    Dropped,
+}
+
+pub struct PlacementCorrection {
+    pub rule_id: String,
+    pub entry_id: String,
+    pub asset_id: String,
+    pub action: PlacementAction,
+}
+
+pub struct PlacementReport {
+    pub queue: Vec<QueueEntry>,
+    pub corrections: Vec<PlacementCorrection>,
 }
 
 impl QueueManager {
@@ -17,44 +40,356 @@ impl QueueManager {
         assets: &[AssetItem],
         emergency_pool: &[AssetItem],
     ) -> QueueBuildResult {
+        Self::build_with(&RunContext::system(), owner_card, assets, emergency_pool)
+    }
+
+    #[must_use]
+    pub fn build_with(
+        run: &RunContext,
+        owner_card: &OwnerCard,
+        assets: &[AssetItem],
+        emergency_pool: &[AssetItem],
+    ) -> QueueBuildResult {
+        let now = run.now();
         let mut queue = Vec::new();
-        let mut cursor = Utc::now();
+        let mut cursor = now;
+        let mut missed_pins = Vec::new();
+
+        // Pinned assets keep their exact start; a pin that cannot air is reported.
+        let mut pinned: Vec<(DateTime<Utc>, &AssetItem)> = Vec::new();
+        for asset in assets {
+            let Some(start_at) = asset.pinned_start_at else {
+                continue;
+            };
+            if asset.qa_status == QaStatus::Passed && start_at >= now {
+                pinned.push((start_at, asset));
+            } else {
+                missed_pins.push(asset.plan_id.clone());
+            }
+        }
+        pinned.sort_by_key(|(start_at, _)| *start_at);
+        let mut pinned = pinned.into_iter().peekable();
+        let mut buffer = Duration::zero();
 
-        for asset in assets.iter().filter(|a| a.qa_status == QaStatus::Passed) {
+        for asset in assets
+            .iter()
+            .filter(|a| a.qa_status == QaStatus::Passed && a.pinned_start_at.is_none())
+        {
+            while let Some((start_at, pin)) =
+                pinned.next_if(|(start_at, _)| cursor + airtime(asset) > *start_at)
+            {
+                queue.push(pinned_entry(run, pin, start_at));
+                buffer += airtime(pin);
+                cursor = cursor.max(start_at + airtime(pin));
+            }
             queue.push(QueueEntry {
-                entry_id: Uuid::new_v4().to_string(),
+                entry_id: run.next_id(),
                 asset_id: asset.asset_id.clone(),
                 start_at: cursor,
                 slot_type: SlotType::Main,
                 fallback_level: 0,
                 curation_trace_id: None,
+                pinned: false,
+                tags: asset.tags.clone(),
             });
-            cursor += Duration::minutes(10);
+            buffer += airtime(asset);
+            cursor += airtime(asset);
+        }
+        for (start_at, pin) in pinned {
+            queue.push(pinned_entry(run, pin, start_at));
+            buffer += airtime(pin);
+            cursor = cursor.max(start_at + airtime(pin));
         }
 
-        let mut buffer_minutes = (queue.len() as i64) * 10;
         let mut emergency_triggered = false;
 
-        if buffer_minutes < i64::from(owner_card.schedule_policy.buffer_critical_minutes) {
+        if buffer.num_minutes() < i64::from(owner_card.schedule_policy.buffer_critical_minutes) {
             emergency_triggered = true;
-            for asset in emergency_pool.iter().take(3) {
+            let rules = &owner_card.editorial_profile.placement_rules;
+            let mut added = 0;
+            for asset in emergency_pool
+                .iter()
+                .filter(|a| a.pinned_start_at.is_none())
+            {
+                if added == 3 {
+                    break;
+                }
+                let breaks_rule = window_violation(rules, &asset.tags, cursor).is_some()
+                    || queue.last().is_some_and(|prev| {
+                        adjacency_violation(rules, &prev.tags, &asset.tags).is_some()
+                    });
+                if breaks_rule {
+                    continue;
+                }
+                added += 1;
                 queue.push(QueueEntry {
-                    entry_id: Uuid::new_v4().to_string(),
+                    entry_id: run.next_id(),
                     asset_id: asset.asset_id.clone(),
                     start_at: cursor,
                     slot_type: SlotType::Emergency,
                     fallback_level: 1,
                     curation_trace_id: None,
+                    pinned: false,
+                    tags: asset.tags.clone(),
                 });
-                cursor += Duration::minutes(10);
+                buffer += airtime(asset);
+                cursor += airtime(asset);
             }
-            buffer_minutes = (queue.len() as i64) * 10;
         }
 
         QueueBuildResult {
             queue,
             emergency_triggered,
-            buffer_minutes,
+            buffer_minutes: buffer.num_minutes(),
+            missed_pins,
+        }
+    }
+
+    // Minutes of programming in `queue`, for entries rebuilt from storage.
+    #[must_use]
+    pub fn buffer_minutes(queue: &[QueueEntry], assets: &[AssetItem]) -> i64 {
+        let airtimes = Airtimes::new(assets);
+        queue
+            .iter()
+            .map(|entry| airtimes.of(entry))
+            .fold(Duration::zero(), |total, slot| total + slot)
+            .num_minutes()
+    }
+
+    // Runs the entries back to back from the first start, each holding the air
+    // for its asset's runtime. Pinned entries keep their start.
+    pub fn reflow(assets: &[AssetItem], queue: &mut [QueueEntry]) {
+        if let Some(origin) = queue.first().map(|entry| entry.start_at) {
+            Airtimes::new(assets).reflow(queue, origin);
         }
     }
+
+    // Walks a built queue and fixes every placement-rule violation: the offending
+    // entry trades slots with a later entry that fits, or is dropped. Pinned entries
+    // never move and are exempt from window rules. Start times are reflowed from
+    // the `assets` runtimes after every move, since entries differ in length.
+    #[must_use]
+    pub fn enforce_placement(
+        owner_card: &OwnerCard,
+        assets: &[AssetItem],
+        mut queue: Vec<QueueEntry>,
+    ) -> PlacementReport {
+        let rules = &owner_card.editorial_profile.placement_rules;
+        let airtimes = Airtimes::new(assets);
+        let Some(origin) = queue.first().map(|entry| entry.start_at) else {
+            return PlacementReport {
+                queue,
+                corrections: Vec::new(),
+            };
+        };
+        let mut corrections = Vec::new();
+        let mut idx = 0;
+        while idx < queue.len() {
+            let Some((rule_id, offender)) = violation_at(rules, &queue, idx) else {
+                idx += 1;
+                continue;
+            };
+            let Some(offender) = offender else {
+                idx += 1;
+                continue;
+            };
+
+            let swap_with = (offender + 1..queue.len()).find(|&other| {
+                if queue[other].pinned {
+                    return false;
+                }
+                let mut trial = queue.clone();
+                trial.swap(offender, other);
+                airtimes.reflow(&mut trial, origin);
+                fits(rules, &trial, offender) && fits(rules, &trial, other)
+            });
+            let entry = &queue[offender];
+            corrections.push(PlacementCorrection {
+                rule_id,
+                entry_id: entry.entry_id.clone(),
+                asset_id: entry.asset_id.clone(),
+                action: if swap_with.is_some() {
+                    PlacementAction::Swapped
+                } else {
+                    PlacementAction::Dropped
+                },
+            });
+            match swap_with {
+                Some(other) => queue.swap(offender, other),
+                None => {
+                    queue.remove(offender);
+                }
+            }
+            airtimes.reflow(&mut queue, origin);
+            idx = idx.min(offender);
+        }
+        PlacementReport { queue, corrections }
+    }
+}
+
+// Returns the broken rule at `idx` and which entry should move, if any can.
+fn violation_at(
+    rules: &[PlacementRule],
+    queue: &[QueueEntry],
+    idx: usize,
+) -> Option<(String, Option<usize>)> {
+    let entry = queue.get(idx)?;
+    if !entry.pinned {
+        if let Some(rule) = window_violation(rules, &entry.tags, entry.start_at) {
+            return Some((rule.rule_id.clone(), Some(idx)));
+        }
+    }
+    let prev = idx.checked_sub(1).and_then(|p| queue.get(p))?;
+    let rule = adjacency_violation(rules, &prev.tags, &entry.tags)?;
+    let offender = if !entry.pinned {
+        Some(idx)
+    } else if !prev.pinned {
+        Some(idx - 1)
+    } else {
+        None
+    };
+    Some((rule.rule_id.clone(), offender))
+}
+
+// An entry fits its slot when it respects its window and both neighbours.
+fn fits(rules: &[PlacementRule], queue: &[QueueEntry], idx: usize) -> bool {
+    let entry = &queue[idx];
+    let window_ok = entry.pinned || window_violation(rules, &entry.tags, entry.start_at).is_none();
+    let prev_ok = idx
+        .checked_sub(1)
+        .is_none_or(|p| adjacency_violation(rules, &queue[p].tags, &entry.tags).is_none());
+    let next_ok = queue
+        .get(idx + 1)
+        .is_none_or(|next| adjacency_violation(rules, &entry.tags, &next.tags).is_none());
+    window_ok && prev_ok && next_ok
+}
+
+// Airtime of each queued asset, looked up by asset id.
+struct Airtimes(HashMap<String, Duration>);
+
+impl Airtimes {
+    fn new(assets: &[AssetItem]) -> Self {
+        Self(
+            assets
+                .iter()
+                .map(|asset| (asset.asset_id.clone(), airtime(asset)))
+                .collect(),
+        )
+    }
+
+    fn of(&self, entry: &QueueEntry) -> Duration {
+        self.0.get(&entry.asset_id).copied().unwrap_or(DEFAULT_SLOT)
+    }
+
+    // Runs the entries back to back from `origin`. Pinned entries keep their
+    // start, and whatever follows waits for them to end.
+    fn reflow(&self, queue: &mut [QueueEntry], origin: DateTime<Utc>) {
+        let mut cursor = origin;
+        for entry in queue {
+            if !entry.pinned {
+                entry.start_at = cursor;
+            }
+            cursor = cursor.max(entry.start_at + self.of(entry));
+        }
+    }
+}
+
+// Slot length of assets with neither a measured nor a planned runtime.
+const DEFAULT_SLOT: Duration = Duration::minutes(10);
+
+// How long an asset holds the air: its runtime after prep trimmed it, else
+// the runtime it was planned with.
+fn airtime(asset: &AssetItem) -> Duration {
+    asset
+        .trim
+        .and_then(|trim| std::time::Duration::try_from_secs_f32(trim.effective_duration_sec).ok())
+        .and_then(|runtime| Duration::from_std(runtime).ok())
+        .filter(|runtime| *runtime > Duration::zero())
+        .or_else(|| {
+            asset
+                .planned_duration_sec
+                .filter(|planned| *planned > 0)
+                .map(|planned| Duration::seconds(i64::from(planned)))
+        })
+        .unwrap_or(DEFAULT_SLOT)
+}
+
+fn pinned_entry(run: &RunContext, asset: &AssetItem, start_at: DateTime<Utc>) -> QueueEntry {
+    QueueEntry {
+        entry_id: run.next_id(),
+        asset_id: asset.asset_id.clone(),
+        start_at,
+        slot_type: SlotType::Main,
+        fallback_level: 0,
+        curation_trace_id: None,
+        pinned: true,
+        tags: asset.tags.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vvtv_types::{Resolution, Trim};
+
+    use super::*;
+
+    fn asset(id: &str, effective_duration_sec: Option<f32>) -> AssetItem {
+        AssetItem {
+            asset_id: id.to_string(),
+            plan_id: format!("plan-{id}"),
+            local_path: format!("runtime/prepared/{id}.mp4"),
+            checksum: String::new(),
+            resolution: Resolution {
+                width: 1280,
+                height: 720,
+            },
+            audio_lufs: -16.0,
+            qa_status: QaStatus::Passed,
+            pinned_start_at: None,
+            tags: vec![],
+            loudness: None,
+            planned_duration_sec: Some(600),
+            qa_report: None,
+            encode_profile: None,
+            renditions: Vec::new(),
+            framing: None,
+            trim: effective_duration_sec.map(|effective_duration_sec| Trim {
+                head_sec: 6.0,
+                tail_sec: 0.0,
+                effective_duration_sec,
+            }),
+            previews: None,
+            subtitles: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn trimmed_runtime_sets_the_airtime() {
+        assert_eq!(
+            airtime(&asset("a", Some(594.5))),
+            Duration::milliseconds(594_500)
+        );
+        // Without a trim, the planned runtime holds the slot.
+        let mut planned = asset("b", None);
+        planned.planned_duration_sec = Some(900);
+        assert_eq!(airtime(&planned), Duration::minutes(15));
+        assert_eq!(airtime(&asset("c", Some(0.0))), Duration::minutes(10));
+        let mut unknown = asset("d", None);
+        unknown.planned_duration_sec = None;
+        assert_eq!(airtime(&unknown), DEFAULT_SLOT);
+
+        let assets = [asset("a", Some(1_800.0)), planned];
+        let entry = |asset_id: &str| QueueEntry {
+            entry_id: format!("e-{asset_id}"),
+            asset_id: asset_id.to_string(),
+            start_at: Utc::now(),
+            slot_type: SlotType::Main,
+            fallback_level: 0,
+            curation_trace_id: None,
+            pinned: false,
+            tags: vec![],
+        };
+        let queue = [entry("a"), entry("b"), entry("gone")];
+        assert_eq!(QueueManager::buffer_minutes(&queue, &assets), 55);
+    }
 }
@@ -0,0 +1,254 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use vvtv_types::{StoragePolicy, StorageUsage};
+
+const BYTES_PER_MB: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct StorageArea {
+    pub name: String,
+    pub root: PathBuf,
+    pub budget_bytes: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct GcReport {
+    pub usage: Vec<StorageUsage>,
+    pub evicted: Vec<PathBuf>,
+}
+
+impl GcReport {
+    #[must_use]
+    pub fn is_full(&self, area: &str) -> bool {
+        self.usage
+            .iter()
+            .any(|usage| usage.area == area && usage.is_full())
+    }
+
+    pub fn under_pressure(&self) -> impl Iterator<Item = &StorageUsage> {
+        self.usage.iter().filter(|usage| usage.under_pressure())
+    }
+}
+
+// Keeps `ingest`, `prepared` and `hls` under the runtime root within their disk
+// budgets. Once an area passes its pressure mark, files nobody holds on to are
+// evicted oldest-first until it is back under the mark. Protected paths
+// (queued assets, the emergency pool), partial downloads, playlists and the
+// segments they list are never evicted.
+pub struct StorageManager {
+    areas: Vec<StorageArea>,
+    pressure_pct: u8,
+}
+
+impl StorageManager {
+    #[must_use]
+    pub fn new(runtime_root: impl AsRef<Path>, policy: &StoragePolicy) -> Self {
+        let root = runtime_root.as_ref();
+        let area = |name: &str, budget_mb: u64| StorageArea {
+            name: name.to_string(),
+            root: root.join(name),
+            budget_bytes: budget_mb.saturating_mul(BYTES_PER_MB),
+        };
+        Self {
+            areas: vec![
+                area("ingest", policy.ingest_budget_mb),
+                area("prepared", policy.prepared_budget_mb),
+                area("hls", policy.hls_budget_mb),
+            ],
+            pressure_pct: policy.pressure_pct,
+        }
+    }
+
+    /// Measures every area and evicts what is over its mark, sparing
+    /// `protected`.
+    ///
+    /// # Errors
+    ///
+    /// Fails when an area cannot be listed or an evicted file cannot be
+    /// removed.
+    pub fn collect(&self, protected: &HashSet<PathBuf>) -> io::Result<GcReport> {
+        let protected: HashSet<PathBuf> = protected.iter().map(|path| canonical(path)).collect();
+        let mut report = GcReport::default();
+        for area in &self.areas {
+            let usage = self.collect_area(area, &protected, &mut report.evicted)?;
+            report.usage.push(usage);
+        }
+        Ok(report)
+    }
+
+    fn collect_area(
+        &self,
+        area: &StorageArea,
+        protected: &HashSet<PathBuf>,
+        evicted: &mut Vec<PathBuf>,
+    ) -> io::Result<StorageUsage> {
+        let mut files = Vec::new();
+        if area.root.exists() {
+            list_files(&canonical(&area.root), &mut files)?;
+        }
+        let listed = playlist_entries(&files);
+        let pressure_bytes = area
+            .budget_bytes
+            .saturating_mul(u64::from(self.pressure_pct))
+            / 100;
+        let mut usage = StorageUsage {
+            area: area.name.clone(),
+            used_bytes: files.iter().map(|file| file.bytes).sum(),
+            budget_bytes: area.budget_bytes,
+            pressure_bytes,
+            evicted_files: 0,
+            evicted_bytes: 0,
+        };
+
+        files.sort_by_key(|file| file.modified);
+        for file in files {
+            if usage.used_bytes < pressure_bytes {
+                break;
+            }
+            if protected.contains(&file.path)
+                || listed.contains(&file.path)
+                || is_pinned(&file.path)
+            {
+                continue;
+            }
+            fs::remove_file(&file.path)?;
+            usage.used_bytes -= file.bytes;
+            usage.evicted_files += 1;
+            usage.evicted_bytes += file.bytes;
+            evicted.push(file.path);
+        }
+        Ok(usage)
+    }
+}
+
+struct StoredFile {
+    path: PathBuf,
+    bytes: u64,
+    modified: SystemTime,
+}
+
+fn list_files(dir: &Path, out: &mut Vec<StoredFile>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        if meta.is_dir() {
+            list_files(&entry.path(), out)?;
+        } else if meta.is_file() {
+            out.push(StoredFile {
+                path: entry.path(),
+                bytes: meta.len(),
+                modified: meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Partial downloads belong to plans still being fetched and playlists are
+// rewritten in place by the streamer.
+fn is_pinned(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == "part" || ext == "m3u8")
+}
+
+// Segments referenced by any playlist in the area are still being served.
+fn playlist_entries(files: &[StoredFile]) -> HashSet<PathBuf> {
+    let mut listed = HashSet::new();
+    for file in files {
+        if file.path.extension().is_none_or(|ext| ext != "m3u8") {
+            continue;
+        }
+        let (Ok(body), Some(dir)) = (fs::read_to_string(&file.path), file.path.parent()) else {
+            continue;
+        };
+        for line in body.lines().map(str::trim) {
+            if !line.is_empty() && !line.starts_with('#') {
+                listed.insert(canonical(&dir.join(line)));
+            }
+        }
+    }
+    listed
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vvtv-storage-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn write_file(path: &Path, bytes: usize, age_secs: u64) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, vec![0_u8; bytes]).unwrap();
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(age_secs))
+            .unwrap();
+    }
+
+    fn manager(root: &Path, budget_bytes: u64) -> StorageManager {
+        let mut manager = StorageManager::new(root, &StoragePolicy::default());
+        for area in &mut manager.areas {
+            area.budget_bytes = budget_bytes;
+        }
+        manager.pressure_pct = 50;
+        manager
+    }
+
+    #[test]
+    fn evicts_oldest_unprotected_files_until_under_pressure() {
+        let root = scratch("evict");
+        let objects = root.join("ingest").join("objects");
+        write_file(&objects.join("oldest.mp4"), 40, 300);
+        write_file(&objects.join("queued.mp4"), 40, 200);
+        write_file(&objects.join("older.mp4"), 40, 100);
+        write_file(&objects.join("newest.mp4"), 40, 0);
+        write_file(&root.join("ingest").join("partial").join("p.part"), 40, 400);
+
+        let protected = HashSet::from([objects.join("queued.mp4")]);
+        let report = manager(&root, 260).collect(&protected).unwrap();
+
+        let ingest = &report.usage[0];
+        assert_eq!(ingest.evicted_files, 2);
+        assert_eq!(ingest.used_bytes, 120);
+        assert!(!objects.join("oldest.mp4").exists());
+        assert!(!objects.join("older.mp4").exists());
+        assert!(objects.join("queued.mp4").exists());
+        assert!(objects.join("newest.mp4").exists());
+        assert!(!ingest.under_pressure());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn listed_segments_are_kept_and_a_full_area_is_reported() {
+        let root = scratch("hls");
+        let hls = root.join("hls");
+        write_file(&hls.join("segment_00001.ts"), 60, 300);
+        write_file(&hls.join("segment_00002.ts"), 60, 200);
+        fs::write(
+            hls.join("index.m3u8"),
+            "#EXTM3U\n#EXTINF:6,\nsegment_00001.ts\n#EXTINF:6,\nsegment_00002.ts\n",
+        )
+        .unwrap();
+
+        let report = manager(&root, 100).collect(&HashSet::new()).unwrap();
+
+        assert!(report.evicted.is_empty());
+        assert!(report.is_full("hls"));
+        assert!(!report.is_full("ingest"));
+        assert_eq!(report.under_pressure().count(), 1);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
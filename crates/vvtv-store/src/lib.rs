@@ -5,7 +5,11 @@ use anyhow::{Context, Result};
 use chrono::{DateTime, Duration, Utc};
 use rusqlite::{Connection, params};
 use serde::{Deserialize, Serialize};
-use vvtv_types::{AssetItem, AuditEvent, PipelineMetrics, PlanItem, QueueEntry};
+use vvtv_types::{
+    AiredItem, AiringHistory, AssetItem, AuditEvent, BrokenSource, DiscoveryInput, FetchPolicy,
+    OwnerCard, PinnedSlot, PipelineMetrics, PlanItem, PlanState, PrepCacheEntry, PrepJob,
+    QueueEntry, SourceLedger, StorageUsage,
+};
 
 #[derive(Debug, Clone)]
 pub struct RecoveredState {
@@ -34,6 +38,31 @@ pub struct ReportData {
     pub metrics: Vec<PipelineMetrics>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlanningRunRecord {
+    pub run_id: String,
+    pub seed: u64,
+    pub started_at: DateTime<Utc>,
+    pub owner_card: OwnerCard,
+    pub pins: Vec<PinnedSlot>,
+    pub inputs: Vec<DiscoveryInput>,
+    #[serde(default)]
+    pub history: AiringHistory,
+    #[serde(default)]
+    pub source_ledger: SourceLedger,
+}
+
+// A downloaded source in the content-addressed asset store and the number of
+// plans referencing it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContentBlob {
+    pub sha256: String,
+    pub path: String,
+    pub bytes: u64,
+    pub ref_count: u32,
+    pub created_at: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AlertStateRecord {
     pub code: String,
@@ -73,6 +102,15 @@ impl StateStore {
         Ok(())
     }
 
+    /// Every stored plan, without the rest of the recovery state.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the plans table cannot be read or a payload does not parse.
+    pub fn load_plans(&self) -> Result<Vec<PlanItem>> {
+        load_json_table(&self.conn, "SELECT payload_json FROM plans")
+    }
+
     pub fn save_assets(&mut self, assets: &[AssetItem]) -> Result<()> {
         let tx = self.conn.transaction()?;
         for asset in assets {
@@ -309,6 +347,379 @@ impl StateStore {
         Ok(())
     }
 
+    /// # Errors
+    ///
+    /// Fails when the pin cannot be serialized or written.
+    pub fn save_pinned_slot(&mut self, pin: &PinnedSlot) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO pinned_slots(pin_id, payload_json, updated_at)
+             VALUES(?1, ?2, ?3)",
+            params![
+                pin.pin_id,
+                serde_json::to_string(pin)?,
+                Utc::now().to_rfc3339()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// True when a pin with `pin_id` existed.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the database cannot be written.
+    pub fn delete_pinned_slot(&mut self, pin_id: &str) -> Result<bool> {
+        let deleted = self
+            .conn
+            .execute("DELETE FROM pinned_slots WHERE pin_id = ?1", [pin_id])?;
+        Ok(deleted > 0)
+    }
+
+    /// # Errors
+    ///
+    /// Fails when the table cannot be read or a payload does not parse.
+    pub fn load_pinned_slots(&self) -> Result<Vec<PinnedSlot>> {
+        load_json_table(
+            &self.conn,
+            "SELECT payload_json FROM pinned_slots ORDER BY pin_id ASC",
+        )
+    }
+
+    /// # Errors
+    ///
+    /// Fails when the record cannot be serialized or written.
+    pub fn save_planning_run(&mut self, record: &PlanningRunRecord) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO planning_runs(run_id, started_at, payload_json)
+             VALUES(?1, ?2, ?3)",
+            params![
+                record.run_id,
+                record.started_at.to_rfc3339(),
+                serde_json::to_string(record)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// # Errors
+    ///
+    /// Fails when the table cannot be read or the payload does not parse.
+    pub fn load_planning_run(&self, run_id: &str) -> Result<Option<PlanningRunRecord>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT payload_json FROM planning_runs WHERE run_id = ?1")?;
+        let mut rows = stmt.query([run_id])?;
+        if let Some(row) = rows.next()? {
+            let payload: String = row.get(0)?;
+            return Ok(Some(serde_json::from_str(&payload)?));
+        }
+        Ok(None)
+    }
+
+    /// Plans whose slot already started count as aired; only the last
+    /// `recent_hours` contribute their themes.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the plans table cannot be read or a payload does not parse.
+    pub fn load_airing_history(
+        &self,
+        now: DateTime<Utc>,
+        recent_hours: i64,
+    ) -> Result<AiringHistory> {
+        let plans: Vec<PlanItem> = load_json_table(&self.conn, "SELECT payload_json FROM plans")?;
+        let since = now - Duration::hours(recent_hours);
+        let mut history = AiringHistory::default();
+        for plan in plans {
+            if !matches!(plan.state, PlanState::Scheduled | PlanState::Committed) {
+                continue;
+            }
+            let Some(aired_at) = plan.slot_start_at.filter(|at| *at <= now) else {
+                continue;
+            };
+            history.aired_sources.insert(plan.source_url);
+            if aired_at >= since {
+                history.recent.push(AiredItem {
+                    aired_at,
+                    theme_tags: plan.theme_tags,
+                    visual_features: plan.visual_features,
+                });
+            }
+        }
+        history.recent.sort_by_key(|item| item.aired_at);
+        Ok(history)
+    }
+
+    /// # Errors
+    ///
+    /// Fails when the table cannot be read or a payload does not parse.
+    pub fn load_source_ledger(&self) -> Result<SourceLedger> {
+        let entries: Vec<BrokenSource> = load_json_table(
+            &self.conn,
+            "SELECT payload_json FROM broken_sources ORDER BY source_url ASC",
+        )?;
+        Ok(SourceLedger {
+            entries: entries
+                .into_iter()
+                .map(|entry| (entry.source_url.clone(), entry))
+                .collect(),
+        })
+    }
+
+    /// Adds a failure of `source_url` to its ledger entry and returns the entry
+    /// as stored.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the earlier entry cannot be read or parsed, or the new one
+    /// cannot be written.
+    pub fn record_source_failure(
+        &mut self,
+        source_url: &str,
+        error: &str,
+        permanent: bool,
+        now: DateTime<Utc>,
+        policy: &FetchPolicy,
+    ) -> Result<BrokenSource> {
+        let previous: Option<BrokenSource> = {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT payload_json FROM broken_sources WHERE source_url = ?1")?;
+            let mut rows = stmt.query([source_url])?;
+            match rows.next()? {
+                Some(row) => Some(serde_json::from_str(&row.get::<_, String>(0)?)?),
+                None => None,
+            }
+        };
+        let entry = BrokenSource::record_failure(
+            previous.as_ref(),
+            source_url,
+            error,
+            permanent,
+            now,
+            policy,
+        );
+        self.conn.execute(
+            "INSERT OR REPLACE INTO broken_sources(source_url, payload_json, expires_at)
+             VALUES(?1, ?2, ?3)",
+            params![
+                entry.source_url,
+                serde_json::to_string(&entry)?,
+                entry.expires_at.to_rfc3339()
+            ],
+        )?;
+        Ok(entry)
+    }
+
+    /// True when `source_url` was in the ledger.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the database cannot be written.
+    pub fn clear_broken_source(&mut self, source_url: &str) -> Result<bool> {
+        let deleted = self.conn.execute(
+            "DELETE FROM broken_sources WHERE source_url = ?1",
+            [source_url],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    /// # Errors
+    ///
+    /// Fails when the database cannot be written.
+    pub fn clear_broken_sources(&mut self) -> Result<usize> {
+        Ok(self.conn.execute("DELETE FROM broken_sources", [])?)
+    }
+
+    /// Forgets entries whose wait ended by `now`.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the database cannot be written.
+    pub fn expire_broken_sources(&mut self, now: DateTime<Utc>) -> Result<usize> {
+        Ok(self.conn.execute(
+            "DELETE FROM broken_sources WHERE expires_at <= ?1",
+            [now.to_rfc3339()],
+        )?)
+    }
+
+    /// Registers `plan_id` as a user of the blob; repeating it is a no-op.
+    /// Returns the blob's reference count.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the blob or reference cannot be written or counted.
+    pub fn add_content_ref(
+        &mut self,
+        sha256: &str,
+        path: &str,
+        bytes: u64,
+        plan_id: &str,
+    ) -> Result<u32> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT OR IGNORE INTO content_blobs(sha256, path, bytes, created_at)
+             VALUES(?1, ?2, ?3, ?4)",
+            params![
+                sha256,
+                path,
+                i64::try_from(bytes).unwrap_or(i64::MAX),
+                Utc::now().to_rfc3339()
+            ],
+        )?;
+        tx.execute(
+            "INSERT OR IGNORE INTO content_refs(sha256, plan_id) VALUES(?1, ?2)",
+            params![sha256, plan_id],
+        )?;
+        let count = content_ref_count(&tx, sha256)?;
+        tx.commit()?;
+        Ok(count)
+    }
+
+    /// Drops the reference of `plan_id` and returns the blob's remaining count.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the database cannot be written or read.
+    pub fn release_content_ref(&mut self, sha256: &str, plan_id: &str) -> Result<u32> {
+        self.conn.execute(
+            "DELETE FROM content_refs WHERE sha256 = ?1 AND plan_id = ?2",
+            params![sha256, plan_id],
+        )?;
+        content_ref_count(&self.conn, sha256)
+    }
+
+    /// # Errors
+    ///
+    /// Fails when the blob tables cannot be read.
+    pub fn load_content_blobs(&self) -> Result<Vec<ContentBlob>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT b.sha256, b.path, b.bytes, b.created_at, COUNT(r.plan_id)
+             FROM content_blobs b LEFT JOIN content_refs r ON r.sha256 = b.sha256
+             GROUP BY b.sha256 ORDER BY b.created_at ASC",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(ContentBlob {
+                sha256: row.get(0)?,
+                path: row.get(1)?,
+                bytes: u64::try_from(row.get::<_, i64>(2)?).unwrap_or_default(),
+                created_at: row.get(3)?,
+                ref_count: u32::try_from(row.get::<_, i64>(4)?).unwrap_or_default(),
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    /// Forgets blobs whose object file was evicted; their references go with them.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the database cannot be written.
+    pub fn remove_content_blobs_at(&mut self, paths: &[String]) -> Result<usize> {
+        let tx = self.conn.transaction()?;
+        let mut removed = 0;
+        for path in paths {
+            tx.execute(
+                "DELETE FROM content_refs WHERE sha256 IN
+                 (SELECT sha256 FROM content_blobs WHERE path = ?1)",
+                [path],
+            )?;
+            removed += tx.execute("DELETE FROM content_blobs WHERE path = ?1", [path])?;
+        }
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    /// # Errors
+    ///
+    /// Fails when the table cannot be read or a payload does not parse.
+    pub fn load_prep_cache(&self) -> Result<Vec<PrepCacheEntry>> {
+        load_json_table(&self.conn, "SELECT payload_json FROM prep_cache")
+    }
+
+    /// # Errors
+    ///
+    /// Fails when an entry cannot be serialized or written.
+    pub fn save_prep_cache(&mut self, entries: &[PrepCacheEntry]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        for entry in entries {
+            tx.execute(
+                "INSERT OR REPLACE INTO prep_cache(input_sha256, profile_hash, payload_json)
+                 VALUES(?1, ?2, ?3)",
+                params![
+                    entry.input_sha256,
+                    entry.profile_hash,
+                    serde_json::to_string(entry)?
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// # Errors
+    ///
+    /// Fails when a job cannot be serialized or written.
+    pub fn save_prep_jobs(&mut self, jobs: &[PrepJob]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        for job in jobs {
+            tx.execute(
+                "INSERT OR REPLACE INTO prep_jobs(job_id, plan_id, state, payload_json, updated_at)
+                 VALUES(?1, ?2, ?3, ?4, ?5)",
+                params![
+                    job.job_id,
+                    job.plan_id,
+                    serde_json::to_value(job.state)?
+                        .as_str()
+                        .unwrap_or_default(),
+                    serde_json::to_string(job)?,
+                    job.updated_at.to_rfc3339()
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Jobs a previous run queued or started but never finished.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the table cannot be read or a payload does not parse.
+    pub fn load_pending_prep_jobs(&self) -> Result<Vec<PrepJob>> {
+        load_json_table(
+            &self.conn,
+            "SELECT payload_json FROM prep_jobs
+             WHERE state IN ('queued', 'running') ORDER BY updated_at ASC",
+        )
+    }
+
+    /// # Errors
+    ///
+    /// Fails when an area cannot be serialized or written.
+    pub fn save_storage_usage(&mut self, usage: &[StorageUsage]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        for area in usage {
+            tx.execute(
+                "INSERT OR REPLACE INTO storage_usage(area, payload_json, updated_at)
+                 VALUES(?1, ?2, datetime('now'))",
+                params![area.area, serde_json::to_string(area)?],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// # Errors
+    ///
+    /// Fails when the table cannot be read or a payload does not parse.
+    pub fn load_storage_usage(&self) -> Result<Vec<StorageUsage>> {
+        load_json_table(
+            &self.conn,
+            "SELECT payload_json FROM storage_usage ORDER BY area ASC",
+        )
+    }
+
     pub fn load_recent_audits(&self, hours: i64) -> Result<Vec<AuditEvent>> {
         let since = (Utc::now() - Duration::hours(hours)).to_rfc3339();
         let mut stmt = self
@@ -342,14 +753,17 @@ impl StateStore {
         let tx = self.conn.transaction()?;
         let deleted_audits =
             tx.execute("DELETE FROM audit_events WHERE ts < ?1", [cutoff.clone()])?;
-        let deleted_metrics = tx.execute("DELETE FROM metric_samples WHERE ts < ?1", [cutoff])?;
+        let deleted_metrics =
+            tx.execute("DELETE FROM metric_samples WHERE ts < ?1", [cutoff.clone()])?;
+        let deleted_runs =
+            tx.execute("DELETE FROM planning_runs WHERE started_at < ?1", [cutoff])?;
         tx.commit()?;
-        Ok(deleted_audits + deleted_metrics)
+        Ok(deleted_audits + deleted_metrics + deleted_runs)
     }
 
     pub fn load_recovery(&self) -> Result<RecoveredState> {
         Ok(RecoveredState {
-            plans: load_json_table(&self.conn, "SELECT payload_json FROM plans")?,
+            plans: self.load_plans()?,
             assets: load_json_table(&self.conn, "SELECT payload_json FROM assets")?,
             queue: load_json_table(
                 &self.conn,
@@ -413,12 +827,73 @@ impl StateStore {
                 last_notified_at TEXT,
                 updated_at TEXT NOT NULL
             );
+
+            CREATE TABLE IF NOT EXISTS planning_runs (
+                run_id TEXT PRIMARY KEY,
+                started_at TEXT NOT NULL,
+                payload_json TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS broken_sources (
+                source_url TEXT PRIMARY KEY,
+                payload_json TEXT NOT NULL,
+                expires_at TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS content_blobs (
+                sha256 TEXT PRIMARY KEY,
+                path TEXT NOT NULL,
+                bytes INTEGER NOT NULL,
+                created_at TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS content_refs (
+                sha256 TEXT NOT NULL,
+                plan_id TEXT NOT NULL,
+                PRIMARY KEY (sha256, plan_id)
+            );
+
+            CREATE TABLE IF NOT EXISTS prep_cache (
+                input_sha256 TEXT NOT NULL,
+                profile_hash TEXT NOT NULL,
+                payload_json TEXT NOT NULL,
+                PRIMARY KEY (input_sha256, profile_hash)
+            );
+
+            CREATE TABLE IF NOT EXISTS prep_jobs (
+                job_id TEXT PRIMARY KEY,
+                plan_id TEXT NOT NULL,
+                state TEXT NOT NULL,
+                payload_json TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS storage_usage (
+                area TEXT PRIMARY KEY,
+                payload_json TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS pinned_slots (
+                pin_id TEXT PRIMARY KEY,
+                payload_json TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            );
             "#,
         )?;
         Ok(())
     }
 }
 
+fn content_ref_count(conn: &Connection, sha256: &str) -> Result<u32> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM content_refs WHERE sha256 = ?1",
+        [sha256],
+        |row| row.get(0),
+    )?;
+    Ok(u32::try_from(count).unwrap_or(u32::MAX))
+}
+
 fn load_json_table<T: for<'de> serde::Deserialize<'de>>(
     conn: &Connection,
     sql: &str,
@@ -453,11 +928,12 @@ fn load_json_table_between<T: for<'de> serde::Deserialize<'de>>(
 mod tests {
     use chrono::{Duration, Utc};
     use vvtv_types::{
-        AssetItem, AuditEvent, PipelineMetrics, PlanItem, PlanState, QaStatus, QueueEntry,
-        Resolution, SlotType,
+        AssetItem, AuditEvent, PinRecurrence, PinTarget, PinnedSlot, PipelineMetrics, PlanItem,
+        PlanState, PrepCacheEntry, PrepJob, PrepJobState, QaStatus, QueueEntry, Resolution,
+        SlotType, StorageUsage,
     };
 
-    use super::{SchedulerCursors, StateStore};
+    use super::{FetchPolicy, SchedulerCursors, StateStore};
 
     fn open_test_store(path: &str) -> StateStore {
         let _ = std::fs::remove_file(path);
@@ -481,6 +957,10 @@ mod tests {
             selection_reason: "ok".to_string(),
             policy_match_score: 1.0,
             state: PlanState::Scheduled,
+            slot_start_at: None,
+            pin_id: None,
+            score_breakdown: None,
+            drop_reason: None,
         };
 
         let asset = AssetItem {
@@ -494,6 +974,17 @@ mod tests {
             },
             audio_lufs: -16.0,
             qa_status: QaStatus::Passed,
+            pinned_start_at: None,
+            tags: vec![],
+            loudness: None,
+            planned_duration_sec: None,
+            qa_report: None,
+            encode_profile: None,
+            renditions: Vec::new(),
+            framing: None,
+            trim: None,
+            previews: None,
+            subtitles: Vec::new(),
         };
 
         let entry = QueueEntry {
@@ -503,6 +994,8 @@ mod tests {
             slot_type: SlotType::Main,
             fallback_level: 0,
             curation_trace_id: None,
+            pinned: false,
+            tags: vec![],
         };
 
         let audit = AuditEvent {
@@ -535,6 +1028,7 @@ mod tests {
 
         let recovered = store.load_recovery().expect("load recovery");
         assert!(!recovered.plans.is_empty());
+        assert_eq!(store.load_plans().expect("load plans").len(), 1);
         assert!(!recovered.assets.is_empty());
         assert!(!recovered.queue.is_empty());
         assert!(!recovered.audits.is_empty());
@@ -639,4 +1133,231 @@ mod tests {
                 .any(|a| a.code == "BUFFER_CRITICAL" && a.active)
         );
     }
+
+    #[test]
+    fn pinned_slots_roundtrip() {
+        let mut store = open_test_store("runtime/state/test-vvtv-pins.db");
+        let pin = PinnedSlot {
+            pin_id: "friday-22h".to_string(),
+            target: PinTarget::Source {
+                source_url: "https://example.com/v/special".to_string(),
+                title: "Special".to_string(),
+                duration_sec: 1800,
+            },
+            start_at: Utc::now(),
+            recurrence: PinRecurrence::Weekly,
+        };
+        store.save_pinned_slot(&pin).expect("save pin");
+        let loaded = store.load_pinned_slots().expect("load pins");
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].target, pin.target);
+
+        assert!(store.delete_pinned_slot("friday-22h").expect("delete pin"));
+        assert!(store.load_pinned_slots().expect("reload pins").is_empty());
+    }
+
+    #[test]
+    fn broken_source_ledger_grows_and_expires() {
+        let mut store = open_test_store("runtime/state/test-vvtv-ledger.db");
+        let policy = FetchPolicy::default();
+        let now = Utc::now();
+        let url = "https://example.com/v/broken";
+
+        store
+            .record_source_failure(url, "timeout", false, now, &policy)
+            .expect("first failure");
+        let second = store
+            .record_source_failure(url, "HTTP 503", false, now, &policy)
+            .expect("second failure");
+        assert_eq!(second.failure_count, 2);
+        assert_eq!(second.last_error, "HTTP 503");
+        assert_eq!(second.retry_after - now, Duration::minutes(60));
+
+        let ledger = store.load_source_ledger().expect("load ledger");
+        assert!(ledger.is_blocked(url, now));
+        assert!(!ledger.is_blocked(url, second.retry_after));
+
+        assert_eq!(
+            store
+                .expire_broken_sources(now + Duration::hours(1))
+                .expect("nothing expired yet"),
+            0
+        );
+        assert_eq!(
+            store
+                .expire_broken_sources(second.expires_at)
+                .expect("expire"),
+            1
+        );
+
+        store
+            .record_source_failure(url, "HTTP 410", true, now, &policy)
+            .expect("permanent failure");
+        assert!(store.clear_broken_source(url).expect("clear"));
+        assert!(
+            store
+                .load_source_ledger()
+                .expect("reload")
+                .entries
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn content_refs_count_distinct_plans() {
+        let mut store = open_test_store("runtime/state/test-vvtv-content.db");
+        let sha = "ab".repeat(32);
+        let path = format!("runtime/ingest/objects/{sha}.mp4");
+
+        assert_eq!(
+            store
+                .add_content_ref(&sha, &path, 42, "plan-a")
+                .expect("ref a"),
+            1
+        );
+        assert_eq!(
+            store
+                .add_content_ref(&sha, &path, 42, "plan-b")
+                .expect("ref b"),
+            2
+        );
+        assert_eq!(
+            store
+                .add_content_ref(&sha, &path, 42, "plan-b")
+                .expect("again"),
+            2
+        );
+
+        let blobs = store.load_content_blobs().expect("blobs");
+        assert_eq!(blobs.len(), 1);
+        assert_eq!(blobs[0].ref_count, 2);
+        assert_eq!(blobs[0].bytes, 42);
+
+        assert_eq!(
+            store.release_content_ref(&sha, "plan-a").expect("release"),
+            1
+        );
+
+        let entry = PrepCacheEntry {
+            input_sha256: sha.clone(),
+            profile_hash: "profile".to_string(),
+            output_path: "runtime/prepared/x.mp4".to_string(),
+            resolution: Resolution {
+                width: 1280,
+                height: 720,
+            },
+            audio_lufs: -16.0,
+            qa_status: QaStatus::Passed,
+            loudness: None,
+            qa_report: None,
+            encode_profile: Some("720p".to_string()),
+            renditions: Vec::new(),
+            framing: None,
+            trim: None,
+            previews: None,
+            subtitles: Vec::new(),
+        };
+        store
+            .save_prep_cache(std::slice::from_ref(&entry))
+            .expect("save cache");
+        store.save_prep_cache(&[entry]).expect("overwrite cache");
+        let cached = store.load_prep_cache().expect("load cache");
+        assert_eq!(cached.len(), 1);
+        assert_eq!(cached[0].input_sha256, sha);
+    }
+
+    #[test]
+    fn only_unfinished_prep_jobs_are_resumed() {
+        let mut store = open_test_store("runtime/state/test-vvtv-prep-jobs.db");
+        let now = Utc::now();
+        let job = |id: &str, state| {
+            let mut job = PrepJob::queued(
+                AssetItem {
+                    asset_id: id.to_string(),
+                    plan_id: format!("plan-{id}"),
+                    local_path: format!("runtime/ingest/{id}.mp4"),
+                    checksum: String::new(),
+                    resolution: Resolution {
+                        width: 1280,
+                        height: 720,
+                    },
+                    audio_lufs: -16.0,
+                    qa_status: QaStatus::Pending,
+                    pinned_start_at: None,
+                    tags: vec![],
+                    loudness: None,
+                    planned_duration_sec: Some(600),
+                    qa_report: None,
+                    encode_profile: None,
+                    renditions: Vec::new(),
+                    framing: None,
+                    trim: None,
+                    previews: None,
+                    subtitles: Vec::new(),
+                },
+                now,
+            );
+            job.state = state;
+            job
+        };
+        store
+            .save_prep_jobs(&[
+                job("a", PrepJobState::Queued),
+                job("b", PrepJobState::Running),
+                job("c", PrepJobState::Done),
+                job("d", PrepJobState::Cancelled),
+            ])
+            .expect("save jobs");
+        let mut finished = job("a", PrepJobState::Failed);
+        finished.error = Some("timed out".to_string());
+        store.save_prep_jobs(&[finished]).expect("update job");
+
+        let pending = store.load_pending_prep_jobs().expect("pending");
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].job_id, "b");
+        assert_eq!(pending[0].state, PrepJobState::Running);
+        assert_eq!(pending[0].asset.planned_duration_sec, Some(600));
+    }
+
+    #[test]
+    fn storage_usage_and_evicted_blobs_round_trip() {
+        let mut store = open_test_store("runtime/state/test-vvtv-storage.db");
+        let sha = "cd".repeat(32);
+        let path = format!("runtime/ingest/objects/{sha}.mp4");
+        store
+            .add_content_ref(&sha, &path, 7, "plan-a")
+            .expect("ref");
+        assert_eq!(
+            store
+                .remove_content_blobs_at(std::slice::from_ref(&path))
+                .expect("remove"),
+            1
+        );
+        assert!(store.load_content_blobs().expect("blobs").is_empty());
+
+        let usage = StorageUsage {
+            area: "ingest".to_string(),
+            used_bytes: 90,
+            budget_bytes: 100,
+            pressure_bytes: 85,
+            evicted_files: 1,
+            evicted_bytes: 7,
+        };
+        store
+            .save_storage_usage(std::slice::from_ref(&usage))
+            .expect("save usage");
+        assert!(usage.under_pressure());
+        assert!(!usage.is_full());
+
+        let full = StorageUsage {
+            used_bytes: 100,
+            ..usage
+        };
+        store
+            .save_storage_usage(std::slice::from_ref(&full))
+            .expect("overwrite usage");
+        let loaded = store.load_storage_usage().expect("load usage");
+        assert_eq!(loaded, vec![full]);
+        assert!(loaded[0].is_full());
+    }
 }
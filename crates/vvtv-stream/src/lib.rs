@@ -1,10 +1,14 @@
 use std::collections::HashMap;
+use std::fmt::Write as _;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::process::Command;
 
 use anyhow::{Context, Result};
-use vvtv_types::{AssetItem, QueueEntry};
+use vvtv_media_tools::{Cue, Ffmpeg, ProbeInfo, RunLimits, StreamKind, parse_cues, render_cues};
+use vvtv_types::{AssetItem, MAIN_RENDITION, QueueEntry, Rendition, SubtitleTrack};
+
+// Group every variant stream points its subtitles at.
+const SUBTITLE_GROUP: &str = "subs";
 
 pub struct HlsStreamer;
 
@@ -19,7 +23,7 @@ impl HlsStreamer {
         let mut out = String::from("#EXTM3U\n#EXT-X-VERSION:3\n");
         for entry in queue {
             out.push_str("#EXTINF:600,\n");
-            out.push_str(&format!("assets/{}.ts\n", entry.asset_id));
+            let _ = writeln!(out, "assets/{}.ts", entry.asset_id);
         }
         out
     }
@@ -27,13 +31,16 @@ impl HlsStreamer {
     pub fn build_hls(
         queue: &[QueueEntry],
         assets: &[AssetItem],
+        // Must match `QualityPolicy.hls_segment_sec` so segments are cut on
+        // the keyframes prep placed.
+        segment_sec: u32,
         output_dir: impl AsRef<Path>,
     ) -> Result<HlsOutput> {
         let output_dir = output_dir.as_ref();
         fs::create_dir_all(output_dir)
             .with_context(|| format!("failed to create hls output dir {}", output_dir.display()))?;
 
-        if !command_exists("ffmpeg") {
+        if !vvtv_media_tools::available() {
             let playlist = Self::render_playlist(queue);
             let playlist_path = output_dir.join("index.m3u8");
             fs::write(&playlist_path, playlist).with_context(|| {
@@ -52,78 +59,604 @@ impl HlsStreamer {
             .iter()
             .map(|asset| (asset.asset_id.as_str(), asset))
             .collect();
-        let concat_path = output_dir.join("concat.txt");
-        let mut concat_manifest = String::new();
+        let queued: Vec<&AssetItem> = queue
+            .iter()
+            .filter_map(|entry| asset_by_id.get(entry.asset_id.as_str()).copied())
+            .collect();
+        if queued.is_empty() {
+            anyhow::bail!("no assets available to generate HLS");
+        }
 
-        for entry in queue {
-            if let Some(asset) = asset_by_id.get(entry.asset_id.as_str()) {
-                let abs_path = fs::canonicalize(&asset.local_path)
-                    .unwrap_or_else(|_| PathBuf::from(&asset.local_path));
-                concat_manifest.push_str(&format!(
-                    "file '{}'\n",
-                    escape_path_for_concat(abs_path.to_string_lossy().as_ref())
-                ));
+        let playlist_path = output_dir.join("index.m3u8");
+        let subtitles = subtitle_renditions(&queued);
+        let mut variants = shared_renditions(&queued);
+        let probes = if subtitles.is_empty() {
+            Vec::new()
+        } else {
+            probe_all(&queued)?
+        };
+        if variants.is_empty() {
+            if subtitles.is_empty() {
+                let files: Vec<&str> = queued
+                    .iter()
+                    .map(|asset| asset.local_path.as_str())
+                    .collect();
+                segment(&files, output_dir, segment_sec)?;
+                return Ok(HlsOutput {
+                    playlist_path,
+                    segment_count_estimate: queue.len(),
+                });
             }
+            // Subtitles need a master playlist, so the main output becomes
+            // its only variant.
+            variants.push(main_variant(&queued, &probes));
         }
 
-        if concat_manifest.is_empty() {
-            anyhow::bail!("no assets available to generate HLS");
+        for variant in &variants {
+            let files: Vec<&str> = queued
+                .iter()
+                .filter_map(|asset| variant_file(asset, &variant.name))
+                .collect();
+            segment(&files, &output_dir.join(&variant.name), segment_sec)
+                .with_context(|| format!("rendition {}", variant.name))?;
         }
-
-        fs::write(&concat_path, concat_manifest).with_context(|| {
+        if !subtitles.is_empty() {
+            let timestamp_map = timestamp_map(&output_dir.join(&variants[0].name));
+            publish_subtitles(
+                &queued,
+                &probes,
+                &subtitles,
+                segment_sec,
+                timestamp_map.as_deref(),
+                output_dir,
+            )?;
+        }
+        fs::write(
+            &playlist_path,
+            Self::render_master_playlist(&variants, &subtitles),
+        )
+        .with_context(|| {
             format!(
-                "failed writing concat manifest at {}",
-                concat_path.display()
+                "failed writing master playlist at {}",
+                playlist_path.display()
             )
         })?;
 
-        let playlist_path = output_dir.join("index.m3u8");
-        let segment_pattern = output_dir.join("segment_%05d.ts");
-
-        let status = Command::new("ffmpeg")
-            .args([
-                "-loglevel",
-                "error",
-                "-nostats",
-                "-y",
-                "-f",
-                "concat",
-                "-safe",
-                "0",
-                "-i",
-                concat_path.to_str().unwrap_or_default(),
-                "-c",
-                "copy",
-                "-f",
-                "hls",
-                "-hls_time",
-                "6",
-                "-hls_list_size",
-                "0",
-                "-hls_flags",
-                "independent_segments",
-                "-hls_segment_filename",
-                segment_pattern.to_str().unwrap_or_default(),
-                playlist_path.to_str().unwrap_or_default(),
-            ])
-            .status()
-            .context("failed to start ffmpeg for hls generation")?;
-
-        if !status.success() {
-            anyhow::bail!("ffmpeg hls generation failed");
-        }
-
         Ok(HlsOutput {
             playlist_path,
             segment_count_estimate: queue.len(),
         })
     }
+
+    // One variant stream per rendition, pointing at `<name>/index.m3u8`,
+    // plus one subtitle rendition per entry of `subtitles` at
+    // `subtitles/<key>/index.m3u8`.
+    #[must_use]
+    pub fn render_master_playlist(variants: &[Rendition], subtitles: &[SubtitleTrack]) -> String {
+        let mut out = String::from("#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-INDEPENDENT-SEGMENTS\n");
+        for track in subtitles {
+            let key = track.key();
+            let _ = write!(
+                out,
+                "#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"{SUBTITLE_GROUP}\",NAME=\"{key}\""
+            );
+            if let Some(language) = &track.language {
+                let _ = write!(out, ",LANGUAGE=\"{}\"", language_tag(language));
+            }
+            out.push_str(",DEFAULT=NO,AUTOSELECT=YES");
+            if track.closed_captions {
+                out.push_str(
+                    ",CHARACTERISTICS=\"public.accessibility.transcribes-spoken-dialog,\
+                     public.accessibility.describes-music-and-sound\"",
+                );
+            }
+            let _ = writeln!(out, ",URI=\"subtitles/{key}/index.m3u8\"");
+        }
+        for variant in variants {
+            let _ = write!(out, "#EXT-X-STREAM-INF:BANDWIDTH={}", variant.bandwidth_bps);
+            if let Some(resolution) = &variant.resolution {
+                let _ = write!(
+                    out,
+                    ",RESOLUTION={}x{}",
+                    resolution.width, resolution.height
+                );
+            }
+            if !variant.codecs.is_empty() {
+                let _ = write!(out, ",CODECS=\"{}\"", variant.codecs);
+            }
+            if !subtitles.is_empty() {
+                let _ = write!(out, ",SUBTITLES=\"{SUBTITLE_GROUP}\"");
+            }
+            let _ = write!(out, "\n{}/index.m3u8\n", variant.name);
+        }
+        out
+    }
+
+    // A WebVTT media playlist of `segment_sec` segments covering
+    // `total_sec`, with the cues of each segment. A cue crossing a boundary
+    // is repeated in both segments, as HLS expects.
+    #[must_use]
+    pub fn render_subtitle_segments(
+        cues: &[Cue],
+        total_sec: f32,
+        segment_sec: u32,
+        timestamp_map: Option<&str>,
+    ) -> (String, Vec<String>) {
+        let length = f64::from(segment_sec.max(1));
+        let total_sec = f64::from(total_sec);
+        let mut playlist = format!(
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:{segment_sec}\n\
+             #EXT-X-MEDIA-SEQUENCE:0\n"
+        );
+        let mut segments = Vec::new();
+        let mut start = 0.0_f64;
+        while start < total_sec {
+            let end = (start + length).min(total_sec);
+            let inside: Vec<Cue> = cues
+                .iter()
+                .filter(|cue| f64::from(cue.start_sec) < end && f64::from(cue.end_sec) > start)
+                .cloned()
+                .collect();
+            let _ = write!(
+                playlist,
+                "#EXTINF:{:.3},\nsegment_{:05}.vtt\n",
+                end - start,
+                segments.len()
+            );
+            segments.push(render_cues(timestamp_map, &inside));
+            start = end;
+        }
+        playlist.push_str("#EXT-X-ENDLIST\n");
+        (playlist, segments)
+    }
+}
+
+// The renditions every queued asset has, since a variant stream must cover
+// the whole queue. Each carries the highest bandwidth and the highest codec
+// profile and level seen for its name, so players are told the most any
+// asset needs, and the list is ordered from the highest bandwidth down.
+fn shared_renditions(queued: &[&AssetItem]) -> Vec<Rendition> {
+    let Some((first, rest)) = queued.split_first() else {
+        return Vec::new();
+    };
+    let mut variants: Vec<Rendition> = first
+        .renditions
+        .iter()
+        .filter(|rung| {
+            rest.iter()
+                .all(|asset| asset.renditions.iter().any(|other| other.name == rung.name))
+        })
+        .cloned()
+        .collect();
+    for variant in &mut variants {
+        for asset in rest {
+            if let Some(other) = asset
+                .renditions
+                .iter()
+                .find(|other| other.name == variant.name)
+            {
+                variant.bandwidth_bps = variant.bandwidth_bps.max(other.bandwidth_bps);
+                variant.codecs = highest_codecs(&variant.codecs, &other.codecs);
+            }
+        }
+    }
+    variants.sort_by_key(|variant| std::cmp::Reverse(variant.bandwidth_bps));
+    variants
+}
+
+// Merges two RFC 6381 codec lists entry by entry: of two entries of the same
+// codec (`avc1`, `mp4a`, ...) the one with the higher profile and level is
+// kept; codecs only one list names are added.
+fn highest_codecs(current: &str, other: &str) -> String {
+    let mut entries: Vec<&str> = current
+        .split(',')
+        .filter(|entry| !entry.is_empty())
+        .collect();
+    for entry in other.split(',').filter(|entry| !entry.is_empty()) {
+        let family = codec_family(entry);
+        match entries
+            .iter_mut()
+            .find(|known| codec_family(known) == family)
+        {
+            Some(known) if codec_rank(entry) > codec_rank(known) => *known = entry,
+            Some(_) => {}
+            None => entries.push(entry),
+        }
+    }
+    entries.join(",")
+}
+
+fn codec_family(entry: &str) -> &str {
+    entry.split_once('.').map_or(entry, |(family, _)| family)
+}
+
+// What a codec entry demands of the player, comparable within its family:
+// `avc1.PPCCLL` ranks by profile then level, others by their dotted numbers
+// (`mp4a.40.5` above `mp4a.40.2`).
+fn codec_rank(entry: &str) -> Vec<u32> {
+    let Some((family, rest)) = entry.split_once('.') else {
+        return Vec::new();
+    };
+    if matches!(family, "avc1" | "avc3") && rest.len() == 6 {
+        return [&rest[..2], &rest[4..]]
+            .iter()
+            .filter_map(|hex| u32::from_str_radix(hex, 16).ok())
+            .collect();
+    }
+    rest.split('.')
+        .filter_map(|part| part.parse().ok())
+        .collect()
+}
+
+// ISO 639-2 codes with a two-letter ISO 639-1 equivalent, bibliographic and
+// terminology forms alike. RFC 5646 wants the two-letter code when one exists.
+const TWO_LETTER_LANGUAGES: &[(&str, &str)] = &[
+    ("ara", "ar"),
+    ("chi", "zh"),
+    ("zho", "zh"),
+    ("cze", "cs"),
+    ("ces", "cs"),
+    ("dan", "da"),
+    ("dut", "nl"),
+    ("nld", "nl"),
+    ("eng", "en"),
+    ("fin", "fi"),
+    ("fre", "fr"),
+    ("fra", "fr"),
+    ("ger", "de"),
+    ("deu", "de"),
+    ("gre", "el"),
+    ("ell", "el"),
+    ("heb", "he"),
+    ("hin", "hi"),
+    ("hun", "hu"),
+    ("ind", "id"),
+    ("ita", "it"),
+    ("jpn", "ja"),
+    ("kor", "ko"),
+    ("nor", "no"),
+    ("pol", "pl"),
+    ("por", "pt"),
+    ("rum", "ro"),
+    ("ron", "ro"),
+    ("rus", "ru"),
+    ("spa", "es"),
+    ("swe", "sv"),
+    ("tha", "th"),
+    ("tur", "tr"),
+    ("ukr", "uk"),
+    ("vie", "vi"),
+];
+
+// The RFC 5646 tag HLS expects in `LANGUAGE` for a language as ffprobe
+// reports it. Codes without a two-letter form are valid tags as they are.
+fn language_tag(language: &str) -> String {
+    let language = language.to_ascii_lowercase();
+    TWO_LETTER_LANGUAGES
+        .iter()
+        .find(|(code, _)| *code == language)
+        .map_or(language, |(_, tag)| (*tag).to_string())
+}
+
+// The file an asset contributes to `variant`.
+fn variant_file<'a>(asset: &'a AssetItem, variant: &str) -> Option<&'a str> {
+    asset
+        .renditions
+        .iter()
+        .find(|rung| rung.name == variant)
+        .map(|rung| rung.local_path.as_str())
+        .or_else(|| (variant == MAIN_RENDITION).then_some(asset.local_path.as_str()))
+}
+
+// The main outputs as one variant, advertised at the highest measured
+// bitrate. Codecs are not known without the ladder's probe, so none are
+// listed.
+fn main_variant(queued: &[&AssetItem], probes: &[ProbeInfo]) -> Rendition {
+    Rendition {
+        name: MAIN_RENDITION.to_string(),
+        local_path: String::new(),
+        resolution: queued
+            .iter()
+            .map(|asset| asset.resolution.clone())
+            .max_by_key(|resolution| resolution.height),
+        bandwidth_bps: probes
+            .iter()
+            .filter_map(|probe| probe.bit_rate)
+            .max()
+            .unwrap_or_default(),
+        codecs: String::new(),
+    }
+}
+
+// One track per subtitle key found on any queued asset; assets without it
+// are silent in that rendition.
+fn subtitle_renditions(queued: &[&AssetItem]) -> Vec<SubtitleTrack> {
+    let mut tracks: Vec<SubtitleTrack> = Vec::new();
+    for track in queued.iter().flat_map(|asset| &asset.subtitles) {
+        if !tracks.iter().any(|other| other.key() == track.key()) {
+            tracks.push(track.clone());
+        }
+    }
+    tracks
+}
+
+fn probe_all(queued: &[&AssetItem]) -> Result<Vec<ProbeInfo>> {
+    queued
+        .iter()
+        .map(|asset| {
+            vvtv_media_tools::probe(Path::new(&asset.local_path), &RunLimits::none())
+                .with_context(|| format!("probing {}", asset.local_path))
+        })
+        .collect()
+}
+
+// Where the variant's timeline starts in MPEG-TS ticks, so players line the
+// cues up with the picture; ffmpeg does not start segments at zero.
+fn timestamp_map(variant_dir: &Path) -> Option<String> {
+    let probe =
+        vvtv_media_tools::probe(&variant_dir.join("segment_00000.ts"), &RunLimits::none()).ok()?;
+    let start = probe
+        .first(StreamKind::Video)
+        .or_else(|| probe.first(StreamKind::Audio))?
+        .start_sec?;
+    let micros = std::time::Duration::try_from_secs_f32(start)
+        .ok()?
+        .as_micros();
+    Some(format!(
+        "X-TIMESTAMP-MAP=MPEGTS:{},LOCAL:00:00:00.000",
+        micros * 9 / 100
+    ))
+}
+
+// Lays the subtitles of every queued asset on the concatenated timeline,
+// each shifted by the runtime of the assets before it, and writes one
+// segmented WebVTT rendition per key under `subtitles/<key>/`.
+fn publish_subtitles(
+    queued: &[&AssetItem],
+    probes: &[ProbeInfo],
+    subtitles: &[SubtitleTrack],
+    segment_sec: u32,
+    timestamp_map: Option<&str>,
+    output_dir: &Path,
+) -> Result<()> {
+    let mut offsets = Vec::with_capacity(queued.len());
+    let mut total_sec = 0.0_f32;
+    for (asset, probe) in queued.iter().zip(probes) {
+        offsets.push(total_sec);
+        total_sec += probe
+            .duration_sec
+            .with_context(|| format!("no duration for {}", asset.local_path))?;
+    }
+
+    for rendition in subtitles {
+        let key = rendition.key();
+        let mut cues = Vec::new();
+        for (asset, offset) in queued.iter().zip(&offsets) {
+            let Some(track) = asset.subtitles.iter().find(|track| track.key() == key) else {
+                continue;
+            };
+            let vtt = fs::read_to_string(&track.local_path)
+                .with_context(|| format!("failed reading {}", track.local_path))?;
+            cues.extend(parse_cues(&vtt).into_iter().map(|mut cue| {
+                cue.start_sec += offset;
+                cue.end_sec += offset;
+                cue
+            }));
+        }
+
+        let dir = output_dir.join("subtitles").join(&key);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create hls output dir {}", dir.display()))?;
+        let (playlist, segments) =
+            HlsStreamer::render_subtitle_segments(&cues, total_sec, segment_sec, timestamp_map);
+        for (index, segment) in segments.iter().enumerate() {
+            let path = dir.join(format!("segment_{index:05}.vtt"));
+            fs::write(&path, segment)
+                .with_context(|| format!("failed writing {}", path.display()))?;
+        }
+        let path = dir.join("index.m3u8");
+        fs::write(&path, playlist).with_context(|| format!("failed writing {}", path.display()))?;
+    }
+    Ok(())
 }
 
-fn command_exists(cmd: &str) -> bool {
-    Command::new(cmd).arg("-version").output().is_ok()
+// Concatenates `files` without re-encoding and cuts them into
+// `segment_sec` segments under `dir`, listed by `dir/index.m3u8`.
+fn segment(files: &[&str], dir: &Path, segment_sec: u32) -> Result<()> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("failed to create hls output dir {}", dir.display()))?;
+    let mut concat_manifest = String::new();
+    for file in files {
+        let abs_path = fs::canonicalize(file).unwrap_or_else(|_| PathBuf::from(file));
+        let _ = writeln!(
+            concat_manifest,
+            "file '{}'",
+            escape_path_for_concat(abs_path.to_string_lossy().as_ref())
+        );
+    }
+    let concat_path = dir.join("concat.txt");
+    fs::write(&concat_path, concat_manifest).with_context(|| {
+        format!(
+            "failed writing concat manifest at {}",
+            concat_path.display()
+        )
+    })?;
+
+    Ffmpeg::new()
+        .concat(&concat_path)
+        .copy()
+        .hls(
+            dir.join("index.m3u8"),
+            dir.join("segment_%05d.ts"),
+            segment_sec,
+        )
+        .run(&RunLimits::none())
+        .context("ffmpeg hls generation failed")?;
+    Ok(())
 }
 
 fn escape_path_for_concat(path: &str) -> String {
     path.replace('\\', "\\\\").replace('\'', "'\\''")
 }
+
+#[cfg(test)]
+mod tests {
+    use vvtv_types::{QaStatus, Resolution};
+
+    use super::*;
+
+    fn rung(name: &str, bandwidth_bps: u64, height: Option<u16>) -> Rendition {
+        Rendition {
+            name: name.to_string(),
+            local_path: format!("runtime/prepared/x/{name}.mp4"),
+            resolution: height.map(|height| Resolution {
+                width: height * 16 / 9,
+                height,
+            }),
+            bandwidth_bps,
+            codecs: if height.is_some() {
+                "avc1.640028,mp4a.40.2".to_string()
+            } else {
+                "mp4a.40.2".to_string()
+            },
+        }
+    }
+
+    fn asset(renditions: Vec<Rendition>) -> AssetItem {
+        AssetItem {
+            asset_id: "a".to_string(),
+            plan_id: "p".to_string(),
+            local_path: "runtime/prepared/x.mp4".to_string(),
+            checksum: String::new(),
+            resolution: Resolution {
+                width: 1920,
+                height: 1080,
+            },
+            audio_lufs: -16.0,
+            qa_status: QaStatus::Passed,
+            pinned_start_at: None,
+            tags: vec![],
+            loudness: None,
+            planned_duration_sec: None,
+            qa_report: None,
+            encode_profile: None,
+            renditions,
+            framing: None,
+            trim: None,
+            previews: None,
+            subtitles: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn master_playlist_lists_the_renditions_every_asset_has() {
+        let full = asset(vec![
+            rung("1080p", 5_160_000, Some(1080)),
+            rung("720p", 2_928_000, Some(720)),
+            rung("audio", 96_000, None),
+        ]);
+        // The busier 720p encode is Main 3.1; the playlist still has to
+        // announce the High 4.0 the other asset needs.
+        let main_720 = Rendition {
+            codecs: "avc1.4d401f,mp4a.40.2".to_string(),
+            ..rung("720p", 3_100_000, Some(720))
+        };
+        let no_top = asset(vec![rung("audio", 96_000, None), main_720]);
+
+        let variants = shared_renditions(&[&full, &no_top]);
+        let names: Vec<&str> = variants.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, ["720p", "audio"]);
+        assert_eq!(variants[0].bandwidth_bps, 3_100_000);
+
+        assert_eq!(
+            HlsStreamer::render_master_playlist(&variants, &[]),
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-INDEPENDENT-SEGMENTS\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=3100000,RESOLUTION=1280x720,CODECS=\"avc1.640028,mp4a.40.2\"\n\
+             720p/index.m3u8\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=96000,CODECS=\"mp4a.40.2\"\n\
+             audio/index.m3u8\n"
+        );
+        assert!(shared_renditions(&[&full, &asset(vec![])]).is_empty());
+
+        assert_eq!(
+            highest_codecs("avc1.4d401f,mp4a.40.2", "avc1.42e01e,mp4a.40.5"),
+            "avc1.4d401f,mp4a.40.5"
+        );
+        assert_eq!(
+            highest_codecs("mp4a.40.2", "avc1.640028"),
+            "mp4a.40.2,avc1.640028"
+        );
+    }
+
+    fn track(language: Option<&str>, closed_captions: bool) -> SubtitleTrack {
+        SubtitleTrack {
+            language: language.map(str::to_string),
+            closed_captions,
+            local_path: "runtime/prepared/x/sub-2.vtt".to_string(),
+        }
+    }
+
+    #[test]
+    fn subtitles_join_the_master_playlist_as_one_group() {
+        let mut first = asset(vec![]);
+        first.subtitles = vec![track(Some("por"), false), track(None, true)];
+        let mut second = asset(vec![]);
+        second.subtitles = vec![track(Some("eng"), false), track(Some("por"), false)];
+        let subtitles = subtitle_renditions(&[&first, &second]);
+        let keys: Vec<String> = subtitles.iter().map(SubtitleTrack::key).collect();
+        assert_eq!(keys, ["por", "und-cc", "eng"]);
+
+        let main = main_variant(&[&first], &[]);
+        assert_eq!(main.name, MAIN_RENDITION);
+        let playlist = HlsStreamer::render_master_playlist(&[main], &subtitles[..2]);
+        assert_eq!(
+            playlist,
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-INDEPENDENT-SEGMENTS\n\
+             #EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"subs\",NAME=\"por\",LANGUAGE=\"pt\",\
+             DEFAULT=NO,AUTOSELECT=YES,URI=\"subtitles/por/index.m3u8\"\n\
+             #EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"subs\",NAME=\"und-cc\",DEFAULT=NO,\
+             AUTOSELECT=YES,CHARACTERISTICS=\"public.accessibility.transcribes-spoken-dialog,\
+             public.accessibility.describes-music-and-sound\",\
+             URI=\"subtitles/und-cc/index.m3u8\"\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=0,RESOLUTION=1920x1080,SUBTITLES=\"subs\"\n\
+             main/index.m3u8\n"
+        );
+    }
+
+    #[test]
+    fn subtitle_languages_are_tagged_per_rfc_5646() {
+        assert_eq!(language_tag("por"), "pt");
+        assert_eq!(language_tag("ENG"), "en");
+        assert_eq!(language_tag("ger"), "de");
+        assert_eq!(language_tag("deu"), "de");
+        assert_eq!(language_tag("pt"), "pt");
+        assert_eq!(language_tag("fil"), "fil");
+    }
+
+    #[test]
+    fn subtitle_segments_repeat_cues_that_cross_a_boundary() {
+        let cue = |start_sec, end_sec, text: &str| Cue {
+            start_sec,
+            end_sec,
+            settings: String::new(),
+            text: text.to_string(),
+        };
+        let cues = [cue(1.0, 2.0, "um"), cue(5.5, 7.0, "dois")];
+        let (playlist, segments) = HlsStreamer::render_subtitle_segments(
+            &cues,
+            10.0,
+            6,
+            Some("X-TIMESTAMP-MAP=MPEGTS:126000,LOCAL:00:00:00.000"),
+        );
+        assert_eq!(
+            playlist,
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:6\n#EXT-X-MEDIA-SEQUENCE:0\n\
+             #EXTINF:6.000,\nsegment_00000.vtt\n#EXTINF:4.000,\nsegment_00001.vtt\n\
+             #EXT-X-ENDLIST\n"
+        );
+        assert_eq!(segments.len(), 2);
+        assert_eq!(parse_cues(&segments[0]).len(), 2);
+        assert_eq!(parse_cues(&segments[1]), [cue(5.5, 7.0, "dois")]);
+        assert!(segments[1].starts_with("WEBVTT\nX-TIMESTAMP-MAP=MPEGTS:126000,"));
+    }
+}
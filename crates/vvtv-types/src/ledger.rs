@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::FetchPolicy;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BrokenSource {
+    pub source_url: String,
+    pub failure_count: u32,
+    pub last_error: String,
+    pub permanent: bool,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub retry_after: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl BrokenSource {
+    // Each failure doubles the wait before the source may be tried again, up to
+    // `broken_retry_max_hours`; permanent failures go straight to the cap. The
+    // entry is forgotten `broken_expiry_hours` after the last failure, but never
+    // before its retry time.
+    #[must_use]
+    pub fn record_failure(
+        previous: Option<&Self>,
+        source_url: &str,
+        error: &str,
+        permanent: bool,
+        now: DateTime<Utc>,
+        policy: &FetchPolicy,
+    ) -> Self {
+        let failure_count = previous
+            .map_or(0, |entry| entry.failure_count)
+            .saturating_add(1);
+        let max_wait =
+            Duration::try_hours(i64::from(policy.broken_retry_max_hours)).unwrap_or(Duration::MAX);
+        let wait = if permanent {
+            max_wait
+        } else {
+            let doublings = (failure_count - 1).min(16);
+            Duration::try_minutes(i64::from(policy.broken_retry_base_minutes))
+                .and_then(|base| base.checked_mul(1 << doublings))
+                .map_or(max_wait, |wait| wait.min(max_wait))
+        };
+        // Waits too long for the calendar end at its last representable instant.
+        let after = |wait: Duration| {
+            now.checked_add_signed(wait)
+                .unwrap_or(DateTime::<Utc>::MAX_UTC)
+        };
+        let retry_after = after(wait);
+        let expires_at = Duration::try_hours(i64::from(policy.broken_expiry_hours))
+            .map_or(DateTime::<Utc>::MAX_UTC, after)
+            .max(retry_after);
+
+        Self {
+            source_url: source_url.to_string(),
+            failure_count,
+            last_error: error.to_string(),
+            permanent,
+            first_seen: previous.map_or(now, |entry| entry.first_seen),
+            last_seen: now,
+            retry_after,
+            expires_at,
+        }
+    }
+
+    #[must_use]
+    pub fn blocks(&self, now: DateTime<Utc>) -> bool {
+        now < self.retry_after
+    }
+}
+
+// Snapshot of the persisted broken-source ledger, keyed by source URL.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct SourceLedger {
+    pub entries: BTreeMap<String, BrokenSource>,
+}
+
+impl SourceLedger {
+    #[must_use]
+    pub fn is_blocked(&self, source_url: &str, now: DateTime<Utc>) -> bool {
+        self.entries
+            .get(source_url)
+            .is_some_and(|entry| entry.blocks(now))
+    }
+
+    pub fn blocked_urls(&self, now: DateTime<Utc>) -> impl Iterator<Item = &str> {
+        self.entries
+            .values()
+            .filter(move |entry| entry.blocks(now))
+            .map(|entry| entry.source_url.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn transient_failures_back_off_exponentially_up_to_the_cap() {
+        let policy = FetchPolicy::default();
+        let now = Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap();
+
+        let mut entry = BrokenSource::record_failure(None, "u", "timeout", false, now, &policy);
+        assert_eq!(entry.retry_after - now, Duration::minutes(30));
+        for _ in 0..3 {
+            entry = BrokenSource::record_failure(Some(&entry), "u", "timeout", false, now, &policy);
+        }
+        assert_eq!(entry.failure_count, 4);
+        assert_eq!(entry.retry_after - now, Duration::minutes(240));
+        for _ in 0..10 {
+            entry = BrokenSource::record_failure(Some(&entry), "u", "timeout", false, now, &policy);
+        }
+        assert_eq!(entry.retry_after - now, Duration::hours(24));
+        assert_eq!(entry.first_seen, now);
+        assert!(entry.expires_at >= entry.retry_after);
+    }
+
+    #[test]
+    fn extreme_policies_cap_the_wait_instead_of_overflowing() {
+        let policy = FetchPolicy {
+            broken_retry_base_minutes: u32::MAX,
+            broken_retry_max_hours: u32::MAX,
+            broken_expiry_hours: u32::MAX,
+            ..FetchPolicy::default()
+        };
+        let now = Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap();
+
+        let mut entry = BrokenSource::record_failure(None, "u", "timeout", false, now, &policy);
+        assert_eq!(
+            entry.retry_after - now,
+            Duration::minutes(i64::from(u32::MAX))
+        );
+        entry.failure_count = u32::MAX;
+        entry = BrokenSource::record_failure(Some(&entry), "u", "timeout", false, now, &policy);
+        assert_eq!(entry.failure_count, u32::MAX);
+        assert_eq!(entry.retry_after, DateTime::<Utc>::MAX_UTC);
+        assert_eq!(entry.expires_at, DateTime::<Utc>::MAX_UTC);
+        let permanent = BrokenSource::record_failure(None, "u", "HTTP 410", true, now, &policy);
+        assert!(permanent.blocks(now + Duration::days(365 * 1000)));
+    }
+
+    #[test]
+    fn permanent_failures_block_until_the_cap() {
+        let policy = FetchPolicy::default();
+        let now = Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap();
+        let entry = BrokenSource::record_failure(None, "u", "HTTP 410", true, now, &policy);
+        let mut ledger = SourceLedger::default();
+        ledger.entries.insert("u".to_string(), entry);
+
+        assert!(ledger.is_blocked("u", now + Duration::hours(23)));
+        assert!(!ledger.is_blocked("u", now + Duration::hours(24)));
+        assert!(!ledger.is_blocked("other", now));
+    }
+}
@@ -1,6 +1,16 @@
-use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 
+mod ledger;
+mod placement;
+mod run;
+
+pub use ledger::{BrokenSource, SourceLedger};
+pub use placement::{PlacementConstraint, PlacementRule, adjacency_violation, window_violation};
+pub use run::{Clock, FixedClock, IdGenerator, RandomIds, RunContext, SeededIds, SystemClock};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OwnerCard {
     pub schema_version: u16,
@@ -12,6 +22,12 @@ pub struct OwnerCard {
     pub curator_policy: CuratorPolicy,
     pub safety_policy: SafetyPolicy,
     pub autotune_policy: AutotunePolicy,
+    #[serde(default)]
+    pub fetch_policy: FetchPolicy,
+    #[serde(default)]
+    pub storage_policy: StoragePolicy,
+    #[serde(default)]
+    pub prep_policy: PrepPolicy,
 }
 
 impl OwnerCard {
@@ -29,6 +45,30 @@ impl OwnerCard {
                 "buffer_critical_minutes must be lower than buffer_target_minutes".to_string(),
             );
         }
+        let threshold = self.editorial_profile.theme_policy.similarity_threshold;
+        if !(threshold > 0.0 && threshold <= 1.0) {
+            return Err("similarity_threshold must be in (0, 1]".to_string());
+        }
+        if self
+            .search_policy
+            .max_domain_share
+            .is_some_and(|share| !(share > 0.0 && share <= 1.0))
+        {
+            return Err("max_domain_share must be in (0, 1]".to_string());
+        }
+        if self.editorial_profile.freshness_policy.half_life_hours <= 0.0 {
+            return Err("half_life_hours must be > 0".to_string());
+        }
+        if !(1..=100).contains(&self.storage_policy.pressure_pct) {
+            return Err("pressure_pct must be in [1, 100]".to_string());
+        }
+        self.quality_policy.validate_encoding()?;
+        if self.prep_policy.max_concurrent_jobs == 0 {
+            return Err("max_concurrent_jobs must be >= 1".to_string());
+        }
+        for rule in &self.editorial_profile.placement_rules {
+            rule.validate()?;
+        }
         if self.autotune_policy.max_daily_adjustment_pct > 20.0 {
             return Err("max_daily_adjustment_pct must be <= 20".to_string());
         }
@@ -41,6 +81,157 @@ pub struct EditorialProfile {
     pub target_avg_duration_sec: u32,
     pub max_consecutive_same_theme: u8,
     pub min_unique_themes_per_block: u8,
+    #[serde(default)]
+    pub theme_policy: ThemePolicy,
+    #[serde(default)]
+    pub placement_rules: Vec<PlacementRule>,
+    #[serde(default)]
+    pub freshness_policy: FreshnessPolicy,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThemePolicy {
+    pub similarity_threshold: f32,
+    #[serde(default)]
+    pub synonyms: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub taxonomy: BTreeMap<String, Vec<String>>,
+}
+
+impl Default for ThemePolicy {
+    fn default() -> Self {
+        Self {
+            similarity_threshold: 0.5,
+            synonyms: BTreeMap::new(),
+            taxonomy: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FreshnessPolicy {
+    pub half_life_hours: f32,
+    pub freshness_weight: f32,
+    pub novelty_bonus: f32,
+    pub recent_theme_penalty: f32,
+    pub recent_theme_window_hours: u16,
+}
+
+impl Default for FreshnessPolicy {
+    fn default() -> Self {
+        Self {
+            half_life_hours: 72.0,
+            freshness_weight: 20.0,
+            novelty_bonus: 15.0,
+            recent_theme_penalty: 25.0,
+            recent_theme_window_hours: 24,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FetchPolicy {
+    pub max_download_mb: u64,
+    pub download_timeout_secs: u64,
+    pub max_redirects: u8,
+    pub max_resume_attempts: u8,
+    pub max_retries: u8,
+    pub retry_backoff_ms: u64,
+    pub broken_retry_base_minutes: u32,
+    pub broken_retry_max_hours: u32,
+    pub broken_expiry_hours: u32,
+    pub max_concurrent_downloads: u8,
+    // Global cap shared by all downloads; 0 means unlimited.
+    pub max_bandwidth_bytes_per_sec: u64,
+    // Directories `file://` sources may be read from; empty disables them.
+    pub file_roots: Vec<String>,
+    pub s3: Option<S3Policy>,
+}
+
+impl Default for FetchPolicy {
+    fn default() -> Self {
+        Self {
+            max_download_mb: 4096,
+            download_timeout_secs: 1800,
+            max_redirects: 5,
+            max_resume_attempts: 3,
+            max_retries: 2,
+            retry_backoff_ms: 2000,
+            broken_retry_base_minutes: 30,
+            broken_retry_max_hours: 24,
+            broken_expiry_hours: 72,
+            max_concurrent_downloads: 3,
+            max_bandwidth_bytes_per_sec: 0,
+            file_roots: Vec::new(),
+            s3: None,
+        }
+    }
+}
+
+// S3-compatible bucket access for `s3://bucket/key` sources. Keys are read from
+// the named environment variables, never from the card itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct S3Policy {
+    pub endpoint: String,
+    pub region: String,
+    // Empty allows every bucket on the endpoint.
+    pub allowed_buckets: Vec<String>,
+    pub access_key_id_env: String,
+    pub secret_access_key_env: String,
+}
+
+impl Default for S3Policy {
+    fn default() -> Self {
+        Self {
+            endpoint: "http://127.0.0.1:9000".to_string(),
+            region: "us-east-1".to_string(),
+            allowed_buckets: Vec::new(),
+            access_key_id_env: "VVTV_S3_ACCESS_KEY_ID".to_string(),
+            secret_access_key_env: "VVTV_S3_SECRET_ACCESS_KEY".to_string(),
+        }
+    }
+}
+
+// Disk budgets for the runtime media directories. `DISK_PRESSURE` is raised
+// once an area passes `pressure_pct` of its budget.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StoragePolicy {
+    pub ingest_budget_mb: u64,
+    pub prepared_budget_mb: u64,
+    pub hls_budget_mb: u64,
+    pub pressure_pct: u8,
+}
+
+impl Default for StoragePolicy {
+    fn default() -> Self {
+        Self {
+            ingest_budget_mb: 20_480,
+            prepared_budget_mb: 20_480,
+            hls_budget_mb: 4096,
+            pressure_pct: 85,
+        }
+    }
+}
+
+// How many ffmpeg jobs prep runs at once and how long one may take before its
+// process is killed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrepPolicy {
+    pub max_concurrent_jobs: u8,
+    pub job_timeout_secs: u64,
+}
+
+impl Default for PrepPolicy {
+    fn default() -> Self {
+        Self {
+            max_concurrent_jobs: 2,
+            job_timeout_secs: 1800,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,6 +239,35 @@ pub struct SearchPolicy {
     pub allowlist_domains: Vec<String>,
     pub blacklist_domains: Vec<String>,
     pub blocked_keywords: Vec<String>,
+    #[serde(default)]
+    pub max_domain_share: Option<f32>,
+    #[serde(default)]
+    pub min_distinct_sources_per_block: u8,
+}
+
+impl SearchPolicy {
+    // Hosts on an allowlisted domain, or a subdomain of one, and on no
+    // blacklisted one.
+    #[must_use]
+    pub fn allows_domain(&self, host: &str) -> bool {
+        self.allowlist_domains
+            .iter()
+            .any(|domain| domain_matches(host, domain))
+            && !self
+                .blacklist_domains
+                .iter()
+                .any(|domain| domain_matches(host, domain))
+    }
+}
+
+// Whether `host` is `domain` itself or one of its subdomains; `evilexample.com`
+// is not under `example.com`.
+#[must_use]
+pub fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|rest| rest.ends_with('.'))
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -57,13 +277,551 @@ pub struct SchedulePolicy {
     pub commit_interval_minutes: u16,
     pub buffer_target_minutes: u16,
     pub buffer_critical_minutes: u16,
+    #[serde(default)]
+    pub pinned_slots: Vec<PinnedSlot>,
+    #[serde(default)]
+    pub optimizer: OptimizerPolicy,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OptimizerPolicy {
+    pub enabled: bool,
+    pub max_iterations: u32,
+    // Plan items the search may score across all candidates. Unlike a
+    // wall-clock budget it stops a seeded run at the same point every time.
+    pub evaluation_budget: u64,
+    pub seed: u64,
+}
+
+impl Default for OptimizerPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_iterations: 20_000,
+            evaluation_budget: 1_000_000,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinnedSlot {
+    pub pin_id: String,
+    pub target: PinTarget,
+    pub start_at: DateTime<Utc>,
+    pub recurrence: PinRecurrence,
+}
+
+impl PinnedSlot {
+    #[must_use]
+    pub fn occurrences_between(
+        &self,
+        from: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Vec<DateTime<Utc>> {
+        let period = match self.recurrence {
+            PinRecurrence::Once => {
+                return if self.start_at >= from && self.start_at < until {
+                    vec![self.start_at]
+                } else {
+                    Vec::new()
+                };
+            }
+            PinRecurrence::Daily => Duration::days(1),
+            PinRecurrence::Weekly => Duration::weeks(1),
+        };
+
+        let mut at = self.start_at;
+        if at < from {
+            let behind = (from - at).num_seconds();
+            let steps = (behind + period.num_seconds() - 1) / period.num_seconds();
+            at += period * i32::try_from(steps).unwrap_or(i32::MAX);
+        }
+
+        let mut out = Vec::new();
+        while at < until {
+            out.push(at);
+            at += period;
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PinTarget {
+    Plan {
+        plan_id: String,
+    },
+    Source {
+        source_url: String,
+        title: String,
+        duration_sec: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PinRecurrence {
+    Once,
+    Daily,
+    Weekly,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct QualityPolicy {
     pub min_resolution_height: u16,
     pub target_audio_lufs: f32,
+    // Measured after normalization; a larger miss rejects the asset.
     pub max_audio_deviation_lufs: f32,
+    pub target_true_peak_dbtp: f32,
+    pub max_true_peak_dbtp: f32,
+    pub target_loudness_range_lu: f32,
+    pub qa_checks: QaThresholds,
+    // Output renditions prep may encode to; each asset gets the largest one
+    // its source fills without upscaling.
+    pub encode_profiles: Vec<EncodeProfile>,
+    // Length of the HLS segments the stream is cut into. Keyframes are placed
+    // on segment boundaries so segments start cleanly.
+    pub hls_segment_sec: u32,
+    pub abr_ladder: AbrPolicy,
+    // How a source whose displayed aspect differs from the output frame is
+    // fitted into it.
+    pub aspect_handling: AspectHandling,
+    // Sources this close to the output aspect, in percent, are scaled as
+    // they are.
+    pub aspect_tolerance_pct: f32,
+    pub trim: TrimPolicy,
+    pub previews: PreviewPolicy,
+    // Text subtitle streams and CEA-608 captions of the source are kept as
+    // WebVTT tracks.
+    pub extract_subtitles: bool,
+}
+
+impl Default for QualityPolicy {
+    fn default() -> Self {
+        Self {
+            min_resolution_height: 720,
+            target_audio_lufs: -16.0,
+            max_audio_deviation_lufs: 2.5,
+            target_true_peak_dbtp: -1.5,
+            max_true_peak_dbtp: -1.0,
+            target_loudness_range_lu: 11.0,
+            qa_checks: QaThresholds::default(),
+            encode_profiles: vec![
+                EncodeProfile {
+                    name: "1080p".to_string(),
+                    width: 1920,
+                    height: 1080,
+                    rate_control: RateControl::Crf(21),
+                    preset: "veryfast".to_string(),
+                    frame_rate: 30,
+                    audio_bitrate_kbps: 160,
+                },
+                EncodeProfile {
+                    name: "720p".to_string(),
+                    width: 1280,
+                    height: 720,
+                    rate_control: RateControl::Crf(23),
+                    preset: "veryfast".to_string(),
+                    frame_rate: 30,
+                    audio_bitrate_kbps: 128,
+                },
+            ],
+            hls_segment_sec: 6,
+            abr_ladder: AbrPolicy::default(),
+            aspect_handling: AspectHandling::Pillarbox,
+            aspect_tolerance_pct: 3.0,
+            trim: TrimPolicy::default(),
+            previews: PreviewPolicy::default(),
+            extract_subtitles: true,
+        }
+    }
+}
+
+// Black or silent stretches at either end of a source are cut before it airs.
+// They are found with the `qa_checks` black and silence thresholds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrimPolicy {
+    pub enabled: bool,
+    // Shorter dead air is left in.
+    pub min_trim_sec: f32,
+    // Never cut more than this from either end, so a quiet opening scene or
+    // end credits survive.
+    pub max_head_sec: f32,
+    pub max_tail_sec: f32,
+}
+
+impl Default for TrimPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_trim_sec: 1.0,
+            max_head_sec: 30.0,
+            max_tail_sec: 60.0,
+        }
+    }
+}
+
+// Stills and a short clip made from every prepared output, so operators and
+// the public page can see what is airing. Heights follow the output aspect.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PreviewPolicy {
+    pub enabled: bool,
+    // Where the poster frame and the clip are taken from, in percent of the
+    // runtime, past any opening titles.
+    pub poster_at_pct: f32,
+    pub poster_width: u16,
+    // One thumbnail per interval, tiled into a single sprite image.
+    pub thumbnail_interval_sec: u32,
+    pub thumbnail_width: u16,
+    pub sprite_columns: u16,
+    pub clip_sec: u32,
+    pub clip_height: u16,
+    pub clip_video_kbps: u32,
+    pub clip_audio_kbps: u32,
+}
+
+impl Default for PreviewPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            poster_at_pct: 10.0,
+            poster_width: 640,
+            thumbnail_interval_sec: 10,
+            thumbnail_width: 160,
+            sprite_columns: 10,
+            clip_sec: 20,
+            clip_height: 360,
+            clip_video_kbps: 400,
+            clip_audio_kbps: 64,
+        }
+    }
+}
+
+impl PreviewPolicy {
+    fn validate(&self) -> Result<(), String> {
+        if !(0.0..100.0).contains(&self.poster_at_pct) {
+            return Err("previews.poster_at_pct must be in [0, 100)".to_string());
+        }
+        if [self.poster_width, self.thumbnail_width, self.clip_height]
+            .iter()
+            .any(|size| *size == 0 || size % 2 != 0)
+        {
+            return Err("preview sizes must be even and non-zero".to_string());
+        }
+        if self.thumbnail_interval_sec == 0 || self.sprite_columns == 0 || self.clip_sec == 0 {
+            return Err(
+                "previews.thumbnail_interval_sec, sprite_columns and clip_sec must be >= 1"
+                    .to_string(),
+            );
+        }
+        if self.clip_video_kbps == 0 || self.clip_audio_kbps == 0 {
+            return Err("preview clip bitrates must be > 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Files prep made to show an asset, stored next to its prepared output.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Previews {
+    pub poster_path: String,
+    pub sprite_path: String,
+    // WebVTT thumbnail track pointing each interval at its tile of the
+    // sprite, as `sprite#xywh=x,y,w,h` relative to the track.
+    pub thumbnails_vtt_path: String,
+    pub clip_path: String,
+}
+
+impl Previews {
+    #[must_use]
+    pub fn files(&self) -> [&str; 4] {
+        [
+            &self.poster_path,
+            &self.sprite_path,
+            &self.thumbnails_vtt_path,
+            &self.clip_path,
+        ]
+    }
+}
+
+// What prep cut from the ends of a source.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct Trim {
+    pub head_sec: f32,
+    pub tail_sec: f32,
+    // Runtime of the prepared output.
+    pub effective_duration_sec: f32,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AspectHandling {
+    Reject,
+    // Scaled to fit, with black bars.
+    Pillarbox,
+    // Scaled to fit over a blurred copy of itself that fills the frame.
+    BlurredPillarbox,
+    // Scaled to fill and the centre cut out.
+    Crop,
+}
+
+// How prep fitted a source into the output frame.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Framing {
+    // The source picture as displayed: rotation and pixel shape applied.
+    pub source: Resolution,
+    pub rotation_deg: u16,
+    // `None` when the source already had the output aspect.
+    pub handling: Option<AspectHandling>,
+}
+
+impl QualityPolicy {
+    // The largest profile whose height the source reaches, so nothing is
+    // upscaled; sources below every profile get the smallest one.
+    #[must_use]
+    pub fn encode_profile_for(&self, source_height: u16) -> Option<&EncodeProfile> {
+        self.encode_profiles
+            .iter()
+            .filter(|profile| profile.height <= source_height)
+            .max_by_key(|profile| profile.height)
+            .or_else(|| {
+                self.encode_profiles
+                    .iter()
+                    .min_by_key(|profile| profile.height)
+            })
+    }
+
+    fn validate_encoding(&self) -> Result<(), String> {
+        if self.encode_profiles.is_empty() {
+            return Err("encode_profiles cannot be empty".to_string());
+        }
+        if self.hls_segment_sec == 0 {
+            return Err("hls_segment_sec must be >= 1".to_string());
+        }
+        if !(0.0..50.0).contains(&self.aspect_tolerance_pct) {
+            return Err("aspect_tolerance_pct must be in [0, 50)".to_string());
+        }
+        if self.trim.min_trim_sec < 0.0
+            || self.trim.max_head_sec < 0.0
+            || self.trim.max_tail_sec < 0.0
+        {
+            return Err("trim durations must be >= 0".to_string());
+        }
+        self.previews.validate()?;
+        let mut names = BTreeSet::new();
+        for profile in &self.encode_profiles {
+            if !names.insert(profile.name.as_str()) {
+                return Err(format!("duplicate encode profile {}", profile.name));
+            }
+            profile.validate()?;
+        }
+        let mut rungs = BTreeSet::from([AUDIO_ONLY_RENDITION, MAIN_RENDITION]);
+        for rung in &self.abr_ladder.renditions {
+            if !rungs.insert(rung.name.as_str()) {
+                return Err(format!("duplicate abr rendition {}", rung.name));
+            }
+            rung.validate()?;
+        }
+        Ok(())
+    }
+}
+
+// One H.264/AAC output rendition. The source is scaled to fit and padded to
+// `width`x`height`, and resampled to `frame_rate`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EncodeProfile {
+    pub name: String,
+    pub width: u16,
+    pub height: u16,
+    pub rate_control: RateControl,
+    pub preset: String,
+    pub frame_rate: u16,
+    pub audio_bitrate_kbps: u32,
+}
+
+impl EncodeProfile {
+    // Frames between keyframes, so every HLS segment starts on one.
+    #[must_use]
+    pub fn gop_frames(&self, segment_sec: u32) -> u32 {
+        u32::from(self.frame_rate) * segment_sec.max(1)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        // x264 with 4:2:0 chroma needs even dimensions.
+        if self.width == 0 || self.height == 0 || self.width % 2 + self.height % 2 != 0 {
+            return Err(format!(
+                "encode profile {} needs even, non-zero dimensions",
+                self.name
+            ));
+        }
+        if self.frame_rate == 0 {
+            return Err(format!(
+                "encode profile {} needs frame_rate >= 1",
+                self.name
+            ));
+        }
+        match self.rate_control {
+            RateControl::Crf(crf) if crf > 51 => {
+                Err(format!("encode profile {} crf must be <= 51", self.name))
+            }
+            RateControl::BitrateKbps(0) => {
+                Err(format!("encode profile {} bitrate must be > 0", self.name))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// Name of the audio-only rung of the ladder.
+pub const AUDIO_ONLY_RENDITION: &str = "audio";
+
+// Name the HLS master playlist gives the main output when it has to list it
+// as a variant of its own.
+pub const MAIN_RENDITION: &str = "main";
+
+// Extra renditions prep encodes for adaptive streaming, all CPU-only x264.
+// Each video rung is encoded only when the source reaches its height, and all
+// of them share the keyframe cadence of `hls_segment_sec`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AbrPolicy {
+    pub enabled: bool,
+    pub renditions: Vec<EncodeProfile>,
+    // Rungs taller than this are skipped, so low-power hosts can drop the
+    // expensive top of the ladder.
+    pub max_height: Option<u16>,
+    // Bitrate of the audio-only rung; `None` leaves it out.
+    pub audio_only_kbps: Option<u32>,
+}
+
+impl Default for AbrPolicy {
+    fn default() -> Self {
+        let rung = |name: &str, width, height, kbps, audio_kbps| EncodeProfile {
+            name: name.to_string(),
+            width,
+            height,
+            rate_control: RateControl::BitrateKbps(kbps),
+            preset: "veryfast".to_string(),
+            frame_rate: 30,
+            audio_bitrate_kbps: audio_kbps,
+        };
+        Self {
+            enabled: false,
+            renditions: vec![
+                rung("1080p", 1920, 1080, 5_000, 160),
+                rung("720p", 1280, 720, 2_800, 128),
+                rung("480p", 854, 480, 1_200, 96),
+            ],
+            max_height: None,
+            audio_only_kbps: Some(96),
+        }
+    }
+}
+
+impl AbrPolicy {
+    // The video rungs to encode for a source of `source_height`, tallest first.
+    #[must_use]
+    pub fn rungs_for(&self, source_height: u16) -> Vec<&EncodeProfile> {
+        let mut rungs: Vec<&EncodeProfile> = self
+            .renditions
+            .iter()
+            .filter(|rung| rung.height <= source_height)
+            .filter(|rung| self.max_height.is_none_or(|max| rung.height <= max))
+            .collect();
+        rungs.sort_by_key(|rung| std::cmp::Reverse(rung.height));
+        rungs
+    }
+}
+
+// One prepared rung of an asset's ladder.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Rendition {
+    pub name: String,
+    pub local_path: String,
+    // `None` for the audio-only rung.
+    pub resolution: Option<Resolution>,
+    // Peak bits per second, as advertised in the HLS master playlist.
+    pub bandwidth_bps: u64,
+    // RFC 6381 codecs, e.g. `avc1.640028,mp4a.40.2`.
+    pub codecs: String,
+}
+
+// A subtitle track of the source, converted to WebVTT and timed to the
+// prepared output.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SubtitleTrack {
+    // ISO 639-2 code the source tagged the track with.
+    pub language: Option<String>,
+    // CEA-608 captions carried in the video rather than a subtitle stream.
+    pub closed_captions: bool,
+    pub local_path: String,
+}
+
+impl SubtitleTrack {
+    // Tracks with the same key on different assets are published as one HLS
+    // subtitle rendition; an asset has at most one track per key.
+    #[must_use]
+    pub fn key(&self) -> String {
+        let language = self.language.as_deref().unwrap_or("und");
+        if self.closed_captions {
+            format!("{language}-cc")
+        } else {
+            language.to_string()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RateControl {
+    // Constant quality; lower is better, 0-51.
+    Crf(u8),
+    // Capped average bitrate.
+    BitrateKbps(u32),
+}
+
+// Limits for the technical QA battery prep runs on every source.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QaThresholds {
+    // `blackdetect` pixel luminance threshold, 0.0-1.0.
+    pub black_pixel_threshold: f32,
+    pub black_min_duration_sec: f32,
+    // Share of the runtime that may be black.
+    pub max_black_ratio: f32,
+    pub freeze_noise_db: f32,
+    pub max_freeze_sec: f32,
+    pub silence_noise_db: f32,
+    pub max_silence_sec: f32,
+    pub max_decode_errors: u32,
+    // Allowed gap between the probed duration and `PlanItem.duration_sec`.
+    pub max_duration_mismatch_pct: f32,
+    pub require_audio: bool,
+    pub max_av_offset_ms: u32,
+}
+
+impl Default for QaThresholds {
+    fn default() -> Self {
+        Self {
+            black_pixel_threshold: 0.10,
+            black_min_duration_sec: 0.5,
+            max_black_ratio: 0.15,
+            freeze_noise_db: -60.0,
+            max_freeze_sec: 8.0,
+            silence_noise_db: -50.0,
+            max_silence_sec: 10.0,
+            max_decode_errors: 0,
+            max_duration_mismatch_pct: 10.0,
+            require_audio: true,
+            max_av_offset_ms: 250,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -114,6 +872,58 @@ pub struct PlanItem {
     pub selection_reason: String,
     pub policy_match_score: f32,
     pub state: PlanState,
+    #[serde(default)]
+    pub slot_start_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub pin_id: Option<String>,
+    #[serde(default)]
+    pub score_breakdown: Option<ScoreBreakdown>,
+    #[serde(default)]
+    pub drop_reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct ScoreBreakdown {
+    pub policy_match: f32,
+    pub theme_diversity: f32,
+    pub duration_fit: f32,
+    pub freshness: f32,
+    pub novelty: f32,
+    pub recent_theme_penalty: f32,
+}
+
+impl ScoreBreakdown {
+    #[must_use]
+    pub fn total(&self) -> f32 {
+        self.policy_match + self.theme_diversity + self.duration_fit + self.freshness + self.novelty
+            - self.recent_theme_penalty
+    }
+}
+
+// What already went on air before a planning run: every source that aired and
+// the tags of recent airings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AiringHistory {
+    pub aired_sources: BTreeSet<String>,
+    pub recent: Vec<AiredItem>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AiredItem {
+    pub aired_at: DateTime<Utc>,
+    pub theme_tags: Vec<String>,
+    pub visual_features: Vec<String>,
+}
+
+impl PlanItem {
+    #[must_use]
+    pub fn content_tags(&self) -> Vec<String> {
+        self.theme_tags
+            .iter()
+            .chain(self.visual_features.iter())
+            .cloned()
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -132,9 +942,225 @@ pub struct AssetItem {
     pub resolution: Resolution,
     pub audio_lufs: f32,
     pub qa_status: QaStatus,
+    #[serde(default)]
+    pub pinned_start_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // Measured by prep; `None` when the asset was never measured.
+    #[serde(default)]
+    pub loudness: Option<LoudnessReport>,
+    // `PlanItem.duration_sec` of the plan behind the asset.
+    #[serde(default)]
+    pub planned_duration_sec: Option<u32>,
+    #[serde(default)]
+    pub qa_report: Option<QaReport>,
+    // Name of the `EncodeProfile` the prepared output was encoded with.
+    #[serde(default)]
+    pub encode_profile: Option<String>,
+    // ABR ladder rungs; empty when the ladder is off.
+    #[serde(default)]
+    pub renditions: Vec<Rendition>,
+    #[serde(default)]
+    pub framing: Option<Framing>,
+    // Set whenever prep measured the runtime, even if nothing was cut.
+    #[serde(default)]
+    pub trim: Option<Trim>,
+    // `None` when previews are off or the asset was rejected.
+    #[serde(default)]
+    pub previews: Option<Previews>,
+    #[serde(default)]
+    pub subtitles: Vec<SubtitleTrack>,
+}
+
+impl AssetItem {
+    // Hash of the downloaded source when it lives in the content-addressed store.
+    #[must_use]
+    pub fn content_sha256(&self) -> Option<&str> {
+        self.checksum.strip_prefix("sha256:")
+    }
+
+    // Every file prep produced for the asset, which must live as long as it.
+    pub fn prepared_files(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.local_path.as_str())
+            .chain(self.renditions.iter().map(|rung| rung.local_path.as_str()))
+            .chain(self.previews.iter().flat_map(Previews::files))
+            .chain(self.subtitles.iter().map(|track| track.local_path.as_str()))
+    }
+}
+
+// Prepared output of one source content under one prep profile.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrepCacheEntry {
+    pub input_sha256: String,
+    pub profile_hash: String,
+    pub output_path: String,
+    pub resolution: Resolution,
+    pub audio_lufs: f32,
+    pub qa_status: QaStatus,
+    #[serde(default)]
+    pub loudness: Option<LoudnessReport>,
+    #[serde(default)]
+    pub qa_report: Option<QaReport>,
+    #[serde(default)]
+    pub encode_profile: Option<String>,
+    #[serde(default)]
+    pub renditions: Vec<Rendition>,
+    #[serde(default)]
+    pub framing: Option<Framing>,
+    #[serde(default)]
+    pub trim: Option<Trim>,
+    #[serde(default)]
+    pub previews: Option<Previews>,
+    #[serde(default)]
+    pub subtitles: Vec<SubtitleTrack>,
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PrepJobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    // The plan was dropped before its output was ready.
+    Cancelled,
+}
+
+// One asset going through prep. Queued and running jobs survive a restart and
+// are picked up again by the next commit window.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrepJob {
+    pub job_id: String,
+    pub plan_id: String,
+    pub state: PrepJobState,
+    // The fetched asset while pending, the prepared one once finished.
+    pub asset: AssetItem,
+    pub attempts: u32,
+    pub error: Option<String>,
+    // `MEDIA_*` code of the failure kind, e.g. `MEDIA_TIMEOUT`.
+    #[serde(default)]
+    pub error_code: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl PrepJob {
+    #[must_use]
+    pub fn queued(asset: AssetItem, now: DateTime<Utc>) -> Self {
+        Self {
+            job_id: asset.asset_id.clone(),
+            plan_id: asset.plan_id.clone(),
+            state: PrepJobState::Queued,
+            asset,
+            attempts: 0,
+            error: None,
+            error_code: None,
+            updated_at: now,
+        }
+    }
+
+    #[must_use]
+    pub fn is_pending(&self) -> bool {
+        matches!(self.state, PrepJobState::Queued | PrepJobState::Running)
+    }
+}
+
+// EBU R128 measurement of an audio track, as reported by ffmpeg `loudnorm`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct LoudnessMeasurement {
+    pub integrated_lufs: f32,
+    pub true_peak_dbtp: f32,
+    pub loudness_range_lu: f32,
+    pub threshold_lufs: f32,
+}
+
+// Loudness of the downloaded source and of the normalized output.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct LoudnessReport {
+    pub source: LoudnessMeasurement,
+    pub normalized: LoudnessMeasurement,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum QaCheck {
+    Prep,
+    Resolution,
+    Loudness,
+    TruePeak,
+    BlackFrames,
+    FrozenVideo,
+    Silence,
+    DecodeErrors,
+    DurationMismatch,
+    VideoStream,
+    AudioStream,
+    AvSync,
+    AspectRatio,
+}
+
+impl QaCheck {
+    // Rejection reason recorded when this check fails.
+    #[must_use]
+    pub fn reason_code(self) -> &'static str {
+        match self {
+            Self::Prep => "QA_PREP_FAILED",
+            Self::Resolution => "QA_LOW_RESOLUTION",
+            Self::Loudness => "QA_LOUDNESS_OFF_TARGET",
+            Self::TruePeak => "QA_TRUE_PEAK",
+            Self::BlackFrames => "QA_BLACK_FRAMES",
+            Self::FrozenVideo => "QA_FROZEN_VIDEO",
+            Self::Silence => "QA_LONG_SILENCE",
+            Self::DecodeErrors => "QA_DECODE_ERRORS",
+            Self::DurationMismatch => "QA_DURATION_MISMATCH",
+            Self::VideoStream => "QA_NO_VIDEO",
+            Self::AudioStream => "QA_NO_AUDIO",
+            Self::AvSync => "QA_AV_DESYNC",
+            Self::AspectRatio => "QA_ASPECT_RATIO",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct QaCheckResult {
+    pub check: QaCheck,
+    pub passed: bool,
+    // What was measured and the limit it was held to, in the check's unit.
+    pub measured: Option<f64>,
+    pub limit: Option<f64>,
+    pub detail: String,
+}
+
+// Every check prep ran on an asset. A single failed check rejects it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct QaReport {
+    pub checks: Vec<QaCheckResult>,
+}
+
+impl QaReport {
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    #[must_use]
+    pub fn failed(&self) -> Vec<QaCheck> {
+        self.checks
+            .iter()
+            .filter(|check| !check.passed)
+            .map(|check| check.check)
+            .collect()
+    }
+
+    #[must_use]
+    pub fn reason_codes(&self) -> Vec<&'static str> {
+        self.failed()
+            .into_iter()
+            .map(QaCheck::reason_code)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Resolution {
     pub width: u16,
     pub height: u16,
@@ -155,6 +1181,10 @@ pub struct QueueEntry {
     pub slot_type: SlotType,
     pub fallback_level: u8,
     pub curation_trace_id: Option<String>,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -185,6 +1215,22 @@ pub struct DiscoveryInput {
 pub struct PlannedDay {
     pub scheduled: Vec<PlanItem>,
     pub reserves: Vec<PlanItem>,
+    #[serde(default)]
+    pub unresolved_pins: Vec<String>,
+    #[serde(default)]
+    pub optimizer_report: Option<OptimizerReport>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OptimizerReport {
+    pub greedy_objective: f64,
+    pub greedy_violations: u32,
+    pub optimized_objective: f64,
+    pub optimized_violations: u32,
+    pub iterations: u32,
+    #[serde(default)]
+    pub evaluated_items: u64,
+    pub applied: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -198,6 +1244,29 @@ pub struct PipelineMetrics {
     pub stream_disruptions: usize,
 }
 
+// Disk usage of one storage area after the last garbage collection.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StorageUsage {
+    pub area: String,
+    pub used_bytes: u64,
+    pub budget_bytes: u64,
+    pub pressure_bytes: u64,
+    pub evicted_files: u64,
+    pub evicted_bytes: u64,
+}
+
+impl StorageUsage {
+    #[must_use]
+    pub fn under_pressure(&self) -> bool {
+        self.used_bytes >= self.pressure_bytes
+    }
+
+    #[must_use]
+    pub fn is_full(&self) -> bool {
+        self.used_bytes >= self.budget_bytes
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DailyReport {
     pub date: String,
@@ -215,7 +1284,7 @@ pub struct WeeklyReport {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use chrono::Utc;
+    use chrono::{TimeZone, Utc};
 
     #[test]
     fn owner_card_validation_works() {
@@ -225,11 +1294,16 @@ mod tests {
                 target_avg_duration_sec: 900,
                 max_consecutive_same_theme: 2,
                 min_unique_themes_per_block: 3,
+                theme_policy: ThemePolicy::default(),
+                placement_rules: vec![],
+                freshness_policy: FreshnessPolicy::default(),
             },
             search_policy: SearchPolicy {
                 allowlist_domains: vec!["example.com".to_string()],
                 blacklist_domains: vec![],
                 blocked_keywords: vec![],
+                max_domain_share: None,
+                min_distinct_sources_per_block: 0,
             },
             schedule_policy: SchedulePolicy {
                 planning_horizon_hours: 24,
@@ -237,11 +1311,14 @@ mod tests {
                 commit_interval_minutes: 30,
                 buffer_target_minutes: 60,
                 buffer_critical_minutes: 20,
+                pinned_slots: vec![],
+                optimizer: OptimizerPolicy::default(),
             },
             quality_policy: QualityPolicy {
                 min_resolution_height: 720,
                 target_audio_lufs: -16.0,
                 max_audio_deviation_lufs: 2.5,
+                ..QualityPolicy::default()
             },
             music_policy: MusicPolicy {
                 preferred_moods: vec!["night".to_string()],
@@ -260,6 +1337,9 @@ mod tests {
                 max_daily_adjustment_pct: 5.0,
                 enabled: true,
             },
+            fetch_policy: FetchPolicy::default(),
+            storage_policy: StoragePolicy::default(),
+            prep_policy: PrepPolicy::default(),
         };
 
         assert!(card.validate().is_ok());
@@ -280,10 +1360,127 @@ mod tests {
             selection_reason: "policy".to_string(),
             policy_match_score: 0.9,
             state: PlanState::Candidate,
+            slot_start_at: None,
+            pin_id: None,
+            score_breakdown: None,
+            drop_reason: None,
         };
         let json = serde_json::to_string(&plan).expect("serialize");
         let back: PlanItem = serde_json::from_str(&json).expect("deserialize");
         assert_eq!(back.plan_id, "p1");
         assert_eq!(back.state, PlanState::Candidate);
     }
+
+    #[test]
+    fn seeded_run_context_repeats_ids() {
+        let now = Utc::now();
+        let a = RunContext::seeded(42, now);
+        let b = RunContext::seeded(42, now);
+        let ids_a: Vec<_> = (0..3).map(|_| a.next_id()).collect();
+        let ids_b: Vec<_> = (0..3).map(|_| b.next_id()).collect();
+        assert_eq!(ids_a, ids_b);
+        assert_ne!(ids_a[0], ids_a[1]);
+        assert_eq!(a.now(), now);
+        assert_ne!(RunContext::seeded(43, now).next_id(), ids_a[0]);
+    }
+
+    #[test]
+    fn weekly_pin_occurrences_follow_recurrence() {
+        let first = Utc.with_ymd_and_hms(2026, 2, 27, 22, 0, 0).unwrap();
+        let pin = PinnedSlot {
+            pin_id: "friday-22h".to_string(),
+            target: PinTarget::Plan {
+                plan_id: "p1".to_string(),
+            },
+            start_at: first,
+            recurrence: PinRecurrence::Weekly,
+        };
+
+        let from = first + Duration::days(10);
+        let hits = pin.occurrences_between(from, from + Duration::days(14));
+        assert_eq!(
+            hits,
+            vec![first + Duration::weeks(2), first + Duration::weeks(3)]
+        );
+        assert!(
+            PinnedSlot {
+                recurrence: PinRecurrence::Once,
+                ..pin
+            }
+            .occurrences_between(from, from + Duration::days(14))
+            .is_empty()
+        );
+    }
+
+    #[test]
+    fn placement_rules_handle_midnight_windows_and_adjacency() {
+        let rules: Vec<PlacementRule> = serde_json::from_str(
+            r#"[
+                {"rule_id": "late", "tag": "horror", "kind": "window", "from": "23:00", "until": "05:00"},
+                {"rule_id": "apart", "tag": "horror", "kind": "never_adjacent", "other_tag": "kids"}
+            ]"#,
+        )
+        .expect("parse rules");
+        let horror = vec!["Horror".to_string()];
+        let kids = vec!["kids".to_string()];
+        let at = |hour| Utc.with_ymd_and_hms(2026, 3, 1, hour, 30, 0).unwrap();
+
+        assert!(window_violation(&rules, &horror, at(23)).is_none());
+        assert!(window_violation(&rules, &horror, at(4)).is_none());
+        assert_eq!(
+            window_violation(&rules, &horror, at(12)).map(|r| r.rule_id.as_str()),
+            Some("late")
+        );
+        assert!(window_violation(&rules, &kids, at(12)).is_none());
+        assert!(adjacency_violation(&rules, &kids, &horror).is_some());
+        assert!(adjacency_violation(&rules, &horror, &horror).is_none());
+    }
+
+    #[test]
+    fn encode_profiles_never_upscale_and_validate() {
+        let mut quality = QualityPolicy::default();
+        let name = |quality: &QualityPolicy, height| {
+            quality
+                .encode_profile_for(height)
+                .map(|profile| profile.name.clone())
+        };
+        assert_eq!(name(&quality, 2160).as_deref(), Some("1080p"));
+        assert_eq!(name(&quality, 1080).as_deref(), Some("1080p"));
+        assert_eq!(name(&quality, 900).as_deref(), Some("720p"));
+        assert_eq!(name(&quality, 480).as_deref(), Some("720p"));
+        assert_eq!(quality.encode_profiles[0].gop_frames(6), 180);
+        assert!(quality.validate_encoding().is_ok());
+
+        let parsed: EncodeProfile = serde_json::from_str(
+            r#"{"name": "480p", "width": 853, "height": 480, "rate_control": {"bitrate_kbps": 1200},
+                "preset": "fast", "frame_rate": 25, "audio_bitrate_kbps": 96}"#,
+        )
+        .expect("parse profile");
+        assert_eq!(parsed.rate_control, RateControl::BitrateKbps(1200));
+        quality.encode_profiles.push(parsed);
+        assert!(quality.validate_encoding().is_err());
+        quality.encode_profiles[2].width = 854;
+        assert!(quality.validate_encoding().is_ok());
+        quality.encode_profiles[2].name = "720p".to_string();
+        assert!(quality.validate_encoding().is_err());
+    }
+
+    #[test]
+    fn abr_rungs_respect_the_source_and_the_height_cap() {
+        let mut abr = AbrPolicy::default();
+        let names = |abr: &AbrPolicy, height| {
+            abr.rungs_for(height)
+                .iter()
+                .map(|rung| rung.name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&abr, 1080), ["1080p", "720p", "480p"]);
+        assert_eq!(names(&abr, 720), ["720p", "480p"]);
+        abr.max_height = Some(720);
+        assert_eq!(names(&abr, 2160), ["720p", "480p"]);
+
+        let mut quality = QualityPolicy::default();
+        quality.abr_ladder.renditions[2].name = AUDIO_ONLY_RENDITION.to_string();
+        assert!(quality.validate_encoding().is_err());
+    }
 }
@@ -0,0 +1,98 @@
+use chrono::{DateTime, NaiveTime, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlacementRule {
+    pub rule_id: String,
+    pub tag: String,
+    #[serde(flatten)]
+    pub constraint: PlacementConstraint,
+}
+
+// Windows are UTC wall-clock times ("HH:MM") and may wrap midnight, e.g.
+// 23:00-05:00. An item carrying the tag may only start inside the window.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PlacementConstraint {
+    Window { from: String, until: String },
+    NeverAdjacent { other_tag: String },
+}
+
+impl PlacementRule {
+    /// # Errors
+    ///
+    /// Describes the first problem: an empty tag or other tag, or a window time
+    /// that is not HH:MM.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.tag.trim().is_empty() {
+            return Err(format!("placement rule {} has an empty tag", self.rule_id));
+        }
+        match &self.constraint {
+            PlacementConstraint::Window { from, until } => parse_minutes(from)
+                .and(parse_minutes(until))
+                .map(|_| ())
+                .ok_or_else(|| format!("placement rule {} window must use HH:MM", self.rule_id)),
+            PlacementConstraint::NeverAdjacent { other_tag } if other_tag.trim().is_empty() => Err(
+                format!("placement rule {} has an empty other_tag", self.rule_id),
+            ),
+            PlacementConstraint::NeverAdjacent { .. } => Ok(()),
+        }
+    }
+
+    #[must_use]
+    pub fn allows_start(&self, tags: &[String], start_at: DateTime<Utc>) -> bool {
+        let PlacementConstraint::Window { from, until } = &self.constraint else {
+            return true;
+        };
+        if !has_tag(tags, &self.tag) {
+            return true;
+        }
+        let (Some(from), Some(until)) = (parse_minutes(from), parse_minutes(until)) else {
+            return true;
+        };
+        let at = start_at.hour() * 60 + start_at.minute();
+        match from.cmp(&until) {
+            std::cmp::Ordering::Less => from <= at && at < until,
+            std::cmp::Ordering::Greater => at >= from || at < until,
+            std::cmp::Ordering::Equal => true,
+        }
+    }
+
+    #[must_use]
+    pub fn allows_adjacent(&self, left: &[String], right: &[String]) -> bool {
+        let PlacementConstraint::NeverAdjacent { other_tag } = &self.constraint else {
+            return true;
+        };
+        let forbidden = |a: &[String], b: &[String]| has_tag(a, &self.tag) && has_tag(b, other_tag);
+        !(forbidden(left, right) || forbidden(right, left))
+    }
+}
+
+#[must_use]
+pub fn window_violation<'a>(
+    rules: &'a [PlacementRule],
+    tags: &[String],
+    start_at: DateTime<Utc>,
+) -> Option<&'a PlacementRule> {
+    rules.iter().find(|rule| !rule.allows_start(tags, start_at))
+}
+
+#[must_use]
+pub fn adjacency_violation<'a>(
+    rules: &'a [PlacementRule],
+    left: &[String],
+    right: &[String],
+) -> Option<&'a PlacementRule> {
+    rules.iter().find(|rule| !rule.allows_adjacent(left, right))
+}
+
+fn has_tag(tags: &[String], wanted: &str) -> bool {
+    tags.iter()
+        .any(|tag| tag.trim().eq_ignore_ascii_case(wanted.trim()))
+}
+
+fn parse_minutes(value: &str) -> Option<u32> {
+    NaiveTime::parse_from_str(value.trim(), "%H:%M")
+        .ok()
+        .map(|time| time.hour() * 60 + time.minute())
+}